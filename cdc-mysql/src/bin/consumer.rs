@@ -1,33 +1,51 @@
 use crossbeam_channel::{bounded, select, Receiver, Sender};
 use futures::StreamExt;
 use std::io::{Error, ErrorKind};
-use tracing::error;
+use tracing::{error, info};
+use tracing_subscriber::prelude::*;
 
-use fluvio_cdc::consumer::MysqlManager;
-use fluvio_cdc::consumer::OffsetStore;
-use fluvio_cdc::consumer::{get_cli_opt, Config};
+use fluvio_cdc::consumer::{allowed_by_filters, parse_uri, Filters};
+use fluvio_cdc::consumer::{get_cli_opt, Config, Profile};
+use fluvio_cdc::consumer::{source_name, SequenceCheck, SequenceTracker};
+use fluvio_cdc::consumer::{with_retry, DeadLetterQueue, Failure, Retry};
+use fluvio_cdc::consumer::{FileOffsetStore, OffsetStore, Offsets, TableOffsetStore};
+use fluvio_cdc::consumer::{
+    FileSink, MysqlManager, ParquetSink, PostgresSink, Sink, SinkConfig, SqliteSink,
+};
+use fluvio_cdc::messages::{is_newer_version, FluvioMessage};
 
 use fluvio::{FluvioError, Offset, PartitionConsumer};
 
+const PARTITION: i32 = 0;
+
 async fn run() -> Result<(), FluvioError> {
     // read profile
     let params = get_cli_opt();
     let config = Config::load(&params.profile)?;
     let profile = config.profile();
 
-    // init store
-    let mut offset_store = OffsetStore::init(profile.last_offset_file()).await?;
-
-    // connect to db
-    println!("Connecting to mysql database... ");
-    let mut mysql = MysqlManager::connect(profile)?;
+    // connect to sink, init stores
+    let (mut sink, mut offset_store) = init_sink(profile)?;
+    let mut sequence_tracker = SequenceTracker::init(
+        offset_store.sequences().clone(),
+        &profile.quarantine_file(),
+        profile.on_gap(),
+    );
+    let mut dead_letter = match profile.dead_letter() {
+        Some(dead_letter) => {
+            Some(DeadLetterQueue::init(&dead_letter, &profile.dead_letter_file()).await?)
+        }
+        None => None,
+    };
+    let retry = profile.retry();
+    let filters = profile.filters();
 
     // create channels
     let ctrl_c_events = ctrl_channel()?;
     let (sender, receiver) = bounded::<String>(100);
 
     // start Fluvio consumer thread
-    let consumer = fluvio::consumer(&profile.topic(), 0).await?;
+    let consumer = fluvio::consumer(&profile.topic(), PARTITION).await?;
     let offset = Offset::absolute(offset_store.offset()).unwrap();
     async_std::task::spawn(consume(consumer, offset, sender));
 
@@ -36,8 +54,16 @@ async fn run() -> Result<(), FluvioError> {
             recv(receiver) -> msg => {
                 match msg {
                     Ok(msg) => {
-                        mysql.update_database(msg)?;
-                        offset_store.increment_offset().await?;
+                        process_message(
+                            &msg,
+                            sink.as_mut(),
+                            offset_store.as_mut(),
+                            &mut sequence_tracker,
+                            &filters,
+                            &retry,
+                            &mut dead_letter,
+                        )
+                        .await?;
                     }
                     Err(err) => {
                         println!("{}", err.to_string());
@@ -49,6 +75,10 @@ async fn run() -> Result<(), FluvioError> {
             recv(ctrl_c_events) -> _ => {
                 println!();
                 println!("Exited by user");
+                println!("Sequence counters: {:?}", sequence_tracker.counters());
+                info!(counters = ?sequence_tracker.counters(), "Sequence counters");
+                sink.flush()?;
+                sink.report();
                 break;
             }
         }
@@ -57,6 +87,149 @@ async fn run() -> Result<(), FluvioError> {
     Ok(())
 }
 
+/// Connect to the sink, and init the offset store (tables in the sink database, or file)
+fn init_sink(profile: &Profile) -> Result<(Box<dyn Sink>, Box<dyn OffsetStore>), Error> {
+    match profile.sink() {
+        SinkConfig::Mysql => {
+            println!("Connecting to mysql database... ");
+            let mut mysql = MysqlManager::connect(profile)?;
+            let offset_store: Box<dyn OffsetStore> = match profile.offsets() {
+                Offsets::File => Box::new(FileOffsetStore::init(
+                    profile.last_offset_file(),
+                    &profile.last_sequence_file(),
+                )?),
+                Offsets::Table(offset_table) => Box::new(TableOffsetStore::init(
+                    mysql.conn(),
+                    &offset_table.table(),
+                    &profile.topic(),
+                    PARTITION,
+                )?),
+            };
+            Ok((Box::new(mysql), offset_store))
+        }
+        SinkConfig::Sqlite => {
+            println!("Opening sqlite database... ");
+            let mut sqlite = SqliteSink::open(&profile.sqlite_file(), profile)?;
+            let offset_store = sqlite.offset_store(&profile.topic(), PARTITION)?;
+            Ok((Box::new(sqlite), Box::new(offset_store)))
+        }
+        SinkConfig::Postgres(postgres) => {
+            println!("Connecting to postgres database... ");
+            let mut postgres = PostgresSink::connect(&postgres.url, profile)?;
+            let offset_store = postgres.offset_store(&profile.topic(), PARTITION)?;
+            Ok((Box::new(postgres), Box::new(offset_store)))
+        }
+        SinkConfig::File(file_output) => {
+            println!("Opening output files... ");
+            let files = FileSink::open(&profile.output_dir(), &file_output, profile)?;
+            Ok((Box::new(files), file_offset_store(profile)?))
+        }
+        SinkConfig::Parquet(parquet_output) => {
+            println!("Opening parquet files... ");
+            let parquet = ParquetSink::open(&profile.output_dir(), &parquet_output, profile)?;
+            Ok((Box::new(parquet), file_offset_store(profile)?))
+        }
+    }
+}
+
+/// Offset store of sinks without an offset table
+fn file_offset_store(profile: &Profile) -> Result<Box<dyn OffsetStore>, Error> {
+    match profile.offsets() {
+        Offsets::File => Ok(Box::new(FileOffsetStore::init(
+            profile.last_offset_file(),
+            &profile.last_sequence_file(),
+        )?)),
+        Offsets::Table(_) => Err(Error::new(
+            ErrorKind::InvalidInput,
+            "offsets store \"table\" requires the mysql sink",
+        )),
+    }
+}
+
+/// Apply message to the sink, temporary errors are retried.
+/// Messages that still fail are sent to the dead-letter queue and skipped,
+/// or stop the consumer if there is no dead-letter queue.
+/// Messages of a newer schema version stop the consumer, their offset is not saved.
+///
+/// If a db-filter is provided, messages may be skipped by filter (source database).
+/// Filters are applied as follows:
+///  - no filters - all messages are processed
+///  - include filters - only messages inside "include" filters are processed
+///  - exclude filters - only messages outside of "exclude" filters are processed
+async fn process_message(
+    msg: &str,
+    sink: &mut dyn Sink,
+    offset_store: &mut dyn OffsetStore,
+    sequence_tracker: &mut SequenceTracker,
+    filters: &Option<Filters>,
+    retry: &Retry,
+    dead_letter: &mut Option<DeadLetterQueue>,
+) -> Result<(), Error> {
+    let offset = offset_store.offset();
+    let flv_message = match FluvioMessage::decode(msg) {
+        Ok(flv_message) => flv_message,
+        // written by a newer producer: read again once the consumer is upgraded
+        Err(err) if is_newer_version(msg) => return Err(err),
+        Err(err) => {
+            let failure = Failure::new(err, 1);
+            send_to_dead_letter(dead_letter, offset, msg, failure).await?;
+            return checkpoint(sink, offset_store, retry).await;
+        }
+    };
+
+    // sequence saved with the offset of the next message, duplicates are ignored
+    offset_store.set_sequence(&source_name(&flv_message.uri), flv_message.sequence);
+    if sequence_tracker.check(&flv_message, msg).await? == SequenceCheck::Skip {
+        return checkpoint(sink, offset_store, retry).await;
+    }
+
+    let result = match parse_uri(&flv_message.uri) {
+        Ok(uri_props) if !allowed_by_filters(filters, &uri_props.db_name) => {
+            println!(
+                "msg for db '{}' skipped by filter",
+                uri_props.db_name.unwrap_or_else(|| "".to_owned())
+            );
+            checkpoint(sink, offset_store, retry).await?;
+            sequence_tracker.applied(&flv_message);
+            return Ok(());
+        }
+        Ok(_) => with_retry(retry, || sink.apply_message(&flv_message, offset_store)).await,
+        Err(err) => Err(Failure::new(err, 1)),
+    };
+    match result {
+        Ok(()) => sequence_tracker.applied(&flv_message),
+        Err(failure) => {
+            send_to_dead_letter(dead_letter, offset, msg, failure).await?;
+            checkpoint(sink, offset_store, retry).await?;
+            sequence_tracker.dead_lettered(&flv_message);
+        }
+    }
+    Ok(())
+}
+
+/// Save the offset of a message not applied, temporary errors are retried
+async fn checkpoint(
+    sink: &mut dyn Sink,
+    offset_store: &mut dyn OffsetStore,
+    retry: &Retry,
+) -> Result<(), Error> {
+    with_retry(retry, || sink.checkpoint(offset_store))
+        .await
+        .map_err(|failure| failure.error)
+}
+
+async fn send_to_dead_letter(
+    dead_letter: &mut Option<DeadLetterQueue>,
+    offset: i64,
+    msg: &str,
+    failure: Failure,
+) -> Result<(), Error> {
+    match dead_letter {
+        Some(dead_letter) => dead_letter.send(offset, msg, &failure).await,
+        None => Err(failure.error),
+    }
+}
+
 async fn consume(
     consumer: PartitionConsumer,
     offset: Offset,
@@ -86,6 +259,11 @@ fn ctrl_channel() -> Result<Receiver<()>, Error> {
 }
 
 fn main() {
+    tracing_subscriber::registry()
+        .with(tracing_subscriber::fmt::layer())
+        .with(tracing_subscriber::EnvFilter::from_default_env())
+        .init();
+
     if let Err(err) = async_std::task::block_on(run()) {
         println!("Error: {}", err.to_string());
     }
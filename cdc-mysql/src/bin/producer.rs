@@ -1,12 +1,13 @@
 use crossbeam_channel::{bounded, select, Receiver};
 use std::io::{Error, ErrorKind};
-use tracing::error;
+use tracing::{error, warn};
 use tracing_subscriber::prelude::*;
 
 use fluvio_cdc::error::CdcError;
-use fluvio_cdc::messages::BinLogMessage;
-use fluvio_cdc::producer::{get_cli_opt, Config};
-use fluvio_cdc::producer::{BinLogManager, FluvioManager, Resume};
+use fluvio_cdc::messages::{BinLogMessage, BnFile};
+use fluvio_cdc::producer::{get_cli_opt, Config, Profile, ResumeMismatch, Source};
+use fluvio_cdc::producer::{BinLogManager, FluvioManager, ReplicationManager};
+use fluvio_cdc::producer::{Resume, ResumeCheck};
 
 async fn run() -> Result<(), CdcError> {
     // read profile
@@ -21,16 +22,33 @@ async fn run() -> Result<(), CdcError> {
     let (sender, receiver) = bounded::<String>(100);
 
     // create fluvio manager
-    let mut flv_manager = FluvioManager::new(profile.topic(), profile.replicas(), None).await?;
-
-    // create binlog manager
-    let bn_manager = BinLogManager::new(&profile, sender)
-        .map_err(|source| CdcError::BinlogFileError { source })?;
+    let mut flv_manager = FluvioManager::new(
+        profile.topic(),
+        profile.replicas(),
+        None,
+        profile.envelope(),
+    )
+    .await?;
 
     // create resume offset or none
     let mut resume = Resume::load(profile.resume_offset_file())
         .await
         .map_err(|source| CdcError::ResumeError { source })?;
+
+    // reconcile with the last message in the topic (sequence continues after it), an
+    // interrupted message is sent again after the complete message before it
+    if !skip_fluvio {
+        let published = flv_manager.get_last_file_offset().await?;
+        if flv_manager.interrupted() {
+            println!("Last message in topic was interrupted, sending it again");
+            warn!("last message in topic was interrupted, resuming from the message before it");
+        }
+        reconcile_resume(&mut resume, published, profile)
+            .await
+            .map_err(|source| CdcError::ResumeError { source })?;
+        println!("Next sequence {}", flv_manager.sequence());
+    }
+
     if let Some(binfile) = resume.binfile.as_ref() {
         println!("Resuming from {:?}", binfile);
     } else {
@@ -38,24 +56,43 @@ async fn run() -> Result<(), CdcError> {
     }
     println!("{:?}", resume);
 
-    let ts_frequency = None;
-    bn_manager.run(resume.clone(), ts_frequency);
+    // create binlog manager (file or replication source)
+    match profile.source() {
+        Source::File => {
+            let bn_manager = BinLogManager::new(&profile, sender)
+                .map_err(|source| CdcError::BinlogFileError { source })?;
+            let ts_frequency = None;
+            bn_manager.run(resume.clone(), ts_frequency);
+        }
+        Source::Replication(replication) => {
+            let rpl_manager = ReplicationManager::new(&profile, replication, sender)
+                .map_err(|source| CdcError::ReplicationError { source })?;
+            rpl_manager.run(resume.clone());
+        }
+    }
 
     loop {
         select! {
             recv(receiver) -> msg => {
                 match msg {
                     Ok(msg) => {
-                        let bn_message: BinLogMessage = serde_json::from_str(&msg)?;
+                        let bn_message = BinLogMessage::decode(&msg)?;
                         let bn_file = bn_message.bn_file.clone();
+                        let mut published = true;
                         if !skip_fluvio {
-                            if let Err(err) = flv_manager.process_msg(bn_message).await {
-                                println!("{}", err.to_string());
-                                error!("{}", err.to_string());
-                                std::process::exit(1);
+                            match flv_manager.process_msg(bn_message).await {
+                                Ok(sent) => published = sent,
+                                Err(err) => {
+                                    println!("{}", err.to_string());
+                                    error!("{}", err.to_string());
+                                    std::process::exit(1);
+                                }
                             }
                         }
-                        resume.update_binfile(bn_file).await?;
+                        // the resume file stays at the last position in the topic
+                        if published {
+                            resume.update_binfile(bn_file).await?;
+                        }
                     },
                     Err(err) => {
                         println!("{}", err.to_string());
@@ -74,6 +111,36 @@ async fn run() -> Result<(), CdcError> {
     Ok(())
 }
 
+/// Resume file is checked against the topic:
+///  - no resume file, or topic ahead of the resume file => resume from the topic
+///  - mismatch => refuse to start, or resume from the topic (resume_mismatch = "warn")
+async fn reconcile_resume(
+    resume: &mut Resume,
+    published: Option<BnFile>,
+    profile: &Profile,
+) -> Result<(), Error> {
+    match resume.check_published(published.as_ref()) {
+        ResumeCheck::Agree => return Ok(()),
+        ResumeCheck::Missing => println!("Resume file missing, resuming from topic"),
+        ResumeCheck::Behind => println!("Resume file behind topic, resuming from topic"),
+        ResumeCheck::Mismatch => {
+            let msg = format!(
+                "resume file {:?} does not match last message in topic {:?}",
+                resume.binfile, published
+            );
+            match profile.resume_mismatch() {
+                ResumeMismatch::Fail => return Err(Error::new(ErrorKind::InvalidData, msg)),
+                ResumeMismatch::Warn => warn!("{}, resuming from topic", msg),
+            }
+        }
+    }
+
+    match published {
+        Some(published) => resume.update_binfile(published).await,
+        None => resume.clear().await,
+    }
+}
+
 fn ctrl_channel() -> Result<Receiver<()>, Error> {
     let (sender, receiver) = bounded(100);
     if let Err(err) = ctrlc::set_handler(move || {
@@ -0,0 +1,204 @@
+//! Conflict Handler
+//!
+//! Each row UPDATE or DELETE is expected to match exactly one row of the target table.
+//! When the target has drifted from the source, the row may be missing (0 rows) or
+//! duplicated (several rows), and the conflict policy decides what happens.
+//!
+//! Conflicts of the log policy are kept until the transaction of the message commits, so
+//! a rolled back (and retried) message is not logged.
+//!
+use serde::Serialize;
+use std::fs::OpenOptions;
+use std::io::{Error, ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use tracing::warn;
+
+use crate::consumer::profile::ConflictPolicy;
+use crate::messages::{FluvioMessage, Operation};
+
+#[derive(Debug, PartialEq)]
+pub enum Resolution {
+    /// Keep the result of the statement and continue
+    Continue,
+    /// Insert the new row of the update
+    Insert,
+}
+
+#[derive(Debug)]
+pub struct ConflictHandler {
+    policy: ConflictPolicy,
+    file: PathBuf,
+    conflicts: u64,
+    pending: Vec<String>,
+}
+
+/// Conflict log entry, one json per line
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ConflictRecord<'a> {
+    operation: &'a str,
+    row: usize,
+    affected_rows: u64,
+    message: &'a FluvioMessage,
+}
+
+impl ConflictHandler {
+    pub fn new(policy: ConflictPolicy, conflict_file: &Path) -> Self {
+        Self {
+            policy,
+            file: conflict_file.to_path_buf(),
+            conflicts: 0,
+            pending: vec![],
+        }
+    }
+
+    pub fn conflicts(&self) -> u64 {
+        self.conflicts
+    }
+
+    /// Check affected rows of the UPDATE or DELETE of a message row
+    pub fn resolve(
+        &mut self,
+        flv_message: &FluvioMessage,
+        row: usize,
+        affected_rows: u64,
+    ) -> Result<Resolution, Error> {
+        if affected_rows == 1 {
+            return Ok(Resolution::Continue);
+        }
+
+        let operation = operation_name(&flv_message.operation);
+        self.conflicts += 1;
+        warn!(
+            uri = %flv_message.uri,
+            sequence = flv_message.sequence,
+            operation,
+            row,
+            affected_rows,
+            policy = ?self.policy,
+            conflicts = self.conflicts,
+            "Conflict"
+        );
+
+        match self.policy {
+            ConflictPolicy::Fail => Err(Error::new(
+                ErrorKind::InvalidData,
+                format!(
+                    "conflict: {} of row {} in {} (sequence {}) matched {} rows",
+                    operation, row, flv_message.uri, flv_message.sequence, affected_rows
+                ),
+            )),
+            ConflictPolicy::Skip => Ok(Resolution::Continue),
+            ConflictPolicy::Insert => match flv_message.operation {
+                Operation::Update(_) if affected_rows == 0 => Ok(Resolution::Insert),
+                _ => Ok(Resolution::Continue),
+            },
+            ConflictPolicy::Log => {
+                self.log(ConflictRecord {
+                    operation,
+                    row,
+                    affected_rows,
+                    message: flv_message,
+                })?;
+                Ok(Resolution::Continue)
+            }
+        }
+    }
+
+    /// Transaction committed, write the conflicts of the message to the conflict file
+    pub fn flush_log(&mut self) -> Result<(), Error> {
+        if self.pending.is_empty() {
+            return Ok(());
+        }
+
+        let lines = self.pending.concat();
+        self.pending.clear();
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.file)?;
+        file.write_all(lines.as_bytes())
+    }
+
+    /// Transaction rolled back, drop the conflicts of the message
+    pub fn discard_log(&mut self) {
+        self.pending.clear();
+    }
+
+    fn log(&mut self, record: ConflictRecord) -> Result<(), Error> {
+        let mut line = serde_json::to_string(&record)?;
+        line.push('\n');
+        self.pending.push(line);
+        Ok(())
+    }
+}
+
+fn operation_name(operation: &Operation) -> &'static str {
+    match operation {
+        Operation::Query(_) => "query",
+        Operation::Add(_) => "insert",
+        Operation::Update(_) => "update",
+        Operation::Delete(_) => "delete",
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::messages::{Cols, DeleteRows, Value};
+    use std::fs;
+
+    const TEST_PATH: &str = "test_files";
+    const CONFLICT_FILE: &str = "conflict-test.conflicts";
+
+    fn build_path() -> PathBuf {
+        let program_dir = std::env::current_dir().unwrap();
+        program_dir.join(TEST_PATH).join(CONFLICT_FILE)
+    }
+
+    fn build_message() -> FluvioMessage {
+        let operation = Operation::Delete(DeleteRows {
+            rows: vec![Cols {
+                cols: vec![Value::String("Pip".to_owned())],
+            }],
+        });
+        FluvioMessage::test_message("flv://srv1/db/pet", operation)
+            .with_sequence(9)
+            .with_columns(&["name"])
+    }
+
+    #[test]
+    fn test_conflict_policies() {
+        let path = build_path();
+        let _ = fs::remove_file(&path);
+        let msg = build_message();
+
+        let mut handler = ConflictHandler::new(ConflictPolicy::Fail, &path);
+        assert_eq!(handler.resolve(&msg, 0, 1).unwrap(), Resolution::Continue);
+        assert!(handler.resolve(&msg, 0, 0).is_err());
+
+        // delete miss is not converted
+        let mut handler = ConflictHandler::new(ConflictPolicy::Insert, &path);
+        assert_eq!(handler.resolve(&msg, 0, 0).unwrap(), Resolution::Continue);
+
+        let mut handler = ConflictHandler::new(ConflictPolicy::Log, &path);
+        assert_eq!(handler.resolve(&msg, 0, 2).unwrap(), Resolution::Continue);
+        assert_eq!(handler.conflicts(), 1);
+
+        // rolled back => not logged
+        handler.discard_log();
+        handler.flush_log().unwrap();
+        assert!(!path.exists());
+
+        assert_eq!(handler.resolve(&msg, 0, 2).unwrap(), Resolution::Continue);
+        handler.flush_log().unwrap();
+
+        let logged = fs::read_to_string(&path).unwrap();
+        let record: serde_json::Value = serde_json::from_str(logged.trim()).unwrap();
+        assert_eq!(record["operation"], "delete");
+        assert_eq!(record["affectedRows"], 2);
+        assert_eq!(record["message"]["sequence"], 9);
+
+        fs::remove_file(&path).expect("delete file failed");
+    }
+}
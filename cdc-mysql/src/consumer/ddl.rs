@@ -0,0 +1,654 @@
+//! DDL Translator
+//!
+//! Translates mysql DDL statements (Query operation) into the statements of a SQL sink.
+//! Statements are parsed with sqlparser (mysql dialect, for backtick quoted names) and rebuilt
+//! for the target database:
+//!  - CREATE TABLE: column types mapped by the sink (see DdlDialect::column_type), column
+//!    options and table constraints (primary key, unique, foreign key, check) kept
+//!  - ALTER TABLE: ADD/DROP/RENAME COLUMN, ADD constraint (see DdlDialect::alters_keys)
+//!  - DROP TABLE, one statement per table
+//!
+//! Database statements are not parsed by sqlparser, they are read from the tokens (see Router)
+//! and translated by the sink. So are index statements, TRUNCATE and RENAME TABLE (see
+//! Translator::translate_tokens). Statements without a table (views, users, grants, full-text
+//! indexes) are ignored with a warning. Table statements that sqlparser cannot read, and types
+//! or options without a translation, return an error.
+//!
+//! Tables are routed to target names (see Router), qualified by the sink, and all names are
+//! quoted, so the target database keeps their case.
+//!
+use sqlparser::ast::{AlterTableOperation, ColumnDef, ColumnOption, DataType, Ident};
+use sqlparser::ast::{ObjectName, Statement, TableConstraint};
+use sqlparser::dialect::MySqlDialect;
+use sqlparser::parser::Parser;
+use std::io::{Error, ErrorKind};
+use tracing::warn;
+
+use crate::consumer::router::{tokenize, Router, Token};
+
+/// DDL syntax of the target database
+pub trait DdlDialect {
+    /// Name of the target database, in errors
+    fn name(&self) -> &'static str;
+
+    /// Column type of a mysql column type
+    fn column_type(&self, data_type: &DataType) -> Result<String, Error>;
+
+    /// Quoted table, in its routed database if known
+    fn qualified_name(&self, db_name: Option<&str>, name: &str) -> String;
+
+    /// Statements of a database statement on the routed database, none by default
+    fn database_statements(&self, _statement: DatabaseStatement, _db_name: &str) -> Vec<String> {
+        vec![]
+    }
+
+    /// Keys can be added to existing tables, otherwise unique keys become unique indexes and
+    /// the other keys are an error
+    fn alters_keys(&self) -> bool {
+        true
+    }
+
+    /// Statement that removes all rows of the (quoted) table
+    fn truncate(&self, table: &str) -> String {
+        format!("TRUNCATE TABLE {}", table)
+    }
+}
+
+/// CREATE/DROP/ALTER DATABASE (or SCHEMA), with its IF [NOT] EXISTS clause
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DatabaseStatement {
+    Create { if_not_exists: bool },
+    Drop { if_exists: bool },
+    Alter,
+}
+
+/// Translate mysql DDL statement into statements of the dialect, in order.
+/// Unqualified tables are in `db_name`, the source database of the message.
+pub fn translate_ddl<D: DdlDialect>(
+    dialect: &D,
+    router: &Router,
+    db_name: Option<&str>,
+    query: &str,
+) -> Result<Vec<String>, Error> {
+    let words = words(query);
+    if let Some(statements) = translate_database(dialect, router, &words) {
+        return Ok(statements);
+    }
+
+    let translator = Translator {
+        dialect,
+        router,
+        db_name,
+    };
+    if let Some(statements) = translator.translate_tokens(query) {
+        return statements.map_err(|err| Error::new(err.kind(), format!("{}: {}", err, query)));
+    }
+    if !is_table_statement(&words) {
+        warn!(
+            query,
+            target = dialect.name(),
+            "Statement without a table, ignored"
+        );
+        return Ok(vec![]);
+    }
+
+    let statements = Parser::parse_sql(&MySqlDialect {}, query).map_err(|err| {
+        Error::new(
+            ErrorKind::InvalidData,
+            format!("cannot parse DDL statement: {}: {}", err, query),
+        )
+    })?;
+
+    let mut translated = vec![];
+    for statement in statements {
+        let target_statements = match statement {
+            Statement::CreateTable {
+                name,
+                columns,
+                constraints,
+                ..
+            } => translator
+                .create_table(&name, &columns, &constraints, is_if_not_exists(&words))
+                .map(|statement| vec![statement]),
+            Statement::AlterTable { name, operation } => translator
+                .alter_table(&name, &operation)
+                .map(|statement| vec![statement]),
+            Statement::Drop {
+                object_type,
+                if_exists,
+                names,
+                ..
+            } if object_type.to_string() == "TABLE" => translator.drop_tables(&names, if_exists),
+            other => Err(translator.unsupported(&other.to_string())),
+        };
+        translated.extend(
+            target_statements
+                .map_err(|err| Error::new(err.kind(), format!("{}: {}", err, query)))?,
+        );
+    }
+
+    Ok(translated)
+}
+
+/// Quote table or column name with double quotes
+pub fn quote_identifier(name: &str) -> String {
+    format!("\"{}\"", name.replace('"', "\"\""))
+}
+
+/// Unsupported construct of the target database, the statement is added by translate_ddl
+pub fn unsupported(target: &str, what: &str) -> Error {
+    Error::new(
+        ErrorKind::InvalidData,
+        format!("cannot translate to {}: {}", target, what),
+    )
+}
+
+fn unreadable() -> Error {
+    Error::new(ErrorKind::InvalidData, "cannot parse DDL statement")
+}
+
+/// Words of the statement (keywords and names), strings and comments skipped
+fn words(query: &str) -> Vec<Token> {
+    tokenize(query)
+        .into_iter()
+        .map(|(token, _)| token)
+        .filter(|token| matches!(token, Token::Word { .. }))
+        .collect()
+}
+
+fn is_keyword(words: &[Token], idx: usize, keyword: &str) -> bool {
+    match words.get(idx) {
+        Some(Token::Word {
+            value,
+            quoted: false,
+        }) => value.eq_ignore_ascii_case(keyword),
+        _ => false,
+    }
+}
+
+/// CREATE TABLE IF NOT EXISTS
+fn is_if_not_exists(words: &[Token]) -> bool {
+    is_keyword(words, 1, "TABLE")
+        && is_keyword(words, 2, "IF")
+        && is_keyword(words, 3, "NOT")
+        && is_keyword(words, 4, "EXISTS")
+}
+
+/// Statements on tables: CREATE/ALTER/DROP [TEMPORARY|ONLINE] TABLE
+fn is_table_statement(words: &[Token]) -> bool {
+    ["CREATE", "ALTER", "DROP"]
+        .iter()
+        .any(|keyword| is_keyword(words, 0, keyword))
+        && (1..=2).any(|idx| is_keyword(words, idx, "TABLE"))
+}
+
+/// Database statements, translated by the dialect, None for other statements:
+///  - CREATE DATABASE/SCHEMA [IF NOT EXISTS] db
+///  - DROP DATABASE/SCHEMA [IF EXISTS] db
+///  - ALTER DATABASE/SCHEMA [db] (character set, collation)
+fn translate_database<D: DdlDialect>(
+    dialect: &D,
+    router: &Router,
+    words: &[Token],
+) -> Option<Vec<String>> {
+    if !is_keyword(words, 1, "DATABASE") && !is_keyword(words, 1, "SCHEMA") {
+        return None;
+    }
+
+    let if_clause = is_keyword(words, 2, "IF");
+    let name_idx = match (if_clause, is_keyword(words, 3, "NOT")) {
+        (true, true) => 5,
+        (true, false) => 4,
+        _ => 2,
+    };
+    let statement = if is_keyword(words, 0, "CREATE") {
+        DatabaseStatement::Create {
+            if_not_exists: if_clause,
+        }
+    } else if is_keyword(words, 0, "DROP") {
+        DatabaseStatement::Drop {
+            if_exists: if_clause,
+        }
+    } else if is_keyword(words, 0, "ALTER") {
+        DatabaseStatement::Alter
+    } else {
+        return None;
+    };
+
+    Some(match words.get(name_idx) {
+        Some(Token::Word { value, .. }) => {
+            dialect.database_statements(statement, &router.database(value))
+        }
+        _ => vec![],
+    })
+}
+
+/// Reader of the statement tokens, for the statements that sqlparser does not read
+struct Tokens {
+    tokens: Vec<Token>,
+    idx: usize,
+}
+
+impl Tokens {
+    fn new(query: &str) -> Self {
+        Self {
+            tokens: tokenize(query)
+                .into_iter()
+                .map(|(token, _)| token)
+                .collect(),
+            idx: 0,
+        }
+    }
+
+    /// Next token is the keyword, it is consumed
+    fn keyword(&mut self, keyword: &str) -> bool {
+        let found = matches!(
+            self.tokens.get(self.idx),
+            Some(Token::Word { value, quoted: false }) if value.eq_ignore_ascii_case(keyword)
+        );
+        if found {
+            self.idx += 1;
+        }
+        found
+    }
+
+    /// Next token is the punctuation, it is consumed
+    fn punct(&mut self, punct: char) -> bool {
+        let found = matches!(self.tokens.get(self.idx), Some(Token::Punct(ch)) if *ch == punct);
+        if found {
+            self.idx += 1;
+        }
+        found
+    }
+
+    fn name(&mut self) -> Option<String> {
+        match self.tokens.get(self.idx) {
+            Some(Token::Word { value, .. }) => {
+                self.idx += 1;
+                Some(value.clone())
+            }
+            _ => None,
+        }
+    }
+
+    /// [database.]table
+    fn object_name(&mut self) -> Option<ObjectName> {
+        let mut names = vec![Ident::new(self.name()?)];
+        if self.punct('.') {
+            names.push(Ident::new(self.name()?));
+        }
+        Some(ObjectName(names))
+    }
+
+    /// Key columns: (column [(length)] [ASC|DESC], ...), lengths and orders are not kept
+    fn key_columns(&mut self) -> Option<Vec<Ident>> {
+        if !self.punct('(') {
+            return None;
+        }
+        let mut columns = vec![];
+        loop {
+            columns.push(Ident::new(self.name()?));
+            if self.punct('(') && (self.name().is_none() || !self.punct(')')) {
+                return None;
+            }
+            let _ = self.keyword("ASC") || self.keyword("DESC");
+            if self.punct(')') {
+                return Some(columns);
+            }
+            if !self.punct(',') {
+                return None;
+            }
+        }
+    }
+}
+
+/// Statement translation, with the dialect, the routing rules and the database of the message
+struct Translator<'a, D> {
+    dialect: &'a D,
+    router: &'a Router,
+    db_name: Option<&'a str>,
+}
+
+impl<'a, D: DdlDialect> Translator<'a, D> {
+    fn unsupported(&self, what: &str) -> Error {
+        unsupported(self.dialect.name(), what)
+    }
+
+    /// Routed database and table names
+    fn route(&self, name: &ObjectName) -> Result<(Option<String>, String), Error> {
+        let (db_name, table) = match name.0.as_slice() {
+            [table] => (self.db_name.map(|db_name| db_name.to_owned()), &table.value),
+            [db_name, table] => (Some(db_name.value.clone()), &table.value),
+            _ => return Err(self.unsupported(&format!("table name {}", name))),
+        };
+
+        Ok(match db_name {
+            Some(db_name) => (
+                Some(self.router.database(&db_name)),
+                self.router.table(&db_name, table),
+            ),
+            None => (None, table.clone()),
+        })
+    }
+
+    /// Routed, qualified and quoted table
+    fn table(&self, name: &ObjectName) -> Result<String, Error> {
+        let (db_name, table) = self.route(name)?;
+        Ok(self.dialect.qualified_name(db_name.as_deref(), &table))
+    }
+
+    fn create_table(
+        &self,
+        name: &ObjectName,
+        columns: &[ColumnDef],
+        constraints: &[TableConstraint],
+        if_not_exists: bool,
+    ) -> Result<String, Error> {
+        if columns.is_empty() {
+            return Err(self.unsupported("CREATE TABLE without columns"));
+        }
+
+        let mut definitions = vec![];
+        for column in columns {
+            definitions.push(self.column_definition(column)?);
+        }
+        for constraint in constraints {
+            definitions.push(self.constraint(constraint)?);
+        }
+
+        Ok(format!(
+            "CREATE TABLE {}{} ({})",
+            if if_not_exists { "IF NOT EXISTS " } else { "" },
+            self.table(name)?,
+            definitions.join(", ")
+        ))
+    }
+
+    /// One statement per ALTER TABLE (sqlparser reads one operation)
+    fn alter_table(
+        &self,
+        name: &ObjectName,
+        operation: &AlterTableOperation,
+    ) -> Result<String, Error> {
+        let table = self.table(name)?;
+
+        match operation {
+            AlterTableOperation::AddColumn { column_def, .. } => Ok(format!(
+                "ALTER TABLE {} ADD COLUMN {}",
+                table,
+                self.column_definition(column_def)?
+            )),
+            AlterTableOperation::DropColumn { column_name, .. } => Ok(format!(
+                "ALTER TABLE {} DROP COLUMN {}",
+                table,
+                quote_identifier(&column_name.value)
+            )),
+            AlterTableOperation::RenameColumn {
+                old_column_name,
+                new_column_name,
+            } => Ok(format!(
+                "ALTER TABLE {} RENAME COLUMN {} TO {}",
+                table,
+                quote_identifier(&old_column_name.value),
+                quote_identifier(&new_column_name.value)
+            )),
+            AlterTableOperation::AddConstraint(constraint) if self.dialect.alters_keys() => Ok(
+                format!("ALTER TABLE {} ADD {}", table, self.constraint(constraint)?),
+            ),
+            AlterTableOperation::AddConstraint(TableConstraint::Unique {
+                name: index,
+                columns,
+                is_primary: false,
+                ..
+            }) => self.unique_index(name, index.as_ref(), columns),
+            AlterTableOperation::AddConstraint(constraint) => {
+                Err(self.unsupported(&format!("keys added to a table: {}", constraint)))
+            }
+            other => Err(self.unsupported(&format!("ALTER TABLE {}", other))),
+        }
+    }
+
+    /// Unique key of an existing table, as an index named after the key, or after its first
+    /// column
+    fn unique_index(
+        &self,
+        name: &ObjectName,
+        index: Option<&Ident>,
+        columns: &[Ident],
+    ) -> Result<String, Error> {
+        match index.or_else(|| columns.first()) {
+            Some(index) => self.create_index(name, &index.value, columns, true),
+            None => Err(self.unsupported("key without columns")),
+        }
+    }
+
+    /// Index <table>_<index>: index names are per table in mysql, and per database or schema
+    /// in the target
+    fn create_index(
+        &self,
+        name: &ObjectName,
+        index: &str,
+        columns: &[Ident],
+        unique: bool,
+    ) -> Result<String, Error> {
+        let (db_name, table) = self.route(name)?;
+        Ok(format!(
+            "CREATE {}INDEX {} ON {} ({})",
+            if unique { "UNIQUE " } else { "" },
+            quote_identifier(&format!("{}_{}", table, index)),
+            self.dialect.qualified_name(db_name.as_deref(), &table),
+            column_list(columns)
+        ))
+    }
+
+    /// Statements that sqlparser does not read, from the tokens, None for other statements:
+    ///  - CREATE [UNIQUE] INDEX index [USING type] ON table (columns) (see create_index)
+    ///  - DROP INDEX index ON table
+    ///  - TRUNCATE [TABLE] table (see DdlDialect::truncate)
+    ///  - RENAME TABLE table TO table, ... - ALTER TABLE ... RENAME TO, in the same database
+    fn translate_tokens(&self, query: &str) -> Option<Result<Vec<String>, Error>> {
+        let mut tokens = Tokens::new(query);
+
+        if tokens.keyword("CREATE") {
+            let unique = tokens.keyword("UNIQUE");
+            if !tokens.keyword("INDEX") {
+                return None;
+            }
+            let index = tokens.name();
+            if tokens.keyword("USING") {
+                tokens.name();
+            }
+            let on_table = match tokens.keyword("ON") {
+                true => tokens.object_name(),
+                false => None,
+            };
+            Some(match (index, on_table, tokens.key_columns()) {
+                (Some(index), Some(name), Some(columns)) => self
+                    .create_index(&name, &index, &columns, unique)
+                    .map(|statement| vec![statement]),
+                _ => Err(unreadable()),
+            })
+        } else if tokens.keyword("DROP") {
+            if !tokens.keyword("INDEX") {
+                return None;
+            }
+            let index = tokens.name();
+            let on_table = match tokens.keyword("ON") {
+                true => tokens.object_name(),
+                false => None,
+            };
+            Some(match (index, on_table) {
+                (Some(index), Some(name)) => self.drop_index(&name, &index),
+                _ => Err(unreadable()),
+            })
+        } else if tokens.keyword("TRUNCATE") {
+            tokens.keyword("TABLE");
+            Some(match tokens.object_name() {
+                Some(name) => self
+                    .table(&name)
+                    .map(|table| vec![self.dialect.truncate(&table)]),
+                None => Err(unreadable()),
+            })
+        } else if tokens.keyword("RENAME") {
+            if !tokens.keyword("TABLE") {
+                return None;
+            }
+            let mut renames = vec![];
+            loop {
+                let from = tokens.object_name();
+                let to = match tokens.keyword("TO") {
+                    true => tokens.object_name(),
+                    false => None,
+                };
+                match (from, to) {
+                    (Some(from), Some(to)) => renames.push((from, to)),
+                    _ => return Some(Err(unreadable())),
+                }
+                if !tokens.punct(',') {
+                    break;
+                }
+            }
+            Some(self.rename_tables(&renames))
+        } else {
+            None
+        }
+    }
+
+    fn drop_index(&self, name: &ObjectName, index: &str) -> Result<Vec<String>, Error> {
+        let (db_name, table) = self.route(name)?;
+        let index = format!("{}_{}", table, index);
+        Ok(vec![format!(
+            "DROP INDEX {}",
+            self.dialect.qualified_name(db_name.as_deref(), &index)
+        )])
+    }
+
+    /// Tables renamed in their database, one statement per table
+    fn rename_tables(&self, renames: &[(ObjectName, ObjectName)]) -> Result<Vec<String>, Error> {
+        let mut statements = vec![];
+        for (from, to) in renames {
+            let (db_name, table) = self.route(from)?;
+            let (new_db_name, new_table) = self.route(to)?;
+            if self.dialect.qualified_name(db_name.as_deref(), &new_table)
+                != self
+                    .dialect
+                    .qualified_name(new_db_name.as_deref(), &new_table)
+            {
+                return Err(self.unsupported("RENAME TABLE to another database"));
+            }
+
+            statements.push(format!(
+                "ALTER TABLE {} RENAME TO {}",
+                self.dialect.qualified_name(db_name.as_deref(), &table),
+                quote_identifier(&new_table)
+            ));
+        }
+
+        Ok(statements)
+    }
+
+    /// DROP TABLE [IF EXISTS] table, ... - one statement per table
+    fn drop_tables(&self, names: &[ObjectName], if_exists: bool) -> Result<Vec<String>, Error> {
+        let mut statements = vec![];
+        for name in names {
+            statements.push(format!(
+                "DROP TABLE {}{}",
+                if if_exists { "IF EXISTS " } else { "" },
+                self.table(name)?
+            ));
+        }
+
+        Ok(statements)
+    }
+
+    /// Column name, type and options:
+    /// NULL, NOT NULL, DEFAULT, PRIMARY KEY, UNIQUE, REFERENCES, CHECK
+    fn column_definition(&self, column: &ColumnDef) -> Result<String, Error> {
+        let mut definition = vec![
+            quote_identifier(&column.name.value),
+            self.dialect.column_type(&column.data_type)?,
+        ];
+
+        for option_def in &column.options {
+            definition.push(match &option_def.option {
+                ColumnOption::Null => "NULL".to_owned(),
+                ColumnOption::NotNull => "NOT NULL".to_owned(),
+                ColumnOption::Default(expr) => format!("DEFAULT ({})", expr),
+                ColumnOption::Unique {
+                    is_primary: true, ..
+                } => "PRIMARY KEY".to_owned(),
+                ColumnOption::Unique {
+                    is_primary: false, ..
+                } => "UNIQUE".to_owned(),
+                ColumnOption::ForeignKey {
+                    foreign_table,
+                    referred_columns,
+                    ..
+                } => self.references(foreign_table, referred_columns)?,
+                ColumnOption::Check(expr) => format!("CHECK ({})", expr),
+            });
+        }
+
+        Ok(definition.join(" "))
+    }
+
+    /// Table constraint, the constraint name is kept
+    fn constraint(&self, constraint: &TableConstraint) -> Result<String, Error> {
+        let (name, definition) = match constraint {
+            TableConstraint::Unique {
+                name,
+                columns,
+                is_primary,
+                ..
+            } => (
+                name,
+                format!(
+                    "{} ({})",
+                    if *is_primary { "PRIMARY KEY" } else { "UNIQUE" },
+                    column_list(columns)
+                ),
+            ),
+            TableConstraint::ForeignKey {
+                name,
+                columns,
+                foreign_table,
+                referred_columns,
+                ..
+            } => (
+                name,
+                format!(
+                    "FOREIGN KEY ({}) {}",
+                    column_list(columns),
+                    self.references(foreign_table, referred_columns)?
+                ),
+            ),
+            TableConstraint::Check { name, expr, .. } => (name, format!("CHECK ({})", expr)),
+        };
+
+        Ok(match name {
+            Some(name) => format!(
+                "CONSTRAINT {} {}",
+                quote_identifier(&name.value),
+                definition
+            ),
+            None => definition,
+        })
+    }
+
+    fn references(&self, table: &ObjectName, columns: &[Ident]) -> Result<String, Error> {
+        Ok(format!(
+            "REFERENCES {} ({})",
+            self.table(table)?,
+            column_list(columns)
+        ))
+    }
+}
+
+/// Quoted column names, comma separated
+fn column_list(columns: &[Ident]) -> String {
+    columns
+        .iter()
+        .map(|column| quote_identifier(&column.value))
+        .collect::<Vec<String>>()
+        .join(", ")
+}
@@ -0,0 +1,126 @@
+//! Dead-Letter Queue
+//!
+//! Messages that cannot be applied (permanent error, or retries exhausted) are written to
+//! the dead-letter queue with the error, and skipped. One json record per message:
+//!  - file: appended to the dead-letter file (JSONL)
+//!  - topic: sent to a fluvio topic
+//!
+use async_std::fs;
+use async_std::io::prelude::WriteExt;
+use fluvio::TopicProducer;
+use serde::Serialize;
+use std::io::{Error, ErrorKind};
+use std::path::{Path, PathBuf};
+use tracing::warn;
+
+use crate::consumer::profile::DeadLetter;
+use crate::consumer::retry::{ErrorClass, Failure};
+
+pub enum DeadLetterQueue {
+    File(PathBuf),
+    Topic(TopicProducer),
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct DeadLetterRecord {
+    offset: i64,
+    error: String,
+    error_class: ErrorClass,
+    attempts: u32,
+    message: serde_json::Value,
+}
+
+impl DeadLetterQueue {
+    pub async fn init(dead_letter: &DeadLetter, dead_letter_file: &Path) -> Result<Self, Error> {
+        match dead_letter {
+            DeadLetter::File => Ok(Self::File(dead_letter_file.to_path_buf())),
+            DeadLetter::Topic(dead_letter_topic) => {
+                let producer = fluvio::producer(&dead_letter_topic.topic)
+                    .await
+                    .map_err(|err| {
+                        Error::new(
+                            ErrorKind::NotConnected,
+                            format!("Dead-letter topic: {}", err),
+                        )
+                    })?;
+                Ok(Self::Topic(producer))
+            }
+        }
+    }
+
+    /// Send message (as received) at offset with the failure
+    pub async fn send(
+        &mut self,
+        offset: i64,
+        json_msg: &str,
+        failure: &Failure,
+    ) -> Result<(), Error> {
+        let line = dead_letter_record(offset, json_msg, failure)?;
+        warn!(offset, error = %failure.error, "Message sent to dead-letter queue");
+
+        match self {
+            Self::File(file) => {
+                let mut file = fs::OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(file)
+                    .await?;
+                file.write_all(line.as_bytes()).await?;
+                file.write_all(b"\n").await
+            }
+            Self::Topic(producer) => producer.send_record(line, 0).await.map_err(|err| {
+                Error::new(
+                    ErrorKind::NotConnected,
+                    format!("Dead-letter topic: {}", err),
+                )
+            }),
+        }
+    }
+}
+
+/// Record as json, messages that are not valid json are kept as string
+fn dead_letter_record(offset: i64, json_msg: &str, failure: &Failure) -> Result<String, Error> {
+    let message = serde_json::from_str(json_msg)
+        .unwrap_or_else(|_| serde_json::Value::String(json_msg.to_owned()));
+    let record = DeadLetterRecord {
+        offset,
+        error: failure.error.to_string(),
+        error_class: failure.class,
+        attempts: failure.attempts,
+        message,
+    };
+
+    Ok(serde_json::to_string(&record)?)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const TEST_PATH: &str = "test_files";
+    const DEAD_LETTER_FILE: &str = "dead-letter-test.jsonl";
+
+    #[test]
+    fn test_dead_letter_file() {
+        let program_dir = std::env::current_dir().unwrap();
+        let path = program_dir.join(TEST_PATH).join(DEAD_LETTER_FILE);
+        let _ = std::fs::remove_file(&path);
+
+        let failure = Failure::new(Error::new(ErrorKind::InvalidData, "Unknown column"), 1);
+        async_std::task::block_on(async {
+            let mut queue = DeadLetterQueue::init(&DeadLetter::File, &path)
+                .await
+                .unwrap();
+            queue.send(7, "{\"sequence\": 3}", &failure).await.unwrap();
+            queue.send(8, "not json", &failure).await.unwrap();
+        });
+
+        let expected = "\
+            {\"offset\":7,\"error\":\"Unknown column\",\"errorClass\":\"permanent\",\"attempts\":1,\"message\":{\"sequence\":3}}\n\
+            {\"offset\":8,\"error\":\"Unknown column\",\"errorClass\":\"permanent\",\"attempts\":1,\"message\":\"not json\"}\n";
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), expected);
+
+        std::fs::remove_file(&path).expect("delete file failed");
+    }
+}
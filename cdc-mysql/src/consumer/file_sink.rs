@@ -0,0 +1,592 @@
+//! File Sink
+//!
+//! Writes the rows of the change stream to rolling files, partitioned by table and date:
+//!     <output_dir>/<db>/<table>/<YYYY-MM-DD>/<table>-<source>-<sequence>.jsonl (or .csv)
+//!
+//! The source (mysql resource name) and sequence are those of the first message of the file,
+//! they are unique for tables routed from several sources.
+//!
+//! Each row is flattened with its metadata: op (insert, update, delete), sequence, bn_file and
+//! bn_offset, then the columns of the row (new values of updates):
+//!  - jsonl: one JSON object per line, keyed by column name (see Value::to_json_value)
+//!  - csv: one line per row, with a header line per file
+//!
+//! Rows are appended to a hidden in-progress file (".<name>.inprogress"), written once per
+//! message before the offset is saved; the end of each message is recorded in a side file
+//! (".<name>.inprogress.ends"). A file is finalized (synced, then renamed to its final name)
+//! when it reaches the size limit, is older than the age limit, the date or the columns of the
+//! table change, or the consumer exits. In-progress files left by a crash are truncated to
+//! their last complete message and finalized when the sink starts. DDL statements (Query
+//! operation) are not written.
+//!
+use chrono::Utc;
+use std::ffi::OsString;
+use std::fs::{self, File, OpenOptions};
+use std::io::{Error, ErrorKind, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+use tracing::{info, warn};
+
+use crate::consumer::offset_store::OffsetStore;
+use crate::consumer::profile::{FileFormat, FileOutput, Profile};
+use crate::consumer::router::Router;
+use crate::consumer::sink::{check_valid_col_count, check_valid_cols, file_source};
+use crate::consumer::sink::{remove_if_exists, route_uri, table_name};
+use crate::consumer::sink::{RollingFiles, Sink, SinkFile};
+use crate::consumer::uri::parse_uri;
+use crate::messages::{FluvioMessage, Operation, Value};
+
+const IN_PROGRESS: &str = ".inprogress";
+const MESSAGE_ENDS: &str = ".ends";
+/// Message end record: size of the in-progress file after the message, 20 digits and a newline
+const END_RECORD_LEN: u64 = 21;
+const METADATA_COLUMNS: &[&str] = &["op", "sequence", "bn_file", "bn_offset"];
+
+pub struct FileSink {
+    output_dir: PathBuf,
+    format: FileFormat,
+    max_file_bytes: u64,
+    router: Router,
+    files: RollingFiles<RollingFile>,
+}
+
+/// In-progress file of a table
+struct RollingFile {
+    path: PathBuf,
+    final_path: PathBuf,
+    file: File,
+    ends: File,
+    messages: u64,
+    date: String,
+    columns: Vec<String>,
+    bytes: u64,
+    opened: Instant,
+}
+
+impl RollingFile {
+    /// Write the lines of a message; on error the file is truncated to its previous size,
+    /// so the message can be retried
+    fn append(&mut self, lines: &[u8]) -> Result<(), Error> {
+        if let Err(err) = self.file.write_all(lines).and_then(|_| self.file.flush()) {
+            self.file.set_len(self.bytes)?;
+            self.file.seek(SeekFrom::Start(self.bytes))?;
+            return Err(err);
+        }
+        self.bytes += lines.len() as u64;
+        Ok(())
+    }
+
+    /// Write the lines of a message, then record its end (see recover_files); on error both
+    /// files are truncated to their previous size, so the message can be retried
+    fn append_message(&mut self, lines: &[u8]) -> Result<(), Error> {
+        let start = self.bytes;
+        self.append(lines)?;
+
+        let record = format!("{:020}\n", self.bytes);
+        if let Err(err) = self
+            .ends
+            .write_all(record.as_bytes())
+            .and_then(|_| self.ends.flush())
+        {
+            let ends_len = self.messages * END_RECORD_LEN;
+            self.ends.set_len(ends_len)?;
+            self.ends.seek(SeekFrom::Start(ends_len))?;
+            self.file.set_len(start)?;
+            self.file.seek(SeekFrom::Start(start))?;
+            self.bytes = start;
+            return Err(err);
+        }
+        self.messages += 1;
+        Ok(())
+    }
+}
+
+impl SinkFile for RollingFile {
+    fn opened(&self) -> Instant {
+        self.opened
+    }
+
+    /// Sync and rename to the final name, the file is complete when it appears
+    fn finalize(self) -> Result<Option<PathBuf>, Error> {
+        self.file.sync_all()?;
+        fs::rename(&self.path, &self.final_path)?;
+        fs::remove_file(ends_path(&self.path))?;
+        Ok(Some(self.final_path))
+    }
+}
+
+impl FileSink {
+    /// Create the output directory, and finalize in-progress files of a previous run
+    pub fn open(
+        output_dir: &Path,
+        file_output: &FileOutput,
+        profile: &Profile,
+    ) -> Result<Self, Error> {
+        fs::create_dir_all(output_dir)?;
+        let recovered = recover_files(output_dir)?;
+        if recovered > 0 {
+            info!(recovered, "In-progress files finalized");
+        }
+
+        Ok(Self {
+            output_dir: output_dir.to_owned(),
+            format: file_output.format(),
+            max_file_bytes: file_output.max_file_bytes(),
+            router: Router::new(&profile.routing()),
+            files: RollingFiles::new(file_output.max_file_age()),
+        })
+    }
+
+    /// Number of files finalized since start
+    pub fn finalized(&self) -> u64 {
+        self.files.finalized()
+    }
+
+    /// In-progress file of the table for the message columns, the current file is finalized
+    /// first if its date or columns changed, or it is too old
+    fn table_file(
+        &mut self,
+        db_name: &str,
+        table: &str,
+        columns: &[String],
+        flv_message: &FluvioMessage,
+    ) -> Result<&mut RollingFile, Error> {
+        let key = (db_name.to_owned(), table.to_owned());
+        let date = Utc::now().format("%Y-%m-%d").to_string();
+
+        let current = self
+            .files
+            .get(&key)
+            .map(|file| file.date == date && file.columns == columns && !self.files.is_old(file));
+        if current == Some(false) {
+            self.files.finalize(&key)?;
+        }
+
+        let dir = self.output_dir.join(db_name).join(table).join(&date);
+        let format = self.format;
+        self.files.get_or_open(&key, || {
+            fs::create_dir_all(&dir)?;
+
+            let name = format!(
+                "{}-{}-{:020}.{}",
+                table,
+                file_source(&flv_message.uri),
+                flv_message.sequence,
+                extension(format)
+            );
+            let path = dir.join(format!(".{}{}", name, IN_PROGRESS));
+            let open = |path: &Path| {
+                OpenOptions::new()
+                    .create(true)
+                    .write(true)
+                    .truncate(true)
+                    .open(path)
+            };
+
+            let mut rolling_file = RollingFile {
+                file: open(&path)?,
+                ends: open(&ends_path(&path))?,
+                path,
+                final_path: dir.join(name),
+                messages: 0,
+                date,
+                columns: columns.to_vec(),
+                bytes: 0,
+                opened: Instant::now(),
+            };
+            if format == FileFormat::Csv {
+                rolling_file.append(csv_header(columns).as_bytes())?;
+            }
+            Ok(rolling_file)
+        })
+    }
+}
+
+impl Sink for FileSink {
+    fn apply_message(
+        &mut self,
+        flv_message: &FluvioMessage,
+        offset_store: &mut dyn OffsetStore,
+    ) -> Result<(), Error> {
+        self.files.finalize_old_files()?;
+
+        let (op, rows): (&str, Vec<&Vec<Value>>) = match &flv_message.operation {
+            Operation::Add(data) => ("insert", data.rows.iter().map(|row| &row.cols).collect()),
+            Operation::Update(data) => (
+                "update",
+                data.rows.iter().map(|row| &row.after_cols).collect(),
+            ),
+            Operation::Delete(data) => ("delete", data.rows.iter().map(|row| &row.cols).collect()),
+            Operation::Query(_) => ("query", vec![]),
+        };
+        if rows.is_empty() {
+            return offset_store.increment_offset();
+        }
+
+        check_valid_cols(&flv_message.columns)?;
+        let columns = flv_message.columns.as_ref().unwrap();
+        let uri_props = route_uri(&self.router, parse_uri(&flv_message.uri)?);
+        let table = table_name(&uri_props)?;
+        let db_name = uri_props.db_name.unwrap_or_default();
+
+        let mut lines = String::new();
+        for values in rows {
+            check_valid_col_count(values.len(), columns.len())?;
+            let row = Row {
+                op,
+                flv_message,
+                columns,
+                values,
+            };
+            lines.push_str(&match self.format {
+                FileFormat::Jsonl => row.to_json_line()?,
+                FileFormat::Csv => row.to_csv_line(),
+            });
+        }
+
+        let max_file_bytes = self.max_file_bytes;
+        let file = self.table_file(&db_name, &table, columns, flv_message)?;
+        file.append_message(lines.as_bytes())?;
+        if file.bytes >= max_file_bytes {
+            self.files.finalize(&(db_name, table))?;
+        }
+
+        offset_store.increment_offset()
+    }
+
+    /// Finalize all in-progress files
+    fn flush(&mut self) -> Result<(), Error> {
+        self.files.flush()
+    }
+
+    fn report(&self) {
+        println!("Files finalized: {}", self.finalized());
+        info!(finalized = self.finalized(), "File sink counters");
+    }
+}
+
+/// Row of a message, with its metadata
+struct Row<'a> {
+    op: &'a str,
+    flv_message: &'a FluvioMessage,
+    columns: &'a [String],
+    values: &'a [Value],
+}
+
+impl<'a> Row<'a> {
+    /// Metadata values, in the order of METADATA_COLUMNS
+    fn metadata(&self) -> Vec<serde_json::Value> {
+        vec![
+            serde_json::Value::from(self.op),
+            serde_json::Value::from(self.flv_message.sequence),
+            serde_json::Value::from(self.flv_message.bn_file.file_name.as_str()),
+            self.flv_message
+                .bn_file
+                .offset
+                .map_or(serde_json::Value::Null, serde_json::Value::from),
+        ]
+    }
+
+    /// JSON object, keys in column order: metadata, then the row columns
+    fn to_json_line(&self) -> Result<String, Error> {
+        let values = self
+            .metadata()
+            .into_iter()
+            .chain(self.values.iter().map(Value::to_json_value));
+        let names = METADATA_COLUMNS
+            .iter()
+            .copied()
+            .chain(self.columns.iter().map(|col| col.as_str()));
+
+        let mut fields: Vec<String> = vec![];
+        for (name, value) in names.zip(values) {
+            let name = serde_json::to_string(name)
+                .map_err(|err| Error::new(ErrorKind::InvalidData, err))?;
+            let value = serde_json::to_string(&value)
+                .map_err(|err| Error::new(ErrorKind::InvalidData, err))?;
+            fields.push(format!("{}:{}", name, value));
+        }
+
+        Ok(format!("{{{}}}\n", fields.join(",")))
+    }
+
+    /// CSV line: metadata, then the row columns; NULL is an empty field
+    fn to_csv_line(&self) -> String {
+        let fields: Vec<String> = self
+            .metadata()
+            .into_iter()
+            .chain(self.values.iter().map(Value::to_json_value))
+            .map(|value| match value {
+                serde_json::Value::Null => String::new(),
+                serde_json::Value::String(text) => csv_field(&text),
+                other => csv_field(&other.to_string()),
+            })
+            .collect();
+
+        format!("{}\n", fields.join(","))
+    }
+}
+
+/// Message ends of an in-progress file
+fn ends_path(path: &Path) -> PathBuf {
+    let mut ends_path = OsString::from(path.as_os_str());
+    ends_path.push(MESSAGE_ENDS);
+    PathBuf::from(ends_path)
+}
+
+fn extension(format: FileFormat) -> &'static str {
+    match format {
+        FileFormat::Jsonl => "jsonl",
+        FileFormat::Csv => "csv",
+    }
+}
+
+fn csv_header(columns: &[String]) -> String {
+    let fields: Vec<String> = METADATA_COLUMNS
+        .iter()
+        .copied()
+        .chain(columns.iter().map(|col| col.as_str()))
+        .map(csv_field)
+        .collect();
+
+    format!("{}\n", fields.join(","))
+}
+
+/// CSV field, quoted if it contains a separator, a quote or a line break (RFC 4180)
+fn csv_field(text: &str) -> String {
+    if text.contains(|c: char| c == ',' || c == '"' || c == '\n' || c == '\r') {
+        format!("\"{}\"", text.replace('"', "\"\""))
+    } else {
+        text.to_owned()
+    }
+}
+
+/// Finalize the in-progress files under dir: the rows after the last recorded message end
+/// (interrupted write, which may end with a complete line) are dropped, files without complete
+/// message are removed. Returns the number of files finalized.
+fn recover_files(dir: &Path) -> Result<u64, Error> {
+    let mut recovered = 0;
+
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            recovered += recover_files(&path)?;
+            continue;
+        }
+
+        let name = match path.file_name().and_then(|name| name.to_str()) {
+            Some(name) if name.starts_with('.') && name.ends_with(IN_PROGRESS) => name,
+            Some(name) if name.starts_with('.') && name.ends_with(MESSAGE_ENDS) => {
+                // ends of a file removed or finalized before the crash
+                if !path
+                    .with_file_name(&name[..name.len() - MESSAGE_ENDS.len()])
+                    .exists()
+                {
+                    remove_if_exists(&path)?;
+                }
+                continue;
+            }
+            _ => continue,
+        };
+        let final_path = path.with_file_name(&name[1..name.len() - IN_PROGRESS.len()]);
+
+        let file = OpenOptions::new().write(true).open(&path)?;
+        let len = file.metadata()?.len();
+        let complete = last_message_end(&ends_path(&path), len)?;
+
+        if complete == 0 {
+            fs::remove_file(&path)?;
+        } else {
+            if complete < len {
+                warn!(file = %path.display(), "Partial message dropped");
+                file.set_len(complete)?;
+            }
+            file.sync_all()?;
+            fs::rename(&path, &final_path)?;
+            recovered += 1;
+        }
+        remove_if_exists(&ends_path(&path))?;
+    }
+
+    Ok(recovered)
+}
+
+/// Last recorded message end within the file length, 0 without complete message. The partial
+/// record of an interrupted write is ignored.
+fn last_message_end(ends_path: &Path, len: u64) -> Result<u64, Error> {
+    let mut content = vec![];
+    match File::open(ends_path) {
+        Ok(mut ends) => ends.read_to_end(&mut content)?,
+        Err(err) if err.kind() == ErrorKind::NotFound => return Ok(0),
+        Err(err) => return Err(err),
+    };
+
+    Ok(content
+        .chunks_exact(END_RECORD_LEN as usize)
+        .rev()
+        .filter_map(|record| {
+            std::str::from_utf8(&record[..record.len() - 1])
+                .ok()
+                .and_then(|end| end.parse::<u64>().ok())
+        })
+        .find(|end| *end <= len)
+        .unwrap_or(0))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::consumer::offset_store::FileOffsetStore;
+    use crate::messages::{BeforeAfterCols, Cols, UpdateRows, WriteRows};
+
+    const TEST_PATH: &str = "test_files";
+    const OUTPUT_DIR: &str = "file-sink-test";
+    const OFFSET_FILE: &str = "file-sink-test.offset";
+
+    fn message(sequence: u64, operation: Operation) -> FluvioMessage {
+        FluvioMessage::test_message("flv://mysql.local/shop/pets", operation)
+            .with_sequence(sequence)
+            .with_position(100 * sequence)
+            .with_columns(&["id", "name"])
+            .with_keys(&["id"])
+    }
+
+    fn pet(id: i64, name: &str) -> Vec<Value> {
+        vec![Value::SignedInteger(id), Value::String(name.to_owned())]
+    }
+
+    /// Finalized and in-progress files of the table, sorted by name (message ends excluded)
+    fn table_files(output_dir: &Path) -> Vec<PathBuf> {
+        let date = Utc::now().format("%Y-%m-%d").to_string();
+        let mut files: Vec<PathBuf> = fs::read_dir(output_dir.join("shop/pets").join(date))
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .filter(|path| !path.to_string_lossy().ends_with(MESSAGE_ENDS))
+            .collect();
+        files.sort();
+        files
+    }
+
+    #[test]
+    fn test_rows() {
+        let flv_message = message(3, Operation::Query(String::new()));
+        let columns = vec!["id".to_owned(), "note".to_owned()];
+        let values = vec![
+            Value::SignedInteger(7),
+            Value::String("a, \"b\"".to_owned()),
+        ];
+        let row = Row {
+            op: "update",
+            flv_message: &flv_message,
+            columns: &columns,
+            values: &values,
+        };
+
+        assert_eq!(
+            row.to_json_line().unwrap(),
+            "{\"op\":\"update\",\"sequence\":3,\"bn_file\":\"binlog.000001\",\"bn_offset\":300,\
+            \"id\":7,\"note\":\"a, \\\"b\\\"\"}\n"
+        );
+        assert_eq!(
+            row.to_csv_line(),
+            "update,3,binlog.000001,300,7,\"a, \"\"b\"\"\"\n"
+        );
+        assert_eq!(
+            csv_header(&columns),
+            "op,sequence,bn_file,bn_offset,id,note\n"
+        );
+    }
+
+    #[test]
+    fn test_file_sink() {
+        let program_dir = std::env::current_dir().unwrap();
+        let output_dir = program_dir.join(TEST_PATH).join(OUTPUT_DIR);
+        let offset_file = program_dir.join(TEST_PATH).join(OFFSET_FILE);
+        let _ = fs::remove_dir_all(&output_dir);
+        let _ = fs::remove_file(&offset_file);
+
+        // roll after each message
+        let file_output: FileOutput =
+            toml::from_str("format = \"csv\"\nmax_file_bytes = 100").unwrap();
+        let mut sink = FileSink::open(&output_dir, &file_output, &Profile::default()).unwrap();
+        let mut offset_store =
+            FileOffsetStore::init(&offset_file, &offset_file.with_extension("sequence")).unwrap();
+
+        let messages = vec![
+            message(
+                1,
+                Operation::Add(WriteRows {
+                    rows: vec![
+                        Cols {
+                            cols: pet(1, "Fluffy"),
+                        },
+                        Cols {
+                            cols: pet(2, "Claws"),
+                        },
+                    ],
+                }),
+            ),
+            message(
+                2,
+                Operation::Query("ALTER TABLE pets ADD born DATE".to_owned()),
+            ),
+            message(
+                3,
+                Operation::Update(UpdateRows {
+                    rows: vec![BeforeAfterCols {
+                        before_cols: pet(2, "Claws"),
+                        after_cols: pet(2, "Bowser"),
+                    }],
+                }),
+            ),
+        ];
+        for flv_message in &messages {
+            sink.apply_message(flv_message, &mut offset_store).unwrap();
+        }
+        assert_eq!(offset_store.offset(), 3);
+
+        // first file finalized (size), second in progress
+        let files = table_files(&output_dir);
+        assert_eq!(files.len(), 2);
+        assert_eq!(
+            files[1].file_name().unwrap(),
+            "pets-mysql.local-00000000000000000001.csv"
+        );
+        assert_eq!(
+            fs::read_to_string(&files[1]).unwrap(),
+            "op,sequence,bn_file,bn_offset,id,name\n\
+            insert,1,binlog.000001,100,1,Fluffy\n\
+            insert,1,binlog.000001,100,2,Claws\n"
+        );
+        assert_eq!(
+            files[0].file_name().unwrap(),
+            ".pets-mysql.local-00000000000000000003.csv.inprogress"
+        );
+
+        // crash while writing a message of two rows: the first row is complete, with a quoted
+        // line break
+        drop(sink);
+        let mut file = OpenOptions::new().append(true).open(&files[0]).unwrap();
+        file.write_all(b"insert,4,binlog.000001,400,3,\"Mr\nWhiskers\"\ninsert,4,bin")
+            .unwrap();
+        drop(file);
+
+        // restart => in-progress file truncated to its last message and finalized
+        let mut sink = FileSink::open(&output_dir, &file_output, &Profile::default()).unwrap();
+        let files = table_files(&output_dir);
+        assert_eq!(
+            files[1].file_name().unwrap(),
+            "pets-mysql.local-00000000000000000003.csv"
+        );
+        assert_eq!(
+            fs::read_to_string(&files[1]).unwrap(),
+            "op,sequence,bn_file,bn_offset,id,name\nupdate,3,binlog.000001,300,2,Bowser\n"
+        );
+        assert!(!ends_path(&files[0]).exists());
+        sink.flush().unwrap();
+        assert_eq!(sink.finalized(), 0);
+
+        fs::remove_dir_all(&output_dir).expect("delete dir failed");
+        fs::remove_file(&offset_file).expect("delete file failed");
+    }
+}
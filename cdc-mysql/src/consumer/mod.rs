@@ -1,12 +1,52 @@
 pub mod cli;
+pub mod conflict_handler;
+pub mod ddl;
+pub mod dead_letter;
+pub mod file_sink;
 pub mod mysql_manager;
 pub mod offset_store;
+pub mod parquet_sink;
+pub mod postgres_ddl;
+pub mod postgres_sink;
 pub mod profile;
+pub mod retry;
+pub mod router;
+pub mod sequence_tracker;
+pub mod sink;
+pub mod sqlite_ddl;
+pub mod sqlite_sink;
+pub mod statement;
+pub mod table_offset_store;
+pub mod uri;
 
 pub use cli::get_cli_opt;
+pub use conflict_handler::ConflictHandler;
+pub use dead_letter::DeadLetterQueue;
+pub use file_sink::FileSink;
 pub use mysql_manager::MysqlManager;
-pub use offset_store::OffsetStore;
+pub use offset_store::{FileOffsetStore, OffsetStore, Sequences, SinkOffsetStore};
+pub use parquet_sink::ParquetSink;
+pub use postgres_sink::PostgresSink;
+pub use profile::ApplyMode;
 pub use profile::Config;
+pub use profile::ConflictPolicy;
 pub use profile::Database;
+pub use profile::DeadLetter;
+pub use profile::FileFormat;
+pub use profile::FileOutput;
 pub use profile::Filters;
+pub use profile::GapPolicy;
+pub use profile::Offsets;
+pub use profile::ParquetOutput;
+pub use profile::PostgresDatabase;
 pub use profile::Profile;
+pub use profile::Retry;
+pub use profile::Routing;
+pub use profile::SinkConfig;
+pub use retry::{with_retry, ErrorClass, Failure};
+pub use router::Router;
+pub use sequence_tracker::{source_name, SequenceCheck, SequenceCounters, SequenceTracker};
+pub use sink::Sink;
+pub use sqlite_sink::SqliteSink;
+pub use table_offset_store::TableOffsetStore;
+pub use uri::{allowed_by_filters, parse_uri, UriProps};
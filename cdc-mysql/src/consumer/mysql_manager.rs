@@ -1,50 +1,132 @@
 //! MYSQL Manager
 //!
-//! Responsible for:
-//!  - mysql server connection
-//!  - converting fluvio messages to mysql query commands
+//! Mysql sink, responsible for:
+//!  - mysql server connection, reopened before the next attempt when lost
+//!  - routing source databases and tables to target names (see Router)
+//!  - converting fluvio messages to mysql prepared statements (see StatementBuilder)
+//!  - applying each message and saving its offset in one transaction
+//!  - checking that each row update or delete matches one row (see ConflictHandler)
 //!
-use http::Uri;
+use chrono::{DateTime, Datelike, Timelike, Utc};
+use mysql::consts::CapabilityFlags;
 use mysql::prelude::*;
-use mysql::{Conn, OptsBuilder};
+use mysql::Value as MyValue;
+use mysql::{Conn, Opts, OptsBuilder, Transaction, TxOpts};
 use std::io::{Error, ErrorKind};
-
-use crate::consumer::profile::{Filters, Profile};
-use crate::messages::{DeleteRows, UpdateRows, WriteRows};
+use std::time::{Duration, Instant, UNIX_EPOCH};
+use tracing::{debug, info, warn};
+
+use crate::consumer::conflict_handler::ConflictHandler;
+use crate::consumer::offset_store::OffsetStore;
+use crate::consumer::profile::Profile;
+use crate::consumer::retry::{is_connection_error, mysql_error};
+use crate::consumer::router::Router;
+use crate::consumer::sink::{route_uri, table_name, Sink};
+use crate::consumer::statement::{Dialect, StatementBuilder};
+use crate::consumer::uri::{parse_uri, UriProps};
 use crate::messages::{FluvioMessage, Operation, Value};
 
+const STMT_CACHE_SIZE: usize = 128;
+
 pub struct MysqlManager {
     conn: Conn,
+    opts: Opts,
+    lost_at: Option<Instant>,
+    reconnect_attempts: u32,
+    reconnects: u64,
     db_name: Option<String>,
-    filters: Option<Filters>,
+    router: Router,
+    statements: StatementBuilder<MysqlDialect>,
+    conflict_handler: ConflictHandler,
 }
 
-#[derive(Debug, PartialEq)]
-pub struct UriProps {
-    db_name: Option<String>,
-    table_name: Option<String>,
+type PreparedQuery = crate::consumer::statement::PreparedQuery<MyValue>;
+
+/// Mysql syntax: backtick quotes, "?" placeholders, "ON DUPLICATE KEY UPDATE" upserts.
+/// Tables are not qualified, the connection is switched to the database of the message.
+pub struct MysqlDialect;
+
+impl Dialect for MysqlDialect {
+    type Param = MyValue;
+
+    fn quote_identifier(&self, name: &str) -> String {
+        quote_identifier(name)
+    }
+
+    fn table(&self, uri_props: &UriProps) -> Result<String, Error> {
+        Ok(quote_identifier(&table_name(uri_props)?))
+    }
+
+    fn bind(&self, value: &Value, params: &mut Vec<MyValue>) -> Result<String, Error> {
+        params.push(to_mysql_value(value));
+        Ok("?".to_owned())
+    }
+
+    /// All columns are updated, key included
+    fn upsert(&self, columns: &[String], _key_idxs: Option<&[usize]>) -> (&'static str, String) {
+        let updates: Vec<String> = columns
+            .iter()
+            .map(|col| format!("{}=VALUES({})", col, col))
+            .collect();
+        (
+            "INSERT",
+            format!(" ON DUPLICATE KEY UPDATE {}", updates.join(", ")),
+        )
+    }
+
+    fn where_one_row(&self, _table: &str, conditions: &str) -> String {
+        format!("WHERE {} LIMIT 1", conditions)
+    }
 }
 
 impl MysqlManager {
     /// Use settings in the profile to connect to database
     ///  - db_name is left empty, as it is not know at this time.
+    ///  - session time zone is UTC, timestamps are sent as UTC date/time.
+    ///  - affected rows of an UPDATE are the matched rows (CLIENT_FOUND_ROWS).
     pub fn connect(profile: &Profile) -> Result<Self, Error> {
-        let opts = OptsBuilder::new()
+        let opts: Opts = OptsBuilder::new()
             .ip_or_hostname(profile.ip_or_host())
             .tcp_port(profile.port())
             .user(profile.user())
-            .pass(profile.password());
-        let conn = Conn::new(opts)
-            .map_err(|err| Error::new(ErrorKind::ConnectionRefused, format!("{}", err)))?;
+            .pass(profile.password())
+            .stmt_cache_size(Some(STMT_CACHE_SIZE))
+            .additional_capabilities(CapabilityFlags::CLIENT_FOUND_ROWS)
+            .into();
+        let conn = open_session(&opts)?;
 
         Ok(Self {
             conn,
+            opts,
+            lost_at: None,
+            reconnect_attempts: 0,
+            reconnects: 0,
             db_name: None,
-            filters: profile.filters(),
+            router: Router::new(&profile.routing()),
+            statements: StatementBuilder::new(MysqlDialect, profile.apply_mode()),
+            conflict_handler: ConflictHandler::new(profile.on_conflict(), &profile.conflict_file()),
         })
     }
 
-    /// Parse message, read operations, and updated the database
+    /// Connection to the target database
+    pub fn conn(&mut self) -> &mut Conn {
+        &mut self.conn
+    }
+
+    /// Number of row conflicts since start
+    pub fn conflicts(&self) -> u64 {
+        self.conflict_handler.conflicts()
+    }
+
+    /// Number of reconnects since start
+    pub fn reconnects(&self) -> u64 {
+        self.reconnects
+    }
+
+    /// Read message operations, and updated the database
+    ///
+    /// Operations and the offset of the next message (table offset store) are applied in
+    /// one transaction. DDL statements commit implicitly in mysql.
     ///
     /// The following operations are supported:
     ///     - Query (create/drop database, create/drop table, alter table)
@@ -52,132 +134,149 @@ impl MysqlManager {
     ///     - Update (update one or or table entries)
     ///     - Delete (delete one or more table entries)
     ///
-    /// If a db-filter is provided, messages may be skipped by filter.
-    /// Filters are applied as follows:
-    ///  - no filters - all messages are processed
-    ///  - include filters - only messages inside "include" filters are processed
-    ///  - exclude filters - only messages outside of "exclude" filters are processed
-    pub fn update_database(&mut self, json_msg: String) -> Result<(), Error> {
-        let flv_message: FluvioMessage = serde_json::from_str(&json_msg)?;
-        let cols = &flv_message.columns;
-        let operation = &flv_message.operation;
+    /// Database and table names are routed to target names, including in DDL statements.
+    fn update_database(
+        &mut self,
+        flv_message: &FluvioMessage,
+        offset_store: &mut dyn OffsetStore,
+    ) -> Result<(), Error> {
         let uri_props = parse_uri(&flv_message.uri)?;
 
-        if !allowed_by_filters(&self.filters, &uri_props.db_name) {
-            println!(
-                "msg for db '{}' skipped by filter",
-                uri_props.db_name.unwrap_or_else(|| "".to_owned())
-            );
-            return Ok(());
-        }
-        println!("{:?}", &flv_message);
+        let source_db = uri_props.db_name.clone();
+        let uri_props = route_uri(&self.router, uri_props);
+        println!("{:?}", flv_message);
         println!("{:?}", &uri_props);
 
-        match operation {
-            Operation::Query(query) => self.send_query_op(&*query, uri_props)?,
-            Operation::Add(data) => self.send_add_op(cols, &data, uri_props)?,
-            Operation::Update(data) => self.send_update_op(cols, &data, uri_props)?,
-            Operation::Delete(data) => self.send_delete_op(cols, &data, uri_props)?,
-        }
+        let queries = match &flv_message.operation {
+            Operation::Query(query) => {
+                let query = self.router.rewrite_query(source_db.as_deref(), query);
+                self.prepare_query_op(&query, &uri_props)?
+            }
+            _ => {
+                let queries = self.statements.build_row_queries(&uri_props, flv_message)?;
+                self.switch_db_if_needed(&uri_props.db_name)?;
+                queries
+            }
+        };
 
-        Ok(())
+        self.apply(Some((flv_message, &uri_props)), &queries, offset_store)
     }
 
-    /// Send Query operation
-    ///     - if "create database", do not switch to db as it does not exist.
-    ///     - any other command, "switch to db first"
-    fn send_query_op(&mut self, query: &str, uri_props: UriProps) -> Result<(), Error> {
-        if !is_query_create_database(query) {
-            self.switch_db_if_needed(&uri_props.db_name)?;
+    fn skip(&mut self, offset_store: &mut dyn OffsetStore) -> Result<(), Error> {
+        if offset_store.is_transactional() {
+            self.apply(None, &[], offset_store)
+        } else {
+            offset_store.increment_offset()
         }
+    }
 
-        exec_query(&mut self.conn, query)
+    /// Mark the connection as lost if the error is a lost connection, the error is returned
+    /// in all cases: the message is retried (see with_retry), and the connection reopened first
+    fn lost_on_error<T>(&mut self, result: Result<T, Error>) -> Result<T, Error> {
+        if let Err(err) = &result {
+            if is_connection_error(err) && self.lost_at.is_none() {
+                warn!(error = %err, "Mysql connection lost");
+                self.lost_at = Some(Instant::now());
+            }
+        }
+        result
     }
 
-    /// Add Rows into table operation
-    ///
-    /// Precondition:
-    ///     - message must have columns
+    /// Reopen a lost connection (one attempt, the backoff is the retry backoff), restore the
+    /// session (time zone and current database), then read the saved offset again.
     ///
-    /// 1) switch db
-    /// 2) build "INSERT INTO" mysql operations
-    /// 3) send operations to mysql
-    ///
-    fn send_add_op(
-        &mut self,
-        cols: &Option<Vec<String>>,
-        data: &WriteRows,
-        uri_props: UriProps,
-    ) -> Result<(), Error> {
-        check_valid_cols(cols)?;
-
-        self.switch_db_if_needed(&uri_props.db_name)?;
+    /// Returns true if the offset moved: the message was committed before the connection
+    /// was lost, and must not be applied again.
+    fn reconnect_if_lost(&mut self, offset_store: &mut dyn OffsetStore) -> Result<bool, Error> {
+        let lost_at = match self.lost_at {
+            Some(lost_at) => lost_at,
+            None => return Ok(false),
+        };
 
-        let table_name = table_name(&uri_props)?;
-        let queries = build_add_queries(table_name, cols.as_ref().unwrap(), &data)?;
-        for query in &queries {
-            exec_query(&mut self.conn, query)?;
+        self.reconnect_attempts += 1;
+        let mut conn = open_session(&self.opts).map_err(|err| {
+            warn!(error = %err, attempts = self.reconnect_attempts, "Mysql reconnect failed");
+            Error::new(
+                ErrorKind::NotConnected,
+                format!("cannot reconnect: {}", err),
+            )
+        })?;
+
+        if let Some(db_name) = &self.db_name {
+            if let Err(err) = use_db(&mut conn, db_name) {
+                warn!(error = %err, "Cannot switch to db after reconnect");
+                self.db_name = None;
+            }
         }
 
-        Ok(())
+        let offset = offset_store.offset();
+        offset_store.reload(&mut conn)?;
+
+        self.conn = conn;
+        self.lost_at = None;
+        self.reconnects += 1;
+        info!(
+            attempts = self.reconnect_attempts,
+            downtime = ?lost_at.elapsed(),
+            reconnects = self.reconnects,
+            "Mysql reconnected"
+        );
+        self.reconnect_attempts = 0;
+
+        Ok(offset_store.offset() > offset)
     }
 
-    /// Update Rows in table operation
-    ///
-    /// Precondition:
-    ///     - message must have columns
-    ///
-    /// 1) switch db
-    /// 2) build "UPDATE" mysql operations
-    /// 3) send operations to mysql
+    /// Execute queries and save the offset of the next message in one transaction.
+    /// On error, the transaction is rolled back when dropped.
     ///
-    fn send_update_op(
+    /// Row updates and deletes are checked by the conflict handler (see exec_queries).
+    /// Logged conflicts are written once the transaction is committed.
+    fn apply(
         &mut self,
-        cols: &Option<Vec<String>>,
-        data: &UpdateRows,
-        uri_props: UriProps,
+        message: Option<(&FluvioMessage, &UriProps)>,
+        queries: &[PreparedQuery],
+        offset_store: &mut dyn OffsetStore,
     ) -> Result<(), Error> {
-        check_valid_cols(cols)?;
-
-        self.switch_db_if_needed(&uri_props.db_name)?;
+        self.conflict_handler.discard_log();
+        let mut tx = self
+            .conn
+            .start_transaction(TxOpts::default())
+            .map_err(|e| mysql_error("Transaction", e))?;
+
+        if let Some((flv_message, uri_props)) = message {
+            self.statements.exec_queries(
+                &mut self.conflict_handler,
+                flv_message,
+                uri_props,
+                queries,
+                |query| exec_prepared(&mut tx, query),
+            )?;
+        }
+        offset_store.save_next_offset(&mut tx)?;
 
-        let table_name = table_name(&uri_props)?;
+        tx.commit().map_err(|e| mysql_error("Commit", e))?;
+        offset_store.increment_offset()?;
 
-        let queries = build_update_queries(table_name, &cols.as_ref().unwrap(), &data)?;
-        for query in &queries {
-            exec_query(&mut self.conn, query)?;
+        // message committed, it must not be retried if the log cannot be written
+        if let Err(err) = self.conflict_handler.flush_log() {
+            warn!(error = %err, "Cannot write conflict log");
         }
-
         Ok(())
     }
 
-    /// Delete Rows from table operation
-    ///
-    /// Precondition:
-    ///     - message must have columns
-    ///
-    /// 1) switch db
-    /// 2) build "DELETE" mysql operations
-    /// 3) send operations to mysql
-    ///
-    fn send_delete_op(
+    /// Prepare Query operation
+    ///     - if "create database", do not switch to db as it does not exist.
+    ///     - any other command, "switch to db first"
+    fn prepare_query_op(
         &mut self,
-        cols: &Option<Vec<String>>,
-        data: &DeleteRows,
-        uri_props: UriProps,
-    ) -> Result<(), Error> {
-        check_valid_cols(cols)?;
-
-        self.switch_db_if_needed(&uri_props.db_name)?;
-
-        let table_name = table_name(&uri_props)?;
-
-        let queries = build_delete_queries(table_name, &cols.as_ref().unwrap(), &data)?;
-        for query in &queries {
-            exec_query(&mut self.conn, query)?;
+        query: &str,
+        uri_props: &UriProps,
+    ) -> Result<Vec<PreparedQuery>, Error> {
+        if !is_query_create_database(query) {
+            self.switch_db_if_needed(&uri_props.db_name)?;
         }
 
-        Ok(())
+        Ok(vec![PreparedQuery::statement(query.to_owned())])
     }
 
     /// Each consumer operation may apply to different database.
@@ -188,178 +287,158 @@ impl MysqlManager {
         }
 
         if let Some(db_name) = db_name {
-            let result = self.conn.select_db(db_name);
-            if !result {
-                Err(Error::new(
-                    ErrorKind::InvalidData,
-                    format!("cannot switch to db '{}'", db_name),
-                ))
-            } else {
-                self.db_name = Some(db_name.clone());
-                Ok(())
-            }
-        } else {
-            Ok(())
+            use_db(&mut self.conn, db_name)?;
+            self.db_name = Some(db_name.clone());
         }
+        Ok(())
     }
 }
 
-/// Build "INSERT INTO" mysql operation and send to mysql server
-fn build_add_queries(
-    table_name: String,
-    cols: &[String],
-    data: &WriteRows,
-) -> Result<Vec<String>, Error> {
-    let columns = cols.join(", ");
-    let mut queries: Vec<String> = vec![];
-
-    for row in &data.rows {
-        check_valid_col_count(row.cols.len(), cols.len())?;
-
-        let row: Vec<String> = row.cols.iter().map(|val| val.to_string()).collect();
-        queries.push(format!(
-            "INSERT INTO {} ({}) VALUES ({})",
-            table_name,
-            columns,
-            row.join(", ")
-        ));
-    }
+/// Open connection and set up the session: time zone is UTC
+fn open_session(opts: &Opts) -> Result<Conn, Error> {
+    let mut conn = Conn::new(opts.clone()).map_err(|err| mysql_error("Connect", err))?;
+    exec_query(&mut conn, "SET time_zone = '+00:00'")?;
+    Ok(conn)
+}
 
-    Ok(queries)
+/// Switch the connection to db
+fn use_db(conn: &mut Conn, db_name: &str) -> Result<(), Error> {
+    let context = format!("cannot switch to db '{}'", db_name);
+    conn.query_drop(format!("USE {}", quote_identifier(db_name)))
+        .map_err(|err| mysql_error(&context, err))
 }
 
-/// Build "UPDATE" mysql operation and send to mysql server
-fn build_update_queries(
-    table_name: String,
-    cols: &[String],
-    data: &UpdateRows,
-) -> Result<Vec<String>, Error> {
-    let preamble = format!("UPDATE {}", table_name);
-    let mut queries: Vec<String> = vec![];
-
-    for row in &data.rows {
-        check_valid_col_count(row.before_cols.len(), cols.len())?;
-        check_valid_col_count(row.after_cols.len(), cols.len())?;
-
-        let mut set_cols: Vec<String> = vec![];
-        let mut where_cols: Vec<String> = vec![];
-        for (idx, before_value) in row.before_cols.iter().enumerate() {
-            let after_value = &row.after_cols[idx];
-
-            if before_value != after_value {
-                set_cols.push(format!("{}={}", &cols[idx], after_value))
+/// If the connection is lost, the error is returned so the message is retried, and the
+/// connection is reopened before the next attempt. A message committed before the connection
+/// was lost (offset moved in the table offset store) is not applied again.
+impl Sink for MysqlManager {
+    fn apply_message(
+        &mut self,
+        flv_message: &FluvioMessage,
+        offset_store: &mut dyn OffsetStore,
+    ) -> Result<(), Error> {
+        if self.reconnect_if_lost(offset_store)? {
+            info!(uri = %flv_message.uri, "Message committed before the connection was lost");
+            if let Err(err) = self.conflict_handler.flush_log() {
+                warn!(error = %err, "Cannot write conflict log");
             }
-
-            match before_value {
-                Value::Null => where_cols.push(format!("{} is NULL", &cols[idx])),
-                _ => where_cols.push(format!("{}={}", &cols[idx], before_value)),
-            };
+            return Ok(());
         }
-        queries.push(format!(
-            "{} SET {} WHERE {}",
-            &preamble,
-            set_cols.join(", "),
-            where_cols.join(" AND ")
-        ));
+        let result = self.update_database(flv_message, offset_store);
+        self.lost_on_error(result)
     }
 
-    Ok(queries)
-}
-
-/// Build "DELETE" mysql operation and send to mysql server
-fn build_delete_queries(
-    table_name: String,
-    cols: &[String],
-    data: &DeleteRows,
-) -> Result<Vec<String>, Error> {
-    let preamble = format!("DELETE FROM {} WHERE", table_name);
-    let mut queries: Vec<String> = vec![];
-
-    for row in &data.rows {
-        check_valid_col_count(row.cols.len(), cols.len())?;
-
-        let mut where_cols: Vec<String> = vec![];
-        for (idx, value) in row.cols.iter().enumerate() {
-            match value {
-                Value::Null => where_cols.push(format!("{} is NULL", &cols[idx])),
-                _ => where_cols.push(format!("{}={}", &cols[idx], value)),
-            };
+    fn checkpoint(&mut self, offset_store: &mut dyn OffsetStore) -> Result<(), Error> {
+        if self.reconnect_if_lost(offset_store)? {
+            return Ok(());
         }
-        queries.push(format!("{} {}", &preamble, where_cols.join(" AND ")));
+        let result = self.skip(offset_store);
+        self.lost_on_error(result)
     }
 
-    Ok(queries)
+    fn report(&self) {
+        println!("Row conflicts: {}", self.conflicts());
+        println!("Mysql reconnects: {}", self.reconnects());
+        info!(
+            conflicts = self.conflicts(),
+            reconnects = self.reconnects(),
+            "Mysql sink counters"
+        );
+    }
 }
 
-/// Use mysql Connection to send query to mysql server and map any resulting errors
-fn exec_query(conn: &mut Conn, query: &str) -> Result<(), Error> {
-    println!("[query] {:?}", query);
-    conn.query_drop(query)
-        .map_err(|e| Error::new(ErrorKind::InvalidData, format!("Query: {}", e)))
+/// Quote table or column name with backticks
+pub fn quote_identifier(name: &str) -> String {
+    format!("`{}`", name.replace('`', "``"))
 }
 
-/// Ensure message has column object, return error otherwise
-fn check_valid_cols(cols: &Option<Vec<String>>) -> Result<(), Error> {
-    if cols.is_none() {
-        Err(Error::new(
-            ErrorKind::InvalidData,
-            "expected column names, found none",
-        ))
-    } else {
-        Ok(())
+/// Convert message value to mysql parameter value
+///  - strings, decimals and json are sent as text, blobs as bytes
+///  - timestamps are sent as UTC date/time (session time zone is UTC)
+fn to_mysql_value(value: &Value) -> MyValue {
+    match value {
+        Value::UnsignedInteger(val) => MyValue::UInt(u64::from(*val)),
+        Value::SignedInteger(val) => MyValue::Int(*val),
+        Value::Float(val) => MyValue::Double(f64::from(*val)),
+        Value::Double(val) => MyValue::Double(*val),
+        Value::String(val) => MyValue::Bytes(val.as_bytes().to_vec()),
+        Value::Enum(val) => MyValue::Int(i64::from(*val)),
+        Value::Blob(bytes) => MyValue::Bytes(bytes.clone()),
+        Value::Year(val) => MyValue::UInt(u64::from(*val)),
+        Value::Date { year, month, day } => {
+            MyValue::Date(*year as u16, *month as u8, *day as u8, 0, 0, 0, 0)
+        }
+        Value::Time {
+            hours,
+            minutes,
+            seconds,
+            subseconds,
+        } => MyValue::Time(
+            false,
+            hours / 24,
+            (hours % 24) as u8,
+            *minutes as u8,
+            *seconds as u8,
+            *subseconds,
+        ),
+        Value::DateTime {
+            year,
+            month,
+            day,
+            hour,
+            minute,
+            second,
+            subsecond,
+        } => MyValue::Date(
+            *year as u16,
+            *month as u8,
+            *day as u8,
+            *hour as u8,
+            *minute as u8,
+            *second as u8,
+            *subsecond,
+        ),
+        Value::Json(val) => MyValue::Bytes(val.to_string().into_bytes()),
+        Value::Decimal(val) => MyValue::Bytes(val.to_string().into_bytes()),
+        Value::Timestamp {
+            unix_time,
+            subsecond,
+        } => {
+            let d = UNIX_EPOCH + Duration::from_secs(*unix_time as u64);
+            let datetime = DateTime::<Utc>::from(d);
+            MyValue::Date(
+                datetime.year() as u16,
+                datetime.month() as u8,
+                datetime.day() as u8,
+                datetime.hour() as u8,
+                datetime.minute() as u8,
+                datetime.second() as u8,
+                *subsecond,
+            )
+        }
+        Value::Null => MyValue::NULL,
     }
 }
 
-/// Ensure URI table_name field, return error otherwise
-fn table_name(uri_props: &UriProps) -> Result<String, Error> {
-    if let Some(table_name) = &uri_props.table_name {
-        Ok(table_name.clone())
-    } else {
-        Err(Error::new(
-            ErrorKind::InvalidData,
-            "expected table-name, found none",
-        ))
-    }
+/// Use mysql Connection to send query to mysql server and map any resulting errors
+fn exec_query<Q: Queryable>(conn: &mut Q, query: &str) -> Result<(), Error> {
+    debug!(query, "Query");
+    conn.query_drop(query).map_err(|e| mysql_error("Query", e))
 }
 
-/// Ensure the number of columns match the number of value objects
-fn check_valid_col_count(expected: usize, found: usize) -> Result<(), Error> {
-    if found < expected {
-        Err(Error::new(
-            ErrorKind::InvalidData,
-            format!("expected at least {} columns, found {}", expected, found),
-        ))
+/// Execute prepared statement (cached by the connection) with its parameters, and
+/// return the affected rows.
+/// Queries without parameters (DDL) are sent as text, as not all of them can be prepared.
+fn exec_prepared(tx: &mut Transaction<'_>, prepared: &PreparedQuery) -> Result<u64, Error> {
+    if prepared.params.is_empty() {
+        exec_query(tx, &prepared.query)?;
     } else {
-        Ok(())
+        debug!(query = %prepared.query, params = ?prepared.params, "Query");
+        tx.exec_drop(prepared.query.as_str(), prepared.params.clone())
+            .map_err(|e| mysql_error("Query", e))?;
     }
-}
 
-/// Parse URI for db_name & table_name
-///
-/// URI format
-///   uri::/<db-label>/<db_name>/<table_name>
-fn parse_uri(uri_str: &str) -> Result<UriProps, Error> {
-    let uri = uri_str
-        .parse::<Uri>()
-        .map_err(|err| Error::new(ErrorKind::InvalidData, format!("{}", err)))?;
-    let path: Vec<&str> = uri.path().split('/').collect();
-
-    let db_name: Option<String> = if path.len() > 1 && !path[1].is_empty() {
-        Some(path[1].to_owned())
-    } else {
-        None
-    };
-
-    let table_name: Option<String> = if path.len() > 2 && !path[2].is_empty() {
-        Some(path[2].to_owned())
-    } else {
-        None
-    };
-
-    Ok(UriProps {
-        db_name,
-        table_name,
-    })
+    Ok(tx.affected_rows())
 }
 
 /// Check if query is "CREATE DATABASE"
@@ -368,32 +447,22 @@ fn is_query_create_database(query: &str) -> bool {
     query_uppercase.trim().contains("CREATE DATABASE")
 }
 
-/// Allowed by filters
-///  - no filters  => true
-///  - include filters => true for match, false otherwise
-///  - exclude filters => false for match, true otherwise
-fn allowed_by_filters(filters: &Option<Filters>, db_name: &Option<String>) -> bool {
-    // disallow all entries without a database
-    if db_name.is_none() {
-        return false;
-    }
-
-    let db_name = db_name.as_ref().unwrap();
-    let db_name = &db_name.to_ascii_lowercase();
-    if let Some(filters) = filters {
-        match filters {
-            Filters::Include { include_dbs: dbs } => dbs.contains(db_name),
-            Filters::Exclude { exclude_dbs: dbs } => !dbs.contains(db_name),
-        }
-    } else {
-        true
-    }
-}
-
 #[cfg(test)]
 mod test {
     use super::*;
-    use crate::messages::{BeforeAfterCols, Cols, Value, WriteRows};
+    use crate::consumer::profile::ApplyMode;
+    use crate::consumer::statement::StatementKind;
+    use crate::messages::{BeforeAfterCols, Cols, Value};
+    use crate::messages::{DeleteRows, UpdateRows, WriteRows};
+    use bigdecimal::BigDecimal;
+
+    fn text(val: &str) -> MyValue {
+        MyValue::Bytes(val.as_bytes().to_vec())
+    }
+
+    fn statements(mode: ApplyMode) -> StatementBuilder<MysqlDialect> {
+        StatementBuilder::new(MysqlDialect, mode)
+    }
 
     #[test]
     fn test_build_add_queries() {
@@ -439,16 +508,38 @@ mod test {
             ],
         };
 
-        let table_name = "pet".to_owned();
-        let result = build_add_queries(table_name, &cols, &data);
+        let result = statements(ApplyMode::Strict).build_add_queries("`pet`", &cols, None, &data);
         if let Err(err) = &result {
             println!("Error: {}", err);
         }
         assert!(result.is_ok());
 
+        let query = "INSERT INTO `pet` (`name`, `owner`, `species`, `sex`, `birth`, `death`) VALUES (?, ?, ?, ?, ?, ?)";
         let expected = vec![
-            "INSERT INTO pet (name, owner, species, sex, birth, death) VALUES (\"Pip\", \"Jake\", \"mouse\", \"m\", \"2020-3-30\", Null)".to_owned(),
-            "INSERT INTO pet (name, owner, species, sex, birth, death) VALUES (\"Spot\", \"Jane\", \"dog\", \"m\", \"2010-11-2\", Null)".to_owned()
+            PreparedQuery {
+                kind: StatementKind::Insert,
+                query: query.to_owned(),
+                params: vec![
+                    text("Pip"),
+                    text("Jake"),
+                    text("mouse"),
+                    text("m"),
+                    MyValue::Date(2020, 3, 30, 0, 0, 0, 0),
+                    MyValue::NULL,
+                ],
+            },
+            PreparedQuery {
+                kind: StatementKind::Insert,
+                query: query.to_owned(),
+                params: vec![
+                    text("Spot"),
+                    text("Jane"),
+                    text("dog"),
+                    text("m"),
+                    MyValue::Date(2010, 11, 2, 0, 0, 0, 0),
+                    MyValue::NULL,
+                ],
+            },
         ];
         assert_eq!(result.unwrap(), expected);
     }
@@ -525,16 +616,39 @@ mod test {
             ],
         };
 
-        let table_name = "pet".to_owned();
-        let result = build_update_queries(table_name, &cols, &data);
+        let result =
+            statements(ApplyMode::Strict).build_update_queries("`pet`", &cols, None, &data);
         if let Err(err) = &result {
             println!("Error: {}", err);
         }
         assert!(result.is_ok());
 
         let expected = vec![
-            "UPDATE pet SET name=\"Mickey\", owner=\"Jack\" WHERE name=\"Pip\" AND owner=\"Jake\" AND species=\"mouse\" AND sex=\"m\" AND birth=\"2020-3-30\" AND death is NULL".to_owned(),
-            "UPDATE pet SET death=\"2020-6-10\" WHERE name=\"Spot\" AND owner=\"Jane\" AND species=\"dog\" AND sex=\"m\" AND birth=\"2010-11-2\" AND death is NULL".to_owned(),
+            PreparedQuery {
+                kind: StatementKind::Update,
+                query: "UPDATE `pet` SET `name`=?, `owner`=? WHERE `name`=? AND `owner`=? AND `species`=? AND `sex`=? AND `birth`=? AND `death` IS NULL LIMIT 1".to_owned(),
+                params: vec![
+                    text("Mickey"),
+                    text("Jack"),
+                    text("Pip"),
+                    text("Jake"),
+                    text("mouse"),
+                    text("m"),
+                    MyValue::Date(2020, 3, 30, 0, 0, 0, 0),
+                ],
+            },
+            PreparedQuery {
+                kind: StatementKind::Update,
+                query: "UPDATE `pet` SET `death`=? WHERE `name`=? AND `owner`=? AND `species`=? AND `sex`=? AND `birth`=? AND `death` IS NULL LIMIT 1".to_owned(),
+                params: vec![
+                    MyValue::Date(2020, 6, 10, 0, 0, 0, 0),
+                    text("Spot"),
+                    text("Jane"),
+                    text("dog"),
+                    text("m"),
+                    MyValue::Date(2010, 11, 2, 0, 0, 0, 0),
+                ],
+            },
         ];
         assert_eq!(result.unwrap(), expected);
     }
@@ -583,118 +697,248 @@ mod test {
             ],
         };
 
-        let table_name = "pet".to_owned();
-        let result = build_delete_queries(table_name, &cols, &data);
+        let result =
+            statements(ApplyMode::Strict).build_delete_queries("`pet`", &cols, None, &data);
         if let Err(err) = &result {
             println!("Error: {}", err);
         }
         assert!(result.is_ok());
 
+        let query = "DELETE FROM `pet` WHERE `name`=? AND `owner`=? AND `species`=? AND `sex`=? AND `birth`=? AND `death` IS NULL LIMIT 1";
         let expected = vec![
-            "DELETE FROM pet WHERE name=\"Pip\" AND owner=\"Jake\" AND species=\"mouse\" AND sex=\"m\" AND birth=\"2020-3-30\" AND death is NULL".to_owned(),
-            "DELETE FROM pet WHERE name=\"Spot\" AND owner=\"Jane\" AND species=\"dog\" AND sex=\"m\" AND birth=\"2010-11-2\" AND death is NULL".to_owned(),
+            PreparedQuery {
+                kind: StatementKind::Delete,
+                query: query.to_owned(),
+                params: vec![
+                    text("Pip"),
+                    text("Jake"),
+                    text("mouse"),
+                    text("m"),
+                    MyValue::Date(2020, 3, 30, 0, 0, 0, 0),
+                ],
+            },
+            PreparedQuery {
+                kind: StatementKind::Delete,
+                query: query.to_owned(),
+                params: vec![
+                    text("Spot"),
+                    text("Jane"),
+                    text("dog"),
+                    text("m"),
+                    MyValue::Date(2010, 11, 2, 0, 0, 0, 0),
+                ],
+            },
         ];
         assert_eq!(result.unwrap(), expected);
     }
 
     #[test]
-    fn test_is_query_create_database() {
-        let q = "create database flvTest".to_owned();
-        assert_eq!(is_query_create_database(&q), true);
+    fn test_build_key_queries() {
+        let cols = vec!["id".to_owned(), "name".to_owned(), "price".to_owned()];
+        let keys = vec!["id".to_owned()];
+        let row = || {
+            vec![
+                Value::SignedInteger(7),
+                Value::String("toy".to_owned()),
+                Value::Float(1.5),
+            ]
+        };
 
-        let q = "CREATE database flvTest".to_owned();
-        assert_eq!(is_query_create_database(&q), true);
+        let data = UpdateRows {
+            rows: vec![BeforeAfterCols {
+                before_cols: row(),
+                after_cols: vec![
+                    Value::SignedInteger(7),
+                    Value::String("toy".to_owned()),
+                    Value::Float(2.5),
+                ],
+            }],
+        };
+        let result =
+            statements(ApplyMode::Strict).build_update_queries("`toys`", &cols, Some(&keys), &data);
+        let expected = vec![PreparedQuery {
+            kind: StatementKind::Update,
+            query: "UPDATE `toys` SET `price`=? WHERE `id`=?".to_owned(),
+            params: vec![MyValue::Double(2.5), MyValue::Int(7)],
+        }];
+        assert_eq!(result.unwrap(), expected);
 
-        let q = " create database test if not exist".to_owned();
-        assert_eq!(is_query_create_database(&q), true);
+        let data = DeleteRows {
+            rows: vec![Cols { cols: row() }],
+        };
+        let result =
+            statements(ApplyMode::Strict).build_delete_queries("`toys`", &cols, Some(&keys), &data);
+        let expected = vec![PreparedQuery {
+            kind: StatementKind::Delete,
+            query: "DELETE FROM `toys` WHERE `id`=?".to_owned(),
+            params: vec![MyValue::Int(7)],
+        }];
+        assert_eq!(result.unwrap(), expected);
 
-        let q = "CREATE TABLE pet (name VARCHAR(20), owner VARCHAR(20), species VARCHAR(20), sex CHAR(1), birth DATE)".to_owned();
-        assert_eq!(is_query_create_database(&q), false);
+        // key column not in message => all columns
+        let keys = vec!["sku".to_owned()];
+        let result =
+            statements(ApplyMode::Strict).build_delete_queries("`toys`", &cols, Some(&keys), &data);
+        assert_eq!(
+            result.unwrap()[0].query,
+            "DELETE FROM `toys` WHERE `id`=? AND `name`=? AND `price`=? LIMIT 1"
+        );
     }
 
     #[test]
-    fn test_allowed_by_filters() {
-        // Test No db
-        assert_eq!(allowed_by_filters(&None, &None), false);
-
-        // Test No filters
-        let db_name = "db-xx".to_owned();
-        assert_eq!(allowed_by_filters(&None, &Some(db_name)), true);
-
-        // Test Include filters
-        let include_filters = Some(Filters::Include {
-            include_dbs: vec!["db-10".to_owned(), "db-11".to_owned()],
-        });
-
-        let db_name = "db-xx".to_owned();
-        assert_eq!(allowed_by_filters(&include_filters, &Some(db_name)), false);
-
-        let db_name = "db-10".to_owned();
-        assert_eq!(allowed_by_filters(&include_filters, &Some(db_name)), true);
-
-        let db_name = "db-11".to_owned();
-        assert_eq!(allowed_by_filters(&include_filters, &Some(db_name)), true);
+    fn test_build_upsert_queries() {
+        let cols = vec!["id".to_owned(), "name".to_owned()];
+        let keys = vec!["id".to_owned()];
+        let upsert = "INSERT INTO `toys` (`id`, `name`) VALUES (?, ?) \
+                      ON DUPLICATE KEY UPDATE `id`=VALUES(`id`), `name`=VALUES(`name`)";
 
-        let db_name = "db-1111".to_owned();
-        assert_eq!(allowed_by_filters(&include_filters, &Some(db_name)), false);
-
-        let db_name = "db-20".to_owned();
-        assert_eq!(allowed_by_filters(&include_filters, &Some(db_name)), false);
-
-        // Test Exclude filters
-        let exclude_filters = Some(Filters::Exclude {
-            exclude_dbs: vec!["db-10".to_owned(), "db-11".to_owned()],
-        });
-
-        let db_name = "db-xx".to_owned();
-        assert_eq!(allowed_by_filters(&exclude_filters, &Some(db_name)), true);
-
-        let db_name = "db-10".to_owned();
-        assert_eq!(allowed_by_filters(&exclude_filters, &Some(db_name)), false);
-
-        let db_name = "db-11".to_owned();
-        assert_eq!(allowed_by_filters(&exclude_filters, &Some(db_name)), false);
+        let data = WriteRows {
+            rows: vec![Cols {
+                cols: vec![Value::SignedInteger(7), Value::String("toy".to_owned())],
+            }],
+        };
+        let result =
+            statements(ApplyMode::Upsert).build_add_queries("`toys`", &cols, Some(&keys), &data);
+        let expected = vec![PreparedQuery {
+            kind: StatementKind::Insert,
+            query: upsert.to_owned(),
+            params: vec![MyValue::Int(7), text("toy")],
+        }];
+        assert_eq!(result.unwrap(), expected);
 
-        let db_name = "db-1111".to_owned();
-        assert_eq!(allowed_by_filters(&exclude_filters, &Some(db_name)), true);
+        // same key => upsert after image, key changed or unknown => update
+        let data = UpdateRows {
+            rows: vec![
+                BeforeAfterCols {
+                    before_cols: vec![Value::SignedInteger(7), Value::String("toy".to_owned())],
+                    after_cols: vec![Value::SignedInteger(7), Value::String("car".to_owned())],
+                },
+                BeforeAfterCols {
+                    before_cols: vec![Value::SignedInteger(7), Value::String("car".to_owned())],
+                    after_cols: vec![Value::SignedInteger(8), Value::String("car".to_owned())],
+                },
+            ],
+        };
+        let result =
+            statements(ApplyMode::Upsert).build_update_queries("`toys`", &cols, Some(&keys), &data);
+        let expected = vec![
+            PreparedQuery {
+                kind: StatementKind::Insert,
+                query: upsert.to_owned(),
+                params: vec![MyValue::Int(7), text("car")],
+            },
+            PreparedQuery {
+                kind: StatementKind::Update,
+                query: "UPDATE `toys` SET `id`=? WHERE `id`=?".to_owned(),
+                params: vec![MyValue::Int(8), MyValue::Int(7)],
+            },
+        ];
+        assert_eq!(result.unwrap(), expected);
 
-        let db_name = "db-20".to_owned();
-        assert_eq!(allowed_by_filters(&exclude_filters, &Some(db_name)), true);
+        let result =
+            statements(ApplyMode::Upsert).build_update_queries("`toys`", &cols, None, &data);
+        assert_eq!(
+            result.unwrap()[0].query,
+            "UPDATE `toys` SET `name`=? WHERE `id`=? AND `name`=? LIMIT 1"
+        );
     }
 
     #[test]
-    fn test_parse_uri() {
-        // Test both
-        let uri = "flv://mysql.local/testdb/people".to_owned();
-        let uri_props = parse_uri(&uri);
-        assert!(uri_props.is_ok());
-
-        let expected = UriProps {
-            db_name: Some("testdb".to_owned()),
-            table_name: Some("people".to_owned()),
+    fn test_conflict_queries() {
+        let data = UpdateRows {
+            rows: vec![BeforeAfterCols {
+                before_cols: vec![Value::SignedInteger(7), Value::String("toy".to_owned())],
+                after_cols: vec![Value::SignedInteger(7), Value::String("car".to_owned())],
+            }],
         };
-        assert_eq!(uri_props.unwrap(), expected);
+        let flv_message =
+            FluvioMessage::test_message("flv://srv1/shop/toys", Operation::Update(data))
+                .with_columns(&["id", "name"])
+                .with_keys(&["id"]);
+
+        let expected = PreparedQuery {
+            kind: StatementKind::Insert,
+            query: "INSERT INTO `toys` (`id`, `name`) VALUES (?, ?)".to_owned(),
+            params: vec![MyValue::Int(7), text("car")],
+        };
+        let statements = statements(ApplyMode::Strict);
+        assert_eq!(
+            statements
+                .build_conflict_insert(&flv_message, "`toys`", 0)
+                .unwrap(),
+            expected
+        );
+        assert!(statements
+            .build_conflict_insert(&flv_message, "`toys`", 1)
+            .is_err());
+
+        // upserts are not checked
+        assert!(!expected.matches_row());
+        let update = PreparedQuery {
+            kind: StatementKind::Update,
+            query: "UPDATE `toys` SET `name`=? WHERE `id`=?".to_owned(),
+            params: vec![text("car"), MyValue::Int(7)],
+        };
+        assert!(update.matches_row());
+        let query = PreparedQuery {
+            kind: StatementKind::Query,
+            query: "DROP TABLE toys".to_owned(),
+            params: vec![],
+        };
+        assert!(!query.matches_row());
+    }
 
-        // Test Db-only
-        let uri = "flv://mysql.local/flvTest".to_owned();
-        let uri_props = parse_uri(&uri);
-        assert!(uri_props.is_ok());
+    #[test]
+    fn test_to_mysql_value() {
+        assert_eq!(
+            to_mysql_value(&Value::String("O'Brien \"Bob\"".to_owned())),
+            text("O'Brien \"Bob\"")
+        );
+        assert_eq!(
+            to_mysql_value(&Value::Blob(vec![0, 159, 146, 150])),
+            MyValue::Bytes(vec![0, 159, 146, 150])
+        );
+        assert_eq!(
+            to_mysql_value(&Value::Json(serde_json::json!({"a": "b"}))),
+            text("{\"a\":\"b\"}")
+        );
+        assert_eq!(
+            to_mysql_value(&Value::Decimal("5.54321".parse::<BigDecimal>().unwrap())),
+            text("5.54321")
+        );
+        assert_eq!(
+            to_mysql_value(&Value::Timestamp {
+                unix_time: 1524885322,
+                subsecond: 123456
+            }),
+            MyValue::Date(2018, 4, 28, 3, 15, 22, 123456)
+        );
+        assert_eq!(
+            to_mysql_value(&Value::Time {
+                hours: 30,
+                minutes: 31,
+                seconds: 30,
+                subseconds: 1000
+            }),
+            MyValue::Time(false, 1, 6, 31, 30, 1000)
+        );
+
+        assert_eq!(quote_identifier("pet"), "`pet`");
+        assert_eq!(quote_identifier("my`pet"), "`my``pet`");
+    }
 
-        let expected = UriProps {
-            db_name: Some("flvTest".to_owned()),
-            table_name: None,
-        };
-        assert_eq!(uri_props.unwrap(), expected);
+    #[test]
+    fn test_is_query_create_database() {
+        let q = "create database flvTest".to_owned();
+        assert_eq!(is_query_create_database(&q), true);
 
-        // Test none
-        let uri = "flv://mysql.local/".to_owned();
-        let uri_props = parse_uri(&uri);
-        assert!(uri_props.is_ok());
+        let q = "CREATE database flvTest".to_owned();
+        assert_eq!(is_query_create_database(&q), true);
 
-        let expected = UriProps {
-            db_name: None,
-            table_name: None,
-        };
-        assert_eq!(uri_props.unwrap(), expected);
+        let q = " create database test if not exist".to_owned();
+        assert_eq!(is_query_create_database(&q), true);
+
+        let q = "CREATE TABLE pet (name VARCHAR(20), owner VARCHAR(20), species VARCHAR(20), sex CHAR(1), birth DATE)".to_owned();
+        assert_eq!(is_query_create_database(&q), false);
     }
 }
@@ -1,48 +1,218 @@
-use async_std::fs;
+//! Offset Store
+//!
+//! Offset of the next record to consume, and last sequence of each source:
+//!  - FileOffsetStore: saved in local files after the record is applied, sequences first
+//!  - TableOffsetStore: saved in the target database, in the transaction that applies the record
+//!  - SinkOffsetStore: saved by the sink in its own tables (sqlite), in the same transaction
+//!
+//! Sequences are saved with the offset, so a restart never sees a record applied without
+//! its sequence (false gap). With the file store, a record saved with its sequence but not
+//! its offset is read again, and skipped as a duplicate.
+//!
+use mysql::{Conn, Transaction};
+use std::collections::BTreeMap;
+use std::fs;
 use std::io::{Error, ErrorKind};
 use std::path::{Path, PathBuf};
 
+pub trait OffsetStore {
+    /// Offset of the next record to consume
+    fn offset(&self) -> i64;
+
+    /// Offset is saved by `save_next_offset` in the database transaction
+    fn is_transactional(&self) -> bool {
+        false
+    }
+
+    /// Save the offset of the next record in the transaction that applies the current record
+    fn save_next_offset(&mut self, _tx: &mut Transaction<'_>) -> Result<(), Error> {
+        Ok(())
+    }
+
+    /// Last sequence of each source saved with the offset
+    fn sequences(&self) -> &BTreeMap<String, u64>;
+
+    /// Sequence of the current record, saved with the offset of the next record.
+    /// Sequences lower than the saved one (duplicates) are ignored.
+    fn set_sequence(&mut self, source: &str, sequence: u64);
+
+    /// Sequence of the current record to save, if it is after the saved one
+    fn current_sequence(&self) -> Option<(&str, u64)>;
+
+    /// Current record applied (transaction committed), move to the next record
+    fn increment_offset(&mut self) -> Result<(), Error>;
+
+    /// Read the saved offset and sequences again, after the database connection was reopened:
+    /// a transaction may have committed before the connection was lost
+    fn reload(&mut self, _conn: &mut Conn) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+/// Sequences saved with the offset, and the sequence of the current record
+#[derive(Debug, Default)]
+pub struct Sequences {
+    saved: BTreeMap<String, u64>,
+    current: Option<(String, u64)>,
+}
+
+impl Sequences {
+    pub fn new(saved: BTreeMap<String, u64>) -> Self {
+        Self {
+            saved,
+            current: None,
+        }
+    }
+
+    pub fn saved(&self) -> &BTreeMap<String, u64> {
+        &self.saved
+    }
+
+    /// Saved sequences read again, the sequence of the current record is kept
+    pub fn reload(&mut self, saved: BTreeMap<String, u64>) {
+        self.saved = saved;
+    }
+
+    pub fn set_current(&mut self, source: &str, sequence: u64) {
+        self.current = Some((source.to_owned(), sequence));
+    }
+
+    /// Sequence of the current record, if it is after the saved one
+    pub fn current(&self) -> Option<(&str, u64)> {
+        match &self.current {
+            Some((source, sequence)) if self.saved.get(source) < Some(sequence) => {
+                Some((source, *sequence))
+            }
+            _ => None,
+        }
+    }
+
+    /// Current record saved, its sequence is the last one of its source
+    pub fn commit(&mut self) {
+        if let Some((source, sequence)) = self.current.take() {
+            let saved = self.saved.entry(source).or_insert(sequence);
+            *saved = sequence.max(*saved);
+        }
+    }
+}
+
 #[derive(Debug)]
-pub struct OffsetStore {
+pub struct FileOffsetStore {
     file: PathBuf,
+    sequence_file: PathBuf,
     offset: i64,
+    sequences: Sequences,
 }
 
-impl OffsetStore {
-    pub async fn init(offset_file: &Path) -> Result<OffsetStore, Error> {
-        let file = get_or_create_file(offset_file).await?;
-        let offset = read_offset(&file).await?;
+impl FileOffsetStore {
+    pub fn init(offset_file: &Path, sequence_file: &Path) -> Result<Self, Error> {
+        let file = get_or_create_file(offset_file)?;
+        let offset = read_offset(&file)?;
+        let sequences = read_sequences(sequence_file)?;
 
-        Ok(Self { file, offset })
+        Ok(Self {
+            file,
+            sequence_file: sequence_file.to_path_buf(),
+            offset,
+            sequences: Sequences::new(sequences),
+        })
     }
+}
 
-    pub fn offset(&self) -> i64 {
+impl OffsetStore for FileOffsetStore {
+    fn offset(&self) -> i64 {
         self.offset
     }
 
-    pub async fn increment_offset(&mut self) -> Result<(), Error> {
+    fn sequences(&self) -> &BTreeMap<String, u64> {
+        self.sequences.saved()
+    }
+
+    fn set_sequence(&mut self, source: &str, sequence: u64) {
+        self.sequences.set_current(source, sequence);
+    }
+
+    fn current_sequence(&self) -> Option<(&str, u64)> {
+        self.sequences.current()
+    }
+
+    /// Sequences are written first: a record saved without its offset is read again,
+    /// and skipped as a duplicate
+    fn increment_offset(&mut self) -> Result<(), Error> {
+        let new_sequence = self.sequences.current().is_some();
+        self.sequences.commit();
+        if new_sequence {
+            write_sequences(&self.sequence_file, self.sequences.saved())?;
+        }
+
         let new_offset = self.offset + 1;
-        write_offset(&self.file, new_offset).await?;
+        write_offset(&self.file, new_offset)?;
         self.offset = new_offset;
 
         Ok(())
     }
 }
 
-async fn get_or_create_file(file_path: &Path) -> Result<PathBuf, Error> {
+/// Offset and sequences saved by the sink in tables of the target database, in the transaction
+/// that applies the record; the store keeps them in memory once committed
+#[derive(Debug)]
+pub struct SinkOffsetStore {
+    offset: i64,
+    sequences: Sequences,
+}
+
+impl SinkOffsetStore {
+    /// Offset and sequences read from the target database
+    pub fn new(offset: i64, sequences: BTreeMap<String, u64>) -> Self {
+        Self {
+            offset,
+            sequences: Sequences::new(sequences),
+        }
+    }
+}
+
+impl OffsetStore for SinkOffsetStore {
+    fn offset(&self) -> i64 {
+        self.offset
+    }
+
+    fn is_transactional(&self) -> bool {
+        true
+    }
+
+    fn sequences(&self) -> &BTreeMap<String, u64> {
+        self.sequences.saved()
+    }
+
+    fn set_sequence(&mut self, source: &str, sequence: u64) {
+        self.sequences.set_current(source, sequence);
+    }
+
+    fn current_sequence(&self) -> Option<(&str, u64)> {
+        self.sequences.current()
+    }
+
+    fn increment_offset(&mut self) -> Result<(), Error> {
+        self.sequences.commit();
+        self.offset += 1;
+        Ok(())
+    }
+}
+
+fn get_or_create_file(file_path: &Path) -> Result<PathBuf, Error> {
     match file_path.exists() {
         true => Ok(file_path.to_path_buf()),
         false => {
             let parent = file_path.parent().unwrap();
-            fs::create_dir_all(&parent).await?;
-            fs::write(&file_path, "0").await?;
+            fs::create_dir_all(&parent)?;
+            fs::write(&file_path, "0")?;
             Ok(file_path.to_path_buf())
         }
     }
 }
 
-async fn read_offset(file: &Path) -> Result<i64, Error> {
-    let bytes = fs::read(file).await?;
+fn read_offset(file: &Path) -> Result<i64, Error> {
+    let bytes = fs::read(file)?;
     let data = String::from_utf8(bytes)
         .map_err(|err| Error::new(ErrorKind::InvalidData, format!("{}", err)))?;
     let result = data
@@ -53,75 +223,129 @@ async fn read_offset(file: &Path) -> Result<i64, Error> {
     Ok(result)
 }
 
-async fn write_offset(file: &Path, offset: i64) -> Result<(), Error> {
-    fs::write(file, offset.to_string()).await?;
+fn write_offset(file: &Path, offset: i64) -> Result<(), Error> {
+    fs::write(file, offset.to_string())?;
     Ok(())
 }
 
+fn read_sequences(file: &Path) -> Result<BTreeMap<String, u64>, Error> {
+    if !file.exists() {
+        if let Some(parent) = file.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        return Ok(BTreeMap::new());
+    }
+
+    let data = fs::read_to_string(file)?;
+    if data.trim().is_empty() {
+        return Ok(BTreeMap::new());
+    }
+    serde_json::from_str(&data).map_err(|err| Error::new(ErrorKind::InvalidData, err))
+}
+
+fn write_sequences(file: &Path, sequences: &BTreeMap<String, u64>) -> Result<(), Error> {
+    let serialized = serde_json::to_string(sequences)?;
+    fs::write(file, serialized)
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
-    use std::fs;
 
     const TEST_PATH: &str = "test_files";
     const OFFSET_FILE: &str = "offset_file.offset";
+    const SEQUENCE_FILE: &str = "offset_file.sequence";
 
     fn build_offset_file_path() -> PathBuf {
         let program_dir = std::env::current_dir().unwrap();
         program_dir.join(TEST_PATH).join(OFFSET_FILE)
     }
 
+    fn build_sequence_file_path() -> PathBuf {
+        let program_dir = std::env::current_dir().unwrap();
+        program_dir.join(TEST_PATH).join(SEQUENCE_FILE)
+    }
+
     fn cleanup(file: PathBuf) {
         fs::remove_file(file).expect("delete file failed");
     }
 
     #[test]
     fn test_offset_all() {
-        async_std::task::block_on(test_create_file_write_and_read_offset());
+        test_create_file_write_and_read_offset();
         test_increment_offset();
+        test_save_sequences();
     }
 
-    async fn test_create_file_write_and_read_offset() {
+    fn test_create_file_write_and_read_offset() {
         let offset_path = build_offset_file_path();
-        let offset_file = get_or_create_file(&offset_path).await;
+        let offset_file = get_or_create_file(&offset_path);
         if let Err(err) = &offset_file {
             println!("{:?}", err);
         };
         assert!(offset_file.is_ok());
 
         let offset_file = offset_file.unwrap();
-        let offset = read_offset(&offset_file).await;
+        let offset = read_offset(&offset_file);
         if offset.is_err() {
             println!("{}", offset.as_ref().unwrap_err());
         }
         assert!(offset.is_ok());
         assert_eq!(offset.unwrap(), 0);
 
-        let res = write_offset(&offset_file, 1).await;
+        let res = write_offset(&offset_file, 1);
         assert!(res.is_ok());
 
-        let offset = read_offset(&offset_file).await;
+        let offset = read_offset(&offset_file);
         assert_eq!(offset.unwrap(), 1);
 
         cleanup(offset_path);
     }
 
     fn test_increment_offset() {
-        async_std::task::block_on(async {
-            let offset_path = build_offset_file_path();
-            let mut offset_store = OffsetStore::init(&offset_path).await;
-            assert!(offset_store.is_ok());
-
-            let offset_store = offset_store.as_mut().unwrap();
-            let res = offset_store.increment_offset().await;
-            assert!(res.is_ok());
-            assert_eq!(offset_store.offset(), 1);
-
-            let offset_store2 = OffsetStore::init(&offset_path).await;
-            assert!(offset_store2.is_ok());
-            assert_eq!(offset_store2.unwrap().offset(), 1);
-
-            cleanup(offset_path);
-        });
+        let offset_path = build_offset_file_path();
+        let sequence_path = build_sequence_file_path();
+        let mut offset_store = FileOffsetStore::init(&offset_path, &sequence_path);
+        assert!(offset_store.is_ok());
+
+        let offset_store = offset_store.as_mut().unwrap();
+        assert!(!offset_store.is_transactional());
+        let res = offset_store.increment_offset();
+        assert!(res.is_ok());
+        assert_eq!(offset_store.offset(), 1);
+
+        let offset_store2 = FileOffsetStore::init(&offset_path, &sequence_path);
+        assert!(offset_store2.is_ok());
+        assert_eq!(offset_store2.unwrap().offset(), 1);
+
+        // no sequence set => no sequence file
+        assert!(!sequence_path.exists());
+        cleanup(offset_path);
+    }
+
+    fn test_save_sequences() {
+        let offset_path = build_offset_file_path();
+        let sequence_path = build_sequence_file_path();
+        let mut offset_store = FileOffsetStore::init(&offset_path, &sequence_path).unwrap();
+
+        offset_store.set_sequence("flv://srv1", 4);
+        offset_store.increment_offset().unwrap();
+        offset_store.set_sequence("flv://srv2", 0);
+        offset_store.increment_offset().unwrap();
+
+        // duplicate => sequence unchanged
+        offset_store.set_sequence("flv://srv1", 2);
+        offset_store.increment_offset().unwrap();
+
+        let offset_store = FileOffsetStore::init(&offset_path, &sequence_path).unwrap();
+        assert_eq!(offset_store.offset(), 3);
+        let expected: BTreeMap<String, u64> =
+            vec![("flv://srv1".to_owned(), 4), ("flv://srv2".to_owned(), 0)]
+                .into_iter()
+                .collect();
+        assert_eq!(offset_store.sequences(), &expected);
+
+        cleanup(offset_path);
+        cleanup(sequence_path);
     }
 }
@@ -0,0 +1,680 @@
+//! Parquet Sink
+//!
+//! Writes the rows of the change stream to Parquet files, per table and schema version:
+//!     <output_dir>/<db>/<table>/v<version>/<table>-<source>-<sequence>.parquet
+//!
+//! The source (mysql resource name) and sequence are those of the first message of the file,
+//! they are unique for tables routed from several sources.
+//!
+//! Rows are converted to Arrow record batches (see messages::record_batch): the schema of a
+//! table comes from its column names and values, with the metadata columns _op, _sequence and
+//! _binlog_pos first. Updates write the new values.
+//!
+//! Rows are buffered per table in a hidden spool file (".<name>.spool", Arrow IPC stream),
+//! written once per message before the offset is saved. The Parquet file is written from the
+//! spool, then renamed to its final name, when it reaches the row limit, is older than the age
+//! limit, the schema of the table changes, an ALTER TABLE statement of the table arrives, or
+//! the consumer exits. Spools left by a crash are written when the sink starts.
+//!
+//! The source and sequence of each spooled batch are recorded next to the spool
+//! (".<name>.spool.sequences"), the batches without a record are dropped. A crash after the
+//! spool write redelivers the message: after recovery, the messages at or below the last
+//! spooled sequence of their source are skipped.
+//!
+//! A table starts a new schema version when its schema changes, the schema of each version is
+//! saved in "v<version>/_schema".
+//!
+use arrow::datatypes::{Schema, SchemaRef};
+use arrow::ipc::reader::StreamReader;
+use arrow::ipc::writer::StreamWriter;
+use arrow::record_batch::RecordBatch;
+use parquet::arrow::ArrowWriter;
+use parquet::basic::Compression;
+use parquet::file::properties::WriterProperties;
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{BufWriter, Error, ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Instant;
+use tracing::{info, warn};
+
+use crate::consumer::offset_store::OffsetStore;
+use crate::consumer::profile::{ParquetOutput, Profile};
+use crate::consumer::router::{tokenize, Router, Token};
+use crate::consumer::sequence_tracker::source_name;
+use crate::consumer::sink::{file_source, remove_if_exists, route_uri, table_name};
+use crate::consumer::sink::{RollingFiles, Sink, SinkFile};
+use crate::consumer::uri::parse_uri;
+use crate::messages::{arrow_error, message_batch, message_schema, FluvioMessage, Operation};
+
+const SPOOL: &str = ".spool";
+const SEQUENCES: &str = ".sequences";
+const IN_PROGRESS: &str = ".inprogress";
+const SCHEMA_FILE: &str = "_schema";
+
+pub struct ParquetSink {
+    output_dir: PathBuf,
+    max_file_rows: u64,
+    router: Router,
+    files: RollingFiles<TableFile>,
+    spooled: BTreeMap<String, u64>,
+}
+
+/// Spool of the next Parquet file of a table
+struct TableFile {
+    schema: SchemaRef,
+    spool_path: PathBuf,
+    final_path: PathBuf,
+    writer: StreamWriter<BufWriter<File>>,
+    sequences: File,
+    rows: u64,
+    opened: Instant,
+}
+
+impl TableFile {
+    fn open(dir: &Path, name: &str, schema: SchemaRef) -> Result<Self, Error> {
+        let spool_path = dir.join(format!(".{}{}", name, SPOOL));
+        let file = File::create(&spool_path)?;
+        let mut writer = StreamWriter::try_new(BufWriter::new(file), &schema)
+            .map_err(|err| arrow_error("Spool", err))?;
+        writer.flush().map_err(|err| arrow_error("Spool", err))?;
+        let sequences = File::create(sequences_path(&spool_path))?;
+
+        Ok(Self {
+            schema,
+            spool_path,
+            final_path: dir.join(name),
+            writer,
+            sequences,
+            rows: 0,
+            opened: Instant::now(),
+        })
+    }
+
+    /// Append the rows of a message to the spool, then record its source and sequence
+    fn write(&mut self, batch: &RecordBatch, flv_message: &FluvioMessage) -> Result<(), Error> {
+        self.writer
+            .write(batch)
+            .and_then(|_| self.writer.flush())
+            .map_err(|err| arrow_error("Spool", err))?;
+        let record = format!(
+            "{}\t{}\n",
+            flv_message.sequence,
+            source_name(&flv_message.uri)
+        );
+        self.sequences.write_all(record.as_bytes())?;
+        self.rows += batch.num_rows() as u64;
+        Ok(())
+    }
+}
+
+impl SinkFile for TableFile {
+    fn opened(&self) -> Instant {
+        self.opened
+    }
+
+    /// Close the spool and write the Parquet file
+    fn finalize(mut self) -> Result<Option<PathBuf>, Error> {
+        self.writer
+            .finish()
+            .map_err(|err| arrow_error("Spool", err))?;
+        let file = self
+            .writer
+            .into_inner()
+            .map_err(|err| arrow_error("Spool", err))?
+            .into_inner()
+            .map_err(|err| err.into_error())?;
+        file.sync_all()?;
+        self.sequences.sync_all()?;
+
+        write_parquet(&self.spool_path, &self.final_path)
+    }
+}
+
+impl ParquetSink {
+    /// Create the output directory, and write the spools of a previous run
+    pub fn open(
+        output_dir: &Path,
+        parquet_output: &ParquetOutput,
+        profile: &Profile,
+    ) -> Result<Self, Error> {
+        fs::create_dir_all(output_dir)?;
+        let mut spooled = BTreeMap::new();
+        let recovered = recover_spools(output_dir, &mut spooled)?;
+        if recovered > 0 {
+            info!(recovered, "Parquet files written from spools");
+        }
+
+        Ok(Self {
+            output_dir: output_dir.to_owned(),
+            max_file_rows: parquet_output.max_file_rows(),
+            router: Router::new(&profile.routing()),
+            files: RollingFiles::new(parquet_output.max_file_age()),
+            spooled,
+        })
+    }
+
+    /// Number of Parquet files written since start
+    pub fn finalized(&self) -> u64 {
+        self.files.finalized()
+    }
+
+    /// Message already written to a spool recovered at start, redelivered because its offset
+    /// was not saved
+    fn spooled(&self, flv_message: &FluvioMessage) -> bool {
+        match self.spooled.get(&source_name(&flv_message.uri)) {
+            Some(last) => flv_message.sequence <= *last,
+            None => false,
+        }
+    }
+
+    /// Spool of the table for the batch, the current spool is finalized first if the schema
+    /// changed
+    fn table_file(
+        &mut self,
+        key: &(String, String),
+        schema: &SchemaRef,
+        flv_message: &FluvioMessage,
+    ) -> Result<&mut TableFile, Error> {
+        if let Some(file) = self.files.get(key) {
+            if &file.schema != schema {
+                self.files.finalize(key)?;
+            }
+        }
+
+        let (db_name, table) = key;
+        let table_dir = self.output_dir.join(db_name).join(table);
+        self.files.get_or_open(key, || {
+            let version = schema_version(&table_dir, schema)?;
+            let dir = table_dir.join(format!("v{}", version));
+
+            let name = format!(
+                "{}-{}-{:020}.parquet",
+                table,
+                file_source(&flv_message.uri),
+                flv_message.sequence
+            );
+            TableFile::open(&dir, &name, schema.clone())
+        })
+    }
+}
+
+impl Sink for ParquetSink {
+    fn apply_message(
+        &mut self,
+        flv_message: &FluvioMessage,
+        offset_store: &mut dyn OffsetStore,
+    ) -> Result<(), Error> {
+        self.files.finalize_old_files()?;
+
+        if self.spooled(flv_message) {
+            info!(
+                sequence = flv_message.sequence,
+                "Message skipped, written to a spool before restart"
+            );
+            return offset_store.increment_offset();
+        }
+
+        if let Operation::Query(query) = &flv_message.operation {
+            let uri_props = parse_uri(&flv_message.uri)?;
+            if let Some((db_name, table)) = altered_table(query, uri_props.db_name.as_deref()) {
+                let key = (
+                    self.router.database(&db_name),
+                    self.router.table(&db_name, &table),
+                );
+                self.files.finalize(&key)?;
+            }
+            return offset_store.increment_offset();
+        }
+
+        let uri_props = route_uri(&self.router, parse_uri(&flv_message.uri)?);
+        let key = (
+            uri_props.db_name.clone().unwrap_or_default(),
+            table_name(&uri_props)?,
+        );
+
+        let current = self.files.get(&key).map(|file| file.schema.clone());
+        let schema = match message_schema(flv_message, current.as_deref())? {
+            Some(schema) => Arc::new(schema),
+            None => return offset_store.increment_offset(),
+        };
+        let batch = message_batch(flv_message, &schema)?;
+
+        let max_file_rows = self.max_file_rows;
+        let file = self.table_file(&key, &schema, flv_message)?;
+        file.write(&batch, flv_message)?;
+        if file.rows >= max_file_rows {
+            self.files.finalize(&key)?;
+        }
+
+        offset_store.increment_offset()
+    }
+
+    /// Write the Parquet files of all spools
+    fn flush(&mut self) -> Result<(), Error> {
+        self.files.flush()
+    }
+
+    fn report(&self) {
+        println!("Parquet files written: {}", self.finalized());
+        info!(finalized = self.finalized(), "Parquet sink counters");
+    }
+}
+
+/// Table of an ALTER TABLE statement: (database, table), in the message database if the
+/// table is not qualified
+fn altered_table(query: &str, db_name: Option<&str>) -> Option<(String, String)> {
+    let tokens: Vec<Token> = tokenize(query)
+        .into_iter()
+        .map(|(token, _)| token)
+        .collect();
+    let is_keyword = |idx: usize, keyword: &str| match tokens.get(idx) {
+        Some(Token::Word {
+            value,
+            quoted: false,
+        }) => value.eq_ignore_ascii_case(keyword),
+        _ => false,
+    };
+    if !is_keyword(0, "ALTER") {
+        return None;
+    }
+
+    let table_idx = (1..3).find(|idx| is_keyword(*idx, "TABLE"))? + 1;
+    let name = |idx: usize| match tokens.get(idx) {
+        Some(Token::Word { value, .. }) => Some(value.clone()),
+        _ => None,
+    };
+    let first = name(table_idx)?;
+    match (tokens.get(table_idx + 1), name(table_idx + 2)) {
+        (Some(Token::Punct('.')), Some(table)) => Some((first, table)),
+        _ => Some((db_name?.to_owned(), first)),
+    }
+}
+
+/// Schema version of the table: the last version if the schema is the same, a new version
+/// otherwise. The schema of the version is saved in its directory.
+fn schema_version(table_dir: &Path, schema: &Schema) -> Result<u32, Error> {
+    let description: String = schema
+        .fields()
+        .iter()
+        .map(|field| format!("{}\t{}\n", field.name(), field.data_type()))
+        .collect();
+
+    let mut last = None;
+    if table_dir.exists() {
+        for entry in fs::read_dir(table_dir)? {
+            let name = entry?.file_name();
+            let version = name
+                .to_str()
+                .and_then(|name| name.strip_prefix('v'))
+                .and_then(|version| version.parse::<u32>().ok());
+            last = last.max(version);
+        }
+    }
+
+    if let Some(last) = last {
+        let saved = fs::read_to_string(table_dir.join(format!("v{}", last)).join(SCHEMA_FILE));
+        if saved.ok().as_ref() == Some(&description) {
+            return Ok(last);
+        }
+    }
+
+    let version = last.map_or(1, |last| last + 1);
+    let dir = table_dir.join(format!("v{}", version));
+    fs::create_dir_all(&dir)?;
+    fs::write(dir.join(SCHEMA_FILE), description)?;
+    info!(table_dir = %table_dir.display(), version, "New schema version");
+    Ok(version)
+}
+
+fn sequences_path(spool_path: &Path) -> PathBuf {
+    let mut sequences_path = spool_path.as_os_str().to_owned();
+    sequences_path.push(SEQUENCES);
+    PathBuf::from(sequences_path)
+}
+
+/// Source and sequence of the spooled batches, in order. The partial record of an interrupted
+/// write is ignored.
+fn spooled_sequences(spool_path: &Path) -> Result<Vec<(String, u64)>, Error> {
+    let content = match fs::read_to_string(sequences_path(spool_path)) {
+        Ok(content) => content,
+        Err(err) if err.kind() == ErrorKind::NotFound => return Ok(vec![]),
+        Err(err) => return Err(err),
+    };
+
+    let complete = content.rfind('\n').map_or(0, |end| end + 1);
+    Ok(content[..complete]
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.splitn(2, '\t');
+            let sequence = fields.next()?.parse::<u64>().ok()?;
+            Some((fields.next()?.to_owned(), sequence))
+        })
+        .collect())
+}
+
+/// Write the Parquet file of a spool: written to a hidden file, then renamed, then the spool
+/// is removed. The batches of an interrupted write, and the batches without a sequence record,
+/// are dropped. Returns the Parquet file, none if the spool has no rows.
+fn write_parquet(spool_path: &Path, final_path: &Path) -> Result<Option<PathBuf>, Error> {
+    let reader = StreamReader::try_new(File::open(spool_path)?, None)
+        .map_err(|err| arrow_error("Spool", err))?;
+    let schema = reader.schema();
+    let recorded = spooled_sequences(spool_path)?.len();
+
+    let mut batches = vec![];
+    for batch in reader {
+        match batch {
+            Ok(batch) => batches.push(batch),
+            Err(err) => {
+                warn!(spool = %spool_path.display(), error = %err, "Incomplete spool");
+                break;
+            }
+        }
+    }
+    if batches.len() > recorded {
+        warn!(
+            spool = %spool_path.display(),
+            dropped = batches.len() - recorded,
+            "Spooled batches without sequence record"
+        );
+        batches.truncate(recorded);
+    }
+    if batches.iter().all(|batch| batch.num_rows() == 0) {
+        fs::remove_file(spool_path)?;
+        remove_if_exists(&sequences_path(spool_path))?;
+        return Ok(None);
+    }
+
+    let name = final_path
+        .file_name()
+        .and_then(|name| name.to_str())
+        .unwrap_or_default();
+    let tmp_path = final_path.with_file_name(format!(".{}{}", name, IN_PROGRESS));
+    let props = WriterProperties::builder()
+        .set_compression(Compression::SNAPPY)
+        .build();
+    let mut writer = ArrowWriter::try_new(File::create(&tmp_path)?, schema, Some(props))
+        .map_err(|err| arrow_error("Parquet", err))?;
+    for batch in &batches {
+        writer
+            .write(batch)
+            .map_err(|err| arrow_error("Parquet", err))?;
+    }
+    writer
+        .into_inner()
+        .map_err(|err| arrow_error("Parquet", err))?
+        .sync_all()?;
+
+    fs::rename(&tmp_path, final_path)?;
+    fs::remove_file(spool_path)?;
+    remove_if_exists(&sequences_path(spool_path))?;
+    Ok(Some(final_path.to_owned()))
+}
+
+/// Write the Parquet files of the spools under dir, and remove the Parquet files of
+/// interrupted writes. The last spooled sequence of each source is added to spooled. Returns
+/// the number of Parquet files written.
+fn recover_spools(dir: &Path, spooled: &mut BTreeMap<String, u64>) -> Result<u64, Error> {
+    let mut recovered = 0;
+
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            recovered += recover_spools(&path, spooled)?;
+            continue;
+        }
+
+        let name = match path.file_name().and_then(|name| name.to_str()) {
+            Some(name) if name.starts_with('.') => name.to_owned(),
+            _ => continue,
+        };
+        if name.ends_with(IN_PROGRESS) {
+            fs::remove_file(&path)?;
+        } else if name.ends_with(SEQUENCES) {
+            // sequences of a spool written before the crash
+            if !path
+                .with_file_name(&name[..name.len() - SEQUENCES.len()])
+                .exists()
+            {
+                remove_if_exists(&path)?;
+            }
+        } else if name.ends_with(SPOOL) {
+            for (source, sequence) in spooled_sequences(&path)? {
+                let last = spooled.entry(source).or_insert(sequence);
+                *last = sequence.max(*last);
+            }
+            let final_path = path.with_file_name(&name[1..name.len() - SPOOL.len()]);
+            if write_parquet(&path, &final_path)?.is_some() {
+                recovered += 1;
+            }
+        }
+    }
+
+    Ok(recovered)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::consumer::offset_store::FileOffsetStore;
+    use crate::messages::{BeforeAfterCols, Cols, UpdateRows, Value, WriteRows};
+    use arrow::array::StringArray;
+    use arrow::datatypes::DataType;
+    use bigdecimal::BigDecimal;
+    use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+    use std::str::FromStr;
+
+    const TEST_PATH: &str = "test_files";
+    const OUTPUT_DIR: &str = "parquet-sink-test";
+    const OFFSET_FILE: &str = "parquet-sink-test.offset";
+
+    fn message(sequence: u64, columns: &[&str], operation: Operation) -> FluvioMessage {
+        FluvioMessage::test_message("flv://mysql.local/shop/toys", operation)
+            .with_sequence(sequence)
+            .with_position(100 * sequence)
+            .with_columns(columns)
+            .with_keys(&["id"])
+    }
+
+    fn toy(id: i64, name: &str, price: &str) -> Vec<Value> {
+        vec![
+            Value::SignedInteger(id),
+            Value::String(name.to_owned()),
+            Value::Decimal(BigDecimal::from_str(price).unwrap()),
+        ]
+    }
+
+    fn read_parquet(path: &Path) -> Vec<RecordBatch> {
+        ParquetRecordBatchReaderBuilder::try_new(File::open(path).unwrap())
+            .unwrap()
+            .build()
+            .unwrap()
+            .map(|batch| batch.unwrap())
+            .collect()
+    }
+
+    fn parquet_files(dir: &Path) -> Vec<PathBuf> {
+        let mut files: Vec<PathBuf> = fs::read_dir(dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .filter(|path| path.extension().and_then(|ext| ext.to_str()) == Some("parquet"))
+            .collect();
+        files.sort();
+        files
+    }
+
+    #[test]
+    fn test_altered_table() {
+        assert_eq!(
+            altered_table("ALTER TABLE toys ADD born DATE", Some("shop")),
+            Some(("shop".to_owned(), "toys".to_owned()))
+        );
+        assert_eq!(
+            altered_table("alter online table `stock`.`items` drop qty", Some("shop")),
+            Some(("stock".to_owned(), "items".to_owned()))
+        );
+        assert_eq!(altered_table("DROP TABLE toys", Some("shop")), None);
+    }
+
+    #[test]
+    fn test_parquet_sink() {
+        let program_dir = std::env::current_dir().unwrap();
+        let output_dir = program_dir.join(TEST_PATH).join(OUTPUT_DIR);
+        let offset_file = program_dir.join(TEST_PATH).join(OFFSET_FILE);
+        let _ = fs::remove_dir_all(&output_dir);
+        let _ = fs::remove_file(&offset_file);
+
+        let parquet_output = ParquetOutput::default();
+        let mut sink =
+            ParquetSink::open(&output_dir, &parquet_output, &Profile::default()).unwrap();
+        let mut offset_store =
+            FileOffsetStore::init(&offset_file, &offset_file.with_extension("sequence")).unwrap();
+
+        let columns = ["id", "name", "price"];
+        let messages = vec![
+            message(
+                1,
+                &columns,
+                Operation::Add(WriteRows {
+                    rows: vec![
+                        Cols {
+                            cols: toy(1, "car", "10.50"),
+                        },
+                        Cols {
+                            cols: toy(2, "bus", "3"),
+                        },
+                    ],
+                }),
+            ),
+            message(
+                2,
+                &columns,
+                Operation::Update(UpdateRows {
+                    rows: vec![BeforeAfterCols {
+                        before_cols: toy(2, "bus", "3"),
+                        after_cols: toy(2, "train", "3"),
+                    }],
+                }),
+            ),
+            // => v1 file written
+            message(
+                3,
+                &[],
+                Operation::Query("ALTER TABLE toys ADD COLUMN color VARCHAR(10)".to_owned()),
+            ),
+            message(
+                4,
+                &["id", "name", "price", "color"],
+                Operation::Add(WriteRows {
+                    rows: vec![Cols {
+                        cols: vec![
+                            Value::SignedInteger(3),
+                            Value::String("kite".to_owned()),
+                            Value::Null,
+                            Value::String("red".to_owned()),
+                        ],
+                    }],
+                }),
+            ),
+        ];
+        for flv_message in &messages {
+            sink.apply_message(flv_message, &mut offset_store).unwrap();
+        }
+        assert_eq!(offset_store.offset(), 4);
+        assert_eq!(sink.finalized(), 1);
+
+        let table_dir = output_dir.join("shop/toys");
+        let files = parquet_files(&table_dir.join("v1"));
+        assert_eq!(
+            files[0].file_name().unwrap(),
+            "toys-mysql.local-00000000000000000001.parquet"
+        );
+        let batches = read_parquet(&files[0]);
+        assert_eq!(batches[0].num_rows(), 3);
+        let op = batches[0]
+            .column(0)
+            .as_any()
+            .downcast_ref::<StringArray>()
+            .unwrap();
+        assert_eq!(op.value(2), "update");
+        assert_eq!(
+            batches[0].schema().field(5).data_type(),
+            &DataType::Decimal256(65, 30)
+        );
+
+        // restart => spool of v2 written
+        drop(sink);
+        let mut sink =
+            ParquetSink::open(&output_dir, &parquet_output, &Profile::default()).unwrap();
+        let files = parquet_files(&table_dir.join("v2"));
+        assert_eq!(files.len(), 1);
+        let batches = read_parquet(&files[0]);
+        assert_eq!(batches[0].schema().field(6).name(), "color");
+        assert_eq!(batches[0].num_rows(), 1);
+
+        // offset of the spooled message not saved => redelivered message skipped
+        sink.apply_message(&messages[3], &mut offset_store).unwrap();
+        assert_eq!(offset_store.offset(), 5);
+        sink.flush().unwrap();
+        assert_eq!(sink.finalized(), 0);
+        assert_eq!(parquet_files(&table_dir.join("v2")).len(), 1);
+
+        fs::remove_dir_all(&output_dir).expect("delete dir failed");
+        fs::remove_file(&offset_file).expect("delete file failed");
+    }
+
+    #[test]
+    fn test_parquet_sink_sources() {
+        let program_dir = std::env::current_dir().unwrap();
+        let output_dir = program_dir
+            .join(TEST_PATH)
+            .join("parquet-sink-sources-test");
+        let offset_file = program_dir
+            .join(TEST_PATH)
+            .join("parquet-sink-sources-test.offset");
+        let _ = fs::remove_dir_all(&output_dir);
+        let _ = fs::remove_file(&offset_file);
+
+        // one file per message
+        let parquet_output: ParquetOutput = toml::from_str("max_file_rows = 1").unwrap();
+        let mut sink =
+            ParquetSink::open(&output_dir, &parquet_output, &Profile::default()).unwrap();
+        let mut offset_store =
+            FileOffsetStore::init(&offset_file, &offset_file.with_extension("sequence")).unwrap();
+
+        // same table and sequence from two sources
+        for (uri, name) in &[
+            ("flv://mysql1.local/shop/toys", "car"),
+            ("flv://mysql2.local/shop/toys", "bus"),
+        ] {
+            let operation = Operation::Add(WriteRows {
+                rows: vec![Cols {
+                    cols: toy(1, name, "2"),
+                }],
+            });
+            let flv_message = FluvioMessage::test_message(uri, operation)
+                .with_sequence(1)
+                .with_position(100)
+                .with_columns(&["id", "name", "price"])
+                .with_keys(&["id"]);
+            sink.apply_message(&flv_message, &mut offset_store).unwrap();
+        }
+        assert_eq!(sink.finalized(), 2);
+
+        let files = parquet_files(&output_dir.join("shop/toys/v1"));
+        let names: Vec<_> = files.iter().map(|file| file.file_name().unwrap()).collect();
+        assert_eq!(
+            names,
+            [
+                "toys-mysql1.local-00000000000000000001.parquet",
+                "toys-mysql2.local-00000000000000000001.parquet"
+            ]
+        );
+        assert_eq!(read_parquet(&files[1])[0].num_rows(), 1);
+
+        fs::remove_dir_all(&output_dir).expect("delete dir failed");
+        fs::remove_file(&offset_file).expect("delete file failed");
+    }
+}
@@ -0,0 +1,292 @@
+//! Postgres DDL
+//!
+//! Translates mysql DDL statements (Query operation) into Postgres statements, with the shared
+//! DDL translator (see ddl): column types are mapped to Postgres types (see pg_type).
+//!
+//! Mysql databases are Postgres schemas: CREATE/DROP DATABASE create and drop the schema
+//! (with its tables), ALTER DATABASE is ignored. Tables are qualified with their schema.
+//!
+use sqlparser::ast::DataType;
+use std::io::Error;
+
+use crate::consumer::ddl::{self, unsupported, DatabaseStatement, DdlDialect};
+use crate::consumer::router::Router;
+
+pub use crate::consumer::ddl::quote_identifier;
+
+const TARGET: &str = "postgres";
+
+/// DDL syntax of Postgres
+pub struct PostgresDdl;
+
+impl DdlDialect for PostgresDdl {
+    fn name(&self) -> &'static str {
+        TARGET
+    }
+
+    fn column_type(&self, data_type: &DataType) -> Result<String, Error> {
+        pg_type(data_type)
+    }
+
+    fn qualified_name(&self, db_name: Option<&str>, name: &str) -> String {
+        qualified_table(db_name, name)
+    }
+
+    /// CREATE SCHEMA, DROP SCHEMA ... CASCADE (tables are dropped), ALTER is ignored
+    fn database_statements(&self, statement: DatabaseStatement, db_name: &str) -> Vec<String> {
+        let schema = quote_identifier(db_name);
+        match statement {
+            DatabaseStatement::Create { if_not_exists } => vec![format!(
+                "CREATE SCHEMA {}{}",
+                if if_not_exists { "IF NOT EXISTS " } else { "" },
+                schema
+            )],
+            DatabaseStatement::Drop { if_exists } => vec![format!(
+                "DROP SCHEMA {}{} CASCADE",
+                if if_exists { "IF EXISTS " } else { "" },
+                schema
+            )],
+            DatabaseStatement::Alter => vec![],
+        }
+    }
+}
+
+/// Translate mysql DDL statement into Postgres statements.
+/// Unqualified tables are in the schema of `db_name`, the source database of the message.
+pub fn translate_ddl(
+    router: &Router,
+    db_name: Option<&str>,
+    query: &str,
+) -> Result<Vec<String>, Error> {
+    ddl::translate_ddl(&PostgresDdl, router, db_name, query)
+}
+
+/// Qualified Postgres table: "schema"."table", or "table" without schema
+pub fn qualified_table(schema: Option<&str>, table: &str) -> String {
+    match schema {
+        Some(schema) => format!("{}.{}", quote_identifier(schema), quote_identifier(table)),
+        None => quote_identifier(table),
+    }
+}
+
+/// Map mysql column type to Postgres type, by the type name (sqlparser keeps unknown
+/// type names as written). Unsigned and display widths are not kept.
+fn pg_type(data_type: &DataType) -> Result<String, Error> {
+    let mysql_type = data_type.to_string();
+    let (name, args) = match mysql_type.find('(') {
+        Some(pos) => (&mysql_type[..pos], &mysql_type[pos..]),
+        None => (mysql_type.as_str(), ""),
+    };
+
+    let pg_type = match name.trim().to_uppercase().as_str() {
+        "TINYINT" | "SMALLINT" | "BOOL" | "BOOLEAN" | "YEAR" | "ENUM" => "smallint",
+        "MEDIUMINT" | "INT" | "INTEGER" => "integer",
+        "BIGINT" | "SET" => "bigint",
+        "FLOAT" | "REAL" => "real",
+        "DOUBLE" | "DOUBLE PRECISION" => "double precision",
+        "DECIMAL" | "DEC" | "NUMERIC" | "FIXED" => return Ok(format!("numeric{}", args)),
+        "CHAR" | "CHARACTER" => return Ok(format!("char{}", args)),
+        "VARCHAR" | "CHARACTER VARYING" => return Ok(format!("varchar{}", args)),
+        "TINYTEXT" | "TEXT" | "MEDIUMTEXT" | "LONGTEXT" | "CLOB" => "text",
+        "BINARY" | "VARBINARY" | "TINYBLOB" | "BLOB" | "MEDIUMBLOB" | "LONGBLOB" | "BYTEA" => {
+            "bytea"
+        }
+        "DATE" => "date",
+        "DATETIME" => "timestamp",
+        "TIMESTAMP" => "timestamptz",
+        "TIME" => "time",
+        "JSON" => "jsonb",
+        _ => return Err(unsupported(TARGET, &format!("column type {}", mysql_type))),
+    };
+    Ok(pg_type.to_owned())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::consumer::profile::Routing;
+    use std::io::ErrorKind;
+
+    fn translate(query: &str) -> Vec<String> {
+        translate_ddl(&Router::default(), Some("shop"), query).unwrap()
+    }
+
+    #[test]
+    fn test_translate_create_table() {
+        let query = "CREATE TABLE pets (id BIGINT NOT NULL, name VARCHAR(20) DEFAULT 'none', \
+            price DECIMAL(10,2), born DATETIME, seen TIMESTAMP, photo LONGBLOB, data JSON, \
+            PRIMARY KEY (id))";
+        let expected = vec![
+            "CREATE TABLE \"shop\".\"pets\" (\"id\" bigint NOT NULL, \"name\" varchar(20) \
+            DEFAULT ('none'), \"price\" numeric(10,2), \"born\" timestamp, \"seen\" timestamptz, \
+            \"photo\" bytea, \"data\" jsonb, PRIMARY KEY (\"id\"))"
+                .to_owned(),
+        ];
+        assert_eq!(translate(query), expected);
+
+        let query = "CREATE TABLE IF NOT EXISTS shop.owners (id INT PRIMARY KEY, \
+            pet_id BIGINT REFERENCES pets (id))";
+        let expected = vec![
+            "CREATE TABLE IF NOT EXISTS \"shop\".\"owners\" (\"id\" integer PRIMARY KEY, \
+            \"pet_id\" bigint REFERENCES \"shop\".\"pets\" (\"id\"))"
+                .to_owned(),
+        ];
+        assert_eq!(translate(query), expected);
+
+        // backtick quoted names
+        let query = "CREATE TABLE `shop`.`orders` (`id` INT NOT NULL, `total` DECIMAL(10,2), \
+            PRIMARY KEY (`id`))";
+        let expected = vec![
+            "CREATE TABLE \"shop\".\"orders\" (\"id\" integer NOT NULL, \
+            \"total\" numeric(10,2), PRIMARY KEY (\"id\"))"
+                .to_owned(),
+        ];
+        assert_eq!(translate(query), expected);
+
+        // no postgres type
+        let err = translate_ddl(
+            &Router::default(),
+            Some("shop"),
+            "CREATE TABLE maps (area GEOMETRY)",
+        )
+        .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+        assert!(err.to_string().contains("column type GEOMETRY"));
+    }
+
+    #[test]
+    fn test_translate_alter_table() {
+        assert_eq!(
+            translate("ALTER TABLE pets ADD COLUMN owner VARCHAR(20) NOT NULL"),
+            vec!["ALTER TABLE \"shop\".\"pets\" ADD COLUMN \"owner\" varchar(20) NOT NULL"]
+        );
+        assert_eq!(
+            translate("ALTER TABLE pets RENAME COLUMN owner TO keeper"),
+            vec!["ALTER TABLE \"shop\".\"pets\" RENAME COLUMN \"owner\" TO \"keeper\""]
+        );
+        assert_eq!(
+            translate("ALTER TABLE pets DROP COLUMN keeper"),
+            vec!["ALTER TABLE \"shop\".\"pets\" DROP COLUMN \"keeper\""]
+        );
+        assert_eq!(
+            translate("ALTER TABLE pets ADD PRIMARY KEY (id)"),
+            vec!["ALTER TABLE \"shop\".\"pets\" ADD PRIMARY KEY (\"id\")"]
+        );
+        assert_eq!(
+            translate("ALTER TABLE `shop`.`orders` ADD COLUMN `note` TEXT"),
+            vec!["ALTER TABLE \"shop\".\"orders\" ADD COLUMN \"note\" text"]
+        );
+        assert_eq!(
+            translate("ALTER TABLE `orders` RENAME COLUMN `note` TO `comment`"),
+            vec!["ALTER TABLE \"shop\".\"orders\" RENAME COLUMN \"note\" TO \"comment\""]
+        );
+    }
+
+    #[test]
+    fn test_translate_statements() {
+        let routing: Routing =
+            toml::from_str("table_prefix = \"src1_\"\n[databases]\nprod_shop = \"analytics\"")
+                .unwrap();
+        let router = Router::new(&routing);
+
+        assert_eq!(
+            translate_ddl(&router, Some("prod_shop"), "DROP TABLE pets, owners").unwrap(),
+            vec![
+                "DROP TABLE \"analytics\".\"src1_pets\"",
+                "DROP TABLE \"analytics\".\"src1_owners\""
+            ]
+        );
+        assert_eq!(
+            translate_ddl(&router, None, "CREATE DATABASE prod_shop").unwrap(),
+            vec!["CREATE SCHEMA \"analytics\""]
+        );
+        assert_eq!(
+            translate_ddl(&router, None, "DROP DATABASE IF EXISTS prod_shop").unwrap(),
+            vec!["DROP SCHEMA IF EXISTS \"analytics\" CASCADE"]
+        );
+        assert!(translate_ddl(
+            &router,
+            None,
+            "ALTER DATABASE prod_shop CHARACTER SET utf8mb4"
+        )
+        .unwrap()
+        .is_empty());
+
+        // statements without a table are ignored
+        for query in &[
+            "GRANT SELECT ON prod_shop.* TO 'bob'@'%'",
+            "CREATE USER 'bob'@'%' IDENTIFIED BY 'secret'",
+            "CREATE VIEW v AS SELECT 1",
+        ] {
+            assert!(translate_ddl(&router, Some("prod_shop"), query)
+                .unwrap()
+                .is_empty());
+        }
+
+        // table statement sqlparser cannot read
+        let err =
+            translate_ddl(&router, Some("prod_shop"), "CREATE TABLE v AS SELECT 1").unwrap_err();
+        assert!(err.to_string().starts_with("cannot translate to postgres"));
+    }
+
+    #[test]
+    fn test_translate_index_statements() {
+        let routing: Routing =
+            toml::from_str("table_prefix = \"src1_\"\n[databases]\nprod_shop = \"analytics\"")
+                .unwrap();
+        let router = Router::new(&routing);
+        let translate = |query| translate_ddl(&router, Some("prod_shop"), query).unwrap();
+
+        assert_eq!(
+            translate("CREATE INDEX idx_name ON pets (name(10), born DESC)"),
+            vec![
+                "CREATE INDEX \"src1_pets_idx_name\" ON \"analytics\".\"src1_pets\" \
+                (\"name\", \"born\")"
+            ]
+        );
+        assert_eq!(
+            translate("CREATE UNIQUE INDEX `idx_tag` USING BTREE ON `prod_shop`.`pets` (`tag`)"),
+            vec![
+                "CREATE UNIQUE INDEX \"src1_pets_idx_tag\" ON \"analytics\".\"src1_pets\" \
+                (\"tag\")"
+            ]
+        );
+        assert_eq!(
+            translate("DROP INDEX idx_name ON pets"),
+            vec!["DROP INDEX \"analytics\".\"src1_pets_idx_name\""]
+        );
+        assert_eq!(
+            translate("TRUNCATE TABLE pets"),
+            vec!["TRUNCATE TABLE \"analytics\".\"src1_pets\""]
+        );
+        assert_eq!(
+            translate("TRUNCATE owners"),
+            vec!["TRUNCATE TABLE \"analytics\".\"src1_owners\""]
+        );
+        assert_eq!(
+            translate("RENAME TABLE pets TO animals, prod_shop.owners TO keepers"),
+            vec![
+                "ALTER TABLE \"analytics\".\"src1_pets\" RENAME TO \"src1_animals\"",
+                "ALTER TABLE \"analytics\".\"src1_owners\" RENAME TO \"src1_keepers\""
+            ]
+        );
+
+        // full-text indexes are ignored
+        assert!(translate("CREATE FULLTEXT INDEX idx_note ON pets (note)").is_empty());
+
+        let err = translate_ddl(
+            &router,
+            Some("prod_shop"),
+            "RENAME TABLE pets TO other.pets",
+        )
+        .unwrap_err();
+        assert!(err.to_string().contains("RENAME TABLE to another database"));
+        let err = translate_ddl(
+            &router,
+            Some("prod_shop"),
+            "CREATE INDEX idx ON pets ((1 + 1))",
+        )
+        .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+    }
+}
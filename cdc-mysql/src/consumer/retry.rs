@@ -0,0 +1,225 @@
+//! Retry Policy
+//!
+//! Errors applying a message are classified by their kind:
+//!  - temporary: lock wait timeout, deadlock, lost connection - retried with exponential backoff
+//!  - permanent: bad SQL, invalid message - not retried
+//!
+//! Mysql, sqlite and postgres client errors are mapped to io errors by `mysql_error`,
+//! `sqlite_error` and `postgres_error`, which keep this distinction.
+//!
+use async_std::task;
+use postgres::error::SqlState;
+use serde::Serialize;
+use std::io::{Error, ErrorKind};
+use tracing::{error, warn};
+
+use crate::consumer::profile::Retry;
+
+// mysql server error codes
+const ER_CON_COUNT_ERROR: u16 = 1040;
+const ER_SERVER_SHUTDOWN: u16 = 1053;
+const ER_LOCK_WAIT_TIMEOUT: u16 = 1205;
+const ER_LOCK_DEADLOCK: u16 = 1213;
+const ER_QUERY_INTERRUPTED: u16 = 1317;
+const ER_QUERY_TIMEOUT: u16 = 3024;
+
+#[derive(Debug, PartialEq, Clone, Copy, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ErrorClass {
+    Temporary,
+    Permanent,
+}
+
+/// Error of the last attempt
+#[derive(Debug)]
+pub struct Failure {
+    pub error: Error,
+    pub class: ErrorClass,
+    pub attempts: u32,
+}
+
+impl Failure {
+    pub fn new(error: Error, attempts: u32) -> Self {
+        Self {
+            class: classify(&error),
+            error,
+            attempts,
+        }
+    }
+}
+
+/// Classify error by kind
+pub fn classify(err: &Error) -> ErrorClass {
+    match err.kind() {
+        ErrorKind::TimedOut
+        | ErrorKind::Interrupted
+        | ErrorKind::WouldBlock
+        | ErrorKind::ConnectionRefused
+        | ErrorKind::ConnectionReset
+        | ErrorKind::ConnectionAborted
+        | ErrorKind::NotConnected
+        | ErrorKind::BrokenPipe
+        | ErrorKind::UnexpectedEof => ErrorClass::Temporary,
+        _ => ErrorClass::Permanent,
+    }
+}
+
+/// Connection to the server lost or refused, the connection must be reopened
+pub fn is_connection_error(err: &Error) -> bool {
+    matches!(
+        err.kind(),
+        ErrorKind::ConnectionRefused
+            | ErrorKind::ConnectionReset
+            | ErrorKind::ConnectionAborted
+            | ErrorKind::NotConnected
+            | ErrorKind::BrokenPipe
+            | ErrorKind::UnexpectedEof
+    )
+}
+
+/// Map mysql client error to io error, temporary errors get a temporary kind (see classify)
+pub fn mysql_error(context: &str, err: mysql::Error) -> Error {
+    let kind = match &err {
+        mysql::Error::IoError(io_err) => io_err.kind(),
+        mysql::Error::DriverError(mysql::DriverError::CouldNotConnect(_)) => {
+            ErrorKind::ConnectionRefused
+        }
+        mysql::Error::MySqlError(server_err) => match server_err.code {
+            ER_LOCK_WAIT_TIMEOUT | ER_QUERY_TIMEOUT => ErrorKind::TimedOut,
+            ER_LOCK_DEADLOCK | ER_QUERY_INTERRUPTED => ErrorKind::Interrupted,
+            ER_CON_COUNT_ERROR => ErrorKind::ConnectionRefused,
+            ER_SERVER_SHUTDOWN => ErrorKind::ConnectionAborted,
+            _ => ErrorKind::InvalidData,
+        },
+        _ => ErrorKind::InvalidData,
+    };
+    Error::new(kind, format!("{}: {}", context, err))
+}
+
+/// Map sqlite error to io error, a busy or locked database is temporary (see classify)
+pub fn sqlite_error(context: &str, err: rusqlite::Error) -> Error {
+    let kind = match &err {
+        rusqlite::Error::SqliteFailure(failure, _) => match failure.code {
+            rusqlite::ErrorCode::DatabaseBusy | rusqlite::ErrorCode::DatabaseLocked => {
+                ErrorKind::TimedOut
+            }
+            _ => ErrorKind::InvalidData,
+        },
+        _ => ErrorKind::InvalidData,
+    };
+    Error::new(kind, format!("{}: {}", context, err))
+}
+
+/// Map postgres client error to io error, temporary errors get a temporary kind (see classify):
+///  - serialization failure, deadlock, lock not available, canceled query
+///  - server shutdown or too many connections, closed connection, network errors
+pub fn postgres_error(context: &str, err: postgres::Error) -> Error {
+    let kind = match err.code() {
+        Some(code)
+            if *code == SqlState::T_R_SERIALIZATION_FAILURE
+                || *code == SqlState::LOCK_NOT_AVAILABLE
+                || *code == SqlState::QUERY_CANCELED =>
+        {
+            ErrorKind::TimedOut
+        }
+        Some(code) if *code == SqlState::T_R_DEADLOCK_DETECTED => ErrorKind::Interrupted,
+        Some(code)
+            if *code == SqlState::TOO_MANY_CONNECTIONS || *code == SqlState::CANNOT_CONNECT_NOW =>
+        {
+            ErrorKind::ConnectionRefused
+        }
+        Some(code) if *code == SqlState::ADMIN_SHUTDOWN || *code == SqlState::CRASH_SHUTDOWN => {
+            ErrorKind::ConnectionAborted
+        }
+        Some(_) => ErrorKind::InvalidData,
+        None if err.is_closed() => ErrorKind::ConnectionAborted,
+        None => std::error::Error::source(&err)
+            .and_then(|source| source.downcast_ref::<Error>())
+            .map_or(ErrorKind::InvalidData, |io_err| io_err.kind()),
+    };
+    Error::new(kind, format!("{}: {}", context, err))
+}
+
+/// Run operation until it succeeds, fails with a permanent error, or retries are exhausted
+pub async fn with_retry<T, F>(retry: &Retry, mut op: F) -> Result<T, Failure>
+where
+    F: FnMut() -> Result<T, Error>,
+{
+    let mut attempts = 0;
+    loop {
+        attempts += 1;
+        let err = match op() {
+            Ok(result) => return Ok(result),
+            Err(err) => err,
+        };
+
+        let retries = attempts - 1;
+        if classify(&err) == ErrorClass::Permanent || retries >= retry.max_retries() {
+            let failure = Failure::new(err, attempts);
+            error!(error = %failure.error, class = ?failure.class, attempts, "Message failed");
+            return Err(failure);
+        }
+
+        let backoff = retry.backoff(retries);
+        warn!(error = %err, attempts, ?backoff, "Temporary error, retrying");
+        task::sleep(backoff).await;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_classify() {
+        let err = Error::new(ErrorKind::TimedOut, "Lock wait timeout exceeded");
+        assert_eq!(classify(&err), ErrorClass::Temporary);
+
+        let err = Error::new(
+            ErrorKind::InvalidData,
+            "You have an error in your SQL syntax",
+        );
+        assert_eq!(classify(&err), ErrorClass::Permanent);
+
+        let err = mysql_error("Query", mysql::Error::IoError(ErrorKind::BrokenPipe.into()));
+        assert_eq!(classify(&err), ErrorClass::Temporary);
+        assert!(is_connection_error(&err));
+
+        let err = Error::new(ErrorKind::Interrupted, "Deadlock found");
+        assert!(!is_connection_error(&err));
+
+        let busy = rusqlite::ffi::Error::new(rusqlite::ffi::SQLITE_BUSY);
+        let err = sqlite_error("Query", rusqlite::Error::SqliteFailure(busy, None));
+        assert_eq!(classify(&err), ErrorClass::Temporary);
+        let err = sqlite_error("Query", rusqlite::Error::InvalidQuery);
+        assert_eq!(classify(&err), ErrorClass::Permanent);
+    }
+
+    #[test]
+    fn test_with_retry() {
+        let retry = Retry::default();
+
+        async_std::task::block_on(async {
+            // temporary error => retried
+            let mut calls = 0;
+            let result = with_retry(&retry, || {
+                calls += 1;
+                match calls {
+                    1 => Err(Error::new(ErrorKind::Interrupted, "Deadlock found")),
+                    _ => Ok(calls),
+                }
+            })
+            .await;
+            assert_eq!(result.unwrap(), 2);
+
+            // permanent error => not retried
+            let result: Result<(), Failure> = with_retry(&retry, || {
+                Err(Error::new(ErrorKind::InvalidData, "Unknown column"))
+            })
+            .await;
+            let failure = result.unwrap_err();
+            assert_eq!(failure.class, ErrorClass::Permanent);
+            assert_eq!(failure.attempts, 1);
+        });
+    }
+}
@@ -0,0 +1,478 @@
+//! Router
+//!
+//! Routing rules map source database and table names (from the message uri) to the names
+//! in the target database:
+//!  - databases: source database => target database
+//!  - tables: "source_db.table" => target table
+//!  - table_prefix: prepended to tables without a table rule
+//!
+//! DDL statements (Query operation) are rewritten with the same rules. The statement is
+//! split into tokens (identifiers, strings, comments) and only the database and table names
+//! at their positions in the grammar are replaced, the rest of the statement is kept as is:
+//!     CREATE/ALTER/DROP DATABASE, CREATE/ALTER/DROP/TRUNCATE/RENAME TABLE,
+//!     CREATE/DROP INDEX ... ON, LIKE and REFERENCES tables
+//!
+use std::collections::BTreeMap;
+use std::ops::Range;
+
+use crate::consumer::mysql_manager::quote_identifier;
+use crate::consumer::profile::Routing;
+
+#[derive(Debug, Default)]
+pub struct Router {
+    databases: BTreeMap<String, String>,
+    tables: BTreeMap<String, String>,
+    table_prefix: Option<String>,
+}
+
+#[derive(Debug, PartialEq)]
+pub(crate) enum Token {
+    Word { value: String, quoted: bool },
+    Str,
+    Punct(char),
+}
+
+#[derive(Debug, PartialEq)]
+enum NameRef {
+    Database(String),
+    Table(Option<String>, String),
+}
+
+impl Router {
+    pub fn new(routing: &Routing) -> Self {
+        Self {
+            databases: routing.databases(),
+            tables: routing.tables(),
+            table_prefix: routing.table_prefix(),
+        }
+    }
+
+    /// No routing rules, names are kept
+    pub fn is_empty(&self) -> bool {
+        self.databases.is_empty() && self.tables.is_empty() && self.table_prefix.is_none()
+    }
+
+    /// Target database of source database
+    pub fn database(&self, db_name: &str) -> String {
+        self.databases
+            .get(db_name)
+            .cloned()
+            .unwrap_or_else(|| db_name.to_owned())
+    }
+
+    /// Target table of source table
+    pub fn table(&self, db_name: &str, table_name: &str) -> String {
+        if let Some(target) = self.tables.get(&format!("{}.{}", db_name, table_name)) {
+            return target.clone();
+        }
+        match &self.table_prefix {
+            Some(prefix) => format!("{}{}", prefix, table_name),
+            None => table_name.to_owned(),
+        }
+    }
+
+    /// Rewrite database and table names of a DDL statement.
+    /// Unqualified tables belong to db_name, the source database of the message.
+    pub fn rewrite_query(&self, db_name: Option<&str>, query: &str) -> String {
+        if self.is_empty() {
+            return query.to_owned();
+        }
+
+        let tokens = tokenize(query);
+        let mut rewritten = String::with_capacity(query.len());
+        let mut last = 0;
+        for (name_ref, span) in statement_names(&tokens) {
+            let name = match name_ref {
+                NameRef::Database(db) => Some(quote_identifier(&self.database(&db))),
+                NameRef::Table(Some(db), table) => Some(format!(
+                    "{}.{}",
+                    quote_identifier(&self.database(&db)),
+                    quote_identifier(&self.table(&db, &table))
+                )),
+                NameRef::Table(None, table) => {
+                    db_name.map(|db| quote_identifier(&self.table(db, &table)))
+                }
+            };
+            if let Some(name) = name {
+                rewritten.push_str(&query[last..span.start]);
+                rewritten.push_str(&name);
+                last = span.end;
+            }
+        }
+        rewritten.push_str(&query[last..]);
+        rewritten
+    }
+}
+
+/// Split statement into tokens with their byte range, whitespace and comments are skipped
+pub(crate) fn tokenize(query: &str) -> Vec<(Token, Range<usize>)> {
+    let chars: Vec<(usize, char)> = query.char_indices().collect();
+    let offset = |idx: usize| chars.get(idx).map_or(query.len(), |(pos, _)| *pos);
+    let mut tokens = vec![];
+
+    let mut idx = 0;
+    while idx < chars.len() {
+        let start = idx;
+        let ch = chars[idx].1;
+        let next = chars.get(idx + 1).map(|(_, ch)| *ch);
+
+        if ch.is_whitespace() {
+            idx += 1;
+        } else if ch == '#' || (ch == '-' && next == Some('-')) {
+            while idx < chars.len() && chars[idx].1 != '\n' {
+                idx += 1;
+            }
+        } else if ch == '/' && next == Some('*') {
+            idx += 2;
+            while idx < chars.len()
+                && !(chars[idx].1 == '*' && chars.get(idx + 1).map(|c| c.1) == Some('/'))
+            {
+                idx += 1;
+            }
+            idx = (idx + 2).min(chars.len());
+        } else if ch == '`' {
+            let mut value = String::new();
+            idx += 1;
+            while idx < chars.len() {
+                if chars[idx].1 == '`' {
+                    if chars.get(idx + 1).map(|c| c.1) == Some('`') {
+                        value.push('`');
+                        idx += 2;
+                        continue;
+                    }
+                    idx += 1;
+                    break;
+                }
+                value.push(chars[idx].1);
+                idx += 1;
+            }
+            tokens.push((
+                Token::Word {
+                    value,
+                    quoted: true,
+                },
+                offset(start)..offset(idx),
+            ));
+        } else if ch == '\'' || ch == '"' {
+            idx += 1;
+            while idx < chars.len() {
+                if chars[idx].1 == '\\' {
+                    idx += 2;
+                } else if chars[idx].1 == ch {
+                    idx += 1;
+                    if chars.get(idx).map(|c| c.1) != Some(ch) {
+                        break;
+                    }
+                    idx += 1;
+                } else {
+                    idx += 1;
+                }
+            }
+            idx = idx.min(chars.len());
+            tokens.push((Token::Str, offset(start)..offset(idx)));
+        } else if is_word_char(ch) {
+            while idx < chars.len() && is_word_char(chars[idx].1) {
+                idx += 1;
+            }
+            let value = query[offset(start)..offset(idx)].to_owned();
+            tokens.push((
+                Token::Word {
+                    value,
+                    quoted: false,
+                },
+                offset(start)..offset(idx),
+            ));
+        } else {
+            idx += 1;
+            tokens.push((Token::Punct(ch), offset(start)..offset(idx)));
+        }
+    }
+
+    tokens
+}
+
+fn is_word_char(ch: char) -> bool {
+    ch.is_alphanumeric() || ch == '_' || ch == '$'
+}
+
+/// Database and table names of the statement, in order
+fn statement_names(tokens: &[(Token, Range<usize>)]) -> Vec<(NameRef, Range<usize>)> {
+    let mut parser = NameParser { tokens, idx: 0 };
+    let mut names = vec![];
+
+    if parser.keyword("CREATE") {
+        parser.keyword("TEMPORARY");
+        if parser.keyword("DATABASE") || parser.keyword("SCHEMA") {
+            parser.keywords(&["IF", "NOT", "EXISTS"]);
+            names.extend(parser.database_name());
+        } else if parser.keyword("TABLE") {
+            parser.keywords(&["IF", "NOT", "EXISTS"]);
+            names.extend(parser.table_name());
+            if parser.keyword("LIKE") || (parser.punct('(') && parser.keyword("LIKE")) {
+                names.extend(parser.table_name());
+            }
+            names.extend(parser.referenced_tables(false));
+        } else {
+            for kind in &["UNIQUE", "FULLTEXT", "SPATIAL"] {
+                parser.keyword(kind);
+            }
+            if parser.keyword("INDEX") && parser.skip_to_keyword("ON") {
+                names.extend(parser.table_name());
+            }
+        }
+    } else if parser.keyword("ALTER") {
+        if parser.keyword("DATABASE") || parser.keyword("SCHEMA") {
+            if !parser.next_is_any(&[
+                "DEFAULT",
+                "CHARACTER",
+                "CHARSET",
+                "COLLATE",
+                "ENCRYPTION",
+                "READ",
+            ]) {
+                names.extend(parser.database_name());
+            }
+        } else if parser.keyword("TABLE") {
+            names.extend(parser.table_name());
+            names.extend(parser.referenced_tables(true));
+        }
+    } else if parser.keyword("DROP") {
+        parser.keyword("TEMPORARY");
+        if parser.keyword("DATABASE") || parser.keyword("SCHEMA") {
+            parser.keywords(&["IF", "EXISTS"]);
+            names.extend(parser.database_name());
+        } else if parser.keyword("TABLE") || parser.keyword("TABLES") {
+            parser.keywords(&["IF", "EXISTS"]);
+            names.extend(parser.table_names());
+        } else if parser.keyword("INDEX") && parser.skip_to_keyword("ON") {
+            names.extend(parser.table_name());
+        }
+    } else if parser.keyword("TRUNCATE") {
+        parser.keyword("TABLE");
+        names.extend(parser.table_name());
+    } else if parser.keyword("RENAME") && (parser.keyword("TABLE") || parser.keyword("TABLES")) {
+        loop {
+            names.extend(parser.table_name());
+            if !parser.keyword("TO") {
+                break;
+            }
+            names.extend(parser.table_name());
+            if !parser.punct(',') {
+                break;
+            }
+        }
+    }
+
+    names
+}
+
+struct NameParser<'a> {
+    tokens: &'a [(Token, Range<usize>)],
+    idx: usize,
+}
+
+impl<'a> NameParser<'a> {
+    fn is_keyword(&self, idx: usize, keyword: &str) -> bool {
+        match self.tokens.get(idx) {
+            Some((
+                Token::Word {
+                    value,
+                    quoted: false,
+                },
+                _,
+            )) => value.eq_ignore_ascii_case(keyword),
+            _ => false,
+        }
+    }
+
+    fn next_is_any(&self, keywords: &[&str]) -> bool {
+        keywords
+            .iter()
+            .any(|keyword| self.is_keyword(self.idx, keyword))
+    }
+
+    /// Consume keyword if next
+    fn keyword(&mut self, keyword: &str) -> bool {
+        let found = self.is_keyword(self.idx, keyword);
+        if found {
+            self.idx += 1;
+        }
+        found
+    }
+
+    /// Consume all keywords, in sequence, if next
+    fn keywords(&mut self, keywords: &[&str]) -> bool {
+        let found = keywords
+            .iter()
+            .enumerate()
+            .all(|(pos, keyword)| self.is_keyword(self.idx + pos, keyword));
+        if found {
+            self.idx += keywords.len();
+        }
+        found
+    }
+
+    fn punct(&mut self, punct: char) -> bool {
+        let found =
+            matches!(self.tokens.get(self.idx), Some((Token::Punct(ch), _)) if *ch == punct);
+        if found {
+            self.idx += 1;
+        }
+        found
+    }
+
+    fn skip_to_keyword(&mut self, keyword: &str) -> bool {
+        while self.idx < self.tokens.len() {
+            if self.keyword(keyword) {
+                return true;
+            }
+            self.idx += 1;
+        }
+        false
+    }
+
+    fn word(&mut self) -> Option<(String, Range<usize>)> {
+        match self.tokens.get(self.idx) {
+            Some((Token::Word { value, .. }, span)) => {
+                self.idx += 1;
+                Some((value.clone(), span.clone()))
+            }
+            _ => None,
+        }
+    }
+
+    fn database_name(&mut self) -> Option<(NameRef, Range<usize>)> {
+        self.word()
+            .map(|(name, span)| (NameRef::Database(name), span))
+    }
+
+    /// Table name: "table" or "db.table"
+    fn table_name(&mut self) -> Option<(NameRef, Range<usize>)> {
+        let (name, span) = self.word()?;
+        if self.punct('.') {
+            let (table, table_span) = self.word()?;
+            return Some((
+                NameRef::Table(Some(name), table),
+                span.start..table_span.end,
+            ));
+        }
+        Some((NameRef::Table(None, name), span))
+    }
+
+    fn table_names(&mut self) -> Vec<(NameRef, Range<usize>)> {
+        let mut names = vec![];
+        while let Some(name) = self.table_name() {
+            names.push(name);
+            if !self.punct(',') {
+                break;
+            }
+        }
+        names
+    }
+
+    /// Tables in the rest of the statement: REFERENCES, and RENAME [TO|AS] if renames
+    fn referenced_tables(&mut self, renames: bool) -> Vec<(NameRef, Range<usize>)> {
+        let mut names = vec![];
+        while self.idx < self.tokens.len() {
+            if self.keyword("REFERENCES") {
+                names.extend(self.table_name());
+            } else if renames && self.keyword("RENAME") {
+                if self.next_is_any(&["COLUMN", "INDEX", "KEY"]) {
+                    continue;
+                }
+                if !self.keyword("TO") {
+                    self.keyword("AS");
+                }
+                names.extend(self.table_name());
+            } else {
+                self.idx += 1;
+            }
+        }
+        names
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn build_router() -> Router {
+        let mut router = Router::default();
+        router
+            .databases
+            .insert("prod_shop".to_owned(), "analytics_shop_copy".to_owned());
+        router
+            .tables
+            .insert("prod_shop.orders".to_owned(), "orders_v2".to_owned());
+        router.table_prefix = Some("src1_".to_owned());
+        router
+    }
+
+    #[test]
+    fn test_route_names() {
+        let router = build_router();
+        assert_eq!(router.database("prod_shop"), "analytics_shop_copy");
+        assert_eq!(router.database("other"), "other");
+        assert_eq!(router.table("prod_shop", "orders"), "orders_v2");
+        assert_eq!(router.table("prod_shop", "items"), "src1_items");
+
+        let router = Router::default();
+        assert!(router.is_empty());
+        assert_eq!(router.table("prod_shop", "items"), "items");
+    }
+
+    #[test]
+    fn test_rewrite_query() {
+        let router = build_router();
+        let db = Some("prod_shop");
+        let queries = vec![
+            (
+                "CREATE DATABASE IF NOT EXISTS prod_shop",
+                "CREATE DATABASE IF NOT EXISTS `analytics_shop_copy`",
+            ),
+            ("drop schema `prod_shop`", "drop schema `analytics_shop_copy`"),
+            (
+                "CREATE TABLE items (id INT, note VARCHAR(20) DEFAULT 'items') ENGINE=InnoDB",
+                "CREATE TABLE `src1_items` (id INT, note VARCHAR(20) DEFAULT 'items') ENGINE=InnoDB",
+            ),
+            (
+                "CREATE TABLE `prod_shop`.`orders` (id INT, item INT, FOREIGN KEY (item) REFERENCES items(id))",
+                "CREATE TABLE `analytics_shop_copy`.`orders_v2` (id INT, item INT, FOREIGN KEY (item) REFERENCES `src1_items`(id))",
+            ),
+            (
+                "ALTER TABLE orders ADD COLUMN `references` INT, RENAME COLUMN a TO b",
+                "ALTER TABLE `orders_v2` ADD COLUMN `references` INT, RENAME COLUMN a TO b",
+            ),
+            (
+                "ALTER TABLE items RENAME TO archive",
+                "ALTER TABLE `src1_items` RENAME TO `src1_archive`",
+            ),
+            (
+                "DROP TABLE IF EXISTS items, prod_shop.orders /* done */",
+                "DROP TABLE IF EXISTS `src1_items`, `analytics_shop_copy`.`orders_v2` /* done */",
+            ),
+            (
+                "RENAME TABLE items TO items_old, orders TO orders_old",
+                "RENAME TABLE `src1_items` TO `src1_items_old`, `orders_v2` TO `src1_orders_old`",
+            ),
+            (
+                "CREATE UNIQUE INDEX idx_name ON items (name)",
+                "CREATE UNIQUE INDEX idx_name ON `src1_items` (name)",
+            ),
+            ("TRUNCATE items", "TRUNCATE `src1_items`"),
+            ("CREATE TABLE t2 LIKE items", "CREATE TABLE `src1_t2` LIKE `src1_items`"),
+            (
+                "CREATE TABLE t3 (a TEXT, CHECK (a LIKE b))",
+                "CREATE TABLE `src1_t3` (a TEXT, CHECK (a LIKE b))",
+            ),
+        ];
+
+        for (query, expected) in queries {
+            assert_eq!(router.rewrite_query(db, query), expected);
+        }
+
+        // no rules => unchanged
+        let query = "CREATE TABLE items (id INT)";
+        assert_eq!(Router::default().rewrite_query(db, query), query);
+    }
+}
@@ -0,0 +1,245 @@
+//! Sequence Tracker
+//!
+//! Each producer numbers its messages with a sequence incremented by one per message.
+//! The last applied sequence of each source (`flv://<mysql_resource_name>` part of the uri)
+//! is saved by the offset store with the offset of the next message (see OffsetStore), and
+//! loaded from it at startup, so that:
+//!  - duplicates (sequence already applied) are skipped
+//!  - gaps (sequences missing) are handled according to the gap policy
+//!
+use async_std::fs;
+use async_std::io::prelude::WriteExt;
+use http::Uri;
+use std::collections::BTreeMap;
+use std::io::{Error, ErrorKind};
+use std::path::{Path, PathBuf};
+use tracing::{info, warn};
+
+use crate::consumer::profile::GapPolicy;
+use crate::messages::FluvioMessage;
+
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct SequenceCounters {
+    pub applied: u64,
+    pub duplicates: u64,
+    pub gaps: u64,
+    pub missing: u64,
+    pub quarantined: u64,
+    pub dead_lettered: u64,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum SequenceCheck {
+    Apply,
+    Skip,
+}
+
+#[derive(Debug, PartialEq)]
+enum Position {
+    First,
+    Next,
+    Duplicate,
+    Gap(u64),
+}
+
+#[derive(Debug)]
+pub struct SequenceTracker {
+    quarantine_file: PathBuf,
+    on_gap: GapPolicy,
+    last: BTreeMap<String, u64>,
+    counters: SequenceCounters,
+}
+
+impl SequenceTracker {
+    /// Tracker starting from the sequences saved by the offset store
+    pub fn init(last: BTreeMap<String, u64>, quarantine_file: &Path, on_gap: GapPolicy) -> Self {
+        Self {
+            quarantine_file: quarantine_file.to_path_buf(),
+            on_gap,
+            last,
+            counters: SequenceCounters::default(),
+        }
+    }
+
+    pub fn counters(&self) -> &SequenceCounters {
+        &self.counters
+    }
+
+    /// Check the message sequence against the last one applied for its source
+    pub async fn check(
+        &mut self,
+        flv_message: &FluvioMessage,
+        json_msg: &str,
+    ) -> Result<SequenceCheck, Error> {
+        let source = source_name(&flv_message.uri);
+        let sequence = flv_message.sequence;
+
+        match position(self.last.get(&source).copied(), sequence) {
+            Position::First | Position::Next => Ok(SequenceCheck::Apply),
+            Position::Duplicate => {
+                self.counters.duplicates += 1;
+                info!(%source, sequence, counters = ?self.counters, "Duplicate message skipped");
+                Ok(SequenceCheck::Skip)
+            }
+            Position::Gap(missing) => {
+                self.counters.gaps += 1;
+                self.counters.missing += missing;
+                warn!(%source, sequence, missing, counters = ?self.counters, "Sequence gap");
+
+                match self.on_gap {
+                    GapPolicy::Halt => Err(Error::new(
+                        ErrorKind::InvalidData,
+                        format!(
+                            "sequence gap for {}: {} messages missing before {}",
+                            source, missing, sequence
+                        ),
+                    )),
+                    GapPolicy::Warn => Ok(SequenceCheck::Apply),
+                    GapPolicy::Quarantine => {
+                        self.quarantine(json_msg).await?;
+                        self.counters.quarantined += 1;
+                        self.last.insert(source, sequence);
+                        Ok(SequenceCheck::Skip)
+                    }
+                }
+            }
+        }
+    }
+
+    /// Message applied, its sequence is the last one of the source
+    pub fn applied(&mut self, flv_message: &FluvioMessage) {
+        self.counters.applied += 1;
+        self.last
+            .insert(source_name(&flv_message.uri), flv_message.sequence);
+    }
+
+    /// Message sent to the dead-letter queue, its sequence is consumed
+    pub fn dead_lettered(&mut self, flv_message: &FluvioMessage) {
+        self.counters.dead_lettered += 1;
+        self.last
+            .insert(source_name(&flv_message.uri), flv_message.sequence);
+    }
+
+    async fn quarantine(&self, json_msg: &str) -> Result<(), Error> {
+        let mut file = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.quarantine_file)
+            .await?;
+        file.write_all(json_msg.replace('\n', " ").as_bytes())
+            .await?;
+        file.write_all(b"\n").await
+    }
+}
+
+fn position(last: Option<u64>, sequence: u64) -> Position {
+    match last {
+        None => Position::First,
+        Some(last) if sequence <= last => Position::Duplicate,
+        Some(last) if sequence == last + 1 => Position::Next,
+        Some(last) => Position::Gap(sequence - last - 1),
+    }
+}
+
+/// Source of the message: "flv://<mysql_resource_name>"
+pub fn source_name(uri: &str) -> String {
+    match uri.parse::<Uri>() {
+        Ok(uri) => format!(
+            "{}://{}",
+            uri.scheme_str().unwrap_or_default(),
+            uri.authority()
+                .map(|auth| auth.as_str())
+                .unwrap_or_default()
+        ),
+        Err(_) => uri.to_owned(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::messages::Operation;
+    use std::fs;
+
+    const TEST_PATH: &str = "test_files";
+    const QUARANTINE_FILE: &str = "sequence-test.quarantine";
+
+    fn build_path(file: &str) -> PathBuf {
+        let program_dir = std::env::current_dir().unwrap();
+        program_dir.join(TEST_PATH).join(file)
+    }
+
+    fn build_message(uri: &str, sequence: u64) -> FluvioMessage {
+        FluvioMessage::test_message(uri, Operation::Query("BEGIN".to_owned()))
+            .with_sequence(sequence)
+    }
+
+    #[test]
+    fn test_position() {
+        assert_eq!(position(None, 7), Position::First);
+        assert_eq!(position(Some(6), 7), Position::Next);
+        assert_eq!(position(Some(7), 7), Position::Duplicate);
+        assert_eq!(position(Some(8), 7), Position::Duplicate);
+        assert_eq!(position(Some(4), 7), Position::Gap(2));
+
+        assert_eq!(
+            source_name("flv://mysql-srv1/flvdb/pet"),
+            "flv://mysql-srv1"
+        );
+    }
+
+    #[test]
+    fn test_sequence_tracker() {
+        let quarantine_path = build_path(QUARANTINE_FILE);
+        let _ = fs::remove_file(&quarantine_path);
+
+        async_std::task::block_on(async {
+            let mut tracker =
+                SequenceTracker::init(BTreeMap::new(), &quarantine_path, GapPolicy::Quarantine);
+
+            // sources are tracked independently
+            for (uri, sequence) in &[("flv://srv1/db/t", 0), ("flv://srv2/db", 5)] {
+                let msg = build_message(uri, *sequence);
+                assert_eq!(tracker.check(&msg, "").await.unwrap(), SequenceCheck::Apply);
+                tracker.applied(&msg);
+            }
+
+            let msg = build_message("flv://srv1/db", 0);
+            assert_eq!(tracker.check(&msg, "").await.unwrap(), SequenceCheck::Skip);
+
+            // gap => quarantined, following message applied
+            let msg = build_message("flv://srv1/db", 3);
+            assert_eq!(
+                tracker.check(&msg, "{\n\"sequence\": 3\n}").await.unwrap(),
+                SequenceCheck::Skip
+            );
+            let msg = build_message("flv://srv1/db", 4);
+            assert_eq!(tracker.check(&msg, "").await.unwrap(), SequenceCheck::Apply);
+            tracker.applied(&msg);
+
+            let expected = SequenceCounters {
+                applied: 3,
+                duplicates: 1,
+                gaps: 1,
+                missing: 2,
+                quarantined: 1,
+                dead_lettered: 0,
+            };
+            assert_eq!(tracker.counters(), &expected);
+            assert_eq!(
+                fs::read_to_string(&quarantine_path).unwrap(),
+                "{ \"sequence\": 3 }\n"
+            );
+
+            // sequences saved by the offset store => halt on gap
+            let saved = vec![("flv://srv2".to_owned(), 5)].into_iter().collect();
+            let mut tracker = SequenceTracker::init(saved, &quarantine_path, GapPolicy::Halt);
+            let msg = build_message("flv://srv2/db", 5);
+            assert_eq!(tracker.check(&msg, "").await.unwrap(), SequenceCheck::Skip);
+            let msg = build_message("flv://srv2/db", 7);
+            assert!(tracker.check(&msg, "").await.is_err());
+        });
+
+        fs::remove_file(&quarantine_path).expect("delete file failed");
+    }
+}
@@ -0,0 +1,267 @@
+//! Sink
+//!
+//! Target of the consumer. The consumer loop parses messages, checks sequences and filters,
+//! retries errors and owns the offset store; the sink applies the messages:
+//!  - MysqlManager: mysql database
+//!  - SqliteSink: local SQLite file
+//!
+//! Helpers shared by the sinks to read the messages, and the rolling files of the file sinks
+//! (FileSink, ParquetSink), are at the end of the file.
+//!
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{Error, ErrorKind};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use tracing::info;
+
+use crate::consumer::offset_store::OffsetStore;
+use crate::consumer::router::Router;
+use crate::consumer::sequence_tracker::source_name;
+use crate::consumer::uri::UriProps;
+use crate::messages::{FluvioMessage, Value};
+
+pub trait Sink {
+    /// Apply message, then save the offset of the next message.
+    /// On error, the message must not be partially applied, so it can be retried.
+    fn apply_message(
+        &mut self,
+        flv_message: &FluvioMessage,
+        offset_store: &mut dyn OffsetStore,
+    ) -> Result<(), Error>;
+
+    /// Message not applied (filtered, duplicate, quarantined, dead-lettered),
+    /// save the offset of the next message
+    fn checkpoint(&mut self, offset_store: &mut dyn OffsetStore) -> Result<(), Error> {
+        offset_store.increment_offset()
+    }
+
+    /// Write buffered changes to the target, called before the consumer exits
+    fn flush(&mut self) -> Result<(), Error> {
+        Ok(())
+    }
+
+    /// Log sink counters, called before the consumer exits
+    fn report(&self) {}
+}
+
+/// Indexes of the key columns, if all of them are in the message
+pub fn key_indexes(
+    cols: &[String],
+    keys: Option<&[String]>,
+    values_len: usize,
+) -> Option<Vec<usize>> {
+    keys.filter(|keys| !keys.is_empty()).and_then(|keys| {
+        keys.iter()
+            .map(|key| {
+                cols.iter()
+                    .position(|col| col == key)
+                    .filter(|idx| *idx < values_len)
+            })
+            .collect()
+    })
+}
+
+/// Key columns are known and have the same values before and after the update
+pub fn same_key(
+    cols: &[String],
+    keys: Option<&[String]>,
+    before: &[Value],
+    after: &[Value],
+) -> bool {
+    match key_indexes(cols, keys, before.len().min(after.len())) {
+        Some(idxs) => idxs.iter().all(|idx| before[*idx] == after[*idx]),
+        None => false,
+    }
+}
+
+/// Ensure message has column object, return error otherwise
+pub fn check_valid_cols(cols: &Option<Vec<String>>) -> Result<(), Error> {
+    if cols.is_none() {
+        Err(Error::new(
+            ErrorKind::InvalidData,
+            "expected column names, found none",
+        ))
+    } else {
+        Ok(())
+    }
+}
+
+/// Ensure URI table_name field, return error otherwise
+pub fn table_name(uri_props: &UriProps) -> Result<String, Error> {
+    if let Some(table_name) = &uri_props.table_name {
+        Ok(table_name.clone())
+    } else {
+        Err(Error::new(
+            ErrorKind::InvalidData,
+            "expected table-name, found none",
+        ))
+    }
+}
+
+/// Ensure the number of columns match the number of value objects
+pub fn check_valid_col_count(expected: usize, found: usize) -> Result<(), Error> {
+    if found < expected {
+        Err(Error::new(
+            ErrorKind::InvalidData,
+            format!("expected at least {} columns, found {}", expected, found),
+        ))
+    } else {
+        Ok(())
+    }
+}
+
+/// Route database and table names of the uri to target names
+pub fn route_uri(router: &Router, uri_props: UriProps) -> UriProps {
+    let table_name = match (&uri_props.db_name, uri_props.table_name) {
+        (Some(db_name), Some(table_name)) => Some(router.table(db_name, &table_name)),
+        (_, table_name) => table_name,
+    };
+
+    UriProps {
+        db_name: uri_props.db_name.map(|db_name| router.database(&db_name)),
+        table_name,
+    }
+}
+
+/// Source of the message in file names: the mysql resource name, characters other than
+/// letters, digits, '-' and '.' replaced by '_'
+pub fn file_source(uri: &str) -> String {
+    let source = source_name(uri);
+    let name = source.splitn(2, "://").last().unwrap_or_default();
+    name.chars()
+        .map(|ch| {
+            if ch.is_ascii_alphanumeric() || ch == '-' || ch == '.' {
+                ch
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+/// File of a file sink, complete when it is finalized under its final name
+pub trait SinkFile {
+    /// Time the file was opened
+    fn opened(&self) -> Instant;
+
+    /// Write the file under its final name, returns the final name, none if the file has no
+    /// rows (removed)
+    fn finalize(self) -> Result<Option<PathBuf>, Error>;
+}
+
+/// Open files of a file sink, one per table: (database, table)
+pub struct RollingFiles<F> {
+    files: BTreeMap<(String, String), F>,
+    max_file_age: Duration,
+    finalized: u64,
+}
+
+impl<F: SinkFile> RollingFiles<F> {
+    pub fn new(max_file_age: Duration) -> Self {
+        Self {
+            files: BTreeMap::new(),
+            max_file_age,
+            finalized: 0,
+        }
+    }
+
+    /// Number of files finalized since start
+    pub fn finalized(&self) -> u64 {
+        self.finalized
+    }
+
+    pub fn get(&self, key: &(String, String)) -> Option<&F> {
+        self.files.get(key)
+    }
+
+    /// Open file of the table, or the file created by open
+    pub fn get_or_open(
+        &mut self,
+        key: &(String, String),
+        open: impl FnOnce() -> Result<F, Error>,
+    ) -> Result<&mut F, Error> {
+        if !self.files.contains_key(key) {
+            let file = open()?;
+            self.files.insert(key.clone(), file);
+        }
+        Ok(self.files.get_mut(key).unwrap())
+    }
+
+    /// File older than the age limit
+    pub fn is_old(&self, file: &F) -> bool {
+        file.opened().elapsed() >= self.max_file_age
+    }
+
+    pub fn finalize(&mut self, key: &(String, String)) -> Result<(), Error> {
+        if let Some(file) = self.files.remove(key) {
+            if let Some(path) = file.finalize()? {
+                self.finalized += 1;
+                info!(file = %path.display(), "File finalized");
+            }
+        }
+        Ok(())
+    }
+
+    /// Finalize the files older than the age limit, tables without messages included
+    pub fn finalize_old_files(&mut self) -> Result<(), Error> {
+        let old_files: Vec<(String, String)> = self
+            .files
+            .iter()
+            .filter(|(_, file)| self.is_old(file))
+            .map(|(key, _)| key.clone())
+            .collect();
+
+        for key in old_files {
+            self.finalize(&key)?;
+        }
+        Ok(())
+    }
+
+    /// Finalize all files
+    pub fn flush(&mut self) -> Result<(), Error> {
+        let keys: Vec<(String, String)> = self.files.keys().cloned().collect();
+        for key in keys {
+            self.finalize(&key)?;
+        }
+        Ok(())
+    }
+}
+
+/// Remove file, already removed files are ignored
+pub fn remove_if_exists(path: &Path) -> Result<(), Error> {
+    match fs::remove_file(path) {
+        Err(err) if err.kind() != ErrorKind::NotFound => Err(err),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::consumer::profile::Routing;
+    use crate::consumer::uri::parse_uri;
+
+    #[test]
+    fn test_route_uri() {
+        let routing: Routing = toml::from_str(
+            "table_prefix = \"src1_\"\n[databases]\nprod_shop = \"analytics_shop_copy\"",
+        )
+        .unwrap();
+        let router = Router::new(&routing);
+
+        let uri_props = parse_uri("flv://mysql.local/prod_shop/orders").unwrap();
+        let expected = UriProps {
+            db_name: Some("analytics_shop_copy".to_owned()),
+            table_name: Some("src1_orders".to_owned()),
+        };
+        assert_eq!(route_uri(&router, uri_props), expected);
+
+        let uri_props = parse_uri("flv://mysql.local/other").unwrap();
+        let expected = UriProps {
+            db_name: Some("other".to_owned()),
+            table_name: None,
+        };
+        assert_eq!(route_uri(&router, uri_props), expected);
+    }
+}
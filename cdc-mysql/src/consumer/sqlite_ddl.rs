@@ -0,0 +1,200 @@
+//! SQLite DDL
+//!
+//! Translates mysql DDL statements (Query operation) into SQLite statements, with the shared
+//! DDL translator (see ddl): column types are mapped to SQLite types (see sqlite_type).
+//!
+//! Database names are dropped, all tables are in one SQLite file, and database statements are
+//! ignored. SQLite cannot add keys to an existing table: unique keys become unique indexes,
+//! the other keys return an error.
+//!
+use sqlparser::ast::DataType;
+use std::io::Error;
+
+use crate::consumer::ddl::{self, DdlDialect};
+use crate::consumer::router::Router;
+
+pub use crate::consumer::ddl::quote_identifier;
+
+/// DDL syntax of SQLite
+pub struct SqliteDdl;
+
+impl DdlDialect for SqliteDdl {
+    fn name(&self) -> &'static str {
+        "sqlite"
+    }
+
+    fn column_type(&self, data_type: &DataType) -> Result<String, Error> {
+        Ok(sqlite_type(data_type).to_owned())
+    }
+
+    /// Routed table, without database
+    fn qualified_name(&self, _db_name: Option<&str>, name: &str) -> String {
+        quote_identifier(name)
+    }
+
+    fn alters_keys(&self) -> bool {
+        false
+    }
+
+    /// SQLite has no TRUNCATE
+    fn truncate(&self, table: &str) -> String {
+        format!("DELETE FROM {}", table)
+    }
+}
+
+/// Translate mysql DDL statement into SQLite statements, in order.
+/// Unqualified tables are in `db_name`, the source database of the message.
+pub fn translate_ddl(
+    router: &Router,
+    db_name: Option<&str>,
+    query: &str,
+) -> Result<Vec<String>, Error> {
+    ddl::translate_ddl(&SqliteDdl, router, db_name, query)
+}
+
+/// SQLite type of a mysql column type, by the type name (sqlparser keeps unknown type names
+/// as written). Enums and sets are sent as their index (integer).
+fn sqlite_type(data_type: &DataType) -> &'static str {
+    let mysql_type = data_type.to_string();
+    let name = match mysql_type.find('(') {
+        Some(pos) => &mysql_type[..pos],
+        None => mysql_type.as_str(),
+    };
+
+    match name.trim().to_uppercase().as_str() {
+        "TINYINT" | "SMALLINT" | "MEDIUMINT" | "INT" | "INTEGER" | "BIGINT" | "SERIAL" | "BIT"
+        | "BOOL" | "BOOLEAN" | "YEAR" | "ENUM" | "SET" => "INTEGER",
+        "FLOAT" | "DOUBLE" | "DOUBLE PRECISION" | "REAL" => "REAL",
+        "DECIMAL" | "DEC" | "NUMERIC" | "FIXED" => "NUMERIC",
+        "BINARY" | "VARBINARY" | "TINYBLOB" | "BLOB" | "MEDIUMBLOB" | "LONGBLOB" | "BYTEA"
+        | "GEOMETRY" | "POINT" | "LINESTRING" | "POLYGON" => "BLOB",
+        _ => "TEXT",
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::consumer::profile::Routing;
+    use std::io::ErrorKind;
+
+    fn translate(query: &str) -> Vec<String> {
+        translate_ddl(&Router::default(), Some("shop"), query).unwrap()
+    }
+
+    #[test]
+    fn test_translate_create_table() {
+        let query = "CREATE TABLE IF NOT EXISTS `shop`.`orders` (`id` INT NOT NULL, \
+            customer VARCHAR(64) NOT NULL, total DECIMAL(10,2) DEFAULT 0, \
+            discount DOUBLE DEFAULT -1.5, note TEXT DEFAULT 'it''s', created DATETIME, \
+            photo LONGBLOB, PRIMARY KEY (id), UNIQUE (customer, created), \
+            CONSTRAINT fk_customer FOREIGN KEY (customer) REFERENCES customers (name))";
+        let expected = vec![
+            "CREATE TABLE IF NOT EXISTS \"orders\" (\"id\" INTEGER NOT NULL, \
+            \"customer\" TEXT NOT NULL, \"total\" NUMERIC DEFAULT (0), \
+            \"discount\" REAL DEFAULT (-1.5), \"note\" TEXT DEFAULT ('it''s'), \
+            \"created\" TEXT, \"photo\" BLOB, PRIMARY KEY (\"id\"), \
+            UNIQUE (\"customer\", \"created\"), CONSTRAINT \"fk_customer\" FOREIGN KEY \
+            (\"customer\") REFERENCES \"customers\" (\"name\"))"
+                .to_owned(),
+        ];
+        assert_eq!(translate(query), expected);
+
+        let query = "CREATE TABLE pet (name VARCHAR(20) PRIMARY KEY, owner_id INT \
+            REFERENCES owners (id), age INT CHECK (age >= 0))";
+        let expected = vec![
+            "CREATE TABLE \"pet\" (\"name\" TEXT PRIMARY KEY, \"owner_id\" INTEGER \
+            REFERENCES \"owners\" (\"id\"), \"age\" INTEGER CHECK (age >= 0))"
+                .to_owned(),
+        ];
+        assert_eq!(translate(query), expected);
+
+        // table statement sqlparser cannot read
+        let err = translate_ddl(
+            &Router::default(),
+            Some("shop"),
+            "CREATE TABLE pet2 LIKE pet",
+        )
+        .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_translate_alter_table() {
+        assert_eq!(
+            translate("ALTER TABLE shop.pet ADD COLUMN age INT DEFAULT 0"),
+            vec!["ALTER TABLE \"pet\" ADD COLUMN \"age\" INTEGER DEFAULT (0)"]
+        );
+        assert_eq!(
+            translate("ALTER TABLE pet DROP COLUMN sex"),
+            vec!["ALTER TABLE \"pet\" DROP COLUMN \"sex\""]
+        );
+        assert_eq!(
+            translate("ALTER TABLE pet RENAME COLUMN owner TO keeper"),
+            vec!["ALTER TABLE \"pet\" RENAME COLUMN \"owner\" TO \"keeper\""]
+        );
+        assert_eq!(
+            translate("ALTER TABLE pet ADD CONSTRAINT idx_age UNIQUE (age)"),
+            vec!["CREATE UNIQUE INDEX \"pet_idx_age\" ON \"pet\" (\"age\")"]
+        );
+
+        // keys of an existing table
+        let err = translate_ddl(
+            &Router::default(),
+            Some("shop"),
+            "ALTER TABLE pet ADD PRIMARY KEY (name)",
+        )
+        .unwrap_err();
+        assert!(err.to_string().contains("keys added to a table"));
+    }
+
+    #[test]
+    fn test_translate_statements() {
+        let routing: Routing =
+            toml::from_str("table_prefix = \"src1_\"\n[tables]\n\"shop.toys\" = \"games\"")
+                .unwrap();
+        let router = Router::new(&routing);
+
+        assert_eq!(
+            translate_ddl(&router, Some("shop"), "DROP TABLE IF EXISTS pet, shop.toys").unwrap(),
+            vec![
+                "DROP TABLE IF EXISTS \"src1_pet\"",
+                "DROP TABLE IF EXISTS \"games\""
+            ]
+        );
+
+        // ignored
+        assert!(translate_ddl(&router, None, "CREATE DATABASE shop")
+            .unwrap()
+            .is_empty());
+        assert!(translate_ddl(&router, None, "DROP SCHEMA IF EXISTS shop")
+            .unwrap()
+            .is_empty());
+        assert!(
+            translate_ddl(&router, Some("shop"), "GRANT SELECT ON shop.* TO 'bob'@'%'")
+                .unwrap()
+                .is_empty()
+        );
+
+        // table without columns
+        let err = translate_ddl(&router, Some("shop"), "CREATE TABLE v AS SELECT 1").unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_translate_index_statements() {
+        assert_eq!(
+            translate("CREATE INDEX idx_age ON shop.pet (age)"),
+            vec!["CREATE INDEX \"pet_idx_age\" ON \"pet\" (\"age\")"]
+        );
+        assert_eq!(
+            translate("DROP INDEX idx_age ON pet"),
+            vec!["DROP INDEX \"pet_idx_age\""]
+        );
+        assert_eq!(translate("TRUNCATE TABLE pet"), vec!["DELETE FROM \"pet\""]);
+        assert_eq!(
+            translate("RENAME TABLE pet TO pets"),
+            vec!["ALTER TABLE \"pet\" RENAME TO \"pets\""]
+        );
+    }
+}
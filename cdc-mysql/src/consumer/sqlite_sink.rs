@@ -0,0 +1,546 @@
+//! SQLite Sink
+//!
+//! Mirrors the change stream into a local SQLite file:
+//!  - rows are added, updated and deleted with prepared statements and typed parameters
+//!    (see StatementBuilder)
+//!  - DDL statements are translated to SQLite (see sqlite_ddl)
+//!  - each message is applied in one transaction, DDL statements included
+//!  - row updates and deletes are checked as in the mysql sink (see ConflictHandler)
+//!  - the offset of the next message and the sequence of each source are saved in the file,
+//!    in the transaction of the message (see offset_store)
+//!
+//! Databases are not kept: the tables of all databases are created in the file, use routing
+//! rules to rename tables with the same name.
+//!
+use chrono::{DateTime, Utc};
+use rusqlite::types::Value as SqlValue;
+use rusqlite::{params, Connection, OptionalExtension, Transaction};
+use std::collections::BTreeMap;
+use std::io::Error;
+use std::path::Path;
+use std::time::{Duration, UNIX_EPOCH};
+use tracing::{debug, info, warn};
+
+use crate::consumer::conflict_handler::ConflictHandler;
+use crate::consumer::offset_store::{OffsetStore, SinkOffsetStore};
+use crate::consumer::profile::Profile;
+use crate::consumer::retry::sqlite_error;
+use crate::consumer::router::Router;
+use crate::consumer::sink::{route_uri, table_name, Sink};
+use crate::consumer::sqlite_ddl::{quote_identifier, translate_ddl};
+use crate::consumer::statement::{Dialect, StatementBuilder};
+use crate::consumer::uri::{parse_uri, UriProps};
+use crate::messages::{fraction, FluvioMessage, Operation, Value};
+
+const OFFSET_TABLE: &str = "fluvio_cdc_offsets";
+const SEQUENCE_TABLE: &str = "fluvio_cdc_sequences";
+
+pub struct SqliteSink {
+    conn: Connection,
+    router: Router,
+    statements: StatementBuilder<SqliteDialect>,
+    conflict_handler: ConflictHandler,
+    offset_key: Option<(String, i32)>,
+}
+
+type PreparedQuery = crate::consumer::statement::PreparedQuery<SqlValue>;
+
+/// SQLite syntax: double quotes, "?" placeholders, "INSERT OR REPLACE" upserts.
+/// Rows of tables without key are matched by rowid.
+pub struct SqliteDialect;
+
+impl Dialect for SqliteDialect {
+    type Param = SqlValue;
+
+    fn quote_identifier(&self, name: &str) -> String {
+        quote_identifier(name)
+    }
+
+    fn table(&self, uri_props: &UriProps) -> Result<String, Error> {
+        Ok(quote_identifier(&table_name(uri_props)?))
+    }
+
+    fn bind(&self, value: &Value, params: &mut Vec<SqlValue>) -> Result<String, Error> {
+        params.push(to_sqlite_value(value));
+        Ok("?".to_owned())
+    }
+
+    /// Existing rows with the same key are replaced
+    fn upsert(&self, _columns: &[String], _key_idxs: Option<&[usize]>) -> (&'static str, String) {
+        ("INSERT OR REPLACE", String::new())
+    }
+
+    fn where_one_row(&self, table: &str, conditions: &str) -> String {
+        format!(
+            "WHERE rowid = (SELECT rowid FROM {} WHERE {} LIMIT 1)",
+            table, conditions
+        )
+    }
+}
+
+impl SqliteSink {
+    /// Open the SQLite file, created if it does not exist
+    pub fn open(sqlite_file: &Path, profile: &Profile) -> Result<Self, Error> {
+        let conn = Connection::open(sqlite_file).map_err(|err| sqlite_error("Open", err))?;
+
+        Ok(Self {
+            conn,
+            router: Router::new(&profile.routing()),
+            statements: StatementBuilder::new(SqliteDialect, profile.apply_mode()),
+            conflict_handler: ConflictHandler::new(profile.on_conflict(), &profile.conflict_file()),
+            offset_key: None,
+        })
+    }
+
+    /// Create the offset tables if needed, and read the offset and sequences of topic
+    /// partition. They are then saved with each message.
+    pub fn offset_store(&mut self, topic: &str, partition: i32) -> Result<SinkOffsetStore, Error> {
+        self.conn
+            .execute_batch(&format!(
+                "CREATE TABLE IF NOT EXISTS {} (\
+                 topic TEXT NOT NULL, \
+                 partition_id INTEGER NOT NULL, \
+                 next_offset INTEGER NOT NULL, \
+                 PRIMARY KEY (topic, partition_id));
+                 CREATE TABLE IF NOT EXISTS {} (\
+                 topic TEXT NOT NULL, \
+                 partition_id INTEGER NOT NULL, \
+                 source TEXT NOT NULL, \
+                 last_sequence INTEGER NOT NULL, \
+                 PRIMARY KEY (topic, partition_id, source));",
+                OFFSET_TABLE, SEQUENCE_TABLE
+            ))
+            .map_err(|err| sqlite_error("Offset tables", err))?;
+
+        let offset: Option<i64> = self
+            .conn
+            .query_row(
+                &format!(
+                    "SELECT next_offset FROM {} WHERE topic=? AND partition_id=?",
+                    OFFSET_TABLE
+                ),
+                params![topic, partition],
+                |row| row.get(0),
+            )
+            .optional()
+            .map_err(|err| sqlite_error("Offset", err))?;
+
+        let mut stmt = self
+            .conn
+            .prepare(&format!(
+                "SELECT source, last_sequence FROM {} WHERE topic=? AND partition_id=?",
+                SEQUENCE_TABLE
+            ))
+            .map_err(|err| sqlite_error("Sequences", err))?;
+        let sequences = stmt
+            .query_map(params![topic, partition], |row| {
+                Ok((row.get::<_, String>(0)?, row.get::<_, i64>(1)? as u64))
+            })
+            .and_then(|rows| rows.collect::<Result<BTreeMap<String, u64>, _>>())
+            .map_err(|err| sqlite_error("Sequences", err))?;
+
+        self.offset_key = Some((topic.to_owned(), partition));
+        Ok(SinkOffsetStore::new(offset.unwrap_or(0), sequences))
+    }
+
+    /// Number of row conflicts since start
+    pub fn conflicts(&self) -> u64 {
+        self.conflict_handler.conflicts()
+    }
+
+    /// Build the statements of the message
+    ///     - Query: translated DDL statements (create/alter/drop table, indexes)
+    ///     - Add, Update, Delete: one statement per row (see StatementBuilder)
+    fn build_queries(
+        &self,
+        flv_message: &FluvioMessage,
+        uri_props: &UriProps,
+        source_db: Option<&str>,
+    ) -> Result<Vec<PreparedQuery>, Error> {
+        match &flv_message.operation {
+            Operation::Query(query) => Ok(translate_ddl(&self.router, source_db, query)?
+                .into_iter()
+                .map(PreparedQuery::statement)
+                .collect()),
+            _ => self.statements.build_row_queries(uri_props, flv_message),
+        }
+    }
+}
+
+/// Statements and the offset of the next message are applied in one transaction.
+/// On error, the transaction is rolled back when dropped.
+impl Sink for SqliteSink {
+    fn apply_message(
+        &mut self,
+        flv_message: &FluvioMessage,
+        offset_store: &mut dyn OffsetStore,
+    ) -> Result<(), Error> {
+        let uri_props = parse_uri(&flv_message.uri)?;
+        let source_db = uri_props.db_name.clone();
+        let uri_props = route_uri(&self.router, uri_props);
+        let queries = self.build_queries(flv_message, &uri_props, source_db.as_deref())?;
+
+        self.conflict_handler.discard_log();
+        let tx = self
+            .conn
+            .transaction()
+            .map_err(|err| sqlite_error("Transaction", err))?;
+
+        self.statements.exec_queries(
+            &mut self.conflict_handler,
+            flv_message,
+            &uri_props,
+            &queries,
+            |query| exec_prepared(&tx, query),
+        )?;
+        save_next_offset(&tx, &self.offset_key, offset_store)?;
+
+        tx.commit().map_err(|err| sqlite_error("Commit", err))?;
+        offset_store.increment_offset()?;
+
+        // message committed, it must not be retried if the log cannot be written
+        if let Err(err) = self.conflict_handler.flush_log() {
+            warn!(error = %err, "Cannot write conflict log");
+        }
+        Ok(())
+    }
+
+    fn checkpoint(&mut self, offset_store: &mut dyn OffsetStore) -> Result<(), Error> {
+        let tx = self
+            .conn
+            .transaction()
+            .map_err(|err| sqlite_error("Transaction", err))?;
+        save_next_offset(&tx, &self.offset_key, offset_store)?;
+
+        tx.commit().map_err(|err| sqlite_error("Commit", err))?;
+        offset_store.increment_offset()
+    }
+
+    fn report(&self) {
+        println!("Row conflicts: {}", self.conflicts());
+        info!(conflicts = self.conflicts(), "Sqlite sink counters");
+    }
+}
+
+/// Save the offset of the next message and the sequence of the current one in the transaction,
+/// if the offsets are kept in the file (see offset_store)
+fn save_next_offset(
+    tx: &Transaction<'_>,
+    offset_key: &Option<(String, i32)>,
+    offset_store: &dyn OffsetStore,
+) -> Result<(), Error> {
+    let (topic, partition) = match offset_key {
+        Some((topic, partition)) if offset_store.is_transactional() => (topic, partition),
+        _ => return Ok(()),
+    };
+
+    if let Some((source, sequence)) = offset_store.current_sequence() {
+        tx.execute(
+            &format!(
+                "INSERT OR REPLACE INTO {} (topic, partition_id, source, last_sequence) \
+                 VALUES (?, ?, ?, ?)",
+                SEQUENCE_TABLE
+            ),
+            params![topic, partition, source, sequence as i64],
+        )
+        .map_err(|err| sqlite_error("Sequence", err))?;
+    }
+
+    tx.execute(
+        &format!(
+            "INSERT OR REPLACE INTO {} (topic, partition_id, next_offset) VALUES (?, ?, ?)",
+            OFFSET_TABLE
+        ),
+        params![topic, partition, offset_store.offset() + 1],
+    )
+    .map_err(|err| sqlite_error("Offset", err))?;
+    Ok(())
+}
+
+/// Convert message value to SQLite parameter value
+///  - integers, floats, strings and blobs keep their type
+///  - dates and times are sent as text: "YYYY-MM-DD HH:MM:SS[.ffffff]", timestamps in UTC
+///  - decimals and json are sent as text
+fn to_sqlite_value(value: &Value) -> SqlValue {
+    match value {
+        Value::UnsignedInteger(val) => SqlValue::Integer(i64::from(*val)),
+        Value::SignedInteger(val) => SqlValue::Integer(*val),
+        Value::Float(val) => SqlValue::Real(f64::from(*val)),
+        Value::Double(val) => SqlValue::Real(*val),
+        Value::String(val) => SqlValue::Text(val.clone()),
+        Value::Enum(val) => SqlValue::Integer(i64::from(*val)),
+        Value::Blob(bytes) => SqlValue::Blob(bytes.clone()),
+        Value::Year(val) => SqlValue::Integer(i64::from(*val)),
+        Value::Date { year, month, day } => {
+            SqlValue::Text(format!("{:04}-{:02}-{:02}", year, month, day))
+        }
+        Value::Time {
+            hours,
+            minutes,
+            seconds,
+            subseconds,
+        } => SqlValue::Text(format!(
+            "{:02}:{:02}:{:02}{}",
+            hours,
+            minutes,
+            seconds,
+            fraction(*subseconds)
+        )),
+        Value::DateTime {
+            year,
+            month,
+            day,
+            hour,
+            minute,
+            second,
+            subsecond,
+        } => SqlValue::Text(format!(
+            "{:04}-{:02}-{:02} {:02}:{:02}:{:02}{}",
+            year,
+            month,
+            day,
+            hour,
+            minute,
+            second,
+            fraction(*subsecond)
+        )),
+        Value::Json(val) => SqlValue::Text(val.to_string()),
+        Value::Decimal(val) => SqlValue::Text(val.to_string()),
+        Value::Timestamp {
+            unix_time,
+            subsecond,
+        } => {
+            let d = UNIX_EPOCH + Duration::from_secs(*unix_time as u64);
+            let datetime = DateTime::<Utc>::from(d);
+            SqlValue::Text(format!(
+                "{}{}",
+                datetime.format("%Y-%m-%d %H:%M:%S"),
+                fraction(*subsecond)
+            ))
+        }
+        Value::Null => SqlValue::Null,
+    }
+}
+
+/// Execute prepared statement (cached by the connection) with its parameters, and
+/// return the affected rows.
+fn exec_prepared(tx: &Transaction<'_>, prepared: &PreparedQuery) -> Result<u64, Error> {
+    debug!(query = %prepared.query, params = ?prepared.params, "Query");
+    let mut stmt = tx
+        .prepare_cached(&prepared.query)
+        .map_err(|err| sqlite_error("Query", err))?;
+    let affected_rows = stmt
+        .execute(&prepared.params)
+        .map_err(|err| sqlite_error("Query", err))?;
+
+    Ok(affected_rows as u64)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::consumer::profile::{ApplyMode, ConflictPolicy};
+    use crate::consumer::statement::StatementKind;
+    use crate::messages::{BeforeAfterCols, Cols};
+    use crate::messages::{DeleteRows, UpdateRows, WriteRows};
+
+    const TEST_PATH: &str = "test_files";
+    const SQLITE_FILE: &str = "sqlite-sink-test.sqlite";
+
+    fn message(uri: &str, operation: Operation) -> FluvioMessage {
+        FluvioMessage::test_message(uri, operation)
+            .with_columns(&["id", "name", "born"])
+            .with_keys(&["id"])
+    }
+
+    fn pet(id: i64, name: &str, born: Value) -> Vec<Value> {
+        vec![
+            Value::SignedInteger(id),
+            Value::String(name.to_owned()),
+            born,
+        ]
+    }
+
+    #[test]
+    fn test_build_queries() {
+        let cols = vec!["id".to_owned(), "name".to_owned()];
+        let keys = vec!["id".to_owned()];
+        let data = UpdateRows {
+            rows: vec![BeforeAfterCols {
+                before_cols: vec![Value::SignedInteger(7), Value::String("car".to_owned())],
+                after_cols: vec![Value::SignedInteger(7), Value::String("bus".to_owned())],
+            }],
+        };
+
+        let strict = StatementBuilder::new(SqliteDialect, ApplyMode::Strict);
+        let upsert = StatementBuilder::new(SqliteDialect, ApplyMode::Upsert);
+
+        let expected = vec![PreparedQuery {
+            kind: StatementKind::Update,
+            query: "UPDATE \"toys\" SET \"name\"=? WHERE \"id\"=?".to_owned(),
+            params: vec![SqlValue::Text("bus".to_owned()), SqlValue::Integer(7)],
+        }];
+        let queries = strict
+            .build_update_queries("\"toys\"", &cols, Some(&keys), &data)
+            .unwrap();
+        assert_eq!(queries, expected);
+
+        let expected = vec![PreparedQuery {
+            kind: StatementKind::Insert,
+            query: "INSERT OR REPLACE INTO \"toys\" (\"id\", \"name\") VALUES (?, ?)".to_owned(),
+            params: vec![SqlValue::Integer(7), SqlValue::Text("bus".to_owned())],
+        }];
+        let queries = upsert
+            .build_update_queries("\"toys\"", &cols, Some(&keys), &data)
+            .unwrap();
+        assert_eq!(queries, expected);
+
+        // no key => one row by rowid
+        let data = DeleteRows {
+            rows: vec![Cols {
+                cols: vec![Value::SignedInteger(7), Value::Null],
+            }],
+        };
+        let expected = vec![PreparedQuery {
+            kind: StatementKind::Delete,
+            query: "DELETE FROM \"toys\" WHERE rowid = (SELECT rowid FROM \"toys\" WHERE \"id\"=? AND \"name\" IS NULL LIMIT 1)".to_owned(),
+            params: vec![SqlValue::Integer(7)],
+        }];
+        assert_eq!(
+            strict
+                .build_delete_queries("\"toys\"", &cols, None, &data)
+                .unwrap(),
+            expected
+        );
+    }
+
+    #[test]
+    fn test_to_sqlite_value() {
+        assert_eq!(
+            to_sqlite_value(&Value::Timestamp {
+                unix_time: 1524885322,
+                subsecond: 123456
+            }),
+            SqlValue::Text("2018-04-28 03:15:22.123456".to_owned())
+        );
+        assert_eq!(
+            to_sqlite_value(&Value::Time {
+                hours: 30,
+                minutes: 31,
+                seconds: 30,
+                subseconds: 0
+            }),
+            SqlValue::Text("30:31:30".to_owned())
+        );
+        assert_eq!(
+            to_sqlite_value(&Value::Json(serde_json::json!({"a": "b"}))),
+            SqlValue::Text("{\"a\":\"b\"}".to_owned())
+        );
+        assert_eq!(
+            to_sqlite_value(&Value::Blob(vec![0, 159])),
+            SqlValue::Blob(vec![0, 159])
+        );
+    }
+
+    #[test]
+    fn test_sqlite_sink() {
+        let program_dir = std::env::current_dir().unwrap();
+        let sqlite_file = program_dir.join(TEST_PATH).join(SQLITE_FILE);
+        let _ = std::fs::remove_file(&sqlite_file);
+
+        let mut sink = SqliteSink::open(&sqlite_file, &Profile::default()).unwrap();
+        sink.conflict_handler =
+            ConflictHandler::new(ConflictPolicy::Fail, &Profile::default().conflict_file());
+        let mut offset_store = sink.offset_store("cdc", 0).unwrap();
+        assert_eq!(offset_store.offset(), 0);
+
+        let born = Value::Date {
+            year: 2018,
+            month: 4,
+            day: 28,
+        };
+        let messages = [
+            message(
+                "flv://mysql.local/shop",
+                Operation::Query(
+                    "CREATE TABLE `pets` (`id` INT NOT NULL, `name` VARCHAR(20), `born` DATE, \
+                    PRIMARY KEY (`id`))"
+                        .to_owned(),
+                ),
+            ),
+            message(
+                "flv://mysql.local/shop/pets",
+                Operation::Add(WriteRows {
+                    rows: vec![
+                        Cols {
+                            cols: pet(1, "Fluffy", born),
+                        },
+                        Cols {
+                            cols: pet(2, "Claws", Value::Null),
+                        },
+                    ],
+                }),
+            ),
+            message(
+                "flv://mysql.local/shop/pets",
+                Operation::Update(UpdateRows {
+                    rows: vec![BeforeAfterCols {
+                        before_cols: pet(2, "Claws", Value::Null),
+                        after_cols: pet(2, "Bowser", Value::Null),
+                    }],
+                }),
+            ),
+            message(
+                "flv://mysql.local/shop/pets",
+                Operation::Delete(DeleteRows {
+                    rows: vec![Cols {
+                        cols: pet(1, "Fluffy", Value::Null),
+                    }],
+                }),
+            ),
+        ];
+        for (idx, flv_message) in messages.iter().enumerate() {
+            offset_store.set_sequence("mysql.local", idx as u64 + 1);
+            sink.apply_message(flv_message, &mut offset_store).unwrap();
+        }
+        assert_eq!(offset_store.offset(), 4);
+
+        // update of a missing row => conflict, rolled back
+        let conflict = message(
+            "flv://mysql.local/shop/pets",
+            Operation::Update(UpdateRows {
+                rows: vec![BeforeAfterCols {
+                    before_cols: pet(9, "Rex", Value::Null),
+                    after_cols: pet(9, "Max", Value::Null),
+                }],
+            }),
+        );
+        offset_store.set_sequence("mysql.local", 5);
+        assert!(sink.apply_message(&conflict, &mut offset_store).is_err());
+        assert_eq!(offset_store.offset(), 4);
+
+        // offset and sequences saved in the file
+        let mut offset_store = sink.offset_store("cdc", 0).unwrap();
+        assert_eq!(offset_store.offset(), 4);
+        assert_eq!(offset_store.sequences().get("mysql.local"), Some(&4));
+
+        // message not applied => offset saved
+        sink.checkpoint(&mut offset_store).unwrap();
+        assert_eq!(sink.offset_store("cdc", 0).unwrap().offset(), 5);
+
+        let mut stmt = sink
+            .conn
+            .prepare("SELECT id, name, born FROM pets")
+            .unwrap();
+        let rows: Vec<(i64, String, Option<String>)> = stmt
+            .query_map(rusqlite::NO_PARAMS, |row| {
+                Ok((row.get(0)?, row.get(1)?, row.get(2)?))
+            })
+            .unwrap()
+            .map(|row| row.unwrap())
+            .collect();
+        assert_eq!(rows, vec![(2, "Bowser".to_owned(), None)]);
+
+        std::fs::remove_file(&sqlite_file).expect("delete file failed");
+    }
+}
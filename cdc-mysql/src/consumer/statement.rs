@@ -0,0 +1,358 @@
+//! Statement Builder
+//!
+//! Prepared statements of row operations, shared by the SQL sinks:
+//!  - Add: "INSERT INTO" one per row
+//!  - Update: "UPDATE" one per row, SET the changed columns WHERE the before image matches
+//!  - Delete: "DELETE" one per row, WHERE the row matches
+//!
+//! The syntax that differs between databases (identifier quotes, placeholders, upserts,
+//! single row match) is provided by the Dialect of the sink.
+//!
+//! Statements are executed by the sink, in the transaction of the message (see exec_queries):
+//! row updates and deletes are checked by the conflict handler.
+//!
+use std::io::{Error, ErrorKind};
+
+use crate::consumer::conflict_handler::{ConflictHandler, Resolution};
+use crate::consumer::profile::ApplyMode;
+use crate::consumer::sink::{check_valid_col_count, check_valid_cols, key_indexes, same_key};
+use crate::consumer::uri::UriProps;
+use crate::messages::{DeleteRows, UpdateRows, WriteRows};
+use crate::messages::{FluvioMessage, Operation, Value};
+
+/// SQL syntax of the target database
+pub trait Dialect {
+    /// Parameter value of the database client
+    type Param;
+
+    /// Quote table or column name
+    fn quote_identifier(&self, name: &str) -> String;
+
+    /// Table of the (routed) uri, as written in statements
+    fn table(&self, uri_props: &UriProps) -> Result<String, Error>;
+
+    /// Placeholder of the value in the statement, the value is added to the parameters
+    fn bind(&self, value: &Value, params: &mut Vec<Self::Param>) -> Result<String, Error>;
+
+    /// Upsert syntax of an insert of the (quoted) columns, with the indexes of the key
+    /// columns if known: the insert verb, and the clause appended to the statement
+    fn upsert(&self, columns: &[String], key_idxs: Option<&[usize]>) -> (&'static str, String);
+
+    /// WHERE clause that matches one row only (tables without key may have duplicate rows)
+    fn where_one_row(&self, table: &str, conditions: &str) -> String;
+}
+
+/// Statement kind: row updates and deletes are checked by the conflict handler
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StatementKind {
+    Query,
+    Insert,
+    Update,
+    Delete,
+}
+
+/// Prepared statement, its kind and its parameters
+#[derive(Debug, PartialEq)]
+pub struct PreparedQuery<P> {
+    pub kind: StatementKind,
+    pub query: String,
+    pub params: Vec<P>,
+}
+
+impl<P> PreparedQuery<P> {
+    /// Statement without parameters (DDL)
+    pub fn statement(query: String) -> Self {
+        Self {
+            kind: StatementKind::Query,
+            query,
+            params: vec![],
+        }
+    }
+
+    /// Query of a row update or delete, expected to match exactly one row
+    ///  - upsert statements of updates insert the row if missing, they are not checked
+    pub fn matches_row(&self) -> bool {
+        matches!(self.kind, StatementKind::Update | StatementKind::Delete)
+    }
+}
+
+/// Builds the statements of row operations in the dialect of the sink
+pub struct StatementBuilder<D> {
+    dialect: D,
+    mode: ApplyMode,
+}
+
+impl<D: Dialect> StatementBuilder<D> {
+    pub fn new(dialect: D, mode: ApplyMode) -> Self {
+        Self { dialect, mode }
+    }
+
+    /// Statements of a row operation (Add, Update, Delete) on the table of the (routed) uri,
+    /// query operations are translated by the sink
+    pub fn build_row_queries(
+        &self,
+        uri_props: &UriProps,
+        flv_message: &FluvioMessage,
+    ) -> Result<Vec<PreparedQuery<D::Param>>, Error> {
+        check_valid_cols(&flv_message.columns)?;
+        let cols = flv_message.columns.as_ref().unwrap();
+        let keys = flv_message.key_columns.as_deref();
+        let table = self.dialect.table(uri_props)?;
+
+        match &flv_message.operation {
+            Operation::Add(data) => self.build_add_queries(&table, cols, keys, data),
+            Operation::Update(data) => self.build_update_queries(&table, cols, keys, data),
+            Operation::Delete(data) => self.build_delete_queries(&table, cols, keys, data),
+            Operation::Query(_) => Ok(vec![]),
+        }
+    }
+
+    /// Build "INSERT INTO" prepared statements, one per row
+    pub fn build_add_queries(
+        &self,
+        table: &str,
+        cols: &[String],
+        keys: Option<&[String]>,
+        data: &WriteRows,
+    ) -> Result<Vec<PreparedQuery<D::Param>>, Error> {
+        let mut queries: Vec<PreparedQuery<D::Param>> = vec![];
+
+        for row in &data.rows {
+            check_valid_col_count(row.cols.len(), cols.len())?;
+
+            queries.push(self.build_insert(table, cols, keys, &row.cols, self.mode)?);
+        }
+
+        Ok(queries)
+    }
+
+    /// Build "UPDATE" prepared statements, one per row
+    ///  - SET changed columns, or all columns if none changed
+    ///  - WHERE the before image matches (see build_where)
+    ///  - upsert mode: rows with an unchanged key are inserted, or updated if they exist
+    pub fn build_update_queries(
+        &self,
+        table: &str,
+        cols: &[String],
+        keys: Option<&[String]>,
+        data: &UpdateRows,
+    ) -> Result<Vec<PreparedQuery<D::Param>>, Error> {
+        let mut queries: Vec<PreparedQuery<D::Param>> = vec![];
+
+        for row in &data.rows {
+            check_valid_col_count(row.before_cols.len(), cols.len())?;
+            check_valid_col_count(row.after_cols.len(), cols.len())?;
+
+            if self.mode == ApplyMode::Upsert
+                && same_key(cols, keys, &row.before_cols, &row.after_cols)
+            {
+                queries.push(self.build_insert(table, cols, keys, &row.after_cols, self.mode)?);
+                continue;
+            }
+
+            let changed: Vec<(&String, &Value)> = cols
+                .iter()
+                .zip(&row.before_cols)
+                .zip(&row.after_cols)
+                .filter(|((_, before_value), after_value)| before_value != after_value)
+                .map(|((col, _), after_value)| (col, after_value))
+                .collect();
+            let set_values = if changed.is_empty() {
+                cols.iter().zip(&row.after_cols).collect()
+            } else {
+                changed
+            };
+
+            let mut set_cols: Vec<String> = vec![];
+            let mut params: Vec<D::Param> = vec![];
+            for (col, value) in set_values {
+                let placeholder = self.dialect.bind(value, &mut params)?;
+                set_cols.push(format!(
+                    "{}={}",
+                    self.dialect.quote_identifier(col),
+                    placeholder
+                ));
+            }
+
+            let where_clause =
+                self.build_where(table, cols, keys, &row.before_cols, &mut params)?;
+            queries.push(PreparedQuery {
+                kind: StatementKind::Update,
+                query: format!(
+                    "UPDATE {} SET {} {}",
+                    table,
+                    set_cols.join(", "),
+                    where_clause
+                ),
+                params,
+            });
+        }
+
+        Ok(queries)
+    }
+
+    /// Build "DELETE" prepared statements, one per row (see build_where)
+    ///  - rows already deleted are not matched, deletes are idempotent in all modes
+    pub fn build_delete_queries(
+        &self,
+        table: &str,
+        cols: &[String],
+        keys: Option<&[String]>,
+        data: &DeleteRows,
+    ) -> Result<Vec<PreparedQuery<D::Param>>, Error> {
+        let mut queries: Vec<PreparedQuery<D::Param>> = vec![];
+
+        for row in &data.rows {
+            check_valid_col_count(row.cols.len(), cols.len())?;
+
+            let mut params: Vec<D::Param> = vec![];
+            let where_clause = self.build_where(table, cols, keys, &row.cols, &mut params)?;
+            queries.push(PreparedQuery {
+                kind: StatementKind::Delete,
+                query: format!("DELETE FROM {} {}", table, where_clause),
+                params,
+            });
+        }
+
+        Ok(queries)
+    }
+
+    /// Build "INSERT INTO" prepared statement for the row values
+    ///  - upsert mode: existing rows with the same key are updated (see Dialect::upsert)
+    fn build_insert(
+        &self,
+        table: &str,
+        cols: &[String],
+        keys: Option<&[String]>,
+        values: &[Value],
+        mode: ApplyMode,
+    ) -> Result<PreparedQuery<D::Param>, Error> {
+        let columns: Vec<String> = cols[..values.len()]
+            .iter()
+            .map(|col| self.dialect.quote_identifier(col))
+            .collect();
+        let mut params: Vec<D::Param> = vec![];
+        let mut placeholders: Vec<String> = vec![];
+        for value in values {
+            placeholders.push(self.dialect.bind(value, &mut params)?);
+        }
+
+        let (insert, clause) = match mode {
+            ApplyMode::Strict => ("INSERT", String::new()),
+            ApplyMode::Upsert => {
+                let key_idxs = key_indexes(cols, keys, values.len());
+                self.dialect.upsert(&columns, key_idxs.as_deref())
+            }
+        };
+
+        Ok(PreparedQuery {
+            kind: StatementKind::Insert,
+            query: format!(
+                "{} INTO {} ({}) VALUES ({}){}",
+                insert,
+                table,
+                columns.join(", "),
+                placeholders.join(", "),
+                clause
+            ),
+            params,
+        })
+    }
+
+    /// Build "INSERT INTO" prepared statement for the new row of an update that matched no row
+    pub fn build_conflict_insert(
+        &self,
+        flv_message: &FluvioMessage,
+        table: &str,
+        row: usize,
+    ) -> Result<PreparedQuery<D::Param>, Error> {
+        check_valid_cols(&flv_message.columns)?;
+        let cols = flv_message.columns.as_ref().unwrap();
+
+        match &flv_message.operation {
+            Operation::Update(data) if row < data.rows.len() => {
+                let values = &data.rows[row].after_cols;
+                check_valid_col_count(values.len(), cols.len())?;
+                self.build_insert(table, cols, None, values, ApplyMode::Strict)
+            }
+            _ => Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("expected update row {}, found none", row),
+            )),
+        }
+    }
+
+    /// Build WHERE clause that matches the row values, NULL values are matched with "IS NULL"
+    ///  - key columns, if all of them are in the message
+    ///  - otherwise all columns, limited to one row (see Dialect::where_one_row)
+    fn build_where(
+        &self,
+        table: &str,
+        cols: &[String],
+        keys: Option<&[String]>,
+        values: &[Value],
+        params: &mut Vec<D::Param>,
+    ) -> Result<String, Error> {
+        let (idxs, has_key) = match key_indexes(cols, keys, values.len()) {
+            Some(idxs) => (idxs, true),
+            None => ((0..cols.len().min(values.len())).collect(), false),
+        };
+
+        let mut where_cols: Vec<String> = vec![];
+        for idx in idxs {
+            let (col, value) = (self.dialect.quote_identifier(&cols[idx]), &values[idx]);
+            match value {
+                Value::Null => where_cols.push(format!("{} IS NULL", col)),
+                _ => {
+                    let placeholder = self.dialect.bind(value, params)?;
+                    where_cols.push(format!("{}={}", col, placeholder));
+                }
+            };
+        }
+
+        let conditions = where_cols.join(" AND ");
+        if has_key {
+            Ok(format!("WHERE {}", conditions))
+        } else {
+            Ok(self.dialect.where_one_row(table, &conditions))
+        }
+    }
+
+    /// Execute the statements of the message with `exec`, which returns the affected rows.
+    ///
+    /// Row updates and deletes are expected to match one row, the others are resolved by
+    /// the conflict handler. In upsert mode, deletes of missing rows are no-ops.
+    pub fn exec_queries<F>(
+        &self,
+        conflict_handler: &mut ConflictHandler,
+        flv_message: &FluvioMessage,
+        uri_props: &UriProps,
+        queries: &[PreparedQuery<D::Param>],
+        mut exec: F,
+    ) -> Result<(), Error>
+    where
+        F: FnMut(&PreparedQuery<D::Param>) -> Result<u64, Error>,
+    {
+        for (row, query) in queries.iter().enumerate() {
+            let affected_rows = exec(query)?;
+
+            if !query.matches_row() {
+                continue;
+            }
+            if self.mode == ApplyMode::Upsert
+                && affected_rows == 0
+                && query.kind == StatementKind::Delete
+            {
+                continue;
+            }
+
+            let resolution = conflict_handler.resolve(flv_message, row, affected_rows)?;
+            if resolution == Resolution::Insert {
+                let table = self.dialect.table(uri_props)?;
+                exec(&self.build_conflict_insert(flv_message, &table, row)?)?;
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,196 @@
+//! Table Offset Store
+//!
+//! The offset of the next record is kept in a table of the target database, one row per
+//! topic partition, and the last sequence of each source in `<table>_sequences`. Both are
+//! updated in the transaction that applies the record, so a crash cannot separate the row
+//! changes from the offset and the sequence.
+//!
+use mysql::prelude::*;
+use mysql::{Conn, Transaction};
+use std::collections::BTreeMap;
+use std::io::{Error, ErrorKind};
+
+use crate::consumer::mysql_manager::quote_identifier;
+use crate::consumer::offset_store::{OffsetStore, Sequences};
+use crate::consumer::retry::mysql_error;
+
+#[derive(Debug)]
+pub struct TableOffsetStore {
+    table: String,
+    sequence_table: String,
+    topic: String,
+    partition: i32,
+    offset: i64,
+    sequences: Sequences,
+}
+
+impl TableOffsetStore {
+    /// Create offset table (and database) if needed and read the offset of topic partition
+    pub fn init(conn: &mut Conn, table: &str, topic: &str, partition: i32) -> Result<Self, Error> {
+        let (db_name, table_name) = split_table_name(table)?;
+        let table = format!(
+            "{}.{}",
+            quote_identifier(db_name),
+            quote_identifier(table_name)
+        );
+        let sequence_table = format!(
+            "{}.{}",
+            quote_identifier(db_name),
+            quote_identifier(&format!("{}_sequences", table_name))
+        );
+
+        exec(conn.query_drop(format!(
+            "CREATE DATABASE IF NOT EXISTS {}",
+            quote_identifier(db_name)
+        )))?;
+        exec(conn.query_drop(format!(
+            "CREATE TABLE IF NOT EXISTS {} (\
+             topic VARCHAR(255) NOT NULL, \
+             partition_id INT NOT NULL, \
+             next_offset BIGINT NOT NULL, \
+             PRIMARY KEY (topic, partition_id))",
+            table
+        )))?;
+        exec(conn.query_drop(format!(
+            "CREATE TABLE IF NOT EXISTS {} (\
+             topic VARCHAR(255) NOT NULL, \
+             partition_id INT NOT NULL, \
+             source VARCHAR(255) NOT NULL, \
+             last_sequence BIGINT UNSIGNED NOT NULL, \
+             PRIMARY KEY (topic, partition_id, source))",
+            sequence_table
+        )))?;
+
+        let mut store = Self {
+            table,
+            sequence_table,
+            topic: topic.to_owned(),
+            partition,
+            offset: 0,
+            sequences: Sequences::default(),
+        };
+        store.read(conn)?;
+        Ok(store)
+    }
+
+    /// Read the offset and sequences of topic partition, the offset row is created if missing
+    fn read(&mut self, conn: &mut Conn) -> Result<(), Error> {
+        let offset: Option<i64> = exec(conn.exec_first(
+            format!(
+                "SELECT next_offset FROM {} WHERE topic=? AND partition_id=?",
+                self.table
+            ),
+            (&self.topic, self.partition),
+        ))?;
+        self.offset = match offset {
+            Some(offset) => offset,
+            None => {
+                exec(conn.exec_drop(
+                    format!(
+                        "INSERT INTO {} (topic, partition_id, next_offset) VALUES (?, ?, 0)",
+                        self.table
+                    ),
+                    (&self.topic, self.partition),
+                ))?;
+                0
+            }
+        };
+
+        let sequences: Vec<(String, u64)> = exec(conn.exec(
+            format!(
+                "SELECT source, last_sequence FROM {} WHERE topic=? AND partition_id=?",
+                self.sequence_table
+            ),
+            (&self.topic, self.partition),
+        ))?;
+        self.sequences.reload(sequences.into_iter().collect());
+        Ok(())
+    }
+}
+
+impl OffsetStore for TableOffsetStore {
+    fn offset(&self) -> i64 {
+        self.offset
+    }
+
+    fn is_transactional(&self) -> bool {
+        true
+    }
+
+    fn save_next_offset(&mut self, tx: &mut Transaction<'_>) -> Result<(), Error> {
+        if let Some((source, sequence)) = self.current_sequence() {
+            exec(tx.exec_drop(
+                format!(
+                    "INSERT INTO {} (topic, partition_id, source, last_sequence) \
+                     VALUES (?, ?, ?, ?) \
+                     ON DUPLICATE KEY UPDATE last_sequence=VALUES(last_sequence)",
+                    self.sequence_table
+                ),
+                (&self.topic, self.partition, source, sequence),
+            ))?;
+        }
+
+        exec(tx.exec_drop(
+            format!(
+                "UPDATE {} SET next_offset=? WHERE topic=? AND partition_id=?",
+                self.table
+            ),
+            (self.offset + 1, &self.topic, self.partition),
+        ))
+    }
+
+    fn sequences(&self) -> &BTreeMap<String, u64> {
+        self.sequences.saved()
+    }
+
+    fn set_sequence(&mut self, source: &str, sequence: u64) {
+        self.sequences.set_current(source, sequence);
+    }
+
+    fn current_sequence(&self) -> Option<(&str, u64)> {
+        self.sequences.current()
+    }
+
+    fn increment_offset(&mut self) -> Result<(), Error> {
+        self.sequences.commit();
+        self.offset += 1;
+        Ok(())
+    }
+
+    fn reload(&mut self, conn: &mut Conn) -> Result<(), Error> {
+        self.read(conn)
+    }
+}
+
+/// Split "db.table" into database and table names
+fn split_table_name(table: &str) -> Result<(&str, &str), Error> {
+    let mut parts = table.splitn(2, '.');
+    match (parts.next(), parts.next()) {
+        (Some(db_name), Some(table_name)) if !db_name.is_empty() && !table_name.is_empty() => {
+            Ok((db_name, table_name))
+        }
+        _ => Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!("offset table '{}': expected 'database.table'", table),
+        )),
+    }
+}
+
+fn exec<T>(result: Result<T, mysql::Error>) -> Result<T, Error> {
+    result.map_err(|err| mysql_error("Offset table", err))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_split_table_name() {
+        assert_eq!(
+            split_table_name("fluvio_cdc.consumer_offsets").unwrap(),
+            ("fluvio_cdc", "consumer_offsets")
+        );
+        assert!(split_table_name("consumer_offsets").is_err());
+        assert!(split_table_name(".consumer_offsets").is_err());
+    }
+}
@@ -0,0 +1,156 @@
+//! Message URI
+//!
+//! Database and table of a message (`flv://<mysql_resource_name>/<database>/<table>`),
+//! and database filters of the consumer.
+//!
+use http::Uri;
+use std::io::{Error, ErrorKind};
+
+use crate::consumer::profile::Filters;
+
+#[derive(Debug, PartialEq)]
+pub struct UriProps {
+    pub db_name: Option<String>,
+    pub table_name: Option<String>,
+}
+
+/// Parse URI for db_name & table_name
+///
+/// URI format
+///   uri::/<db-label>/<db_name>/<table_name>
+pub fn parse_uri(uri_str: &str) -> Result<UriProps, Error> {
+    let uri = uri_str
+        .parse::<Uri>()
+        .map_err(|err| Error::new(ErrorKind::InvalidData, format!("{}", err)))?;
+    let path: Vec<&str> = uri.path().split('/').collect();
+
+    let db_name: Option<String> = if path.len() > 1 && !path[1].is_empty() {
+        Some(path[1].to_owned())
+    } else {
+        None
+    };
+
+    let table_name: Option<String> = if path.len() > 2 && !path[2].is_empty() {
+        Some(path[2].to_owned())
+    } else {
+        None
+    };
+
+    Ok(UriProps {
+        db_name,
+        table_name,
+    })
+}
+
+/// Allowed by filters
+///  - no filters  => true
+///  - include filters => true for match, false otherwise
+///  - exclude filters => false for match, true otherwise
+pub fn allowed_by_filters(filters: &Option<Filters>, db_name: &Option<String>) -> bool {
+    // disallow all entries without a database
+    if db_name.is_none() {
+        return false;
+    }
+
+    let db_name = db_name.as_ref().unwrap();
+    let db_name = &db_name.to_ascii_lowercase();
+    if let Some(filters) = filters {
+        match filters {
+            Filters::Include { include_dbs: dbs } => dbs.contains(db_name),
+            Filters::Exclude { exclude_dbs: dbs } => !dbs.contains(db_name),
+        }
+    } else {
+        true
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_allowed_by_filters() {
+        // Test No db
+        assert_eq!(allowed_by_filters(&None, &None), false);
+
+        // Test No filters
+        let db_name = "db-xx".to_owned();
+        assert_eq!(allowed_by_filters(&None, &Some(db_name)), true);
+
+        // Test Include filters
+        let include_filters = Some(Filters::Include {
+            include_dbs: vec!["db-10".to_owned(), "db-11".to_owned()],
+        });
+
+        let db_name = "db-xx".to_owned();
+        assert_eq!(allowed_by_filters(&include_filters, &Some(db_name)), false);
+
+        let db_name = "db-10".to_owned();
+        assert_eq!(allowed_by_filters(&include_filters, &Some(db_name)), true);
+
+        let db_name = "db-11".to_owned();
+        assert_eq!(allowed_by_filters(&include_filters, &Some(db_name)), true);
+
+        let db_name = "db-1111".to_owned();
+        assert_eq!(allowed_by_filters(&include_filters, &Some(db_name)), false);
+
+        let db_name = "db-20".to_owned();
+        assert_eq!(allowed_by_filters(&include_filters, &Some(db_name)), false);
+
+        // Test Exclude filters
+        let exclude_filters = Some(Filters::Exclude {
+            exclude_dbs: vec!["db-10".to_owned(), "db-11".to_owned()],
+        });
+
+        let db_name = "db-xx".to_owned();
+        assert_eq!(allowed_by_filters(&exclude_filters, &Some(db_name)), true);
+
+        let db_name = "db-10".to_owned();
+        assert_eq!(allowed_by_filters(&exclude_filters, &Some(db_name)), false);
+
+        let db_name = "db-11".to_owned();
+        assert_eq!(allowed_by_filters(&exclude_filters, &Some(db_name)), false);
+
+        let db_name = "db-1111".to_owned();
+        assert_eq!(allowed_by_filters(&exclude_filters, &Some(db_name)), true);
+
+        let db_name = "db-20".to_owned();
+        assert_eq!(allowed_by_filters(&exclude_filters, &Some(db_name)), true);
+    }
+
+    #[test]
+    fn test_parse_uri() {
+        // Test both
+        let uri = "flv://mysql.local/testdb/people".to_owned();
+        let uri_props = parse_uri(&uri);
+        assert!(uri_props.is_ok());
+
+        let expected = UriProps {
+            db_name: Some("testdb".to_owned()),
+            table_name: Some("people".to_owned()),
+        };
+        assert_eq!(uri_props.unwrap(), expected);
+
+        // Test Db-only
+        let uri = "flv://mysql.local/flvTest".to_owned();
+        let uri_props = parse_uri(&uri);
+        assert!(uri_props.is_ok());
+
+        let expected = UriProps {
+            db_name: Some("flvTest".to_owned()),
+            table_name: None,
+        };
+        assert_eq!(uri_props.unwrap(), expected);
+
+        // Test none
+        let uri = "flv://mysql.local/".to_owned();
+        let uri_props = parse_uri(&uri);
+        assert!(uri_props.is_ok());
+
+        let expected = UriProps {
+            db_name: None,
+            table_name: None,
+        };
+        assert_eq!(uri_props.unwrap(), expected);
+    }
+}
@@ -38,6 +38,8 @@ pub enum CdcError {
     ResumeError { source: IoError },
     #[error("Binlog file error")]
     BinlogFileError { source: IoError },
+    #[error("Replication client error")]
+    ReplicationError { source: IoError },
     #[error("CDC config error")]
     ConfigError { source: IoError },
     #[error("Sql Parser error")]
@@ -1,15 +1,40 @@
 use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+use crate::messages::{Gtid, GtidSet, Transaction};
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct BnFile {
     pub file_name: String,
     pub offset: Option<u64>,
+
+    /// transactions completed, including the one ended by this event
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub gtid_set: Option<GtidSet>,
+
+    /// transaction of this event
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub gtid: Option<Gtid>,
+
+    /// position of this event in its transaction
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub transaction: Option<Transaction>,
+
+    /// time of the event, in seconds since the epoch
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timestamp: Option<u32>,
 }
 
 impl BnFile {
     #![allow(dead_code)] // used in unit tests
     pub fn new(file_name: String, offset: Option<u64>) -> Self {
-        Self { file_name, offset }
+        Self {
+            file_name,
+            offset,
+            gtid_set: None,
+            gtid: None,
+            transaction: None,
+            timestamp: None,
+        }
     }
 }
@@ -1,17 +1,28 @@
 use http::uri::Parts;
 use http::Uri;
 use serde::{Deserialize, Serialize};
+use std::io::Error;
 
-use crate::messages::{BnFile, Operation};
+use crate::messages::{decode_versioned, BnFile, ColumnType, Operation, SCHEMA_VERSION};
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct BinLogMessage {
+    /// shape of the record, 0 for records without version
+    #[serde(default)]
+    pub schema_version: u32,
+
     pub uri: String,
     pub bn_file: BnFile,
 
     #[serde(skip_serializing_if = "Option::is_none")]
     pub columns: Option<Vec<String>>,
 
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub column_types: Option<Vec<ColumnType>>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub key_columns: Option<Vec<String>>,
+
     pub operation: Operation,
 }
 
@@ -20,21 +31,37 @@ impl BinLogMessage {
         mysql_resource_name: &str,
         db_name: &str,
         table_name: Option<&str>,
-        file_name: &str,
-        offset: Option<u64>,
+        bn_file: BnFile,
         columns: Option<Vec<String>>,
         operation: Operation,
     ) -> Self {
         Self {
+            schema_version: SCHEMA_VERSION,
             uri: make_uri(mysql_resource_name, db_name, table_name),
-            bn_file: BnFile {
-                file_name: file_name.to_string(),
-                offset,
-            },
+            bn_file,
             columns,
+            column_types: None,
+            key_columns: None,
             operation,
         }
     }
+
+    /// Decode a record of any schema version up to SCHEMA_VERSION
+    pub fn decode(json: &str) -> Result<Self, Error> {
+        decode_versioned(json)
+    }
+
+    /// Column types, aligned with columns
+    pub fn with_column_types(mut self, column_types: Option<Vec<ColumnType>>) -> Self {
+        self.column_types = column_types;
+        self
+    }
+
+    /// Columns that identify a row (primary or unique key)
+    pub fn with_key_columns(mut self, key_columns: Option<Vec<String>>) -> Self {
+        self.key_columns = key_columns;
+        self
+    }
 }
 
 fn make_uri(mysql_resource_name: &str, db_name: &str, table_name: Option<&str>) -> String {
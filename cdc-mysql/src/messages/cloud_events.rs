@@ -0,0 +1,253 @@
+//! CloudEvents Envelope
+//!
+//! CloudEvents 1.0 JSON events built from the binlog messages of the producer:
+//!  - rows events: one event per row, of type "mysql.row.insert", "mysql.row.update" or
+//!    "mysql.row.delete". The data is the row keyed by column name, or {"before", "after"}
+//!    for updates.
+//!  - statements: one "mysql.query" event, with {"query": ..} as data.
+//!
+//! The source is the uri of the message (flv://<name>/<db>[/<table>]), the id is
+//! "<sequence>-<row>", and the time is the binlog event timestamp.
+//!
+//! Each event carries the sequence of the message in the "sequence" extension, a string of
+//! 20 digits (extension attributes are strings or 32 bit integers) that orders as a number, and
+//! the last event of the message the binlog position (JSON encoded) in the "bnfile" extension,
+//! so the producer can resume from the topic. The schema version of the message (see
+//! schema_version) is in the "schemaversion" extension.
+//!
+use chrono::prelude::DateTime;
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value as JsonValue};
+use std::io::{Error, ErrorKind};
+use std::time::{Duration, UNIX_EPOCH};
+
+use crate::messages::row_ops::{message_columns, row_object};
+use crate::messages::{BinLogMessage, BnFile, Operation, SCHEMA_VERSION};
+
+const SPEC_VERSION: &str = "1.0";
+const CONTENT_TYPE: &str = "application/json";
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct CloudEvent {
+    pub specversion: String,
+    pub id: String,
+    pub source: String,
+
+    #[serde(rename = "type")]
+    pub event_type: String,
+
+    pub datacontenttype: String,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub time: Option<String>,
+
+    /// sequence of the message, zero padded to 20 digits (extension)
+    pub sequence: String,
+
+    /// schema version of the message, 0 for events published before the field (extension)
+    #[serde(default)]
+    pub schemaversion: u32,
+
+    /// binlog position of the message, JSON encoded, on its last event (extension)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bnfile: Option<String>,
+
+    pub data: JsonValue,
+}
+
+impl CloudEvent {
+    /// Sequence of the message
+    pub fn sequence(&self) -> Result<u64, Error> {
+        self.sequence.parse::<u64>().map_err(|err| {
+            Error::new(
+                ErrorKind::InvalidData,
+                format!("invalid sequence {:?}: {}", self.sequence, err),
+            )
+        })
+    }
+
+    /// Binlog position of the message, None for events before the last one
+    pub fn bn_file(&self) -> Result<Option<BnFile>, Error> {
+        match self.bnfile.as_ref() {
+            Some(bnfile) => serde_json::from_str(bnfile)
+                .map(Some)
+                .map_err(|err| Error::new(ErrorKind::InvalidData, err)),
+            None => Ok(None),
+        }
+    }
+}
+
+/// CloudEvents of a binlog message with its sequence
+pub fn cloud_events(bn_message: &BinLogMessage, sequence: u64) -> Result<Vec<CloudEvent>, Error> {
+    let time = bn_message.bn_file.timestamp.map(event_time);
+    let event = |event_type: &str, row: usize, data: JsonValue| CloudEvent {
+        specversion: SPEC_VERSION.to_owned(),
+        id: format!("{}-{}", sequence, row),
+        source: bn_message.uri.clone(),
+        event_type: event_type.to_owned(),
+        datacontenttype: CONTENT_TYPE.to_owned(),
+        time: time.clone(),
+        sequence: format!("{:020}", sequence),
+        schemaversion: SCHEMA_VERSION,
+        bnfile: None,
+        data,
+    };
+
+    let mut events = vec![];
+    match &bn_message.operation {
+        Operation::Query(query) => {
+            let mut data = Map::new();
+            data.insert("query".to_owned(), JsonValue::String(query.clone()));
+            events.push(event("mysql.query", 0, JsonValue::Object(data)));
+        }
+        Operation::Add(data) => {
+            let columns = message_columns(bn_message)?;
+            for (row, cols) in data.rows.iter().enumerate() {
+                let after = row_object(columns, &cols.cols);
+                events.push(event("mysql.row.insert", row, JsonValue::Object(after)));
+            }
+        }
+        Operation::Update(data) => {
+            let columns = message_columns(bn_message)?;
+            for (row, cols) in data.rows.iter().enumerate() {
+                let mut data = Map::new();
+                let before = row_object(columns, &cols.before_cols);
+                let after = row_object(columns, &cols.after_cols);
+                data.insert("before".to_owned(), JsonValue::Object(before));
+                data.insert("after".to_owned(), JsonValue::Object(after));
+                events.push(event("mysql.row.update", row, JsonValue::Object(data)));
+            }
+        }
+        Operation::Delete(data) => {
+            let columns = message_columns(bn_message)?;
+            for (row, cols) in data.rows.iter().enumerate() {
+                let before = row_object(columns, &cols.cols);
+                events.push(event("mysql.row.delete", row, JsonValue::Object(before)));
+            }
+        }
+    }
+
+    if let Some(last) = events.last_mut() {
+        let bnfile = serde_json::to_string(&bn_message.bn_file)
+            .map_err(|err| Error::new(ErrorKind::InvalidData, err))?;
+        last.bnfile = Some(bnfile);
+    }
+    Ok(events)
+}
+
+/// RFC 3339 time of a binlog event timestamp
+fn event_time(timestamp: u32) -> String {
+    let d = UNIX_EPOCH + Duration::from_secs(u64::from(timestamp));
+    let datetime = DateTime::<Utc>::from(d);
+    datetime.format("%Y-%m-%dT%H:%M:%SZ").to_string()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::messages::{BeforeAfterCols, Cols, DeleteRows, UpdateRows, Value, WriteRows};
+    use serde_json::json;
+
+    fn bn_message(table: Option<&str>, operation: Operation) -> BinLogMessage {
+        let mut bn_file = BnFile::new("binlog.000003".to_owned(), Some(832));
+        bn_file.timestamp = Some(1_600_000_000);
+        let columns = table.map(|_| vec!["id".to_owned(), "name".to_owned()]);
+        BinLogMessage::new("mysql-srv1", "flvDb", table, bn_file, columns, operation)
+    }
+
+    fn pet(id: i64, name: &str) -> Vec<Value> {
+        vec![Value::SignedInteger(id), Value::String(name.to_owned())]
+    }
+
+    #[test]
+    fn test_row_events() {
+        let msg = bn_message(
+            Some("pet"),
+            Operation::Add(WriteRows {
+                rows: vec![
+                    Cols {
+                        cols: pet(1, "Puffball"),
+                    },
+                    Cols {
+                        cols: pet(2, "Jack"),
+                    },
+                ],
+            }),
+        );
+        let events = cloud_events(&msg, 5).unwrap();
+        assert_eq!(events.len(), 2);
+
+        let expected = json!({
+            "specversion": "1.0",
+            "id": "5-1",
+            "source": "flv://mysql-srv1/flvDb/pet",
+            "type": "mysql.row.insert",
+            "datacontenttype": "application/json",
+            "time": "2020-09-13T12:26:40Z",
+            "sequence": "00000000000000000005",
+            "schemaversion": 1,
+            "bnfile": "{\"fileName\":\"binlog.000003\",\"offset\":832,\"timestamp\":1600000000}",
+            "data": {"id": 2, "name": "Jack"}
+        });
+        assert_eq!(serde_json::to_value(&events[1]).unwrap(), expected);
+
+        // position on the last event only
+        assert_eq!(events[0].id, "5-0");
+        assert_eq!(events[0].sequence().unwrap(), 5);
+        assert_eq!(events[0].bn_file().unwrap(), None);
+        assert_eq!(events[1].bn_file().unwrap(), Some(msg.bn_file.clone()));
+
+        let msg = bn_message(
+            Some("pet"),
+            Operation::Update(UpdateRows {
+                rows: vec![BeforeAfterCols {
+                    before_cols: pet(1, "Puffball"),
+                    after_cols: pet(1, "Fluffy"),
+                }],
+            }),
+        );
+        let events = cloud_events(&msg, 6).unwrap();
+        assert_eq!(events[0].event_type, "mysql.row.update");
+        assert_eq!(
+            events[0].data,
+            json!({
+                "before": {"id": 1, "name": "Puffball"},
+                "after": {"id": 1, "name": "Fluffy"}
+            })
+        );
+
+        let msg = bn_message(
+            Some("pet"),
+            Operation::Delete(DeleteRows {
+                rows: vec![Cols {
+                    cols: pet(1, "Fluffy"),
+                }],
+            }),
+        );
+        let events = cloud_events(&msg, 7).unwrap();
+        assert_eq!(events[0].event_type, "mysql.row.delete");
+        assert_eq!(events[0].data, json!({"id": 1, "name": "Fluffy"}));
+    }
+
+    #[test]
+    fn test_query_event() {
+        let query = "ALTER TABLE pet ADD COLUMN last_vaccine DATE".to_owned();
+        let mut msg = bn_message(None, Operation::Query(query.clone()));
+        msg.bn_file.timestamp = None;
+        let events = cloud_events(&msg, 8).unwrap();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].event_type, "mysql.query");
+        assert_eq!(events[0].source, "flv://mysql-srv1/flvDb");
+        assert_eq!(events[0].data, json!({ "query": query }));
+
+        // no timestamp => no time attribute
+        let encoded = serde_json::to_string(&events[0]).unwrap();
+        assert!(!encoded.contains("\"time\""));
+
+        // events read back from the topic
+        let decoded: CloudEvent = serde_json::from_str(&encoded).unwrap();
+        assert_eq!(decoded, events[0]);
+        assert_eq!(decoded.bn_file().unwrap(), Some(msg.bn_file.clone()));
+    }
+}
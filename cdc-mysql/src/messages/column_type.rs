@@ -0,0 +1,32 @@
+use serde::{Deserialize, Serialize};
+
+/// Column type from the binlog table map
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ColumnType {
+    /// MySQL type name, with precision for decimals and fractional seconds for times
+    pub data_type: String,
+    pub nullable: bool,
+
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub unsigned: bool,
+
+    /// only known with binlog_row_metadata=FULL
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub primary_key: bool,
+}
+
+impl ColumnType {
+    pub fn new(data_type: &str, nullable: bool) -> Self {
+        Self {
+            data_type: data_type.to_owned(),
+            nullable,
+            unsigned: false,
+            primary_key: false,
+        }
+    }
+}
+
+fn is_false(value: &bool) -> bool {
+    !*value
+}
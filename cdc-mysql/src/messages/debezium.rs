@@ -0,0 +1,408 @@
+//! Debezium Envelope
+//!
+//! Records in the shape of the Debezium MySQL connector, built from the binlog messages of
+//! the producer:
+//!  - rows events: one change event per row, with before/after objects keyed by column name,
+//!    op "c" (insert), "u" (update) or "d" (delete), and a source block with the binlog file,
+//!    position, row and table. Each delete is followed by a tombstone. Updates of the key are
+//!    a delete, its tombstone, and an insert.
+//!  - statements: schema change events, with the database name and the ddl.
+//!
+//! Fluvio records have no key, so each record carries the key and the value of the Debezium
+//! record: {"key": {..}, "value": {..}}. The key holds the key columns of the row (null for
+//! tables without key) or the database name of a schema change. Tombstones have a null value.
+//!
+//! The source block also carries the sequence of the message, and the binlog position
+//! (bn_file) on the last event of the message, so the producer can resume from the topic,
+//! and the schema version of the message (see schema_version).
+//!
+use http::Uri;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value as JsonValue};
+use std::io::{Error, ErrorKind};
+
+use crate::messages::row_ops::{message_columns, row_object};
+use crate::messages::{BinLogMessage, BnFile, Operation, SCHEMA_VERSION};
+
+const CONNECTOR: &str = "mysql";
+const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+pub type DebeziumRow = Map<String, JsonValue>;
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct DebeziumRecord {
+    pub key: Option<JsonValue>,
+    pub value: Option<DebeziumEvent>,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[serde(untagged)]
+pub enum DebeziumEvent {
+    Change(ChangeEvent),
+    SchemaChange(SchemaChangeEvent),
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct ChangeEvent {
+    pub before: Option<DebeziumRow>,
+    pub after: Option<DebeziumRow>,
+    pub source: DebeziumSource,
+    pub op: String,
+    pub ts_ms: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct SchemaChangeEvent {
+    pub source: DebeziumSource,
+    #[serde(rename = "databaseName")]
+    pub database_name: String,
+    pub ddl: String,
+    pub ts_ms: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct DebeziumSource {
+    pub version: String,
+    pub connector: String,
+    pub name: String,
+    pub ts_ms: u64,
+    pub snapshot: String,
+    pub db: String,
+    pub table: Option<String>,
+    pub gtid: Option<String>,
+    pub file: String,
+    pub pos: u64,
+    pub row: u64,
+
+    /// sequence of the message
+    pub sequence: u64,
+
+    /// schema version of the message, 0 for records published before the field
+    #[serde(default)]
+    pub schema_version: u32,
+
+    /// binlog position of the message, on its last event
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bn_file: Option<BnFile>,
+}
+
+impl DebeziumRecord {
+    fn change(key: Option<JsonValue>, event: ChangeEvent) -> Self {
+        Self {
+            key,
+            value: Some(DebeziumEvent::Change(event)),
+        }
+    }
+
+    fn tombstone(key: JsonValue) -> Self {
+        Self {
+            key: Some(key),
+            value: None,
+        }
+    }
+
+    pub fn is_tombstone(&self) -> bool {
+        self.value.is_none()
+    }
+
+    /// Source block of the record, None for tombstones
+    pub fn source(&self) -> Option<&DebeziumSource> {
+        match self.value.as_ref()? {
+            DebeziumEvent::Change(event) => Some(&event.source),
+            DebeziumEvent::SchemaChange(event) => Some(&event.source),
+        }
+    }
+
+    fn source_mut(&mut self) -> Option<&mut DebeziumSource> {
+        match self.value.as_mut()? {
+            DebeziumEvent::Change(event) => Some(&mut event.source),
+            DebeziumEvent::SchemaChange(event) => Some(&mut event.source),
+        }
+    }
+}
+
+/// Debezium records of a binlog message with its sequence, ts_ms is the processing time
+pub fn debezium_records(
+    bn_message: &BinLogMessage,
+    sequence: u64,
+    ts_ms: u64,
+) -> Result<Vec<DebeziumRecord>, Error> {
+    let (name, db, table) = split_uri(&bn_message.uri)?;
+    let bn_file = &bn_message.bn_file;
+    let source = |row: u64| DebeziumSource {
+        version: VERSION.to_owned(),
+        connector: CONNECTOR.to_owned(),
+        name: name.clone(),
+        ts_ms: bn_file
+            .timestamp
+            .map_or(0, |timestamp| u64::from(timestamp) * 1000),
+        snapshot: "false".to_owned(),
+        db: db.clone(),
+        table: table.clone(),
+        gtid: bn_file.gtid.as_ref().map(|gtid| gtid.to_string()),
+        file: bn_file.file_name.clone(),
+        pos: bn_file.offset.unwrap_or_default(),
+        row,
+        sequence,
+        schema_version: SCHEMA_VERSION,
+        bn_file: None,
+    };
+    let change =
+        |before: Option<DebeziumRow>, after: Option<DebeziumRow>, op: &str, row: u64| ChangeEvent {
+            before,
+            after,
+            source: source(row),
+            op: op.to_owned(),
+            ts_ms,
+        };
+
+    let mut records = vec![];
+    match &bn_message.operation {
+        Operation::Query(query) => {
+            let mut key = Map::new();
+            key.insert("databaseName".to_owned(), JsonValue::String(db.clone()));
+            records.push(DebeziumRecord {
+                key: Some(JsonValue::Object(key)),
+                value: Some(DebeziumEvent::SchemaChange(SchemaChangeEvent {
+                    source: source(0),
+                    database_name: db.clone(),
+                    ddl: query.clone(),
+                    ts_ms,
+                })),
+            });
+        }
+        Operation::Add(data) => {
+            let columns = message_columns(bn_message)?;
+            for (row, cols) in data.rows.iter().enumerate() {
+                let after = row_object(columns, &cols.cols);
+                let key = row_key(bn_message, &after);
+                let event = change(None, Some(after), "c", row as u64);
+                records.push(DebeziumRecord::change(key, event));
+            }
+        }
+        Operation::Update(data) => {
+            let columns = message_columns(bn_message)?;
+            for (row, cols) in data.rows.iter().enumerate() {
+                let before = row_object(columns, &cols.before_cols);
+                let after = row_object(columns, &cols.after_cols);
+                let before_key = row_key(bn_message, &before);
+                let after_key = row_key(bn_message, &after);
+
+                if before_key == after_key {
+                    let event = change(Some(before), Some(after), "u", row as u64);
+                    records.push(DebeziumRecord::change(after_key, event));
+                } else {
+                    let event = change(Some(before), None, "d", row as u64);
+                    records.push(DebeziumRecord::change(before_key.clone(), event));
+                    records.extend(before_key.map(DebeziumRecord::tombstone));
+                    let event = change(None, Some(after), "c", row as u64);
+                    records.push(DebeziumRecord::change(after_key, event));
+                }
+            }
+        }
+        Operation::Delete(data) => {
+            let columns = message_columns(bn_message)?;
+            for (row, cols) in data.rows.iter().enumerate() {
+                let before = row_object(columns, &cols.cols);
+                let key = row_key(bn_message, &before);
+                let event = change(Some(before), None, "d", row as u64);
+                records.push(DebeziumRecord::change(key.clone(), event));
+                records.extend(key.map(DebeziumRecord::tombstone));
+            }
+        }
+    }
+
+    if let Some(source) = records
+        .iter_mut()
+        .rev()
+        .find_map(|record| record.source_mut())
+    {
+        source.bn_file = Some(bn_file.clone());
+    }
+    Ok(records)
+}
+
+/// Resource name, database and table of a message uri: flv://<name>/<db>[/<table>]
+fn split_uri(uri: &str) -> Result<(String, String, Option<String>), Error> {
+    let uri = uri
+        .parse::<Uri>()
+        .map_err(|err| Error::new(ErrorKind::InvalidData, format!("{}", err)))?;
+    let name = uri.host().unwrap_or_default().to_owned();
+    let mut path = uri.path().split('/').skip(1);
+    let db = path.next().unwrap_or_default().to_owned();
+    let table = path
+        .next()
+        .filter(|table| !table.is_empty())
+        .map(str::to_owned);
+
+    Ok((name, db, table))
+}
+
+/// Key columns of the row, None for tables without key
+fn row_key(bn_message: &BinLogMessage, row: &DebeziumRow) -> Option<JsonValue> {
+    let key_columns = bn_message
+        .key_columns
+        .as_ref()
+        .filter(|keys| !keys.is_empty())?;
+    let key: DebeziumRow = key_columns
+        .iter()
+        .map(|column| {
+            let value = row.get(column).cloned().unwrap_or(JsonValue::Null);
+            (column.clone(), value)
+        })
+        .collect();
+
+    Some(JsonValue::Object(key))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::messages::{BeforeAfterCols, Cols, DeleteRows, UpdateRows, Value, WriteRows};
+    use serde_json::json;
+
+    fn bn_message(table: Option<&str>, operation: Operation) -> BinLogMessage {
+        let mut bn_file = BnFile::new("binlog.000003".to_owned(), Some(832));
+        bn_file.timestamp = Some(1_600_000_000);
+        let columns = table.map(|_| vec!["id".to_owned(), "name".to_owned()]);
+        BinLogMessage::new("mysql-srv1", "flvDb", table, bn_file, columns, operation)
+            .with_key_columns(table.map(|_| vec!["id".to_owned()]))
+    }
+
+    fn pet(id: i64, name: &str) -> Vec<Value> {
+        vec![Value::SignedInteger(id), Value::String(name.to_owned())]
+    }
+
+    #[test]
+    fn test_change_events() {
+        let msg = bn_message(
+            Some("pet"),
+            Operation::Add(WriteRows {
+                rows: vec![
+                    Cols {
+                        cols: pet(1, "Puffball"),
+                    },
+                    Cols {
+                        cols: pet(2, "Jack"),
+                    },
+                ],
+            }),
+        );
+        let records = debezium_records(&msg, 5, 1_600_000_001_000).unwrap();
+        assert_eq!(records.len(), 2);
+
+        let expected = json!({
+            "key": {"id": 2},
+            "value": {
+                "before": null,
+                "after": {"id": 2, "name": "Jack"},
+                "source": {
+                    "version": VERSION,
+                    "connector": "mysql",
+                    "name": "mysql-srv1",
+                    "ts_ms": 1_600_000_000_000u64,
+                    "snapshot": "false",
+                    "db": "flvDb",
+                    "table": "pet",
+                    "gtid": null,
+                    "file": "binlog.000003",
+                    "pos": 832,
+                    "row": 1,
+                    "sequence": 5,
+                    "schema_version": 1,
+                    "bn_file": {"fileName": "binlog.000003", "offset": 832, "timestamp": 1_600_000_000}
+                },
+                "op": "c",
+                "ts_ms": 1_600_000_001_000u64
+            }
+        });
+        assert_eq!(serde_json::to_value(&records[1]).unwrap(), expected);
+
+        // position on the last event only
+        assert_eq!(records[0].source().unwrap().bn_file, None);
+        assert_eq!(
+            records[1].source().unwrap().bn_file,
+            Some(msg.bn_file.clone())
+        );
+
+        // update of the key => delete, tombstone, insert
+        let msg = bn_message(
+            Some("pet"),
+            Operation::Update(UpdateRows {
+                rows: vec![
+                    BeforeAfterCols {
+                        before_cols: pet(1, "Puffball"),
+                        after_cols: pet(1, "Fluffy"),
+                    },
+                    BeforeAfterCols {
+                        before_cols: pet(2, "Jack"),
+                        after_cols: pet(3, "Jack"),
+                    },
+                ],
+            }),
+        );
+        let records = debezium_records(&msg, 6, 0).unwrap();
+        let ops: Vec<Option<&str>> = records
+            .iter()
+            .map(|record| match record.value.as_ref() {
+                Some(DebeziumEvent::Change(event)) => Some(event.op.as_str()),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(ops, vec![Some("u"), Some("d"), None, Some("c")]);
+        assert_eq!(records[2].key, Some(json!({"id": 2})));
+        assert_eq!(records[3].key, Some(json!({"id": 3})));
+    }
+
+    #[test]
+    fn test_delete_tombstones() {
+        let msg = bn_message(
+            Some("pet"),
+            Operation::Delete(DeleteRows {
+                rows: vec![Cols {
+                    cols: pet(1, "Puffball"),
+                }],
+            }),
+        );
+        let records = debezium_records(&msg, 7, 0).unwrap();
+        assert_eq!(records.len(), 2);
+        assert!(records[1].is_tombstone());
+        assert_eq!(
+            serde_json::to_string(&records[1]).unwrap(),
+            "{\"key\":{\"id\":1},\"value\":null}"
+        );
+
+        // position on the delete, before its tombstone
+        let source = records[0].source().unwrap();
+        assert_eq!(source.bn_file, Some(msg.bn_file.clone()));
+
+        // tables without key => no tombstone
+        let mut msg = msg;
+        msg.key_columns = None;
+        let records = debezium_records(&msg, 7, 0).unwrap();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].key, None);
+    }
+
+    #[test]
+    fn test_schema_change_event() {
+        let query = "ALTER TABLE pet ADD COLUMN last_vaccine DATE".to_owned();
+        let msg = bn_message(None, Operation::Query(query.clone()));
+        let records = debezium_records(&msg, 8, 0).unwrap();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].key, Some(json!({"databaseName": "flvDb"})));
+
+        // records read back from the topic
+        let encoded = serde_json::to_string(&records[0]).unwrap();
+        let decoded: DebeziumRecord = serde_json::from_str(&encoded).unwrap();
+        assert_eq!(decoded, records[0]);
+        match decoded.value {
+            Some(DebeziumEvent::SchemaChange(event)) => {
+                assert_eq!(event.ddl, query);
+                assert_eq!(event.source.table, None);
+                assert_eq!(event.source.bn_file, Some(msg.bn_file.clone()));
+            }
+            _ => panic!("wrong variant"),
+        }
+    }
+}
@@ -1,9 +1,15 @@
 use serde::{Deserialize, Serialize};
+use std::io::Error;
 
-use crate::messages::{BinLogMessage, BnFile, Operation};
+use crate::messages::SCHEMA_VERSION;
+use crate::messages::{decode_versioned, BinLogMessage, BnFile, ColumnType, Operation};
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct FluvioMessage {
+    /// shape of the record, 0 for records without version
+    #[serde(default)]
+    pub schema_version: u32,
+
     pub uri: String,
     pub sequence: u64,
     pub bn_file: BnFile,
@@ -11,17 +17,73 @@ pub struct FluvioMessage {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub columns: Option<Vec<String>>,
 
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub column_types: Option<Vec<ColumnType>>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub key_columns: Option<Vec<String>>,
+
     pub operation: Operation,
 }
 
 impl FluvioMessage {
     pub fn new(bn_message: BinLogMessage, sequence: u64) -> Self {
         Self {
+            schema_version: SCHEMA_VERSION,
             uri: bn_message.uri,
             sequence,
             bn_file: bn_message.bn_file,
             columns: bn_message.columns,
+            column_types: bn_message.column_types,
+            key_columns: bn_message.key_columns,
             operation: bn_message.operation,
         }
     }
+
+    /// Decode a record of any schema version up to SCHEMA_VERSION
+    pub fn decode(json: &str) -> Result<Self, Error> {
+        decode_versioned(json)
+    }
+}
+
+/// Test messages: sequence 1 at position 4 of binlog.000001, without columns
+#[cfg(test)]
+impl FluvioMessage {
+    pub fn test_message(uri: &str, operation: Operation) -> Self {
+        Self {
+            schema_version: SCHEMA_VERSION,
+            uri: uri.to_owned(),
+            sequence: 1,
+            bn_file: BnFile::new("binlog.000001".to_owned(), Some(4)),
+            columns: None,
+            column_types: None,
+            key_columns: None,
+            operation,
+        }
+    }
+
+    pub fn with_sequence(mut self, sequence: u64) -> Self {
+        self.sequence = sequence;
+        self
+    }
+
+    pub fn with_position(mut self, position: u64) -> Self {
+        self.bn_file.offset = Some(position);
+        self
+    }
+
+    pub fn with_columns(mut self, columns: &[&str]) -> Self {
+        self.columns = Some(columns.iter().map(|col| col.to_string()).collect());
+        self
+    }
+
+    pub fn with_column_types(mut self, column_types: Vec<ColumnType>) -> Self {
+        self.column_types = Some(column_types);
+        self
+    }
+
+    pub fn with_keys(mut self, key_columns: &[&str]) -> Self {
+        self.key_columns = Some(key_columns.iter().map(|col| col.to_string()).collect());
+        self
+    }
 }
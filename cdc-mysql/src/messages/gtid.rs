@@ -0,0 +1,355 @@
+//!
+//! # Global Transaction Identifiers
+//!
+//! Both flavors are supported, and serialized in the format used by the server:
+//!  - MySQL: "<source_uuid>:<transaction_id>", sets as "<uuid>:1-5:7,<uuid2>:1-3"
+//!  - MariaDB: "<domain_id>-<server_id>-<seq_no>", sets as "0-1-100,1-2-7"
+//!
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+use std::fmt;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(into = "String", try_from = "String")]
+pub enum Gtid {
+    MySql {
+        sid: String,
+        gno: u64,
+    },
+    MariaDb {
+        domain_id: u32,
+        server_id: u32,
+        seq_no: u64,
+    },
+}
+
+/// Executed transactions
+///  - MySQL: intervals of transaction ids (inclusive) per source uuid
+///  - MariaDB: last transaction per replication domain
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(into = "String", try_from = "String")]
+pub struct GtidSet {
+    sids: BTreeMap<String, Vec<(u64, u64)>>,
+    domains: BTreeMap<u32, (u32, u64)>,
+}
+
+impl GtidSet {
+    pub fn is_empty(&self) -> bool {
+        self.sids.is_empty() && self.domains.is_empty()
+    }
+
+    pub fn is_mariadb(&self) -> bool {
+        !self.domains.is_empty()
+    }
+
+    pub fn contains(&self, gtid: &Gtid) -> bool {
+        match gtid {
+            Gtid::MySql { sid, gno } => matches!(
+                self.sids.get(sid),
+                Some(intervals) if intervals.iter().any(|(start, end)| start <= gno && gno <= end)
+            ),
+            Gtid::MariaDb {
+                domain_id, seq_no, ..
+            } => matches!(
+                self.domains.get(domain_id),
+                Some((_, last_seq_no)) if seq_no <= last_seq_no
+            ),
+        }
+    }
+
+    pub fn add(&mut self, gtid: &Gtid) {
+        match gtid {
+            Gtid::MySql { sid, gno } => self.add_interval(sid, *gno, *gno),
+            Gtid::MariaDb {
+                domain_id,
+                server_id,
+                seq_no,
+            } => {
+                let last = self
+                    .domains
+                    .entry(*domain_id)
+                    .or_insert((*server_id, *seq_no));
+                if *seq_no >= last.1 {
+                    *last = (*server_id, *seq_no);
+                }
+            }
+        }
+    }
+
+    /// Add interval of MySQL transaction ids (inclusive), merge with adjacent intervals
+    pub fn add_interval(&mut self, sid: &str, start: u64, end: u64) {
+        let intervals = self.sids.entry(sid.to_ascii_lowercase()).or_default();
+        intervals.push((start, end));
+        intervals.sort_unstable();
+
+        let mut merged: Vec<(u64, u64)> = vec![];
+        for (start, end) in intervals.drain(..) {
+            match merged.last_mut() {
+                Some(last) if start <= last.1.saturating_add(1) => last.1 = last.1.max(end),
+                _ => merged.push((start, end)),
+            }
+        }
+        *intervals = merged;
+    }
+
+    pub fn union(&mut self, other: &GtidSet) {
+        for (sid, intervals) in &other.sids {
+            for (start, end) in intervals {
+                self.add_interval(sid, *start, *end);
+            }
+        }
+        for (domain_id, (server_id, seq_no)) in &other.domains {
+            self.add(&Gtid::MariaDb {
+                domain_id: *domain_id,
+                server_id: *server_id,
+                seq_no: *seq_no,
+            });
+        }
+    }
+
+    /// All transactions in this set are also in other set
+    pub fn is_subset(&self, other: &GtidSet) -> bool {
+        let sids_covered = self.sids.iter().all(|(sid, intervals)| {
+            intervals.iter().all(|(start, end)| {
+                matches!(
+                    other.sids.get(sid),
+                    Some(other_intervals) if other_intervals
+                        .iter()
+                        .any(|(o_start, o_end)| o_start <= start && end <= o_end)
+                )
+            })
+        });
+        let domains_covered = self.domains.iter().all(|(domain_id, (_, seq_no))| {
+            matches!(
+                other.domains.get(domain_id),
+                Some((_, other_seq_no)) if seq_no <= other_seq_no
+            )
+        });
+
+        sids_covered && domains_covered
+    }
+
+    /// MySQL intervals (inclusive) per source uuid
+    pub fn sids(&self) -> impl Iterator<Item = (&String, &Vec<(u64, u64)>)> {
+        self.sids.iter()
+    }
+}
+
+impl fmt::Display for Gtid {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Gtid::MySql { sid, gno } => write!(f, "{}:{}", sid, gno),
+            Gtid::MariaDb {
+                domain_id,
+                server_id,
+                seq_no,
+            } => write!(f, "{}-{}-{}", domain_id, server_id, seq_no),
+        }
+    }
+}
+
+impl FromStr for Gtid {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let value = value.trim();
+        if let Some((sid, gno)) = split_once(value, ':') {
+            let gno = gno.parse::<u64>().map_err(|err| err.to_string())?;
+            return Ok(Gtid::MySql {
+                sid: parse_sid(sid)?,
+                gno,
+            });
+        }
+
+        let parts: Vec<&str> = value.split('-').collect();
+        if parts.len() != 3 {
+            return Err(format!("invalid gtid '{}'", value));
+        }
+        Ok(Gtid::MariaDb {
+            domain_id: parts[0].parse::<u32>().map_err(|err| err.to_string())?,
+            server_id: parts[1].parse::<u32>().map_err(|err| err.to_string())?,
+            seq_no: parts[2].parse::<u64>().map_err(|err| err.to_string())?,
+        })
+    }
+}
+
+impl fmt::Display for GtidSet {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut entries: Vec<String> = vec![];
+
+        for (sid, intervals) in &self.sids {
+            let mut entry = sid.clone();
+            for (start, end) in intervals {
+                if start == end {
+                    entry.push_str(&format!(":{}", start));
+                } else {
+                    entry.push_str(&format!(":{}-{}", start, end));
+                }
+            }
+            entries.push(entry);
+        }
+
+        for (domain_id, (server_id, seq_no)) in &self.domains {
+            entries.push(format!("{}-{}-{}", domain_id, server_id, seq_no));
+        }
+
+        write!(f, "{}", entries.join(","))
+    }
+}
+
+impl FromStr for GtidSet {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let mut gtid_set = GtidSet::default();
+
+        for entry in value.split(',').map(|entry| entry.trim()) {
+            if entry.is_empty() {
+                continue;
+            }
+
+            if entry.contains(':') {
+                let mut parts = entry.split(':');
+                let sid = parse_sid(parts.next().unwrap_or(""))?;
+                for interval in parts {
+                    let (start, end) = match split_once(interval, '-') {
+                        Some((start, end)) => (start, end),
+                        None => (interval, interval),
+                    };
+                    let start = start.parse::<u64>().map_err(|err| err.to_string())?;
+                    let end = end.parse::<u64>().map_err(|err| err.to_string())?;
+                    gtid_set.add_interval(&sid, start, end);
+                }
+            } else {
+                gtid_set.add(&entry.parse::<Gtid>()?);
+            }
+        }
+
+        Ok(gtid_set)
+    }
+}
+
+impl From<Gtid> for String {
+    fn from(gtid: Gtid) -> Self {
+        gtid.to_string()
+    }
+}
+
+impl TryFrom<String> for Gtid {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl From<GtidSet> for String {
+    fn from(gtid_set: GtidSet) -> Self {
+        gtid_set.to_string()
+    }
+}
+
+impl TryFrom<String> for GtidSet {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+fn split_once(value: &str, delimiter: char) -> Option<(&str, &str)> {
+    let pos = value.find(delimiter)?;
+    Some((&value[..pos], &value[pos + 1..]))
+}
+
+fn parse_sid(sid: &str) -> Result<String, String> {
+    let sid = sid.trim().to_ascii_lowercase();
+    let hex_digits = sid.chars().filter(|c| *c != '-').count();
+    if hex_digits != 32 || !sid.chars().all(|c| c == '-' || c.is_ascii_hexdigit()) {
+        return Err(format!("invalid source uuid '{}'", sid));
+    }
+
+    Ok(sid)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const SID: &str = "3e11fa47-71ca-11e1-9e33-c80aa9429562";
+
+    #[test]
+    fn test_gtid_from_str() {
+        let gtid = format!("{}:23", SID).parse::<Gtid>().unwrap();
+        assert_eq!(
+            gtid,
+            Gtid::MySql {
+                sid: SID.to_owned(),
+                gno: 23
+            }
+        );
+        assert_eq!(gtid.to_string(), format!("{}:23", SID));
+
+        let gtid = "0-1-100".parse::<Gtid>().unwrap();
+        assert_eq!(
+            gtid,
+            Gtid::MariaDb {
+                domain_id: 0,
+                server_id: 1,
+                seq_no: 100
+            }
+        );
+        assert_eq!(gtid.to_string(), "0-1-100");
+
+        assert!("not-a-gtid".parse::<Gtid>().is_err());
+        assert!("xyz:1".parse::<Gtid>().is_err());
+    }
+
+    #[test]
+    fn test_mysql_gtid_set() {
+        let mut gtid_set = format!("{}:1-5:7", SID).parse::<GtidSet>().unwrap();
+        let gtid = |gno| Gtid::MySql {
+            sid: SID.to_owned(),
+            gno,
+        };
+
+        assert!(gtid_set.contains(&gtid(1)));
+        assert!(gtid_set.contains(&gtid(5)));
+        assert!(!gtid_set.contains(&gtid(6)));
+        assert!(gtid_set.contains(&gtid(7)));
+
+        // add => intervals are merged
+        gtid_set.add(&gtid(6));
+        assert_eq!(gtid_set.to_string(), format!("{}:1-7", SID));
+
+        let subset = format!("{}:2-4", SID).parse::<GtidSet>().unwrap();
+        assert!(subset.is_subset(&gtid_set));
+        assert!(!gtid_set.is_subset(&subset));
+        assert!(GtidSet::default().is_subset(&gtid_set));
+    }
+
+    #[test]
+    fn test_mariadb_gtid_set() {
+        let mut gtid_set = "0-1-100, 1-2-7".parse::<GtidSet>().unwrap();
+        assert!(gtid_set.is_mariadb());
+        assert!(gtid_set.contains(&"0-1-99".parse().unwrap()));
+        assert!(!gtid_set.contains(&"0-1-101".parse().unwrap()));
+        assert!(!gtid_set.contains(&"2-1-1".parse().unwrap()));
+
+        gtid_set.add(&"0-3-101".parse().unwrap());
+        assert_eq!(gtid_set.to_string(), "0-3-101,1-2-7");
+    }
+
+    #[test]
+    fn test_gtid_set_serde() {
+        let gtid_set = format!("{}:1-5:7,0-1-100", SID).parse::<GtidSet>().unwrap();
+
+        let json_data = serde_json::to_string(&gtid_set).unwrap();
+        assert_eq!(json_data, format!("\"{}:1-5:7,0-1-100\"", SID));
+
+        let val_res: Result<GtidSet, _> = serde_json::from_str(&json_data);
+        assert_eq!(val_res.unwrap(), gtid_set);
+    }
+}
@@ -1,13 +1,27 @@
 mod bn_file;
 mod bn_message;
+mod cloud_events;
+mod column_type;
+mod debezium;
 mod flv_message;
+mod gtid;
 mod operations;
+mod record_batch;
 mod row_ops;
+mod schema_version;
+mod transaction;
 mod values;
 
 pub use bn_file::*;
 pub use bn_message::*;
+pub use cloud_events::*;
+pub use column_type::*;
+pub use debezium::*;
 pub use flv_message::*;
+pub use gtid::*;
 pub use operations::*;
+pub use record_batch::*;
 pub use row_ops::*;
+pub use schema_version::*;
+pub use transaction::*;
 pub use values::*;
@@ -0,0 +1,795 @@
+//! Arrow Record Batches
+//!
+//! Converts the row events of a table (WriteRows, UpdateRows, DeleteRows) to Arrow record
+//! batches. Each batch starts with the metadata columns _op (insert, update, delete), _sequence
+//! and _binlog_pos ("file:offset"), followed by one column per table column (see
+//! column_arrow_type and arrow_type).
+//! Updates give the values after the update.
+//!
+use arrow::array::{ArrayRef, BinaryArray, Date32Array, Decimal128Array, Decimal256Array};
+use arrow::array::{Float32Array, Float64Array, Int16Array, Int32Array, Int64Array};
+use arrow::array::{StringArray, Time64MicrosecondArray, TimestampMicrosecondArray};
+use arrow::array::{UInt64Array, UInt8Array};
+use arrow::compute::concat_batches;
+use arrow::datatypes::{i256, DataType, Field, Schema, SchemaRef, TimeUnit};
+use arrow::record_batch::RecordBatch;
+use bigdecimal::BigDecimal;
+use chrono::NaiveDate;
+use std::fmt::Display;
+use std::io::{Error, ErrorKind};
+use std::sync::Arc;
+
+use super::ColumnType;
+use super::{FluvioMessage, Operation, Value};
+
+/// Metadata columns of the record batches
+pub const METADATA_FIELDS: &[&str] = &["_op", "_sequence", "_binlog_pos"];
+
+// mysql DECIMAL maximum, for decimal columns without column type
+const MAX_DECIMAL_PRECISION: u8 = 65;
+const MAX_DECIMAL_SCALE: i8 = 30;
+const MICROS_PER_DAY: i64 = 86_400_000_000;
+
+/// Record batches of the row events of one table, in order: consecutive messages with the same
+/// schema share a batch. Queries are skipped, messages of another table are an error.
+pub fn record_batches(messages: &[FluvioMessage]) -> Result<Vec<RecordBatch>, Error> {
+    let mut batches = vec![];
+    let mut pending: Vec<RecordBatch> = vec![];
+    let mut schema: Option<SchemaRef> = None;
+    let mut table_uri: Option<&str> = None;
+
+    for flv_message in messages {
+        if let Operation::Query(_) = flv_message.operation {
+            continue;
+        }
+        match table_uri {
+            Some(uri) if uri != flv_message.uri => {
+                return Err(Error::new(
+                    ErrorKind::InvalidInput,
+                    format!("expected messages of {}, found {}", uri, flv_message.uri),
+                ))
+            }
+            _ => table_uri = Some(&flv_message.uri),
+        }
+
+        let next = match message_schema(flv_message, schema.as_deref())? {
+            Some(next) => Arc::new(next),
+            None => continue,
+        };
+        if let Some(schema) = schema.as_ref().filter(|schema| **schema != next) {
+            batches.push(concat(schema, &pending)?);
+            pending.clear();
+        }
+        pending.push(message_batch(flv_message, &next)?);
+        schema = Some(next);
+    }
+
+    if let Some(schema) = schema {
+        batches.push(concat(&schema, &pending)?);
+    }
+    Ok(batches)
+}
+
+/// Schema of the rows of a message, None for queries and messages without rows. Columns of the
+/// current schema keep their type when the message has no value for them.
+pub fn message_schema(
+    flv_message: &FluvioMessage,
+    current: Option<&Schema>,
+) -> Result<Option<Schema>, Error> {
+    let rows = match message_rows(flv_message) {
+        Some((_, rows)) if !rows.is_empty() => rows,
+        _ => return Ok(None),
+    };
+    let columns = message_columns(flv_message, &rows)?;
+
+    Ok(Some(batch_schema(
+        columns,
+        flv_message.column_types.as_deref(),
+        &rows,
+        current,
+    )))
+}
+
+/// Record batch of the rows of a message in schema, built by message_schema. Queries give an
+/// empty batch.
+pub fn message_batch(
+    flv_message: &FluvioMessage,
+    schema: &SchemaRef,
+) -> Result<RecordBatch, Error> {
+    let (op, rows) = match message_rows(flv_message) {
+        Some((op, rows)) if !rows.is_empty() => (op, rows),
+        _ => return Ok(RecordBatch::new_empty(schema.clone())),
+    };
+
+    let columns = message_columns(flv_message, &rows)?;
+    let fields = schema.fields().iter().skip(METADATA_FIELDS.len());
+    if columns.len() != fields.len() || fields.zip(columns).any(|(field, col)| field.name() != col)
+    {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            "message columns do not match the schema",
+        ));
+    }
+
+    record_batch(schema, op, flv_message, &rows)
+}
+
+/// Operation and rows of a row event, None for queries
+fn message_rows(flv_message: &FluvioMessage) -> Option<(&'static str, Vec<&Vec<Value>>)> {
+    match &flv_message.operation {
+        Operation::Add(data) => Some(("insert", data.rows.iter().map(|row| &row.cols).collect())),
+        Operation::Update(data) => Some((
+            "update",
+            data.rows.iter().map(|row| &row.after_cols).collect(),
+        )),
+        Operation::Delete(data) => {
+            Some(("delete", data.rows.iter().map(|row| &row.cols).collect()))
+        }
+        Operation::Query(_) => None,
+    }
+}
+
+/// Column names of a row event, with a name for each value
+fn message_columns<'a>(
+    flv_message: &'a FluvioMessage,
+    rows: &[&Vec<Value>],
+) -> Result<&'a [String], Error> {
+    let columns = flv_message
+        .columns
+        .as_deref()
+        .ok_or_else(|| Error::new(ErrorKind::InvalidData, "expected column names, found none"))?;
+    for values in rows {
+        if columns.len() < values.len() {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!(
+                    "expected at least {} columns, found {}",
+                    values.len(),
+                    columns.len()
+                ),
+            ));
+        }
+    }
+    Ok(columns)
+}
+
+fn concat(schema: &SchemaRef, batches: &[RecordBatch]) -> Result<RecordBatch, Error> {
+    concat_batches(schema, batches).map_err(|err| arrow_error("Batch", err))
+}
+
+/// Arrow type of a value, None for NULL:
+///  - integers: UInt8, Int64, enums: Int16, years: Int32, floats: Float32, Float64
+///  - strings and json: Utf8, blobs: Binary
+///  - decimals: Decimal128/Decimal256 with the precision and scale of the column type
+///  - dates: Date32, times: Time64(us), datetimes: Timestamp(us), timestamps: Timestamp(us, UTC)
+pub fn arrow_type(value: &Value, column_type: Option<&ColumnType>) -> Option<DataType> {
+    let data_type = match value {
+        Value::UnsignedInteger(_) => DataType::UInt8,
+        Value::SignedInteger(_) => DataType::Int64,
+        Value::Float(_) => DataType::Float32,
+        Value::Double(_) => DataType::Float64,
+        Value::String(_) | Value::Json(_) => DataType::Utf8,
+        Value::Enum(_) => DataType::Int16,
+        Value::Blob(_) => DataType::Binary,
+        Value::Year(_) => DataType::Int32,
+        Value::Date { .. } => DataType::Date32,
+        Value::Time { .. } => DataType::Time64(TimeUnit::Microsecond),
+        Value::DateTime { .. } => DataType::Timestamp(TimeUnit::Microsecond, None),
+        Value::Timestamp { .. } => DataType::Timestamp(TimeUnit::Microsecond, Some("UTC".into())),
+        Value::Decimal(_) => decimal_type(column_type),
+        Value::Null => return None,
+    };
+    Some(data_type)
+}
+
+/// Arrow type of a column type, None for the types without a fixed value variant (bit, set,
+/// geometry): the type of the values is used. Matches the type of the column values given by
+/// arrow_type, text columns are blobs.
+pub fn column_arrow_type(column_type: &ColumnType) -> Option<DataType> {
+    let name = column_type
+        .data_type
+        .split('(')
+        .next()
+        .unwrap_or_default()
+        .trim();
+    let data_type = match name {
+        "tinyint" | "smallint" | "mediumint" | "int" | "bigint" => DataType::Int64,
+        "float" => DataType::Float32,
+        "double" => DataType::Float64,
+        "char" | "varchar" | "json" => DataType::Utf8,
+        "enum" => DataType::Int16,
+        "tinyblob" | "blob" | "mediumblob" | "longblob" => DataType::Binary,
+        "year" => DataType::Int32,
+        "date" => DataType::Date32,
+        "time" => DataType::Time64(TimeUnit::Microsecond),
+        "datetime" => DataType::Timestamp(TimeUnit::Microsecond, None),
+        "timestamp" => DataType::Timestamp(TimeUnit::Microsecond, Some("UTC".into())),
+        "decimal" => decimal_type(Some(column_type)),
+        _ => return None,
+    };
+    Some(data_type)
+}
+
+/// Decimal type of the column: "decimal(precision,scale)", mysql maximum if unknown
+fn decimal_type(column_type: Option<&ColumnType>) -> DataType {
+    let (precision, scale) = column_type
+        .and_then(|column_type| {
+            let args = column_type
+                .data_type
+                .strip_prefix("decimal(")?
+                .strip_suffix(')')?;
+            let mut args = args.split(',');
+            let precision = args.next()?.trim().parse::<u8>().ok()?;
+            let scale = args.next()?.trim().parse::<i8>().ok()?;
+            Some((precision, scale))
+        })
+        .unwrap_or((MAX_DECIMAL_PRECISION, MAX_DECIMAL_SCALE));
+
+    if precision <= 38 {
+        DataType::Decimal128(precision, scale)
+    } else {
+        DataType::Decimal256(precision, scale)
+    }
+}
+
+/// Schema of the rows: metadata fields, then one nullable field per column. The type of a column
+/// comes from its column type, NULL values do not change it. Without column type, it is the
+/// type of its first value that is not NULL, columns without values keep the type of the
+/// current schema, or are Utf8.
+fn batch_schema(
+    columns: &[String],
+    column_types: Option<&[ColumnType]>,
+    rows: &[&Vec<Value>],
+    current: Option<&Schema>,
+) -> Schema {
+    let mut fields = vec![
+        Field::new(METADATA_FIELDS[0], DataType::Utf8, false),
+        Field::new(METADATA_FIELDS[1], DataType::UInt64, false),
+        Field::new(METADATA_FIELDS[2], DataType::Utf8, false),
+    ];
+
+    for (idx, column) in columns.iter().enumerate() {
+        let column_type = column_types.and_then(|column_types| column_types.get(idx));
+        let data_type = column_type
+            .and_then(column_arrow_type)
+            .or_else(|| {
+                rows.iter()
+                    .find_map(|row| arrow_type(row.get(idx).unwrap_or(&Value::Null), column_type))
+            })
+            .or_else(|| {
+                current
+                    .and_then(|schema| schema.field_with_name(column).ok())
+                    .map(|field| field.data_type().clone())
+            })
+            .unwrap_or(DataType::Utf8);
+        fields.push(Field::new(column, data_type, true));
+    }
+
+    Schema::new(fields)
+}
+
+/// Record batch of the rows, with the metadata of the message
+fn record_batch(
+    schema: &SchemaRef,
+    op: &str,
+    flv_message: &FluvioMessage,
+    rows: &[&Vec<Value>],
+) -> Result<RecordBatch, Error> {
+    let bn_file = &flv_message.bn_file;
+    let binlog_pos = match bn_file.offset {
+        Some(offset) => format!("{}:{}", bn_file.file_name, offset),
+        None => bn_file.file_name.clone(),
+    };
+
+    let mut arrays: Vec<ArrayRef> = vec![
+        Arc::new(StringArray::from(vec![op; rows.len()])),
+        Arc::new(UInt64Array::from(vec![flv_message.sequence; rows.len()])),
+        Arc::new(StringArray::from(vec![binlog_pos; rows.len()])),
+    ];
+    for (idx, field) in schema
+        .fields()
+        .iter()
+        .skip(METADATA_FIELDS.len())
+        .enumerate()
+    {
+        let values: Vec<&Value> = rows
+            .iter()
+            .map(|row| row.get(idx).unwrap_or(&Value::Null))
+            .collect();
+        let array = column_array(field.data_type(), &values)
+            .map_err(|err| Error::new(err.kind(), format!("column {}: {}", field.name(), err)))?;
+        arrays.push(array);
+    }
+
+    RecordBatch::try_new(schema.clone(), arrays).map_err(|err| arrow_error("Batch", err))
+}
+
+/// Array of the column values, values must have the variant of the column type (or be NULL)
+fn column_array(data_type: &DataType, values: &[&Value]) -> Result<ArrayRef, Error> {
+    let array: ArrayRef = match data_type {
+        DataType::UInt8 => Arc::new(
+            convert(values, data_type, |value| match value {
+                Value::UnsignedInteger(val) => Some(Ok(*val)),
+                _ => None,
+            })?
+            .into_iter()
+            .collect::<UInt8Array>(),
+        ),
+        DataType::Int64 => Arc::new(Int64Array::from(convert(
+            values,
+            data_type,
+            |value| match value {
+                Value::SignedInteger(val) => Some(Ok(*val)),
+                _ => None,
+            },
+        )?)),
+        DataType::Int16 => Arc::new(Int16Array::from(convert(
+            values,
+            data_type,
+            |value| match value {
+                Value::Enum(val) => Some(Ok(*val)),
+                _ => None,
+            },
+        )?)),
+        DataType::Int32 => Arc::new(Int32Array::from(convert(
+            values,
+            data_type,
+            |value| match value {
+                Value::Year(val) => Some(Ok(*val as i32)),
+                _ => None,
+            },
+        )?)),
+        DataType::Float32 => {
+            Arc::new(Float32Array::from(convert(
+                values,
+                data_type,
+                |value| match value {
+                    Value::Float(val) => Some(Ok(*val)),
+                    _ => None,
+                },
+            )?))
+        }
+        DataType::Float64 => {
+            Arc::new(Float64Array::from(convert(
+                values,
+                data_type,
+                |value| match value {
+                    Value::Double(val) => Some(Ok(*val)),
+                    _ => None,
+                },
+            )?))
+        }
+        DataType::Utf8 => Arc::new(StringArray::from(convert(
+            values,
+            data_type,
+            |value| match value {
+                Value::String(val) => Some(Ok(val.clone())),
+                Value::Json(val) => Some(Ok(val.to_string())),
+                _ => None,
+            },
+        )?)),
+        DataType::Binary => {
+            let blobs = convert(values, data_type, |value| match value {
+                Value::Blob(bytes) => Some(Ok(bytes.as_slice())),
+                _ => None,
+            })?;
+            Arc::new(BinaryArray::from(blobs))
+        }
+        DataType::Date32 => {
+            Arc::new(Date32Array::from(convert(
+                values,
+                data_type,
+                |value| match value {
+                    Value::Date { year, month, day } => {
+                        Some(epoch_days(*year, *month, *day).map(|days| days as i32))
+                    }
+                    _ => None,
+                },
+            )?))
+        }
+        DataType::Time64(TimeUnit::Microsecond) => Arc::new(Time64MicrosecondArray::from(convert(
+            values,
+            data_type,
+            |value| match value {
+                Value::Time {
+                    hours,
+                    minutes,
+                    seconds,
+                    subseconds,
+                } => Some(Ok(day_micros(*hours, *minutes, *seconds, *subseconds))),
+                _ => None,
+            },
+        )?)),
+        DataType::Timestamp(TimeUnit::Microsecond, None) => Arc::new(
+            TimestampMicrosecondArray::from(convert(values, data_type, |value| match value {
+                Value::DateTime {
+                    year,
+                    month,
+                    day,
+                    hour,
+                    minute,
+                    second,
+                    subsecond,
+                } => Some(epoch_days(*year, *month, *day).map(|days| {
+                    days * MICROS_PER_DAY + day_micros(*hour, *minute, *second, *subsecond)
+                })),
+                _ => None,
+            })?),
+        ),
+        DataType::Timestamp(TimeUnit::Microsecond, Some(tz)) => Arc::new(
+            TimestampMicrosecondArray::from(convert(values, data_type, |value| match value {
+                Value::Timestamp {
+                    unix_time,
+                    subsecond,
+                } => Some(Ok(i64::from(*unix_time) * 1_000_000 + i64::from(*subsecond))),
+                _ => None,
+            })?)
+            .with_timezone(tz.clone()),
+        ),
+        DataType::Decimal128(precision, scale) => Arc::new(
+            Decimal128Array::from(convert(values, data_type, |value| match value {
+                Value::Decimal(val) => Some(
+                    unscaled(val, *scale)
+                        .parse::<i128>()
+                        .map_err(|err| arrow_error("Decimal", err)),
+                ),
+                _ => None,
+            })?)
+            .with_precision_and_scale(*precision, *scale)
+            .map_err(|err| arrow_error("Decimal", err))?,
+        ),
+        DataType::Decimal256(precision, scale) => Arc::new(
+            Decimal256Array::from(convert(values, data_type, |value| match value {
+                Value::Decimal(val) => Some(
+                    i256::from_string(&unscaled(val, *scale))
+                        .ok_or_else(|| arrow_error("Decimal", "out of range")),
+                ),
+                _ => None,
+            })?)
+            .with_precision_and_scale(*precision, *scale)
+            .map_err(|err| arrow_error("Decimal", err))?,
+        ),
+        _ => {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("unsupported type {}", data_type),
+            ))
+        }
+    };
+
+    Ok(array)
+}
+
+/// Convert the values of a column, NULL values are None. The conversion returns None for
+/// values of another type, which is an error.
+fn convert<'a, T, F>(
+    values: &[&'a Value],
+    data_type: &DataType,
+    conversion: F,
+) -> Result<Vec<Option<T>>, Error>
+where
+    F: Fn(&'a Value) -> Option<Result<T, Error>>,
+{
+    values
+        .iter()
+        .map(|value| match value {
+            Value::Null => Ok(None),
+            value => match conversion(value) {
+                Some(result) => result.map(Some),
+                None => Err(Error::new(
+                    ErrorKind::InvalidData,
+                    format!("expected {} value, found {:?}", data_type, value),
+                )),
+            },
+        })
+        .collect()
+}
+
+/// Days since 1970-01-01, mysql zero dates are an error
+fn epoch_days(year: u32, month: u32, day: u32) -> Result<i64, Error> {
+    let date = NaiveDate::from_ymd_opt(year as i32, month, day).ok_or_else(|| {
+        Error::new(
+            ErrorKind::InvalidData,
+            format!("invalid date: {:04}-{:02}-{:02}", year, month, day),
+        )
+    })?;
+    let epoch = NaiveDate::from_ymd_opt(1970, 1, 1).unwrap();
+    Ok(date.signed_duration_since(epoch).num_days())
+}
+
+/// Microseconds since midnight (mysql times may exceed 24 hours)
+fn day_micros(hours: u32, minutes: u32, seconds: u32, micros: u32) -> i64 {
+    (i64::from(hours) * 3600 + i64::from(minutes) * 60 + i64::from(seconds)) * 1_000_000
+        + i64::from(micros)
+}
+
+/// Unscaled digits of the decimal at scale
+fn unscaled(value: &BigDecimal, scale: i8) -> String {
+    let (digits, _) = value.with_scale(i64::from(scale)).as_bigint_and_exponent();
+    digits.to_string()
+}
+
+pub(crate) fn arrow_error(context: &str, err: impl Display) -> Error {
+    Error::new(ErrorKind::InvalidData, format!("{}: {}", context, err))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::messages::{BeforeAfterCols, Cols, UpdateRows, WriteRows};
+    use arrow::array::Array;
+    use std::str::FromStr;
+
+    fn message(
+        sequence: u64,
+        table: &str,
+        columns: &[&str],
+        operation: Operation,
+    ) -> FluvioMessage {
+        FluvioMessage::test_message(&format!("flv://mysql.local/shop/{}", table), operation)
+            .with_sequence(sequence)
+            .with_position(100 * sequence)
+            .with_columns(columns)
+    }
+
+    fn add(rows: Vec<Vec<Value>>) -> Operation {
+        Operation::Add(WriteRows {
+            rows: rows.into_iter().map(|cols| Cols { cols }).collect(),
+        })
+    }
+
+    #[test]
+    fn test_arrow_types() {
+        let decimal = || Value::Decimal(BigDecimal::from_str("1.5").unwrap());
+        let values = vec![
+            (Value::UnsignedInteger(1), DataType::UInt8),
+            (Value::SignedInteger(-1), DataType::Int64),
+            (Value::Float(1.5), DataType::Float32),
+            (Value::Double(1.5), DataType::Float64),
+            (Value::String("a".to_owned()), DataType::Utf8),
+            (Value::Enum(2), DataType::Int16),
+            (Value::Blob(vec![1]), DataType::Binary),
+            (Value::Year(2020), DataType::Int32),
+            (
+                Value::Date {
+                    year: 2020,
+                    month: 1,
+                    day: 2,
+                },
+                DataType::Date32,
+            ),
+            (
+                Value::Time {
+                    hours: 30,
+                    minutes: 0,
+                    seconds: 0,
+                    subseconds: 0,
+                },
+                DataType::Time64(TimeUnit::Microsecond),
+            ),
+            (
+                Value::Timestamp {
+                    unix_time: 1,
+                    subsecond: 0,
+                },
+                DataType::Timestamp(TimeUnit::Microsecond, Some("UTC".into())),
+            ),
+            (Value::Json(serde_json::json!([1])), DataType::Utf8),
+            (decimal(), DataType::Decimal256(65, 30)),
+        ];
+        for (value, data_type) in values {
+            assert_eq!(arrow_type(&value, None), Some(data_type.clone()));
+            // every type converts its values
+            let array = column_array(&data_type, &[&value, &Value::Null]).unwrap();
+            assert_eq!(array.len(), 2);
+            assert_eq!(array.null_count(), 1);
+        }
+        assert_eq!(arrow_type(&Value::Null, None), None);
+
+        let column_type = ColumnType::new("decimal(40,4)", false);
+        assert_eq!(
+            arrow_type(&decimal(), Some(&column_type)),
+            Some(DataType::Decimal256(40, 4))
+        );
+
+        // mysql zero dates have no arrow value
+        let zero_date = Value::Date {
+            year: 0,
+            month: 0,
+            day: 0,
+        };
+        assert!(column_array(&DataType::Date32, &[&zero_date]).is_err());
+    }
+
+    #[test]
+    fn test_column_arrow_types() {
+        let column_types = [
+            ("int", Some(DataType::Int64)),
+            ("varchar", Some(DataType::Utf8)),
+            ("enum", Some(DataType::Int16)),
+            ("mediumblob", Some(DataType::Binary)),
+            ("time(3)", Some(DataType::Time64(TimeUnit::Microsecond))),
+            (
+                "timestamp(6)",
+                Some(DataType::Timestamp(
+                    TimeUnit::Microsecond,
+                    Some("UTC".into()),
+                )),
+            ),
+            ("decimal(10,2)", Some(DataType::Decimal128(10, 2))),
+            ("set", None),
+            ("bit", None),
+        ];
+        for (data_type, arrow_type) in column_types.iter() {
+            let column_type = ColumnType::new(data_type, true);
+            assert_eq!(&column_arrow_type(&column_type), arrow_type);
+        }
+    }
+
+    #[test]
+    fn test_message_batch() {
+        let mut flv_message = message(
+            7,
+            "toys",
+            &["seen", "data", "photo", "price"],
+            add(vec![
+                vec![
+                    Value::DateTime {
+                        year: 2018,
+                        month: 4,
+                        day: 28,
+                        hour: 3,
+                        minute: 15,
+                        second: 22,
+                        subsecond: 5,
+                    },
+                    Value::Json(serde_json::json!({"a": 1})),
+                    Value::Blob(vec![0, 159]),
+                    Value::Decimal(BigDecimal::from_str("10.5").unwrap()),
+                ],
+                vec![Value::Null, Value::Null, Value::Null, Value::Null],
+            ]),
+        );
+        flv_message.column_types = Some(vec![
+            ColumnType::new("datetime", true),
+            ColumnType::new("json", true),
+            ColumnType::new("blob", true),
+            ColumnType::new("decimal(10,2)", true),
+        ]);
+
+        let schema = Arc::new(message_schema(&flv_message, None).unwrap().unwrap());
+        let data_types: Vec<DataType> = schema
+            .fields()
+            .iter()
+            .map(|field| field.data_type().clone())
+            .collect();
+        assert_eq!(
+            data_types,
+            vec![
+                DataType::Utf8,
+                DataType::UInt64,
+                DataType::Utf8,
+                DataType::Timestamp(TimeUnit::Microsecond, None),
+                DataType::Utf8,
+                DataType::Binary,
+                DataType::Decimal128(10, 2),
+            ]
+        );
+
+        let batch = message_batch(&flv_message, &schema).unwrap();
+        assert_eq!(batch.num_rows(), 2);
+        let seen = batch
+            .column(3)
+            .as_any()
+            .downcast_ref::<TimestampMicrosecondArray>()
+            .unwrap();
+        assert_eq!(seen.value(0), 1_524_885_322_000_005);
+        assert!(seen.is_null(1));
+        let price = batch
+            .column(6)
+            .as_any()
+            .downcast_ref::<Decimal128Array>()
+            .unwrap();
+        assert_eq!(price.value_as_string(0), "10.50");
+        let binlog_pos = batch
+            .column(2)
+            .as_any()
+            .downcast_ref::<StringArray>()
+            .unwrap();
+        assert_eq!(binlog_pos.value(0), "binlog.000001:700");
+
+        // NULL values keep the type of the column
+        let mut null_message = message(
+            8,
+            "toys",
+            &["seen", "data", "photo", "price"],
+            add(vec![vec![
+                Value::Null,
+                Value::Null,
+                Value::Null,
+                Value::Null,
+            ]]),
+        );
+        null_message.column_types = flv_message.column_types.clone();
+        assert_eq!(
+            message_schema(&null_message, None).unwrap().unwrap(),
+            *schema
+        );
+
+        // value of another type
+        flv_message.operation = add(vec![vec![Value::String("now".to_owned())]]);
+        let err = message_batch(&flv_message, &schema).unwrap_err();
+        assert!(err.to_string().starts_with("column seen: expected"));
+
+        // columns of another schema
+        flv_message.columns = Some(vec!["id".to_owned()]);
+        assert!(message_batch(&flv_message, &schema).is_err());
+    }
+
+    #[test]
+    fn test_record_batches() {
+        let toy =
+            |id: i64, name: &str| vec![Value::SignedInteger(id), Value::String(name.to_owned())];
+        let messages = vec![
+            message(
+                1,
+                "toys",
+                &["id", "name"],
+                add(vec![toy(1, "car"), toy(2, "bus")]),
+            ),
+            message(
+                2,
+                "toys",
+                &["id", "name"],
+                Operation::Update(UpdateRows {
+                    rows: vec![BeforeAfterCols {
+                        before_cols: toy(2, "bus"),
+                        after_cols: toy(2, "train"),
+                    }],
+                }),
+            ),
+            message(
+                3,
+                "",
+                &[],
+                Operation::Query("ALTER TABLE toys ADD color TEXT".to_owned()),
+            ),
+            message(
+                4,
+                "toys",
+                &["id", "name", "color"],
+                add(vec![vec![
+                    Value::SignedInteger(3),
+                    Value::Null,
+                    Value::String("red".to_owned()),
+                ]]),
+            ),
+        ];
+
+        let batches = record_batches(&messages).unwrap();
+        assert_eq!(batches.len(), 2);
+        assert_eq!(batches[0].num_rows(), 3);
+        assert_eq!(batches[1].num_rows(), 1);
+        assert_eq!(batches[1].schema().field(5).name(), "color");
+        let op = batches[0]
+            .column(0)
+            .as_any()
+            .downcast_ref::<StringArray>()
+            .unwrap();
+        let ops: Vec<&str> = (0..op.len()).map(|idx| op.value(idx)).collect();
+        assert_eq!(ops, vec!["insert", "insert", "update"]);
+        let name = batches[0]
+            .column(4)
+            .as_any()
+            .downcast_ref::<StringArray>()
+            .unwrap();
+        assert_eq!(name.value(2), "train");
+
+        let other_table = vec![
+            message(1, "toys", &["id", "name"], add(vec![toy(1, "car")])),
+            message(5, "cars", &["id"], add(vec![vec![Value::SignedInteger(1)]])),
+        ];
+        let err = record_batches(&other_table).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
+    }
+}
@@ -1,6 +1,8 @@
 use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value as JsonValue};
+use std::io::{Error, ErrorKind};
 
-use crate::messages::Value;
+use crate::messages::{BinLogMessage, Value};
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct WriteRows {
@@ -28,6 +30,23 @@ pub struct BeforeAfterCols {
     pub after_cols: Vec<Value>,
 }
 
+/// Row values keyed by column name, as plain JSON
+pub(crate) fn row_object(columns: &[String], values: &[Value]) -> Map<String, JsonValue> {
+    columns
+        .iter()
+        .zip(values)
+        .map(|(column, value)| (column.clone(), value.to_json_value()))
+        .collect()
+}
+
+/// Column names of a rows message
+pub(crate) fn message_columns(bn_message: &BinLogMessage) -> Result<&[String], Error> {
+    bn_message
+        .columns
+        .as_deref()
+        .ok_or_else(|| Error::new(ErrorKind::InvalidData, "expected column names, found none"))
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
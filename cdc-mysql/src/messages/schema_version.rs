@@ -0,0 +1,259 @@
+//! Schema Version
+//!
+//! Shape of the FluvioMessage and BinLogMessage records, in their `schema_version` field:
+//!  - 0: records published before the field, without `schema_version`
+//!  - 1: `schema_version` field, same shape as version 0 otherwise. Values are externally
+//!    tagged enums ({"SignedInteger": 7}, {"Date": {..}}, "Null").
+//!
+//! Decoders accept every version up to SCHEMA_VERSION and reject newer records, so consumers
+//! are upgraded before (or with) their producers. Older decoders ignore `schema_version`.
+//! A newer record is not malformed: the consumer stops on it, without saving its offset or
+//! sending it to the dead-letter queue, and reads it again once upgraded.
+//!
+//! The Debezium and CloudEvents envelopes carry the version in the source block and in the
+//! "schemaversion" extension.
+//!
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use std::io::{Error, ErrorKind};
+
+/// Version of the records written by this crate
+pub const SCHEMA_VERSION: u32 = 1;
+
+#[derive(Deserialize)]
+struct Versioned {
+    #[serde(default)]
+    schema_version: u32,
+}
+
+/// Schema version of a json record, 0 for records without version
+pub fn schema_version(json: &str) -> Result<u32, Error> {
+    let versioned: Versioned =
+        serde_json::from_str(json).map_err(|err| Error::new(ErrorKind::InvalidData, err))?;
+    Ok(versioned.schema_version)
+}
+
+/// Record of a newer version than SCHEMA_VERSION, written by a newer producer: fatal for the
+/// consumer, never skipped
+pub fn is_newer_version(json: &str) -> bool {
+    matches!(schema_version(json), Ok(version) if version > SCHEMA_VERSION)
+}
+
+/// Decode a record of any version up to SCHEMA_VERSION
+pub(crate) fn decode_versioned<T: DeserializeOwned>(json: &str) -> Result<T, Error> {
+    match schema_version(json)? {
+        // version 0 has the shape of version 1, without schema_version
+        0 | 1 => serde_json::from_str(json).map_err(|err| Error::new(ErrorKind::InvalidData, err)),
+        version => Err(Error::new(
+            ErrorKind::InvalidData,
+            format!(
+                "unsupported schema version {}, expected {} or lower: upgrade the consumer",
+                version, SCHEMA_VERSION
+            ),
+        )),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::messages::{BeforeAfterCols, BinLogMessage, BnFile, Cols, ColumnType};
+    use crate::messages::{FluvioMessage, Operation, Transaction, UpdateRows, Value, WriteRows};
+    use bigdecimal::BigDecimal;
+    use serde_json::{json, Value as JsonValue};
+    use std::fs;
+    use std::str::FromStr;
+
+    const TEST_PATH: &str = "test_files/messages";
+
+    fn golden(file: &str) -> String {
+        let path = std::env::current_dir().unwrap().join(TEST_PATH).join(file);
+        fs::read_to_string(path).unwrap()
+    }
+
+    fn golden_value(file: &str) -> JsonValue {
+        serde_json::from_str(&golden(file)).unwrap()
+    }
+
+    fn columns() -> Vec<String> {
+        let columns = [
+            "tiny", "id", "weight", "price", "name", "size", "photo", "born", "birthday",
+            "feeding", "updated", "extra", "cost", "created", "note",
+        ];
+        columns.iter().map(|column| column.to_string()).collect()
+    }
+
+    /// One value of each variant, aligned with columns
+    fn values() -> Vec<Value> {
+        vec![
+            Value::UnsignedInteger(1),
+            Value::SignedInteger(-7),
+            Value::Float(1.5),
+            Value::Double(2.25),
+            Value::String("Puffball".to_owned()),
+            Value::Enum(2),
+            Value::Blob(b"ab".to_vec()),
+            Value::Year(2020),
+            Value::Date {
+                year: 2020,
+                month: 9,
+                day: 13,
+            },
+            Value::Time {
+                hours: 12,
+                minutes: 26,
+                seconds: 40,
+                subseconds: 500_000,
+            },
+            Value::DateTime {
+                year: 2020,
+                month: 9,
+                day: 13,
+                hour: 12,
+                minute: 26,
+                second: 40,
+                subsecond: 0,
+            },
+            Value::Json(json!({"color": "white"})),
+            Value::Decimal(BigDecimal::from_str("12.50").unwrap()),
+            Value::Timestamp {
+                unix_time: 1_600_000_000,
+                subsecond: 0,
+            },
+            Value::Null,
+        ]
+    }
+
+    fn bn_file() -> BnFile {
+        let mut bn_file = BnFile::new("binlog.000003".to_owned(), Some(832));
+        let gtid = "3e11fa47-71ca-11e1-9e33-c80aa9429562:23";
+        bn_file.gtid_set = Some("3e11fa47-71ca-11e1-9e33-c80aa9429562:1-23".parse().unwrap());
+        bn_file.gtid = Some(gtid.parse().unwrap());
+        bn_file.transaction = Some(Transaction::new(gtid.to_owned(), 0, true));
+        bn_file.timestamp = Some(1_600_000_000);
+        bn_file
+    }
+
+    fn v1_binlog_message() -> BinLogMessage {
+        let operation = Operation::Update(UpdateRows {
+            rows: vec![BeforeAfterCols {
+                before_cols: vec![Value::SignedInteger(7), Value::String("toy".to_owned())],
+                after_cols: vec![Value::SignedInteger(7), Value::Null],
+            }],
+        });
+        let columns = Some(vec!["id".to_owned(), "name".to_owned()]);
+        BinLogMessage::new(
+            "mysql-srv1",
+            "flvDb",
+            Some("toys"),
+            bn_file(),
+            columns,
+            operation,
+        )
+        .with_column_types(Some(vec![
+            ColumnType::new("int", false),
+            ColumnType::new("varchar(20)", true),
+        ]))
+        .with_key_columns(Some(vec!["id".to_owned()]))
+    }
+
+    fn v1_fluvio_message() -> FluvioMessage {
+        let operation = Operation::Add(WriteRows {
+            rows: vec![Cols { cols: values() }],
+        });
+        let bn_message = BinLogMessage::new(
+            "mysql-srv1",
+            "flvDb",
+            Some("pet"),
+            bn_file(),
+            Some(columns()),
+            operation,
+        )
+        .with_key_columns(Some(vec!["id".to_owned()]));
+        FluvioMessage::new(bn_message, 5)
+    }
+
+    fn row_values(operation: &Operation) -> &[Value] {
+        match operation {
+            Operation::Add(data) => &data.rows[0].cols,
+            _ => panic!("wrong variant"),
+        }
+    }
+
+    #[test]
+    fn test_schema_version() {
+        assert_eq!(schema_version("{\"uri\":\"flv://srv1/db\"}").unwrap(), 0);
+        assert_eq!(schema_version("{\"schema_version\":1}").unwrap(), 1);
+        assert!(schema_version("not json").is_err());
+    }
+
+    #[test]
+    fn test_encode_v1() {
+        assert_eq!(
+            serde_json::to_value(v1_fluvio_message()).unwrap(),
+            golden_value("v1/fluvio_message.json")
+        );
+        assert_eq!(
+            serde_json::to_value(v1_binlog_message()).unwrap(),
+            golden_value("v1/binlog_message.json")
+        );
+    }
+
+    #[test]
+    fn test_decode_v1() {
+        let flv_message = FluvioMessage::decode(&golden("v1/fluvio_message.json")).unwrap();
+        assert_eq!(flv_message.schema_version, 1);
+        assert_eq!(flv_message.sequence, 5);
+        assert_eq!(flv_message.bn_file, bn_file());
+        assert_eq!(flv_message.key_columns, Some(vec!["id".to_owned()]));
+        assert_eq!(row_values(&flv_message.operation), values().as_slice());
+
+        let bn_message = BinLogMessage::decode(&golden("v1/binlog_message.json")).unwrap();
+        assert_eq!(bn_message.schema_version, 1);
+        assert_eq!(bn_message.uri, "flv://mysql-srv1/flvDb/toys");
+        assert_eq!(bn_message.column_types.unwrap().len(), 2);
+        match bn_message.operation {
+            Operation::Update(data) => assert_eq!(data.rows[0].after_cols[1], Value::Null),
+            _ => panic!("wrong variant"),
+        }
+    }
+
+    #[test]
+    fn test_decode_v0() {
+        let flv_message = FluvioMessage::decode(&golden("v0/fluvio_message.json")).unwrap();
+        assert_eq!(flv_message.schema_version, 0);
+        assert_eq!(flv_message.uri, "flv://mysql-srv1/flvDb/pet");
+        assert_eq!(flv_message.sequence, 5);
+        assert_eq!(
+            flv_message.bn_file,
+            BnFile::new("binlog.000003".to_owned(), Some(832))
+        );
+        assert_eq!(flv_message.columns, Some(columns()));
+        assert_eq!(flv_message.column_types, None);
+        assert_eq!(flv_message.key_columns, None);
+        assert_eq!(row_values(&flv_message.operation), values().as_slice());
+
+        let bn_message = BinLogMessage::decode(&golden("v0/binlog_message.json")).unwrap();
+        assert_eq!(bn_message.schema_version, 0);
+        assert_eq!(bn_message.uri, "flv://mysql-srv1/flvDb");
+        assert_eq!(bn_message.columns, None);
+        match bn_message.operation {
+            Operation::Query(query) => assert_eq!(query, "CREATE TABLE toys (id INT)"),
+            _ => panic!("wrong variant"),
+        }
+    }
+
+    #[test]
+    fn test_decode_unsupported_version() {
+        let mut record = golden_value("v1/fluvio_message.json");
+        record["schema_version"] = json!(SCHEMA_VERSION + 1);
+        let err = FluvioMessage::decode(&record.to_string()).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+        assert!(err.to_string().contains("unsupported schema version 2"));
+
+        // newer records are fatal, malformed records are not
+        assert!(is_newer_version(&record.to_string()));
+        assert!(!is_newer_version(&golden("v1/fluvio_message.json")));
+        assert!(!is_newer_version("not json"));
+    }
+}
@@ -0,0 +1,20 @@
+use serde::{Deserialize, Serialize};
+
+/// Position of a message in its source transaction
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Transaction {
+    /// gtid, or "file:offset" of the first event when gtids are off
+    pub id: String,
+
+    /// index of the message in the transaction, starting at 0
+    pub index: u64,
+
+    /// last message of the transaction
+    pub end: bool,
+}
+
+impl Transaction {
+    pub fn new(id: String, index: u64, end: bool) -> Self {
+        Self { id, index, end }
+    }
+}
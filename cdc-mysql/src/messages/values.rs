@@ -2,6 +2,7 @@ use bigdecimal::BigDecimal;
 use chrono::prelude::DateTime;
 use chrono::Utc;
 use serde::{Deserialize, Serialize};
+use serde_json::Value as JsonValue;
 use std::fmt;
 use std::time::{Duration, UNIX_EPOCH};
 
@@ -92,6 +93,91 @@ impl fmt::Display for Value {
     }
 }
 
+impl Value {
+    /// Plain JSON value, for row outputs keyed by column name (the serde form is tagged):
+    ///  - numbers as numbers, decimals as strings (precision kept)
+    ///  - blobs as UTF-8 text (mysql text columns), or hex if not valid UTF-8
+    ///  - dates "YYYY-MM-DD", times "HH:MM:SS[.ffffff]", datetimes "YYYY-MM-DD HH:MM:SS[.ffffff]",
+    ///    timestamps in UTC "YYYY-MM-DDTHH:MM:SS[.ffffff]Z"
+    pub fn to_json_value(&self) -> JsonValue {
+        match self {
+            Value::UnsignedInteger(val) => JsonValue::from(*val),
+            Value::SignedInteger(val) => JsonValue::from(*val),
+            Value::Float(val) => JsonValue::from(*val),
+            Value::Double(val) => JsonValue::from(*val),
+            Value::String(val) => JsonValue::from(val.as_str()),
+            Value::Enum(val) => JsonValue::from(*val),
+            Value::Blob(bytes) => match std::str::from_utf8(bytes) {
+                Ok(text) => JsonValue::from(text),
+                Err(_) => JsonValue::from(
+                    bytes
+                        .iter()
+                        .map(|byte| format!("{:02x}", byte))
+                        .collect::<String>(),
+                ),
+            },
+            Value::Year(val) => JsonValue::from(*val),
+            Value::Date { year, month, day } => {
+                JsonValue::from(format!("{:04}-{:02}-{:02}", year, month, day))
+            }
+            Value::Time {
+                hours,
+                minutes,
+                seconds,
+                subseconds,
+            } => JsonValue::from(format!(
+                "{:02}:{:02}:{:02}{}",
+                hours,
+                minutes,
+                seconds,
+                fraction(*subseconds)
+            )),
+            Value::DateTime {
+                year,
+                month,
+                day,
+                hour,
+                minute,
+                second,
+                subsecond,
+            } => JsonValue::from(format!(
+                "{:04}-{:02}-{:02} {:02}:{:02}:{:02}{}",
+                year,
+                month,
+                day,
+                hour,
+                minute,
+                second,
+                fraction(*subsecond)
+            )),
+            Value::Json(val) => val.clone(),
+            Value::Decimal(val) => JsonValue::from(val.to_string()),
+            Value::Timestamp {
+                unix_time,
+                subsecond,
+            } => {
+                let d = UNIX_EPOCH + Duration::from_secs(*unix_time as u64);
+                let datetime = DateTime::<Utc>::from(d);
+                JsonValue::from(format!(
+                    "{}{}Z",
+                    datetime.format("%Y-%m-%dT%H:%M:%S"),
+                    fraction(*subsecond)
+                ))
+            }
+            Value::Null => JsonValue::Null,
+        }
+    }
+}
+
+/// Fractional seconds from microseconds, empty if none
+pub fn fraction(micros: u32) -> String {
+    if micros == 0 {
+        String::new()
+    } else {
+        format!(".{:06}", micros)
+    }
+}
+
 // Creates a new SystemTime from the specified number of whole seconds
 
 #[cfg(test)]
@@ -205,4 +291,45 @@ mod test {
             "\"2018-04-28 03:15:22.000000000\"".to_owned()
         );
     }
+    #[test]
+    fn test_json_values() {
+        assert_eq!(
+            Value::SignedInteger(-10).to_json_value(),
+            serde_json::json!(-10)
+        );
+        assert_eq!(
+            Value::Blob(String::from("hello").into_bytes()).to_json_value(),
+            serde_json::json!("hello")
+        );
+        assert_eq!(
+            Value::Blob(vec![0, 159]).to_json_value(),
+            serde_json::json!("009f")
+        );
+        assert_eq!(
+            Value::Decimal("5.54321".parse::<BigDecimal>().unwrap()).to_json_value(),
+            serde_json::json!("5.54321")
+        );
+        assert_eq!(
+            Value::DateTime {
+                year: 2000,
+                month: 3,
+                day: 30,
+                hour: 21,
+                minute: 31,
+                second: 30,
+                subsecond: 1000
+            }
+            .to_json_value(),
+            serde_json::json!("2000-03-30 21:31:30.001000")
+        );
+        assert_eq!(
+            Value::Timestamp {
+                unix_time: 1524885322,
+                subsecond: 0
+            }
+            .to_json_value(),
+            serde_json::json!("2018-04-28T03:15:22Z")
+        );
+        assert_eq!(Value::Null.to_json_value(), JsonValue::Null);
+    }
 }
@@ -89,6 +89,13 @@ pub fn get_file_id(path: &Path) -> i32 {
     file_id
 }
 
+/// Base name and index of a binlog file name ("binlog.000003" => ("binlog", 3))
+pub fn split_file_name(file_name: &str) -> Option<(&str, u64)> {
+    let dot = file_name.rfind('.')?;
+    let index = file_name[dot + 1..].parse().ok()?;
+    Some((&file_name[..dot], index))
+}
+
 #[cfg(test)]
 mod test {
     use super::BinLogFile;
@@ -2,101 +2,179 @@ use crossbeam_channel::Sender;
 use mysql_binlog::event::TypeCode;
 use mysql_binlog::{parse_file, BinlogEvent};
 use std::io::{Error, ErrorKind};
-use tracing::{debug, instrument, trace};
+use tracing::{debug, instrument, trace, warn};
 
 use crate::error::CdcError;
-use crate::messages::{BeforeAfterCols, BinLogMessage, Cols, Operation};
+use crate::messages::{BeforeAfterCols, BinLogMessage, BnFile, Cols, ColumnType, Operation};
 use crate::messages::{DeleteRows, UpdateRows, WriteRows};
-use crate::producer::Filters;
+use crate::producer::masking::TableMask;
+use crate::producer::{Filters, Masking, Profile};
 
-use super::parse_query;
+use super::GtidTracker;
 use super::LocalStore;
+use super::{parse_query, parse_query_tables, retain_query_tables};
+
+/// Parser state carried across events and binlog files
+#[derive(Debug)]
+pub struct ParserState {
+    pub urn: String,
+    pub filters: Option<Filters>,
+    pub masking: Option<Masking>,
+    pub local_store: LocalStore,
+    pub gtids: GtidTracker,
+
+    /// last message of an open transaction, sent once the next message or the end of
+    /// the transaction is known
+    pub pending: Option<BinLogMessage>,
+}
+
+impl ParserState {
+    pub fn new(profile: &Profile) -> Result<Self, Error> {
+        Ok(Self {
+            urn: profile.mysql_resource_name().clone(),
+            filters: profile.filters(),
+            masking: profile.masking(),
+            local_store: LocalStore::new(profile.local_store_file())?,
+            gtids: GtidTracker::default(),
+            pending: None,
+        })
+    }
+}
 
-#[instrument(skip(sender, log_file, offset, filters, local_store))]
+#[instrument(skip(sender, log_file, offset, state))]
 pub fn parse_records_from_file(
     sender: &Sender<String>,
     log_file: &str,
     file_name: &str,
     offset: Option<u64>,
-    filters: Option<&Filters>,
-    local_store: &mut LocalStore,
-    urn: &str,
+    state: &mut ParserState,
 ) -> Result<Option<u64>, CdcError> {
     let mut latest_offset = None;
+    state.gtids.set_file(log_file, file_name)?;
 
     for event in parse_file(&log_file, offset)? {
         debug!(?event, "Event from binlog parser:");
         if let Ok(event) = event {
             latest_offset = Some(event.offset);
-            process_event(sender, file_name, event, offset, filters, local_store, urn)?;
+
+            state.gtids.advance_to(event.offset)?;
+            send_pending_if_ended(sender, state);
+            if state.gtids.is_replayed(file_name, event.offset) {
+                trace!(offset = event.offset, "Event sent before restart, skipped");
+                continue;
+            }
+
+            process_event(sender, file_name, event, offset, state)?;
         }
     }
 
+    // transaction may end after the last rows event (XID)
+    state.gtids.advance_to_next_data()?;
+    send_pending_if_ended(sender, state);
+
     Ok(latest_offset)
 }
 
-#[instrument(skip(sender, file_name, event, offset, filters, local_store, urn))]
+#[instrument(skip(sender, file_name, event, offset, state))]
 fn process_event(
     sender: &Sender<String>,
     file_name: &str,
-    event: BinlogEvent,
+    mut event: BinlogEvent,
     offset: Option<u64>,
-    filters: Option<&Filters>,
-    local_store: &mut LocalStore,
-    urn: &str,
+    state: &mut ParserState,
 ) -> Result<(), CdcError> {
-    let allowed = allowed_by_filters(
-        filters,
-        event.schema.as_deref(),
-        event.schema_name.as_deref(),
-    );
-
-    if !allowed {
+    if !apply_filters(state.filters.as_ref(), &mut event) {
         return Ok(());
     }
     if same_offset(offset, event.offset) {
         return Ok(());
     }
 
-    let msg = event_to_message(event, file_name, local_store, urn)?;
-    if !msg.is_empty() {
-        debug!("Sending message: {}", &msg);
-        sender.send(msg).expect("Send message error");
+    if let Some(msg) = event_to_message(event, file_name, state)? {
+        send_in_transaction(sender, msg, state);
     }
 
     Ok(())
 }
 
+/// Messages are sent one behind within a transaction, so the last one carries the
+/// end of transaction marker
+fn send_in_transaction(sender: &Sender<String>, msg: BinLogMessage, state: &mut ParserState) {
+    if let Some(pending) = state.pending.take() {
+        send_message(sender, &pending);
+    }
+
+    match msg.bn_file.transaction.as_ref() {
+        Some(transaction) if !transaction.end => state.pending = Some(msg),
+        _ => send_message(sender, &msg),
+    }
+}
+
+/// Send the pending message, with end marker, if its transaction has ended
+fn send_pending_if_ended(sender: &Sender<String>, state: &mut ParserState) {
+    let ended = match state.pending.as_ref() {
+        Some(pending) => match pending.bn_file.transaction.as_ref() {
+            Some(transaction) => !state.gtids.is_open(&transaction.id),
+            None => true,
+        },
+        None => false,
+    };
+
+    if ended {
+        if let Some(mut pending) = state.pending.take() {
+            if let Some(transaction) = pending.bn_file.transaction.as_mut() {
+                transaction.end = true;
+            }
+            pending.bn_file.gtid_set = state.gtids.gtid_set();
+            send_message(sender, &pending);
+        }
+    }
+}
+
+fn send_message(sender: &Sender<String>, msg: &BinLogMessage) {
+    let encoded = serde_json::to_string_pretty(msg).unwrap();
+    debug!("Sending message: {}", &encoded);
+    sender.send(encoded).expect("Send message error");
+}
+
 fn event_to_message(
     event: BinlogEvent,
     file_name: &str,
-    local_store: &mut LocalStore,
-    urn: &str,
-) -> Result<String, CdcError> {
+    state: &mut ParserState,
+) -> Result<Option<BinLogMessage>, CdcError> {
     debug!("{:?}", event);
-    match event.type_code {
-        TypeCode::QueryEvent => process_query_event(event, file_name, local_store, urn),
-        TypeCode::WriteRowsEventV2 => process_write_rows_event(event, file_name, local_store, urn),
-        TypeCode::UpdateRowsEventV2 => {
-            process_update_rows_event(event, file_name, local_store, urn)
-        }
-        TypeCode::DeleteRowsEventV2 => {
-            process_delete_rows_event(event, file_name, local_store, urn)
-        }
+    let bn_file = BnFile {
+        file_name: file_name.to_owned(),
+        offset: Some(event.offset),
+        gtid_set: state.gtids.gtid_set(),
+        gtid: state.gtids.gtid().cloned(),
+        transaction: None,
+        timestamp: state.gtids.timestamp(),
+    };
+
+    let msg = match event.type_code {
+        TypeCode::QueryEvent => process_query_event(event, bn_file, state),
+        TypeCode::WriteRowsEventV2 => process_write_rows_event(event, bn_file, state),
+        TypeCode::UpdateRowsEventV2 => process_update_rows_event(event, bn_file, state),
+        TypeCode::DeleteRowsEventV2 => process_delete_rows_event(event, bn_file, state),
         _ => Err(to_err(format!(
             "Warning: Event '{:?}' skipped (evt2msg)",
             event.type_code
         ))
         .into()),
-    }
+    }?;
+
+    Ok(msg.map(|mut msg| {
+        msg.bn_file.transaction = state.gtids.next_transaction();
+        msg
+    }))
 }
 
 fn process_query_event(
     event: BinlogEvent,
-    file_name: &str,
-    local_store: &mut LocalStore,
-    urn: &str,
-) -> Result<String, CdcError> {
+    bn_file: BnFile,
+    state: &mut ParserState,
+) -> Result<Option<BinLogMessage>, CdcError> {
     if event.schema.is_none() {
         return Err(to_err(format!(
             "Error: '{:?}' missing 'schema' field.",
@@ -107,160 +185,330 @@ fn process_query_event(
     let schema = event.schema.as_ref().unwrap();
     let table_ops = parse_query(&event.query)?;
 
-    local_store.update_store(schema, table_ops)?;
+    state.local_store.update_store(schema, table_ops)?;
 
+    // transaction boundaries are carried by the messages
     if skip_query_event(&event.query) {
-        return Ok("".to_owned());
+        return Ok(None);
     }
 
     // generate message
-    let offset = Some(event.offset);
-
     let query = event.query.as_ref().unwrap_or(&"".to_owned()).clone();
     let op = Operation::Query(query);
 
-    let msg = BinLogMessage::new(urn, schema, None, file_name, offset, None, op);
-
-    // encode
-    let encoded = serde_json::to_string_pretty(&msg).unwrap();
+    let msg = BinLogMessage::new(&state.urn, schema, None, bn_file, None, op);
 
-    Ok(encoded)
+    Ok(Some(msg))
 }
 
 fn process_write_rows_event(
     event: BinlogEvent,
-    file_name: &str,
-    local_store: &mut LocalStore,
-    urn: &str,
-) -> Result<String, CdcError> {
+    bn_file: BnFile,
+    state: &mut ParserState,
+) -> Result<Option<BinLogMessage>, CdcError> {
     let (schema, table) = get_schema_table(&event)?;
-    let columns = local_store.get_columns(&schema, &table)?;
+    let (columns, column_types) = table_columns(state, &schema, &table)?;
+    let key_columns = table_key_columns(state, &schema, &table, &columns, &column_types);
+    let mask = table_mask(state.masking.as_ref(), &schema, &table, &columns);
+    let key_columns = mask_key_columns(mask.as_ref(), &columns, key_columns);
+    let column_types = mask_column_types(mask.as_ref(), column_types);
 
     // generate message
-    let offset = Some(event.offset);
-
     let rows_json_str = serde_json::to_string(&event.rows)?;
     let rows: Vec<Cols> = serde_json::from_str(&rows_json_str)?;
+    let (columns, rows) = mask_rows(mask.as_ref(), columns, rows);
     let op = Operation::Add(WriteRows { rows });
 
     let msg = BinLogMessage::new(
-        urn,
+        &state.urn,
         &schema,
         Some(&table),
-        file_name,
-        offset,
+        bn_file,
         Some(columns),
         op,
-    );
-
-    // encode
-    let encoded = serde_json::to_string_pretty(&msg).unwrap();
+    )
+    .with_column_types(column_types)
+    .with_key_columns(key_columns);
 
-    Ok(encoded)
+    Ok(Some(msg))
 }
 
 fn process_update_rows_event(
     event: BinlogEvent,
-    file_name: &str,
-    local_store: &mut LocalStore,
-    urn: &str,
-) -> Result<String, CdcError> {
+    bn_file: BnFile,
+    state: &mut ParserState,
+) -> Result<Option<BinLogMessage>, CdcError> {
     let (schema, table) = get_schema_table(&event)?;
-    let columns = local_store.get_columns(&schema, &table)?;
+    let (columns, column_types) = table_columns(state, &schema, &table)?;
+    let key_columns = table_key_columns(state, &schema, &table, &columns, &column_types);
+    let mask = table_mask(state.masking.as_ref(), &schema, &table, &columns);
+    let key_columns = mask_key_columns(mask.as_ref(), &columns, key_columns);
+    let column_types = mask_column_types(mask.as_ref(), column_types);
 
     // generate message
-    let offset = Some(event.offset);
-
     let rows_json_str = serde_json::to_string(&event.rows)?;
     let rows: Vec<BeforeAfterCols> = serde_json::from_str(&rows_json_str)?;
+    let (columns, rows) = mask_before_after_rows(mask.as_ref(), columns, rows);
     let op = Operation::Update(UpdateRows { rows });
 
     let msg = BinLogMessage::new(
-        urn,
+        &state.urn,
         &schema,
         Some(&table),
-        file_name,
-        offset,
+        bn_file,
         Some(columns),
         op,
-    );
-
-    // encode
-    let encoded = serde_json::to_string_pretty(&msg).unwrap();
+    )
+    .with_column_types(column_types)
+    .with_key_columns(key_columns);
 
-    Ok(encoded)
+    Ok(Some(msg))
 }
 
 fn process_delete_rows_event(
     event: BinlogEvent,
-    file_name: &str,
-    local_store: &mut LocalStore,
-    urn: &str,
-) -> Result<String, CdcError> {
+    bn_file: BnFile,
+    state: &mut ParserState,
+) -> Result<Option<BinLogMessage>, CdcError> {
     let (schema, table) = get_schema_table(&event)?;
-    let columns = local_store.get_columns(&schema, &table)?;
+    let (columns, column_types) = table_columns(state, &schema, &table)?;
+    let key_columns = table_key_columns(state, &schema, &table, &columns, &column_types);
+    let mask = table_mask(state.masking.as_ref(), &schema, &table, &columns);
+    let key_columns = mask_key_columns(mask.as_ref(), &columns, key_columns);
+    let column_types = mask_column_types(mask.as_ref(), column_types);
 
     // generate message
-    let offset = Some(event.offset);
-
     let rows_json_str = serde_json::to_string(&event.rows)?;
     let rows: Vec<Cols> = serde_json::from_str(&rows_json_str)?;
+    let (columns, rows) = mask_rows(mask.as_ref(), columns, rows);
     let op = Operation::Delete(DeleteRows { rows });
 
     let msg = BinLogMessage::new(
-        urn,
+        &state.urn,
         &schema,
         Some(&table),
-        file_name,
-        offset,
+        bn_file,
         Some(columns),
         op,
-    );
+    )
+    .with_column_types(column_types)
+    .with_key_columns(key_columns);
 
-    // encode
-    let encoded = serde_json::to_string_pretty(&msg).unwrap();
+    Ok(Some(msg))
+}
+
+/// Column names and types of a rows event table:
+///  - names from the local store, or from the table map when the store does not know the
+///    table (binlog_row_metadata=FULL)
+///  - types from the table map, if it matches the columns
+fn table_columns(
+    state: &mut ParserState,
+    schema: &str,
+    table: &str,
+) -> Result<(Vec<String>, Option<Vec<ColumnType>>), Error> {
+    let table_map = state.gtids.table_map(schema, table);
+
+    let columns = match state.local_store.get_columns(schema, table) {
+        Ok(columns) => columns,
+        Err(err) => match table_map.and_then(|table_map| table_map.column_names.clone()) {
+            Some(columns) => columns,
+            None => return Err(err),
+        },
+    };
+
+    let column_types = match table_map {
+        Some(table_map) if table_map.column_types.len() == columns.len() => {
+            Some(table_map.column_types.clone())
+        }
+        Some(_) => {
+            warn!(%schema, %table, "Table map does not match local store columns");
+            None
+        }
+        None => None,
+    };
+
+    Ok((columns, column_types))
+}
+
+/// Key columns of a rows event table:
+///  - from the local store (CREATE TABLE primary or unique key)
+///  - otherwise, primary key flags of the table map types (binlog_row_metadata=FULL)
+fn table_key_columns(
+    state: &ParserState,
+    schema: &str,
+    table: &str,
+    columns: &[String],
+    column_types: &Option<Vec<ColumnType>>,
+) -> Option<Vec<String>> {
+    let key_columns = state
+        .local_store
+        .get_key_columns(schema, table)
+        .or_else(|| {
+            let keys: Vec<String> = columns
+                .iter()
+                .zip(column_types.as_ref()?)
+                .filter(|(_, column_type)| column_type.primary_key)
+                .map(|(column, _)| column.clone())
+                .collect();
+            Some(keys)
+        })?;
+
+    if key_columns.is_empty() || !key_columns.iter().all(|key| columns.contains(key)) {
+        return None;
+    }
+    Some(key_columns)
+}
+
+/// Masked key columns (dropped, nulled, hashed or truncated) no longer identify a row
+fn mask_key_columns(
+    mask: Option<&TableMask>,
+    columns: &[String],
+    key_columns: Option<Vec<String>>,
+) -> Option<Vec<String>> {
+    let mask = match mask {
+        Some(mask) => mask,
+        None => return key_columns,
+    };
+
+    key_columns.filter(|keys| {
+        columns
+            .iter()
+            .enumerate()
+            .all(|(idx, column)| !keys.contains(column) || !mask.is_masked(idx))
+    })
+}
+
+/// Column masks of the table, if any masking rule applies
+fn table_mask<'a>(
+    masking: Option<&'a Masking>,
+    schema: &str,
+    table: &str,
+    columns: &[String],
+) -> Option<TableMask<'a>> {
+    let mask = masking.and_then(|masking| masking.table_mask(schema, table, columns));
+    if mask.is_some() {
+        trace!(%schema, %table, "Masking columns");
+    }
+    mask
+}
+
+fn mask_column_types(
+    mask: Option<&TableMask>,
+    column_types: Option<Vec<ColumnType>>,
+) -> Option<Vec<ColumnType>> {
+    match mask {
+        Some(mask) => column_types.map(|column_types| mask.column_types(column_types)),
+        None => column_types,
+    }
+}
+
+fn mask_rows(
+    mask: Option<&TableMask>,
+    columns: Vec<String>,
+    rows: Vec<Cols>,
+) -> (Vec<String>, Vec<Cols>) {
+    match mask {
+        Some(mask) => (
+            mask.columns(columns),
+            rows.into_iter()
+                .map(|row| Cols {
+                    cols: mask.row(row.cols),
+                })
+                .collect(),
+        ),
+        None => (columns, rows),
+    }
+}
 
-    Ok(encoded)
+fn mask_before_after_rows(
+    mask: Option<&TableMask>,
+    columns: Vec<String>,
+    rows: Vec<BeforeAfterCols>,
+) -> (Vec<String>, Vec<BeforeAfterCols>) {
+    match mask {
+        Some(mask) => (
+            mask.columns(columns),
+            rows.into_iter()
+                .map(|row| BeforeAfterCols {
+                    before_cols: mask.row(row.before_cols),
+                    after_cols: mask.row(row.after_cols),
+                })
+                .collect(),
+        ),
+        None => (columns, rows),
+    }
 }
 
-/// Allowed by filter algorithm applies to schema or schema_name.
+/// Filters apply to schema or schema_name, and table names. Returns false if the event
+/// is filtered out:
 ///  - no schema or schema_name => true
 ///  - no filters => true
-///  - include filters matched => true
-///  - exclude filters matched => false
-#[instrument(skip(filters, schema, schema_name))]
-fn allowed_by_filters(
-    filters: Option<&Filters>,
-    schema: Option<&str>,
-    schema_name: Option<&str>,
-) -> bool {
-    trace!(?schema, ?schema_name, "Checking filters");
+///  - row events => database and table matched by filters
+///  - query events => tables in the statement matched by filters; statements that mix
+///    allowed and excluded tables are rewritten to the allowed tables. Database
+///    statements (no table) are matched by database
+#[instrument(skip(filters, event))]
+fn apply_filters(filters: Option<&Filters>, event: &mut BinlogEvent) -> bool {
+    let filters = match filters {
+        Some(filters) => filters,
+        None => return true,
+    };
+
     // set db name
-    let db_name = if let Some(schema) = schema {
+    let db_name = if let Some(schema) = event.schema.as_deref() {
         schema
-    } else if let Some(schema) = schema_name {
+    } else if let Some(schema) = event.schema_name.as_deref() {
         schema
     } else {
         return true;
     };
     let db_name = db_name.to_ascii_lowercase();
-    trace!(?db_name, "Checking DB name");
-
-    if let Some(filters) = filters {
-        match filters {
-            Filters::Include { include_dbs: dbs } => {
-                let dbs: Vec<_> = dbs.iter().map(|s| &**s).collect();
-                trace!("Checking if {:?} includes {}", &dbs, &db_name);
-                dbs.contains(&&*db_name)
-            }
-            Filters::Exclude { exclude_dbs: dbs } => {
-                let dbs: Vec<_> = dbs.iter().map(|s| &**s).collect();
-                trace!("Checking if {:?} excludes {}", &dbs, &db_name);
-                !dbs.contains(&&*db_name)
-            }
-        }
-    } else {
+
+    let tables = match (&event.table_name, &event.type_code) {
+        (Some(table), _) => vec![(db_name.clone(), table.to_ascii_lowercase())],
+        (None, TypeCode::QueryEvent) => parse_query_tables(&event.query)
+            .iter()
+            .map(|name| split_table_name(&db_name, name))
+            .collect(),
+        (None, _) => vec![],
+    };
+
+    let allowed_tables = tables
+        .iter()
+        .filter(|(db_name, table_name)| filters.allows_table(db_name, table_name))
+        .count();
+    let allowed = if tables.is_empty() {
+        filters.allows_db(&db_name)
+    } else if allowed_tables == tables.len() {
         true
+    } else if allowed_tables == 0 {
+        false
+    } else {
+        // keep the allowed tables only, excluded tables must not reach the topic
+        let keep = |name: &str| {
+            let (db_name, table_name) = split_table_name(&db_name, name);
+            filters.allows_table(&db_name, &table_name)
+        };
+        let query = event
+            .query
+            .as_deref()
+            .and_then(|query| retain_query_tables(query, keep));
+        debug!(?query, "Statement restricted to allowed tables");
+        let allowed = query.is_some();
+        event.query = query;
+        allowed
+    };
+    debug!(%db_name, ?tables, allowed, "Filters applied");
+
+    allowed
+}
+
+/// Table name from a statement, "db.table" overrides the default database
+fn split_table_name(db_name: &str, name: &str) -> (String, String) {
+    let name = name.replace('`', "").replace('"', "").to_ascii_lowercase();
+    match name.find('.') {
+        Some(pos) => (name[..pos].to_owned(), name[pos + 1..].to_owned()),
+        None => (db_name.to_owned(), name),
     }
 }
 
@@ -275,7 +523,8 @@ fn same_offset(local_offset: Option<u64>, event_offset: u64) -> bool {
 
 fn skip_query_event(query: &Option<String>) -> bool {
     if let Some(query) = query {
-        return query.to_lowercase().as_str() == "begin";
+        let query = query.to_lowercase();
+        return query == "begin" || query == "commit";
     }
     true
 }
@@ -1,9 +1,10 @@
 use async_std::fs;
 use std::io::Error;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use tracing::debug;
 
-use crate::messages::BnFile;
+use super::split_file_name;
+use crate::messages::{BnFile, GtidSet};
 use crate::util::expand_tilde;
 
 #[derive(Debug, Clone)]
@@ -12,6 +13,20 @@ pub struct Resume {
     pub binfile: Option<BnFile>,
 }
 
+/// Resume file compared with the last message published to the topic
+#[derive(Debug, PartialEq)]
+pub enum ResumeCheck {
+    /// same position, or nothing published and nothing to resume
+    Agree,
+    /// no resume file, the topic has messages
+    Missing,
+    /// topic ahead of the resume file: the producer stopped after publishing a message
+    /// and before saving its position
+    Behind,
+    /// positions differ, the topic is not ahead of the resume file
+    Mismatch,
+}
+
 impl Resume {
     pub fn new<P: Into<PathBuf>>(path: P, binfile: BnFile) -> Result<Self, Error> {
         Ok(Self {
@@ -47,6 +62,13 @@ impl Resume {
         })
     }
 
+    /// File name of the position currently stored in the resume file at `path`
+    pub fn stored_file(path: &Path) -> Option<String> {
+        let resume_contents = std::fs::read_to_string(path).ok()?;
+        let bnfile = serde_json::from_str::<BnFile>(&resume_contents).ok()?;
+        Some(bnfile.file_name)
+    }
+
     pub fn file(&self) -> Option<&str> {
         self.binfile.as_ref().map(|it| &*it.file_name)
     }
@@ -55,6 +77,21 @@ impl Resume {
         self.binfile.as_ref().and_then(|it| it.offset)
     }
 
+    pub fn gtid_set(&self) -> Option<&GtidSet> {
+        self.binfile.as_ref().and_then(|it| it.gtid_set.as_ref())
+    }
+
+    /// Check the resume position against the last message published to the topic
+    pub fn check_published(&self, published: Option<&BnFile>) -> ResumeCheck {
+        match (self.binfile.as_ref(), published) {
+            (None, None) => ResumeCheck::Agree,
+            (None, Some(_)) => ResumeCheck::Missing,
+            (Some(binfile), Some(published)) if binfile == published => ResumeCheck::Agree,
+            (Some(binfile), Some(published)) if is_ahead(published, binfile) => ResumeCheck::Behind,
+            (Some(_), _) => ResumeCheck::Mismatch,
+        }
+    }
+
     pub async fn update_binfile(&mut self, binfile: BnFile) -> Result<(), Error> {
         let serialized = serde_json::to_string(&binfile).unwrap();
         debug!("Writing binlog: {}", serialized);
@@ -63,8 +100,108 @@ impl Resume {
         Ok(())
     }
 
+    /// Resume from start
+    pub async fn clear(&mut self) -> Result<(), Error> {
+        debug!("Clearing binlog");
+        fs::write(&self.path, "").await?;
+        self.binfile = None;
+        Ok(())
+    }
+
     async fn read_binfile(path: &async_std::path::Path) -> Result<Option<BnFile>, Error> {
         let resume_contents = fs::read_to_string(&path).await?;
         Ok(serde_json::from_str::<BnFile>(&resume_contents).ok())
     }
 }
+
+/// Published position after the resume position:
+///  - gtid sets, when both have one: the published set contains the resume set
+///  - file and offset: later file of the same binlog, or later offset in the same file
+fn is_ahead(published: &BnFile, resume: &BnFile) -> bool {
+    if let (Some(published_set), Some(resume_set)) = (&published.gtid_set, &resume.gtid_set) {
+        if !resume_set.is_subset(published_set) {
+            return false;
+        }
+        if published_set != resume_set {
+            return true;
+        }
+    }
+
+    match (
+        split_file_name(&published.file_name),
+        split_file_name(&resume.file_name),
+    ) {
+        (Some((published_base, published_idx)), Some((resume_base, resume_idx)))
+            if published_base == resume_base =>
+        {
+            (published_idx, published.offset) > (resume_idx, resume.offset)
+        }
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_check_published() {
+        let bn_file = BnFile::new("binlog.000003".to_owned(), Some(1146));
+        let resume = Resume::empty("resume").unwrap();
+        assert_eq!(resume.check_published(None), ResumeCheck::Agree);
+        assert_eq!(resume.check_published(Some(&bn_file)), ResumeCheck::Missing);
+
+        let resume = Resume::new("resume", bn_file.clone()).unwrap();
+        assert_eq!(resume.check_published(Some(&bn_file)), ResumeCheck::Agree);
+        assert_eq!(resume.check_published(None), ResumeCheck::Mismatch);
+
+        // topic ahead: later offset, later file
+        let published = BnFile::new("binlog.000003".to_owned(), Some(1461));
+        assert_eq!(
+            resume.check_published(Some(&published)),
+            ResumeCheck::Behind
+        );
+        let published = BnFile::new("binlog.000004".to_owned(), Some(4));
+        assert_eq!(
+            resume.check_published(Some(&published)),
+            ResumeCheck::Behind
+        );
+
+        // topic behind, other binlog
+        let published = BnFile::new("binlog.000003".to_owned(), Some(832));
+        assert_eq!(
+            resume.check_published(Some(&published)),
+            ResumeCheck::Mismatch
+        );
+        let published = BnFile::new("mysql-bin.000004".to_owned(), Some(4));
+        assert_eq!(
+            resume.check_published(Some(&published)),
+            ResumeCheck::Mismatch
+        );
+    }
+
+    #[test]
+    fn test_check_published_gtids() {
+        let sid = "3e11fa47-71ca-11e1-9e33-c80aa9429562";
+        let bn_file = |file_name: &str, gtid_set: &str| {
+            let mut bn_file = BnFile::new(file_name.to_owned(), Some(1146));
+            bn_file.gtid_set = Some(format!("{}:{}", sid, gtid_set).parse().unwrap());
+            bn_file
+        };
+        let resume = Resume::new("resume", bn_file("binlog.000003", "1-23")).unwrap();
+
+        // failover: other file name, more transactions
+        let published = bn_file("mysql-bin.000001", "1-24");
+        assert_eq!(
+            resume.check_published(Some(&published)),
+            ResumeCheck::Behind
+        );
+
+        // missing transactions
+        let published = bn_file("binlog.000004", "1-20");
+        assert_eq!(
+            resume.check_published(Some(&published)),
+            ResumeCheck::Mismatch
+        );
+    }
+}
@@ -0,0 +1,192 @@
+/// FakeServer (tests only)
+///
+/// Mysql source stand-in for replication tests. Accepts a single replica connection,
+/// answers session setup commands, and replays binlog files from disk on COM_BINLOG_DUMP.
+/// The stream ends with an EOF packet after the last file.
+///
+use std::fs;
+use std::io::prelude::*;
+use std::io::{Error, ErrorKind};
+use std::net::{TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+use std::thread;
+
+use super::raw_event::{le_u32, EventHeader, CHECKSUM_LEN, EVENT_HEADER_LEN, ROTATE_EVENT};
+
+const COM_QUERY: u8 = 0x03;
+const COM_BINLOG_DUMP: u8 = 0x12;
+const COM_REGISTER_SLAVE: u8 = 0x15;
+
+const OK_PACKET: [u8; 7] = [0, 0, 0, 2, 0, 0, 0];
+const EOF_PACKET: [u8; 5] = [0xfe, 0, 0, 2, 0];
+const ARTIFICIAL_FLAG: u16 = 0x20;
+const BINLOG_CHECKSUM: &str = "CRC32";
+
+/// Start server on a random local port, return the port
+pub fn start(base_dir: PathBuf, files: &[&str]) -> Result<u16, Error> {
+    let listener = TcpListener::bind("127.0.0.1:0")?;
+    let port = listener.local_addr()?.port();
+    let files: Vec<String> = files.iter().map(|file| file.to_string()).collect();
+
+    thread::spawn(move || {
+        if let Ok((stream, _)) = listener.accept() {
+            if let Err(err) = serve(stream, &base_dir, &files) {
+                println!("fake server: {}", err);
+            }
+        }
+    });
+
+    Ok(port)
+}
+
+fn serve(mut stream: TcpStream, base_dir: &Path, files: &[String]) -> Result<(), Error> {
+    write_packet(&mut stream, 0, &handshake())?;
+    let (_, seq) = read_packet(&mut stream)?;
+    write_packet(&mut stream, seq + 1, &OK_PACKET)?;
+
+    loop {
+        let (command, _) = read_packet(&mut stream)?;
+        match command.first() {
+            Some(&COM_QUERY) => {
+                let query = String::from_utf8_lossy(&command[1..]).to_uppercase();
+                if query.starts_with("SELECT") {
+                    write_single_value(&mut stream, BINLOG_CHECKSUM)?;
+                } else {
+                    write_packet(&mut stream, 1, &OK_PACKET)?;
+                }
+            }
+            Some(&COM_REGISTER_SLAVE) => write_packet(&mut stream, 1, &OK_PACKET)?,
+            Some(&COM_BINLOG_DUMP) => return dump(&mut stream, base_dir, files, &command),
+            _ => {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    format!("unexpected command {:?}", command.first()),
+                ))
+            }
+        }
+    }
+}
+
+/// Replay files starting at the requested file/position
+fn dump(
+    stream: &mut TcpStream,
+    base_dir: &Path,
+    files: &[String],
+    command: &[u8],
+) -> Result<(), Error> {
+    let pos = le_u32(command, 1) as usize;
+    let file_name = String::from_utf8_lossy(&command[11..]).to_string();
+    let first = if file_name.is_empty() {
+        0
+    } else {
+        files
+            .iter()
+            .position(|file| *file == file_name)
+            .ok_or_else(|| Error::new(ErrorKind::NotFound, file_name.clone()))?
+    };
+
+    let mut seq = 1;
+    for (idx, file) in files.iter().enumerate().skip(first) {
+        let start_pos = if idx == first { pos } else { 4 };
+        let bytes = fs::read(base_dir.join(file))?;
+
+        write_event(stream, &mut seq, &fake_rotate(file, start_pos))?;
+
+        let mut offset = 4;
+        while offset < bytes.len() {
+            let header = EventHeader::parse(&bytes[offset..])
+                .ok_or_else(|| Error::new(ErrorKind::InvalidData, "invalid event"))?;
+            let end = offset + header.event_size as usize;
+            let mut event = bytes[offset..end].to_vec();
+
+            if offset == 4 {
+                // format description is always sent, not positioned when resuming
+                if start_pos > 4 {
+                    event[13..17].copy_from_slice(&[0; 4]);
+                }
+                write_event(stream, &mut seq, &event)?;
+            } else if offset >= start_pos {
+                write_event(stream, &mut seq, &event)?;
+            }
+
+            offset = end;
+        }
+    }
+
+    write_packet(stream, seq, &EOF_PACKET)
+}
+
+fn fake_rotate(file: &str, pos: usize) -> Vec<u8> {
+    let event_size = EVENT_HEADER_LEN + 8 + file.len() + CHECKSUM_LEN;
+
+    let mut event = vec![];
+    event.extend_from_slice(&0u32.to_le_bytes());
+    event.push(ROTATE_EVENT);
+    event.extend_from_slice(&1u32.to_le_bytes());
+    event.extend_from_slice(&(event_size as u32).to_le_bytes());
+    event.extend_from_slice(&0u32.to_le_bytes());
+    event.extend_from_slice(&ARTIFICIAL_FLAG.to_le_bytes());
+    event.extend_from_slice(&(pos as u64).to_le_bytes());
+    event.extend_from_slice(file.as_bytes());
+    event.extend_from_slice(&[0; CHECKSUM_LEN]);
+
+    event
+}
+
+fn handshake() -> Vec<u8> {
+    let mut packet = vec![10];
+    packet.extend_from_slice(b"8.0.20-fake\0");
+    packet.extend_from_slice(&1u32.to_le_bytes());
+    packet.extend_from_slice(&[0x2a; 8]);
+    packet.push(0);
+    packet.extend_from_slice(&0xffffu16.to_le_bytes());
+    packet.push(33);
+    packet.extend_from_slice(&2u16.to_le_bytes());
+    packet.extend_from_slice(&0x000fu16.to_le_bytes());
+    packet.push(21);
+    packet.extend_from_slice(&[0; 10]);
+    packet.extend_from_slice(&[0x2b; 12]);
+    packet.push(0);
+    packet.extend_from_slice(b"mysql_native_password\0");
+
+    packet
+}
+
+/// Result set with one column and one row
+fn write_single_value(stream: &mut TcpStream, value: &str) -> Result<(), Error> {
+    let mut row = vec![value.len() as u8];
+    row.extend_from_slice(value.as_bytes());
+
+    write_packet(stream, 1, &[1])?;
+    write_packet(stream, 2, &[3, b'd', b'e', b'f'])?;
+    write_packet(stream, 3, &EOF_PACKET)?;
+    write_packet(stream, 4, &row)?;
+    write_packet(stream, 5, &EOF_PACKET)
+}
+
+fn write_event(stream: &mut TcpStream, seq: &mut u8, event: &[u8]) -> Result<(), Error> {
+    let mut packet = vec![0];
+    packet.extend_from_slice(event);
+    write_packet(stream, *seq, &packet)?;
+    *seq = seq.wrapping_add(1);
+
+    Ok(())
+}
+
+fn write_packet(stream: &mut TcpStream, seq: u8, payload: &[u8]) -> Result<(), Error> {
+    let len = (payload.len() as u32).to_le_bytes();
+    stream.write_all(&[len[0], len[1], len[2], seq])?;
+    stream.write_all(payload)?;
+    stream.flush()
+}
+
+fn read_packet(stream: &mut TcpStream) -> Result<(Vec<u8>, u8), Error> {
+    let mut header = [0; 4];
+    stream.read_exact(&mut header)?;
+    let len = u32::from_le_bytes([header[0], header[1], header[2], 0]) as usize;
+
+    let mut payload = vec![0; len];
+    stream.read_exact(&mut payload)?;
+
+    Ok((payload, header[3]))
+}
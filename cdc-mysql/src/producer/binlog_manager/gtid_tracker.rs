@@ -0,0 +1,597 @@
+/// GtidTracker
+///
+/// Follows the raw events of the binlog file being parsed and keeps track of:
+///     - the transaction (GTID) of the current event
+///     - the set of transactions completed so far (executed GTID set)
+///     - the transaction boundaries (GTID, BEGIN, XID/COMMIT), with or without gtids
+///     - the table maps (column types) of the rows events
+///     - the time of the current event
+///
+/// All are attached to each message (BnFile) and saved in the resume file. On restart,
+/// transactions in the executed set are skipped, as well as the events of the open
+/// transaction that were already sent.
+///
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::prelude::*;
+use std::io::{BufReader, Error, ErrorKind, SeekFrom};
+use std::path::Path;
+use tracing::{debug, trace};
+
+use super::raw_event::{checksum_len, is_data_event, parse_gtid, parse_previous_gtids, query_text};
+use super::raw_event::{EventHeader, BINLOG_MAGIC, EVENT_HEADER_LEN};
+use super::raw_event::{ANONYMOUS_GTID_LOG_EVENT, FORMAT_DESCRIPTION_EVENT, GTID_LOG_EVENT};
+use super::raw_event::{MARIADB_GTID_EVENT, MARIADB_GTID_LIST_EVENT, PREVIOUS_GTIDS_LOG_EVENT};
+use super::raw_event::{QUERY_EVENT, TABLE_MAP_EVENT, XID_EVENT};
+use super::table_map::{parse_table_map, TableMap};
+use crate::messages::{BnFile, Gtid, GtidSet, Transaction};
+
+// previous gtids are written right after the format description event
+const PREVIOUS_GTIDS_MAX_EVENTS: usize = 4;
+
+#[derive(Debug, Default)]
+pub struct GtidTracker {
+    file_name: String,
+    reader: Option<EventReader>,
+    checksum_len: usize,
+
+    executed: GtidSet,
+    gtid: Option<Gtid>,
+    open: bool,
+    explicit: bool,
+
+    tx_id: Option<String>,
+    tx_index: u64,
+
+    table_maps: BTreeMap<(String, String), TableMap>,
+    timestamp: Option<u32>,
+
+    resume: Option<BnFile>,
+}
+
+impl GtidTracker {
+    /// Position to resume from, its executed transactions are added to the tracker
+    pub fn set_resume(&mut self, resume: Option<&BnFile>) {
+        if let Some(gtid_set) = resume.and_then(|resume| resume.gtid_set.as_ref()) {
+            self.executed.union(gtid_set);
+        }
+        self.resume = resume.cloned();
+    }
+
+    /// Switch to binlog file, no-op if the file is already tracked
+    pub fn set_file(&mut self, log_file: &str, file_name: &str) -> Result<(), Error> {
+        if self.reader.is_some() && self.file_name == file_name {
+            return Ok(());
+        }
+
+        debug!(%file_name, "Tracking gtids");
+        self.file_name = file_name.to_owned();
+        self.reader = Some(EventReader::open(Path::new(log_file))?);
+        self.checksum_len = 0;
+        self.gtid = None;
+        self.open = false;
+        self.explicit = false;
+        self.tx_id = None;
+
+        Ok(())
+    }
+
+    /// Apply raw events up to (and including) the event at offset
+    pub fn advance_to(&mut self, offset: u64) -> Result<(), Error> {
+        while let Some(reader) = self.reader.as_mut() {
+            let position = reader.position();
+            if position > offset {
+                break;
+            }
+            match reader.next_event()? {
+                Some(event) => self.apply(&event, position),
+                None => break,
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Apply raw events up to the next statement or rows event, so the end of the
+    /// transaction (XID) is known before more events are parsed
+    pub fn advance_to_next_data(&mut self) -> Result<(), Error> {
+        while let Some(reader) = self.reader.as_mut() {
+            let position = reader.position();
+            if !matches!(reader.peek_type_code()?, Some(type_code) if !is_data_event(type_code)) {
+                break;
+            }
+            match reader.next_event()? {
+                Some(event) => self.apply(&event, position),
+                None => break,
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Transactions completed, including the one ended by the current event
+    pub fn gtid_set(&self) -> Option<GtidSet> {
+        if self.executed.is_empty() {
+            None
+        } else {
+            Some(self.executed.clone())
+        }
+    }
+
+    /// Transaction of the current event
+    pub fn gtid(&self) -> Option<&Gtid> {
+        self.gtid.as_ref()
+    }
+
+    /// Time of the current event, in seconds since the epoch
+    pub fn timestamp(&self) -> Option<u32> {
+        self.timestamp
+    }
+
+    /// Position of the next message in the current transaction, None outside of
+    /// transactions. Continues the resume transaction, if interrupted.
+    pub fn next_transaction(&mut self) -> Option<Transaction> {
+        let id = self.tx_id.clone()?;
+        let index = self.tx_index;
+        self.tx_index += 1;
+
+        Some(Transaction::new(id, index, !self.open))
+    }
+
+    /// Transaction is still in progress at the current event
+    pub fn is_open(&self, tx_id: &str) -> bool {
+        self.open && self.tx_id.as_deref() == Some(tx_id)
+    }
+
+    /// Latest table map of the table, the one of the current rows event
+    pub fn table_map(&self, db_name: &str, table_name: &str) -> Option<&TableMap> {
+        self.table_maps
+            .get(&(db_name.to_owned(), table_name.to_owned()))
+    }
+
+    /// Current event was sent before the restart:
+    ///  - its transaction is in the resume executed set, or
+    ///  - it belongs to the resume transaction, at or before the resume offset
+    pub fn is_replayed(&self, file_name: &str, offset: u64) -> bool {
+        let (resume, gtid) = match (self.resume.as_ref(), self.gtid.as_ref()) {
+            (Some(resume), Some(gtid)) => (resume, gtid),
+            _ => return false,
+        };
+
+        if let Some(gtid_set) = resume.gtid_set.as_ref() {
+            if gtid_set.contains(gtid) {
+                return true;
+            }
+        }
+
+        resume.gtid.as_ref() == Some(gtid)
+            && resume.file_name == file_name
+            && matches!(resume.offset, Some(resume_offset) if offset <= resume_offset)
+    }
+
+    fn apply(&mut self, event: &[u8], offset: u64) {
+        let header = match EventHeader::parse(event) {
+            Some(header) => header,
+            None => return,
+        };
+        self.timestamp = Some(header.timestamp);
+
+        match header.type_code {
+            FORMAT_DESCRIPTION_EVENT => self.checksum_len = checksum_len(event),
+            PREVIOUS_GTIDS_LOG_EVENT | MARIADB_GTID_LIST_EVENT => {
+                if let Some(previous) = parse_previous_gtids(event, self.checksum_len) {
+                    self.executed.union(&previous);
+                }
+            }
+            GTID_LOG_EVENT | MARIADB_GTID_EVENT => {
+                if let Some((gtid, explicit)) = parse_gtid(event) {
+                    trace!(%gtid, "Transaction started");
+                    self.gtid = Some(gtid);
+                    self.explicit = explicit;
+                    self.start_transaction(offset);
+                }
+            }
+            ANONYMOUS_GTID_LOG_EVENT => {
+                self.gtid = None;
+                self.explicit = false;
+                self.start_transaction(offset);
+            }
+            QUERY_EVENT => {
+                let query = query_text(event, self.checksum_len).unwrap_or_default();
+                let query = query.trim().to_ascii_uppercase();
+                if query == "BEGIN" || query.starts_with("XA START") {
+                    self.explicit = true;
+                    if !self.open {
+                        self.start_transaction(offset);
+                    }
+                } else if !self.explicit || query == "COMMIT" || query == "ROLLBACK" {
+                    // statements outside BEGIN (DDL) are transactions on their own
+                    if !self.open {
+                        self.start_transaction(offset);
+                    }
+                    self.end_transaction();
+                }
+            }
+            XID_EVENT => self.end_transaction(),
+            TABLE_MAP_EVENT => {
+                if let Some(table_map) = parse_table_map(event, self.checksum_len) {
+                    let key = (table_map.db_name.clone(), table_map.table_name.clone());
+                    self.table_maps.insert(key, table_map);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Transactions are identified by gtid, or by their first event without gtids
+    fn start_transaction(&mut self, offset: u64) {
+        let tx_id = match self.gtid.as_ref() {
+            Some(gtid) => gtid.to_string(),
+            None => format!("{}:{}", self.file_name, offset),
+        };
+
+        self.tx_index = match self.resume.as_ref().and_then(|bn| bn.transaction.as_ref()) {
+            Some(resume) if resume.id == tx_id => resume.index + 1,
+            _ => 0,
+        };
+        self.tx_id = Some(tx_id);
+        self.open = true;
+    }
+
+    fn end_transaction(&mut self) {
+        if self.open {
+            if let Some(gtid) = self.gtid.as_ref() {
+                self.executed.add(gtid);
+            }
+        }
+        self.open = false;
+        self.explicit = false;
+    }
+}
+
+/// Transactions executed before the binlog file (PREVIOUS_GTIDS or GTID_LIST event)
+pub fn previous_gtids(log_file: &Path) -> Result<Option<GtidSet>, Error> {
+    let mut reader = EventReader::open(log_file)?;
+    let mut checksum = 0;
+
+    for _ in 0..PREVIOUS_GTIDS_MAX_EVENTS {
+        let event = match reader.next_event()? {
+            Some(event) => event,
+            None => break,
+        };
+
+        match event[4] {
+            FORMAT_DESCRIPTION_EVENT => checksum = checksum_len(&event),
+            PREVIOUS_GTIDS_LOG_EVENT | MARIADB_GTID_LIST_EVENT => {
+                return Ok(parse_previous_gtids(&event, checksum));
+            }
+            _ => {}
+        }
+    }
+
+    Ok(None)
+}
+
+/// Sequential reader of raw binlog events
+///  - the file may still be written to, incomplete events are read again on the next call
+#[derive(Debug)]
+struct EventReader {
+    reader: BufReader<File>,
+    position: u64,
+}
+
+impl EventReader {
+    fn open(log_file: &Path) -> Result<Self, Error> {
+        let mut reader = BufReader::new(File::open(log_file)?);
+
+        let mut magic = [0; 4];
+        reader.read_exact(&mut magic)?;
+        if magic != BINLOG_MAGIC {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("{:?} is not a binlog file", log_file),
+            ));
+        }
+
+        Ok(Self {
+            reader,
+            position: BINLOG_MAGIC.len() as u64,
+        })
+    }
+
+    /// Offset of the next event
+    fn position(&self) -> u64 {
+        self.position
+    }
+
+    /// Type of the next event, the event is read again by `next_event`
+    fn peek_type_code(&mut self) -> Result<Option<u8>, Error> {
+        let mut header = [0; EVENT_HEADER_LEN];
+        if !self.read_or_rewind(&mut header)? {
+            return Ok(None);
+        }
+        self.reader.seek(SeekFrom::Start(self.position))?;
+
+        Ok(Some(header[4]))
+    }
+
+    fn next_event(&mut self) -> Result<Option<Vec<u8>>, Error> {
+        let mut event = vec![0; EVENT_HEADER_LEN];
+        if !self.read_or_rewind(&mut event)? {
+            return Ok(None);
+        }
+
+        let event_size = EventHeader::parse(&event).map_or(0, |header| header.event_size);
+        if (event_size as usize) < EVENT_HEADER_LEN {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("invalid event at offset {}", self.position),
+            ));
+        }
+
+        event.resize(event_size as usize, 0);
+        if !self.read_or_rewind(&mut event[EVENT_HEADER_LEN..])? {
+            return Ok(None);
+        }
+
+        self.position += event_size as u64;
+        Ok(Some(event))
+    }
+
+    /// Fill buffer, on end of file rewind to the start of the current event
+    fn read_or_rewind(&mut self, buf: &mut [u8]) -> Result<bool, Error> {
+        match self.reader.read_exact(buf) {
+            Ok(()) => Ok(true),
+            Err(err) if err.kind() == ErrorKind::UnexpectedEof => {
+                self.reader.seek(SeekFrom::Start(self.position))?;
+                Ok(false)
+            }
+            Err(err) => Err(err),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+    use std::path::PathBuf;
+
+    use super::*;
+    use crate::producer::binlog_manager::raw_event::{encode_gtid_set, CHECKSUM_LEN};
+
+    const TEST_PATH: &str = "test_files";
+    const BL_FILE1: &str = "binlog.000001";
+    const GTID_FILE: &str = "gtid-tracker-test.000001";
+    const GTID_RESUME_FILE: &str = "gtid-tracker-test.000002";
+    const TX_FILE: &str = "gtid-tracker-test.000003";
+    const SID: &str = "3e11fa47-71ca-11e1-9e33-c80aa9429562";
+    const EVENT_TIME: u32 = 1_600_000_000;
+    const WRITE_ROWS_EVENT: u8 = 30;
+
+    fn get_base_dir() -> PathBuf {
+        let program_dir = std::env::current_dir().unwrap();
+        program_dir.join(TEST_PATH)
+    }
+
+    fn gtid(gno: u64) -> Gtid {
+        Gtid::MySql {
+            sid: SID.to_owned(),
+            gno,
+        }
+    }
+
+    fn gtid_set(value: &str) -> GtidSet {
+        format!("{}:{}", SID, value).parse().unwrap()
+    }
+
+    fn gtid_body(gno: u64) -> Vec<u8> {
+        let sid: Vec<u8> = (0..16)
+            .map(|idx| {
+                let hex: String = SID.chars().filter(|c| *c != '-').collect();
+                u8::from_str_radix(&hex[idx * 2..idx * 2 + 2], 16).unwrap()
+            })
+            .collect();
+
+        let mut body = vec![1];
+        body.extend_from_slice(&sid);
+        body.extend_from_slice(&gno.to_le_bytes());
+        body
+    }
+
+    fn query_body(query: &str) -> Vec<u8> {
+        let mut body = vec![0; 8];
+        body.push(2);
+        body.extend_from_slice(&[0; 4]);
+        body.extend_from_slice(b"db\0");
+        body.extend_from_slice(query.as_bytes());
+        body
+    }
+
+    /// Binlog file with gtids
+    fn write_gtid_file(path: &Path) -> Vec<u64> {
+        let events: Vec<(u8, Vec<u8>)> = vec![
+            (PREVIOUS_GTIDS_LOG_EVENT, encode_gtid_set(&gtid_set("1-2"))),
+            (GTID_LOG_EVENT, gtid_body(3)),
+            (QUERY_EVENT, query_body("BEGIN")),
+            (TABLE_MAP_EVENT, vec![0; 10]),
+            (XID_EVENT, 7u64.to_le_bytes().to_vec()),
+            (GTID_LOG_EVENT, gtid_body(4)),
+            (QUERY_EVENT, query_body("CREATE TABLE t (a int)")),
+        ];
+
+        write_binlog_file(path, events)
+    }
+
+    /// Binlog file with events: format description copied from a MySQL 8.0 binlog
+    fn write_binlog_file(path: &Path, events: Vec<(u8, Vec<u8>)>) -> Vec<u64> {
+        let source = fs::read(get_base_dir().join(BL_FILE1)).unwrap();
+        let fde_size = EventHeader::parse(&source[4..]).unwrap().event_size as usize;
+
+        let mut bytes = source[..4 + fde_size].to_vec();
+        let mut offsets = vec![];
+        for (type_code, body) in events {
+            let start = bytes.len();
+            let event_size = EVENT_HEADER_LEN + body.len() + CHECKSUM_LEN;
+            let timestamp = EVENT_TIME + offsets.len() as u32;
+            offsets.push(start as u64);
+
+            bytes.extend_from_slice(&timestamp.to_le_bytes());
+            bytes.push(type_code);
+            bytes.extend_from_slice(&1u32.to_le_bytes());
+            bytes.extend_from_slice(&(event_size as u32).to_le_bytes());
+            bytes.extend_from_slice(&((start + event_size) as u32).to_le_bytes());
+            bytes.extend_from_slice(&[0; 2]);
+            bytes.extend_from_slice(&body);
+            bytes.extend_from_slice(&[0; CHECKSUM_LEN]);
+        }
+        fs::write(path, bytes).unwrap();
+
+        offsets
+    }
+
+    #[test]
+    fn test_gtid_tracker() {
+        let path = get_base_dir().join(GTID_FILE);
+        let offsets = write_gtid_file(&path);
+        let log_file = path.to_str().unwrap();
+
+        assert_eq!(previous_gtids(&path).unwrap(), Some(gtid_set("1-2")));
+
+        let mut tracker = GtidTracker::default();
+        tracker.set_file(log_file, GTID_FILE).unwrap();
+
+        // transaction 3 in progress
+        tracker.advance_to(offsets[3]).unwrap();
+        assert_eq!(tracker.gtid(), Some(&gtid(3)));
+        assert_eq!(tracker.timestamp(), Some(EVENT_TIME + 3));
+        assert_eq!(tracker.gtid_set(), Some(gtid_set("1-2")));
+
+        // xid => transaction 3 completed
+        tracker.advance_to(offsets[4]).unwrap();
+        assert_eq!(tracker.gtid(), Some(&gtid(3)));
+        assert_eq!(tracker.gtid_set(), Some(gtid_set("1-3")));
+
+        // ddl outside of BEGIN => transaction on its own
+        tracker.advance_to(offsets[6]).unwrap();
+        assert_eq!(tracker.gtid(), Some(&gtid(4)));
+        assert_eq!(tracker.gtid_set(), Some(gtid_set("1-4")));
+
+        fs::remove_file(&path).expect("delete gtid file failed");
+    }
+
+    #[test]
+    fn test_gtid_tracker_resume() {
+        let path = get_base_dir().join(GTID_RESUME_FILE);
+        let offsets = write_gtid_file(&path);
+        let log_file = path.to_str().unwrap();
+
+        // stopped in the middle of transaction 3
+        let resume = BnFile {
+            file_name: GTID_RESUME_FILE.to_owned(),
+            offset: Some(offsets[3]),
+            gtid_set: Some(gtid_set("1-2")),
+            gtid: Some(gtid(3)),
+            transaction: None,
+            timestamp: None,
+        };
+        let mut tracker = GtidTracker::default();
+        tracker.set_resume(Some(&resume));
+        tracker.set_file(log_file, GTID_RESUME_FILE).unwrap();
+
+        tracker.advance_to(offsets[3]).unwrap();
+        assert!(tracker.is_replayed(GTID_RESUME_FILE, offsets[3]));
+        tracker.advance_to(offsets[4]).unwrap();
+        assert!(!tracker.is_replayed(GTID_RESUME_FILE, offsets[4]));
+
+        // same transaction, file renamed => resumed by executed set only
+        assert!(!tracker.is_replayed("binlog.000009", offsets[3]));
+
+        // transaction 4 not executed
+        tracker.advance_to(offsets[6]).unwrap();
+        assert!(!tracker.is_replayed(GTID_RESUME_FILE, offsets[6]));
+
+        // transaction 3 executed => skipped in full
+        let resume = BnFile {
+            file_name: "binlog.000009".to_owned(),
+            offset: Some(4),
+            gtid_set: Some(gtid_set("1-3")),
+            gtid: Some(gtid(3)),
+            transaction: None,
+            timestamp: None,
+        };
+        let mut tracker = GtidTracker::default();
+        tracker.set_resume(Some(&resume));
+        tracker.set_file(log_file, GTID_RESUME_FILE).unwrap();
+        tracker.advance_to(offsets[4]).unwrap();
+        assert!(tracker.is_replayed(GTID_RESUME_FILE, offsets[4]));
+        tracker.advance_to(offsets[6]).unwrap();
+        assert!(!tracker.is_replayed(GTID_RESUME_FILE, offsets[6]));
+
+        fs::remove_file(&path).expect("delete gtid file failed");
+    }
+
+    #[test]
+    fn test_transactions() {
+        let path = get_base_dir().join(TX_FILE);
+        let anonymous_gtid = vec![0; 25];
+        let offsets = write_binlog_file(
+            &path,
+            vec![
+                (ANONYMOUS_GTID_LOG_EVENT, anonymous_gtid.clone()),
+                (QUERY_EVENT, query_body("BEGIN")),
+                (TABLE_MAP_EVENT, vec![0; 10]),
+                (WRITE_ROWS_EVENT, vec![0; 10]),
+                (WRITE_ROWS_EVENT, vec![0; 10]),
+                (XID_EVENT, 7u64.to_le_bytes().to_vec()),
+                (ANONYMOUS_GTID_LOG_EVENT, anonymous_gtid),
+                (QUERY_EVENT, query_body("CREATE TABLE t (a int)")),
+            ],
+        );
+        let log_file = path.to_str().unwrap();
+        let tx1 = format!("{}:{}", TX_FILE, offsets[0]);
+        let tx2 = format!("{}:{}", TX_FILE, offsets[6]);
+
+        let mut tracker = GtidTracker::default();
+        tracker.set_file(log_file, TX_FILE).unwrap();
+
+        // without gtids, transactions are identified by their first event
+        tracker.advance_to(offsets[3]).unwrap();
+        assert_eq!(tracker.gtid(), None);
+        assert_eq!(
+            tracker.next_transaction(),
+            Some(Transaction::new(tx1.clone(), 0, false))
+        );
+        tracker.advance_to(offsets[4]).unwrap();
+        assert_eq!(
+            tracker.next_transaction(),
+            Some(Transaction::new(tx1.clone(), 1, false))
+        );
+        assert!(tracker.is_open(&tx1));
+
+        // xid applied, next statement not
+        tracker.advance_to_next_data().unwrap();
+        assert!(!tracker.is_open(&tx1));
+        assert!(tracker.is_open(&tx2));
+
+        // ddl => transaction on its own, ended by the statement
+        tracker.advance_to(offsets[7]).unwrap();
+        assert_eq!(
+            tracker.next_transaction(),
+            Some(Transaction::new(tx2, 0, true))
+        );
+
+        // resume in the middle of a transaction => index continues
+        let mut resume = BnFile::new(TX_FILE.to_owned(), Some(offsets[3]));
+        resume.transaction = Some(Transaction::new(tx1.clone(), 0, false));
+        let mut tracker = GtidTracker::default();
+        tracker.set_resume(Some(&resume));
+        tracker.set_file(log_file, TX_FILE).unwrap();
+        tracker.advance_to(offsets[4]).unwrap();
+        assert_eq!(
+            tracker.next_transaction(),
+            Some(Transaction::new(tx1, 1, false))
+        );
+
+        fs::remove_file(&path).expect("delete gtid file failed");
+    }
+}
@@ -20,6 +20,10 @@ pub struct LocalStore {
 #[derive(Debug, Serialize, Deserialize)]
 pub struct DbStore {
     dbs: BTreeMap<DbName, TableStore>,
+
+    /// Key columns (primary or unique key) of tables that have one
+    #[serde(default)]
+    keys: BTreeMap<DbName, TableStore>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -31,6 +35,7 @@ impl Default for DbStore {
     fn default() -> Self {
         Self {
             dbs: BTreeMap::new(),
+            keys: BTreeMap::new(),
         }
     }
 }
@@ -66,18 +71,26 @@ impl LocalStore {
     pub fn get_columns(&mut self, db_name: &str, table_name: &str) -> Result<Vec<String>, Error> {
         self.store.get_columns(db_name, table_name)
     }
+
+    pub fn get_key_columns(&self, db_name: &str, table_name: &str) -> Option<Vec<String>> {
+        self.store.get_key_columns(db_name, table_name)
+    }
 }
 
 impl DbStore {
     fn update_store(&mut self, db_name: &str, table_ops: Vec<TableOp>) -> Result<(), Error> {
         for table_op in table_ops {
             match table_op {
-                TableOp::CreateTable(table_name, columns) => {
-                    self.create_table(db_name, table_name, columns)?;
+                TableOp::CreateTable(table_name, columns, keys) => {
+                    self.create_table(db_name, table_name.clone(), columns)?;
+                    self.set_key_columns(db_name, table_name, keys);
                 }
                 TableOp::AlterTable(table_name, column_op) => {
                     self.alter_table(db_name, table_name, column_op);
                 }
+                TableOp::AddPrimaryKey(table_name, keys) => {
+                    self.set_key_columns(db_name, table_name, keys);
+                }
                 TableOp::DropTable(table_names) => {
                     self.drop_tables(db_name, table_names);
                 }
@@ -123,13 +136,38 @@ impl DbStore {
     }
 
     fn drop_tables(&mut self, db_name: &str, table_names: Vec<String>) {
-        if let Some(table_store) = self.dbs.get_mut(db_name) {
-            for table_name in &table_names {
-                table_store.tables.remove(table_name);
+        for store in &mut [&mut self.dbs, &mut self.keys] {
+            if let Some(table_store) = store.get_mut(db_name) {
+                for table_name in &table_names {
+                    table_store.tables.remove(table_name);
+                }
+
+                if table_store.tables.is_empty() {
+                    store.remove(db_name);
+                }
             }
+        }
+    }
+
+    /// Replace table key columns, empty keys remove them
+    fn set_key_columns(&mut self, db_name: &str, table_name: String, keys: Vec<String>) {
+        if keys.is_empty() {
+            self.drop_key_columns(db_name, &table_name);
+            return;
+        }
 
+        self.keys
+            .entry(db_name.to_string())
+            .or_default()
+            .tables
+            .insert(table_name, keys);
+    }
+
+    fn drop_key_columns(&mut self, db_name: &str, table_name: &str) {
+        if let Some(table_store) = self.keys.get_mut(db_name) {
+            table_store.tables.remove(table_name);
             if table_store.tables.is_empty() {
-                self.dbs.remove(db_name);
+                self.keys.remove(db_name);
             }
         }
     }
@@ -149,23 +187,39 @@ impl DbStore {
         old_column: String,
         new_column: String,
     ) {
-        if let Some(table_store) = self.dbs.get_mut(db_name) {
-            if let Some(columns) = table_store.tables.get_mut(&table_name) {
-                for column in columns.iter_mut() {
-                    if *column == old_column {
-                        *column = new_column.clone();
+        for store in &mut [&mut self.dbs, &mut self.keys] {
+            if let Some(table_store) = store.get_mut(db_name) {
+                if let Some(columns) = table_store.tables.get_mut(&table_name) {
+                    for column in columns.iter_mut() {
+                        if *column == old_column {
+                            *column = new_column.clone();
+                        }
                     }
                 }
             }
         }
     }
 
+    /// Dropping a key column leaves the table without a known key
     fn drop_table_column(&mut self, db_name: &str, table_name: String, column: String) {
         if let Some(table_store) = self.dbs.get_mut(db_name) {
             if let Some(columns) = table_store.tables.get_mut(&table_name) {
                 columns.retain(|x| *x != column);
             }
         }
+
+        if let Some(keys) = self.get_key_columns(db_name, &table_name) {
+            if keys.contains(&column) {
+                self.drop_key_columns(db_name, &table_name);
+            }
+        }
+    }
+
+    fn get_key_columns(&self, db_name: &str, table_name: &str) -> Option<Vec<String>> {
+        self.keys
+            .get(db_name)
+            .and_then(|table_store| table_store.tables.get(table_name))
+            .cloned()
     }
 
     fn get_columns(&mut self, db_name: &str, table_name: &str) -> Result<Vec<String>, Error> {
@@ -216,6 +270,7 @@ mod test {
         let op = TableOp::CreateTable(
             "pet".to_owned(),
             vec!["c1".to_owned(), "c2".to_owned(), "c3".to_owned()],
+            vec![],
         );
         let result = db_store.update_store(&"db".to_owned(), vec![op]);
         let expected_result =
@@ -224,20 +279,20 @@ mod test {
         assert_eq!(format!("{:?}", db_store.dbs), expected_result);
 
         // db: create pet values(c1) => error (table already exists)
-        let op = TableOp::CreateTable("pet".to_owned(), vec!["c1".to_owned()]);
+        let op = TableOp::CreateTable("pet".to_owned(), vec!["c1".to_owned()], vec![]);
         let result = db_store.update_store(&"db".to_owned(), vec![op]);
         assert!(result.is_err());
         assert_eq!(format!("{:?}", db_store.dbs), expected_result);
 
         // db: create pet2 values(c1) => ok
-        let op = TableOp::CreateTable("pet2".to_owned(), vec!["c1".to_owned()]);
+        let op = TableOp::CreateTable("pet2".to_owned(), vec!["c1".to_owned()], vec![]);
         let result = db_store.update_store(&"db".to_owned(), vec![op]);
         let expected_result = "{\"db\": TableStore { tables: {\"pet\": [\"c1\", \"c2\", \"c3\"], \"pet2\": [\"c1\"]} }}";
         assert!(result.is_ok());
         assert_eq!(format!("{:?}", db_store.dbs), expected_result);
 
         // db2: create pet2 values(c1) => ok
-        let op = TableOp::CreateTable("pet2".to_owned(), vec!["c1".to_owned()]);
+        let op = TableOp::CreateTable("pet2".to_owned(), vec!["c1".to_owned()], vec![]);
         let result = db_store.update_store(&"db2".to_owned(), vec![op]);
         let expected_result = "{\"db\": TableStore { tables: {\"pet\": [\"c1\", \"c2\", \"c3\"], \"pet2\": [\"c1\"]} }, \"db2\": TableStore { tables: {\"pet2\": [\"c1\"]} }}";
         assert!(result.is_ok());
@@ -292,4 +347,46 @@ mod test {
         assert!(result.is_ok());
         assert_eq!(format!("{:?}", db_store.dbs), expected_result);
     }
+
+    #[test]
+    fn test_key_columns() {
+        let mut db_store = DbStore::default();
+        let columns = vec!["id".to_owned(), "name".to_owned(), "email".to_owned()];
+
+        // create with key, rename key column
+        let ops = vec![
+            TableOp::CreateTable("pet".to_owned(), columns.clone(), vec!["id".to_owned()]),
+            TableOp::AlterTable(
+                "pet".to_owned(),
+                ColumnOp::Rename("id".to_owned(), "pet_id".to_owned()),
+            ),
+        ];
+        assert!(db_store.update_store("db", ops).is_ok());
+        assert_eq!(
+            db_store.get_key_columns("db", "pet"),
+            Some(vec!["pet_id".to_owned()])
+        );
+
+        // drop key column => no key
+        let op = TableOp::AlterTable("pet".to_owned(), ColumnOp::Drop("pet_id".to_owned()));
+        assert!(db_store.update_store("db", vec![op]).is_ok());
+        assert_eq!(db_store.get_key_columns("db", "pet"), None);
+
+        // add primary key, drop table
+        let op = TableOp::AddPrimaryKey("pet".to_owned(), vec!["email".to_owned()]);
+        assert!(db_store.update_store("db", vec![op]).is_ok());
+        assert_eq!(
+            db_store.get_key_columns("db", "pet"),
+            Some(vec!["email".to_owned()])
+        );
+
+        let op = TableOp::DropTable(vec!["pet".to_owned()]);
+        assert!(db_store.update_store("db", vec![op]).is_ok());
+        assert_eq!(db_store.get_key_columns("db", "pet"), None);
+        assert!(db_store.keys.is_empty());
+
+        // store files without keys
+        let db_store: DbStore = serde_json::from_str("{\"dbs\":{}}").unwrap();
+        assert!(db_store.keys.is_empty());
+    }
 }
@@ -8,15 +8,17 @@ use std::io::{Error, ErrorKind};
 use std::path::{Path, PathBuf};
 use std::thread;
 use std::time::Duration;
-use tracing::{debug, error, instrument, trace};
+use tracing::{debug, error, info, instrument, trace, warn};
 
 use super::parse_records_from_file;
+use super::previous_gtids;
 use super::IndexFile;
-use super::LocalStore;
+use super::ParserState;
 use super::Resume;
 use super::{get_file_id, BinLogFile};
 use crate::error::CdcError;
-use crate::producer::{Filters, Profile};
+use crate::messages::GtidSet;
+use crate::producer::Profile;
 use crate::util::expand_tilde;
 
 const DELAY_MIN_MILIS: u64 = 500;
@@ -25,13 +27,11 @@ const DELAY_MIN_MILIS: u64 = 500;
 pub struct BinLogManager {
     sender: Sender<String>,
     base_dir: PathBuf,
-    filters: Option<Filters>,
 
     index_file: IndexFile,
     current_file: Option<BinLogFile>,
 
-    local_store: LocalStore,
-    urn: String,
+    state: ParserState,
 }
 
 impl BinLogManager {
@@ -41,11 +41,9 @@ impl BinLogManager {
         Ok(Self {
             sender,
             base_dir: base_dir.clone(),
-            filters: profile.filters(),
             index_file: IndexFile::new(&base_dir, bn_index_file)?,
             current_file: None,
-            local_store: LocalStore::new(profile.local_store_file())?,
-            urn: profile.mysql_resource_name().clone(),
+            state: ParserState::new(profile)?,
         })
     }
 
@@ -85,16 +83,46 @@ impl BinLogManager {
         Ok(())
     }
 
+    /// Resume from the gtid set if the binlog files have gtids, otherwise (or if the
+    /// transactions are no longer in the binlog files) from file/offset.
     fn set_current_file(&mut self, resume: &Resume) -> Result<(), Error> {
-        let (file, offset) = match &resume.binfile {
-            Some(binfile) => (binfile.file_name.clone(), binfile.offset),
-            None => (self.get_first_index_file()?, None),
+        let gtid_file = match resume.gtid_set() {
+            Some(gtid_set) => self.get_file_by_gtids(gtid_set)?,
+            None => None,
         };
 
+        let (file, offset) = match (&resume.binfile, gtid_file) {
+            (Some(_), Some(file)) => {
+                info!(%file, "Resuming from gtid set");
+                (file, None)
+            }
+            (Some(binfile), None) => (binfile.file_name.clone(), binfile.offset),
+            (None, _) => (self.get_first_index_file()?, None),
+        };
+
+        self.state.gtids.set_resume(resume.binfile.as_ref());
         self.current_file = Some(BinLogFile::new(&self.base_dir, &file, offset)?);
         Ok(())
     }
 
+    /// Last binlog file started before the first transaction missing from gtid set
+    fn get_file_by_gtids(&self, gtid_set: &GtidSet) -> Result<Option<String>, Error> {
+        let mut found = None;
+
+        for file in self.index_file.get_bin_log_files()? {
+            let previous = previous_gtids(&self.base_dir.join(&file))?.unwrap_or_default();
+            if !previous.is_subset(gtid_set) {
+                break;
+            }
+            found = Some(file);
+        }
+
+        if found.is_none() {
+            warn!(%gtid_set, "Transactions purged from binlog files, resuming from file/offset");
+        }
+        Ok(found)
+    }
+
     fn get_first_index_file(&self) -> Result<String, Error> {
         let all_files = self.index_file.get_bin_log_files()?;
         if let Some(first_file) = all_files.first() {
@@ -133,9 +161,7 @@ impl BinLogManager {
             &current_file.path_to_string(),
             current_file.file_name(),
             current_file.offset(),
-            self.filters.as_ref(),
-            &mut self.local_store,
-            &self.urn,
+            &mut self.state,
         )?;
         self.current_file.as_mut().unwrap().set_offset(new_offset);
 
@@ -159,9 +185,7 @@ impl BinLogManager {
                 &current_file.path_to_string(),
                 current_file.file_name(),
                 current_file.offset(),
-                self.filters.as_ref(),
-                &mut self.local_store,
-                &self.urn,
+                &mut self.state,
             )?;
 
             self.current_file.as_mut().unwrap().set_offset(new_offset);
@@ -240,7 +264,9 @@ mod test {
                 resume_offset_file: base_path.join(LOCAL_STORE),
                 local_store_file: base_path.join(RESUME_OFFSET),
             },
+            source: None,
             filters: None,
+            masking: None,
             fluvio: None,
         }
     }
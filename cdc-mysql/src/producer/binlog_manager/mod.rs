@@ -2,19 +2,36 @@ mod binlog_file;
 mod binlog_index_file;
 mod binlog_parser;
 mod binlog_resume;
+#[cfg(test)]
+mod fake_server;
+mod gtid_tracker;
 mod local_store;
 mod manager;
 mod query_parser;
+mod raw_event;
+mod relay_log;
+mod replication_client;
+mod replication_manager;
+mod table_map;
 
 pub use binlog_parser::parse_records_from_file;
+pub use binlog_parser::ParserState;
 pub use manager::BinLogManager;
+pub use replication_manager::ReplicationManager;
 
 pub use binlog_file::get_file_id;
+pub use binlog_file::split_file_name;
 pub use binlog_file::BinLogFile;
 pub use binlog_index_file::IndexFile;
-pub use binlog_resume::Resume;
+pub use binlog_resume::{Resume, ResumeCheck};
+pub use gtid_tracker::{previous_gtids, GtidTracker};
+pub use relay_log::{purge_relay_files, RelayLog};
+pub use replication_client::ReplicationClient;
+pub use table_map::TableMap;
 
 pub use query_parser::parse_query;
+pub use query_parser::parse_query_tables;
+pub use query_parser::retain_query_tables;
 pub use query_parser::ColumnOp;
 pub use query_parser::TableOp;
 
@@ -1,4 +1,4 @@
-use sqlparser::ast::{AlterTableOperation, Statement};
+use sqlparser::ast::{AlterTableOperation, ColumnDef, ColumnOption, Statement, TableConstraint};
 use sqlparser::dialect::GenericDialect;
 use sqlparser::parser::Parser;
 /// query_parser.rs
@@ -6,7 +6,7 @@ use sqlparser::parser::Parser;
 /// The columns must be cashed as they are needed to construct ROW UPDATE operations.
 ///
 /// Parses column information from the following MySQL commands:
-///     CREATE TABLE
+///     CREATE TABLE (columns and key columns)
 ///     ALTER TABLE
 ///     DROP TABLE
 ///
@@ -22,8 +22,9 @@ type OldName = String;
 type NewName = String;
 
 pub enum TableOp {
-    CreateTable(Name, Vec<Column>),
+    CreateTable(Name, Vec<Column>, Vec<Column>),
     AlterTable(Name, ColumnOp),
+    AddPrimaryKey(Name, Vec<Column>),
     DropTable(Vec<Name>),
 }
 
@@ -36,12 +37,18 @@ pub enum ColumnOp {
 impl fmt::Display for TableOp {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
-            TableOp::CreateTable(table, columns) => write!(
-                f,
-                "Create Table {} - add columns ({})",
-                table,
-                columns.join(", ")
-            ),
+            TableOp::CreateTable(table, columns, keys) => {
+                write!(
+                    f,
+                    "Create Table {} - add columns ({})",
+                    table,
+                    columns.join(", ")
+                )?;
+                if !keys.is_empty() {
+                    write!(f, " - key ({})", keys.join(", "))?;
+                }
+                Ok(())
+            }
             TableOp::AlterTable(table, column_op) => match column_op {
                 ColumnOp::Add(name) => write!(f, "Alter Table {} - add column {}", table, name),
                 ColumnOp::Rename(old_name, new_name) => write!(
@@ -51,6 +58,12 @@ impl fmt::Display for TableOp {
                 ),
                 ColumnOp::Drop(name) => write!(f, "Alter Table {} - remove column {}", table, name),
             },
+            TableOp::AddPrimaryKey(table, keys) => write!(
+                f,
+                "Alter Table {} - add primary key ({})",
+                table,
+                keys.join(", ")
+            ),
             TableOp::DropTable(tables) => write!(f, "Drop Tables ({})", tables.join(", ")),
         }
     }
@@ -71,15 +84,19 @@ pub fn parse_query(query: &Option<String>) -> Result<Vec<TableOp>, CdcError> {
 
         for statement in ast {
             match statement {
-                Statement::CreateTable { name, columns, .. } => {
-                    table_ops.push(TableOp::CreateTable(
-                        name.to_string(),
-                        columns
-                            .iter()
-                            .map(|x| x.name.to_string())
-                            .collect::<Vec<String>>(),
-                    ))
-                }
+                Statement::CreateTable {
+                    name,
+                    columns,
+                    constraints,
+                    ..
+                } => table_ops.push(TableOp::CreateTable(
+                    name.to_string(),
+                    columns
+                        .iter()
+                        .map(|x| x.name.to_string())
+                        .collect::<Vec<String>>(),
+                    key_columns(&columns, &constraints),
+                )),
                 Statement::AlterTable { name, operation } => match operation {
                     AlterTableOperation::AddColumn { column_def } => {
                         table_ops.push(TableOp::AlterTable(
@@ -100,6 +117,14 @@ pub fn parse_query(query: &Option<String>) -> Result<Vec<TableOp>, CdcError> {
                             ColumnOp::Drop(column_name.to_string()),
                         ))
                     }
+                    AlterTableOperation::AddConstraint(TableConstraint::Unique {
+                        columns,
+                        is_primary: true,
+                        ..
+                    }) => table_ops.push(TableOp::AddPrimaryKey(
+                        name.to_string(),
+                        columns.iter().map(|x| x.to_string()).collect(),
+                    )),
                     _ => {}
                 },
                 Statement::Drop {
@@ -119,6 +144,124 @@ pub fn parse_query(query: &Option<String>) -> Result<Vec<TableOp>, CdcError> {
     Ok(table_ops)
 }
 
+/// Columns that identify a row:
+///  - primary key (table constraint or column option)
+///  - otherwise, the first unique key with NOT NULL columns
+///  - otherwise, none
+fn key_columns(columns: &[ColumnDef], constraints: &[TableConstraint]) -> Vec<Column> {
+    let mut unique_keys: Vec<Vec<Column>> = vec![];
+
+    for constraint in constraints {
+        if let TableConstraint::Unique {
+            columns: key,
+            is_primary,
+            ..
+        } = constraint
+        {
+            let key: Vec<Column> = key.iter().map(|x| x.to_string()).collect();
+            if *is_primary {
+                return key;
+            }
+            unique_keys.push(key);
+        }
+    }
+
+    for column in columns {
+        for option in &column.options {
+            if let ColumnOption::Unique { is_primary } = option.option {
+                if is_primary {
+                    return vec![column.name.to_string()];
+                }
+                unique_keys.push(vec![column.name.to_string()]);
+            }
+        }
+    }
+
+    let is_not_null = |name: &Column| {
+        columns.iter().any(|column| {
+            column.name.to_string() == *name
+                && column
+                    .options
+                    .iter()
+                    .any(|option| matches!(option.option, ColumnOption::NotNull))
+        })
+    };
+    unique_keys
+        .into_iter()
+        .find(|key| key.iter().all(is_not_null))
+        .unwrap_or_default()
+}
+
+/// Tables affected by a DDL statement (names as written, may include the database)
+///  - statements that cannot be parsed or have no table return an empty list
+pub fn parse_query_tables(query: &Option<String>) -> Vec<String> {
+    let query = match query {
+        Some(query) => query,
+        None => return vec![],
+    };
+
+    let dialect = GenericDialect {};
+    let ast = match Parser::parse_sql(&dialect, query) {
+        Ok(ast) => ast,
+        Err(_) => return vec![],
+    };
+
+    let mut tables = vec![];
+    for statement in ast {
+        match statement {
+            Statement::CreateTable { name, .. } | Statement::AlterTable { name, .. } => {
+                tables.push(name.to_string())
+            }
+            Statement::Drop {
+                object_type, names, ..
+            } => {
+                if object_type.to_string() == "TABLE" {
+                    tables.extend(names.iter().map(|x| x.to_string()));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    tables
+}
+
+/// Query restricted to the tables kept by `keep` (names as written, may include the database)
+///  - tables not kept are removed from DROP TABLE lists
+///  - statements left without a kept table are removed
+///  - None if no statement is left, or the query cannot be parsed
+pub fn retain_query_tables<F: Fn(&str) -> bool>(query: &str, keep: F) -> Option<String> {
+    let dialect = GenericDialect {};
+    let ast = Parser::parse_sql(&dialect, query).ok()?;
+
+    let mut statements = vec![];
+    for mut statement in ast {
+        match &mut statement {
+            Statement::CreateTable { name, .. } | Statement::AlterTable { name, .. } => {
+                if !keep(&name.to_string()) {
+                    continue;
+                }
+            }
+            Statement::Drop {
+                object_type, names, ..
+            } if object_type.to_string() == "TABLE" => {
+                names.retain(|name| keep(&name.to_string()));
+                if names.is_empty() {
+                    continue;
+                }
+            }
+            _ => {}
+        }
+        statements.push(statement.to_string());
+    }
+
+    if statements.is_empty() {
+        None
+    } else {
+        Some(statements.join("; "))
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -137,6 +280,38 @@ mod test {
         assert_eq!(result.to_string(), expected);
     }
 
+    #[test]
+    fn test_parse_query_create_table_keys() {
+        let queries = vec![
+            (
+                "CREATE TABLE t (id INT, name VARCHAR(20), PRIMARY KEY (id, name))",
+                "Create Table t - add columns (id, name) - key (id, name)",
+            ),
+            (
+                "CREATE TABLE t (id INT PRIMARY KEY, email VARCHAR(50) NOT NULL UNIQUE)",
+                "Create Table t - add columns (id, email) - key (id)",
+            ),
+            (
+                "CREATE TABLE t (id INT UNIQUE, email VARCHAR(50) NOT NULL, UNIQUE (email))",
+                "Create Table t - add columns (id, email) - key (email)",
+            ),
+            (
+                "CREATE TABLE t (id INT UNIQUE, name VARCHAR(20))",
+                "Create Table t - add columns (id, name)",
+            ),
+            (
+                "ALTER TABLE t ADD PRIMARY KEY (id)",
+                "Alter Table t - add primary key (id)",
+            ),
+        ];
+
+        for (query, expected) in queries {
+            let ops = parse_query(&Some(query.to_string())).unwrap();
+            assert_eq!(ops.len(), 1);
+            assert_eq!(ops[0].to_string(), expected);
+        }
+    }
+
     #[test]
     fn test_parse_query_alter_table_add_column() {
         let query = "ALTER TABLE pet ADD hello DATE";
@@ -246,4 +421,47 @@ mod test {
         let expected = "Drop Tables (species)";
         assert_eq!(result.to_string(), expected);
     }
+
+    #[test]
+    fn test_parse_query_tables() {
+        let query = Some("CREATE TABLE shop.orders (id INT)".to_owned());
+        assert_eq!(parse_query_tables(&query), vec!["shop.orders".to_owned()]);
+
+        let query = Some("ALTER TABLE orders RENAME COLUMN qty TO quantity".to_owned());
+        assert_eq!(parse_query_tables(&query), vec!["orders".to_owned()]);
+
+        let query = Some("DROP TABLE orders, audit_log".to_owned());
+        assert_eq!(
+            parse_query_tables(&query),
+            vec!["orders".to_owned(), "audit_log".to_owned()]
+        );
+
+        let query = Some("CREATE DATABASE shop".to_owned());
+        assert!(parse_query_tables(&query).is_empty());
+    }
+
+    #[test]
+    fn test_retain_query_tables() {
+        let keep = |name: &str| name != "audit_log";
+
+        let query = "DROP TABLE orders, audit_log";
+        assert_eq!(
+            retain_query_tables(query, keep),
+            Some("DROP TABLE orders".to_owned())
+        );
+
+        let query = "DROP TABLE IF EXISTS audit_log, orders, shop.items";
+        assert_eq!(
+            retain_query_tables(query, keep),
+            Some("DROP TABLE IF EXISTS orders, shop.items".to_owned())
+        );
+
+        let query = "DROP TABLE audit_log";
+        assert_eq!(retain_query_tables(query, keep), None);
+
+        let query = "ALTER TABLE audit_log ADD COLUMN note TEXT";
+        assert_eq!(retain_query_tables(query, keep), None);
+
+        assert_eq!(retain_query_tables("not a statement", keep), None);
+    }
 }
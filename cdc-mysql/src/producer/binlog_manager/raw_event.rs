@@ -0,0 +1,418 @@
+/// RawEvent
+///
+/// Minimal decoder for binlog v4 events, used where the raw bytes are needed:
+///     - replication client (events arrive over the network)
+///     - relay log writer (events are spooled to disk before parsing)
+///
+/// Event header layout (19 bytes):
+///     timestamp (4) | type_code (1) | server_id (4) | event_size (4) | log_pos (4) | flags (2)
+///
+use crate::messages::{Gtid, GtidSet};
+
+pub const BINLOG_MAGIC: [u8; 4] = [0xfe, b'b', b'i', b'n'];
+pub const EVENT_HEADER_LEN: usize = 19;
+pub const CHECKSUM_LEN: usize = 4;
+
+pub const QUERY_EVENT: u8 = 2;
+pub const ROTATE_EVENT: u8 = 4;
+pub const FORMAT_DESCRIPTION_EVENT: u8 = 15;
+pub const XID_EVENT: u8 = 16;
+pub const TABLE_MAP_EVENT: u8 = 19;
+pub const WRITE_ROWS_EVENT_V1: u8 = 23;
+pub const UPDATE_ROWS_EVENT_V1: u8 = 24;
+pub const DELETE_ROWS_EVENT_V1: u8 = 25;
+pub const HEARTBEAT_LOG_EVENT: u8 = 27;
+pub const IGNORABLE_LOG_EVENT: u8 = 28;
+pub const WRITE_ROWS_EVENT: u8 = 30;
+pub const UPDATE_ROWS_EVENT: u8 = 31;
+pub const DELETE_ROWS_EVENT: u8 = 32;
+pub const GTID_LOG_EVENT: u8 = 33;
+pub const ANONYMOUS_GTID_LOG_EVENT: u8 = 34;
+pub const PREVIOUS_GTIDS_LOG_EVENT: u8 = 35;
+pub const MARIADB_GTID_EVENT: u8 = 162;
+pub const MARIADB_GTID_LIST_EVENT: u8 = 163;
+
+const LOG_EVENT_ARTIFICIAL_F: u16 = 0x20;
+const LOG_EVENT_IGNORABLE_F: u16 = 0x80;
+const MARIADB_FL_STANDALONE: u8 = 0x01;
+const CHECKSUM_ALG_CRC32: u8 = 1;
+const SERVER_VERSION_LEN: usize = 50;
+const QUERY_POST_HEADER_LEN: usize = 13;
+const SID_LEN: usize = 16;
+
+#[derive(Debug, PartialEq)]
+pub struct EventHeader {
+    pub timestamp: u32,
+    pub type_code: u8,
+    pub server_id: u32,
+    pub event_size: u32,
+    pub log_pos: u32,
+    pub flags: u16,
+}
+
+impl EventHeader {
+    pub fn parse(event: &[u8]) -> Option<Self> {
+        if event.len() < EVENT_HEADER_LEN {
+            return None;
+        }
+
+        Some(Self {
+            timestamp: le_u32(event, 0),
+            type_code: event[4],
+            server_id: le_u32(event, 5),
+            event_size: le_u32(event, 9),
+            log_pos: le_u32(event, 13),
+            flags: le_u16(event, 17),
+        })
+    }
+
+    /// Artificial events (such as the fake ROTATE sent at the start of a dump) are not
+    /// part of the binlog file.
+    pub fn is_artificial(&self) -> bool {
+        self.log_pos == 0 || self.flags & LOG_EVENT_ARTIFICIAL_F != 0
+    }
+
+    /// Offset of the event in the binlog file, None for artificial events
+    pub fn start_offset(&self) -> Option<u64> {
+        if self.is_artificial() {
+            return None;
+        }
+        (self.log_pos as u64).checked_sub(self.event_size as u64)
+    }
+}
+
+/// Events turned into messages by the binlog parser (statements and rows)
+pub fn is_data_event(type_code: u8) -> bool {
+    matches!(
+        type_code,
+        QUERY_EVENT
+            | WRITE_ROWS_EVENT_V1
+            | UPDATE_ROWS_EVENT_V1
+            | DELETE_ROWS_EVENT_V1
+            | WRITE_ROWS_EVENT
+            | UPDATE_ROWS_EVENT
+            | DELETE_ROWS_EVENT
+    )
+}
+
+/// Next binlog file name carried by a ROTATE event
+///     body: position (8) | file name (rest of event, minus checksum)
+pub fn rotate_file_name(event: &[u8], checksum_len: usize) -> Option<String> {
+    let start = EVENT_HEADER_LEN + 8;
+    let end = event.len().checked_sub(checksum_len)?;
+    if end < start {
+        return None;
+    }
+
+    String::from_utf8(event[start..end].to_vec()).ok()
+}
+
+/// Checksum length announced by a FORMAT_DESCRIPTION event
+///     body: binlog_version (2) | server_version (50) | ... | checksum_alg (1) | checksum (4)
+///     servers older than 5.6.1 do not write checksums
+pub fn checksum_len(event: &[u8]) -> usize {
+    let version_start = EVENT_HEADER_LEN + 2;
+    if event.len() < version_start + SERVER_VERSION_LEN + CHECKSUM_LEN + 1 {
+        return 0;
+    }
+
+    let version =
+        String::from_utf8_lossy(&event[version_start..version_start + SERVER_VERSION_LEN]);
+    let numbers: Vec<u32> = version
+        .trim_end_matches('\0')
+        .split(|c: char| !c.is_ascii_digit())
+        .take(3)
+        .map(|part| part.parse::<u32>().unwrap_or(0))
+        .collect();
+    if numbers < vec![5, 6, 1] {
+        return 0;
+    }
+
+    if event[event.len() - CHECKSUM_LEN - 1] == CHECKSUM_ALG_CRC32 {
+        CHECKSUM_LEN
+    } else {
+        0
+    }
+}
+
+/// Transaction id carried by a GTID event, and whether the transaction spans several
+/// events (MariaDB does not write BEGIN, the GTID event opens the transaction)
+///     MySQL:   flags (1) | sid (16) | gno (8) | ...
+///     MariaDB: seq_no (8) | domain_id (4) | flags (1) | ...
+pub fn parse_gtid(event: &[u8]) -> Option<(Gtid, bool)> {
+    let header = EventHeader::parse(event)?;
+    let body = &event[EVENT_HEADER_LEN..];
+
+    match header.type_code {
+        GTID_LOG_EVENT if body.len() >= 1 + SID_LEN + 8 => {
+            let gtid = Gtid::MySql {
+                sid: format_sid(&body[1..1 + SID_LEN]),
+                gno: le_u64(body, 1 + SID_LEN),
+            };
+            Some((gtid, false))
+        }
+        MARIADB_GTID_EVENT if body.len() >= 13 => {
+            let gtid = Gtid::MariaDb {
+                domain_id: le_u32(body, 8),
+                server_id: header.server_id,
+                seq_no: le_u64(body, 0),
+            };
+            Some((gtid, body[12] & MARIADB_FL_STANDALONE == 0))
+        }
+        _ => None,
+    }
+}
+
+/// Transactions executed before the binlog file was started
+///     MySQL PREVIOUS_GTIDS: n_sids (8) | { sid (16) | n_intervals (8) | { start (8) | end (8) }* }*
+///     MariaDB GTID_LIST:    count (4) | { domain_id (4) | server_id (4) | seq_no (8) }*
+pub fn parse_previous_gtids(event: &[u8], checksum_len: usize) -> Option<GtidSet> {
+    let header = EventHeader::parse(event)?;
+    let end = event.len().checked_sub(checksum_len)?;
+    let body = event.get(EVENT_HEADER_LEN..end)?;
+
+    match header.type_code {
+        PREVIOUS_GTIDS_LOG_EVENT => decode_gtid_set(body),
+        MARIADB_GTID_LIST_EVENT => {
+            let count = (le_u32(body.get(..4)?, 0) & 0x0fff_ffff) as usize;
+            let mut gtid_set = GtidSet::default();
+            for idx in 0..count {
+                let entry = body.get(4 + idx * 16..4 + (idx + 1) * 16)?;
+                gtid_set.add(&Gtid::MariaDb {
+                    domain_id: le_u32(entry, 0),
+                    server_id: le_u32(entry, 4),
+                    seq_no: le_u64(entry, 8),
+                });
+            }
+            Some(gtid_set)
+        }
+        _ => None,
+    }
+}
+
+/// MySQL binary encoding of a GTID set (intervals with exclusive end), used by
+/// PREVIOUS_GTIDS events and COM_BINLOG_DUMP_GTID.
+pub fn encode_gtid_set(gtid_set: &GtidSet) -> Vec<u8> {
+    let sids: Vec<_> = gtid_set.sids().collect();
+
+    let mut data = vec![];
+    data.extend_from_slice(&(sids.len() as u64).to_le_bytes());
+    for (sid, intervals) in sids {
+        data.extend_from_slice(&parse_sid(sid));
+        data.extend_from_slice(&(intervals.len() as u64).to_le_bytes());
+        for (start, end) in intervals {
+            data.extend_from_slice(&start.to_le_bytes());
+            data.extend_from_slice(&(end + 1).to_le_bytes());
+        }
+    }
+
+    data
+}
+
+fn decode_gtid_set(data: &[u8]) -> Option<GtidSet> {
+    let mut gtid_set = GtidSet::default();
+    let n_sids = le_u64(data.get(..8)?, 0);
+
+    let mut pos = 8;
+    for _ in 0..n_sids {
+        let sid = format_sid(data.get(pos..pos + SID_LEN)?);
+        let n_intervals = le_u64(data.get(pos + SID_LEN..pos + SID_LEN + 8)?, 0);
+        pos += SID_LEN + 8;
+
+        for _ in 0..n_intervals {
+            let interval = data.get(pos..pos + 16)?;
+            let (start, end) = (le_u64(interval, 0), le_u64(interval, 8));
+            if end > start {
+                gtid_set.add_interval(&sid, start, end - 1);
+            }
+            pos += 16;
+        }
+    }
+
+    Some(gtid_set)
+}
+
+/// SQL statement of a QUERY event
+///     post header: thread_id (4) | exec_time (4) | db_len (1) | error_code (2) | status_vars_len (2)
+///     body: status_vars | db name | 0x00 | query
+pub fn query_text(event: &[u8], checksum_len: usize) -> Option<String> {
+    let post_header = event.get(EVENT_HEADER_LEN..EVENT_HEADER_LEN + QUERY_POST_HEADER_LEN)?;
+    let db_len = post_header[8] as usize;
+    let status_vars_len = le_u16(post_header, 11) as usize;
+
+    let start = EVENT_HEADER_LEN + QUERY_POST_HEADER_LEN + status_vars_len + db_len + 1;
+    let end = event.len().checked_sub(checksum_len)?;
+    let query = event.get(start..end)?;
+
+    Some(String::from_utf8_lossy(query).to_string())
+}
+
+/// Copy of an event with a new log_pos (checksum recomputed)
+pub fn with_log_pos(event: &[u8], log_pos: u32, checksum_len: usize) -> Vec<u8> {
+    let mut event = event.to_vec();
+    event[13..17].copy_from_slice(&log_pos.to_le_bytes());
+    set_checksum(&mut event, checksum_len);
+
+    event
+}
+
+/// IGNORABLE event covering [start, end) of a binlog file, written in the relay log for
+/// parts of the source file that were not sent by the server.
+pub fn ignorable_event(start: u64, end: u64, checksum_len: usize) -> Option<Vec<u8>> {
+    let event_size = end.checked_sub(start)? as usize;
+    if event_size < EVENT_HEADER_LEN + checksum_len {
+        return None;
+    }
+
+    let mut event = vec![0; event_size];
+    event[4] = IGNORABLE_LOG_EVENT;
+    event[9..13].copy_from_slice(&(event_size as u32).to_le_bytes());
+    event[13..17].copy_from_slice(&(end as u32).to_le_bytes());
+    event[17..19].copy_from_slice(&LOG_EVENT_IGNORABLE_F.to_le_bytes());
+    set_checksum(&mut event, checksum_len);
+
+    Some(event)
+}
+
+fn set_checksum(event: &mut [u8], checksum_len: usize) {
+    if checksum_len == CHECKSUM_LEN && event.len() >= CHECKSUM_LEN {
+        let end = event.len() - CHECKSUM_LEN;
+        let checksum = crc32fast::hash(&event[..end]);
+        event[end..].copy_from_slice(&checksum.to_le_bytes());
+    }
+}
+
+fn format_sid(bytes: &[u8]) -> String {
+    let hex: String = bytes.iter().map(|byte| format!("{:02x}", byte)).collect();
+    format!(
+        "{}-{}-{}-{}-{}",
+        &hex[0..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..32]
+    )
+}
+
+fn parse_sid(sid: &str) -> Vec<u8> {
+    let hex: Vec<char> = sid.chars().filter(|c| c.is_ascii_hexdigit()).collect();
+    hex.chunks(2)
+        .map(|pair| {
+            let pair: String = pair.iter().collect();
+            u8::from_str_radix(&pair, 16).unwrap_or(0)
+        })
+        .collect()
+}
+
+pub fn le_u16(bytes: &[u8], pos: usize) -> u16 {
+    u16::from_le_bytes([bytes[pos], bytes[pos + 1]])
+}
+
+pub fn le_u32(bytes: &[u8], pos: usize) -> u32 {
+    u32::from_le_bytes([bytes[pos], bytes[pos + 1], bytes[pos + 2], bytes[pos + 3]])
+}
+
+pub fn le_u64(bytes: &[u8], pos: usize) -> u64 {
+    let mut value = [0; 8];
+    value.copy_from_slice(&bytes[pos..pos + 8]);
+    u64::from_le_bytes(value)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::fs;
+
+    const TEST_PATH: &str = "test_files";
+    const BL_FILE1: &str = "binlog.000001";
+
+    #[test]
+    fn test_event_header() {
+        let program_dir = std::env::current_dir().unwrap();
+        let bytes = fs::read(program_dir.join(TEST_PATH).join(BL_FILE1)).unwrap();
+        assert_eq!(&bytes[..4], &BINLOG_MAGIC);
+
+        // first event is always the format description event
+        let header = EventHeader::parse(&bytes[4..]).unwrap();
+        assert_eq!(header.type_code, FORMAT_DESCRIPTION_EVENT);
+        assert_eq!(header.event_size, 121);
+        assert_eq!(header.log_pos, 125);
+        assert_eq!(header.start_offset(), Some(4));
+        assert!(!header.is_artificial());
+    }
+
+    #[test]
+    fn test_rotate_file_name() {
+        let mut event = vec![0; EVENT_HEADER_LEN];
+        event[4] = ROTATE_EVENT;
+        event.extend_from_slice(&4u64.to_le_bytes());
+        event.extend_from_slice(b"binlog.000002");
+
+        let header = EventHeader::parse(&event).unwrap();
+        assert!(header.is_artificial());
+        assert_eq!(header.start_offset(), None);
+        assert_eq!(
+            rotate_file_name(&event, 0),
+            Some("binlog.000002".to_owned())
+        );
+
+        event.extend_from_slice(&[0; CHECKSUM_LEN]);
+        assert_eq!(
+            rotate_file_name(&event, CHECKSUM_LEN),
+            Some("binlog.000002".to_owned())
+        );
+    }
+
+    #[test]
+    fn test_checksum() {
+        let program_dir = std::env::current_dir().unwrap();
+        let bytes = fs::read(program_dir.join(TEST_PATH).join(BL_FILE1)).unwrap();
+        let header = EventHeader::parse(&bytes[4..]).unwrap();
+        let fde = &bytes[4..4 + header.event_size as usize];
+        assert_eq!(checksum_len(fde), CHECKSUM_LEN);
+
+        // recomputed checksum matches the one written by the server
+        assert_eq!(with_log_pos(fde, header.log_pos, CHECKSUM_LEN), fde);
+
+        let event = ignorable_event(125, 200, CHECKSUM_LEN).unwrap();
+        let header = EventHeader::parse(&event).unwrap();
+        assert_eq!(header.type_code, IGNORABLE_LOG_EVENT);
+        assert_eq!(header.start_offset(), Some(125));
+        assert!(ignorable_event(125, 130, CHECKSUM_LEN).is_none());
+    }
+
+    #[test]
+    fn test_gtid_events() {
+        let sid = "3e11fa47-71ca-11e1-9e33-c80aa9429562";
+
+        // mysql gtid
+        let mut event = vec![0; EVENT_HEADER_LEN];
+        event[4] = GTID_LOG_EVENT;
+        event.push(1);
+        event.extend_from_slice(&parse_sid(sid));
+        event.extend_from_slice(&23u64.to_le_bytes());
+        let gtid = Gtid::MySql {
+            sid: sid.to_owned(),
+            gno: 23,
+        };
+        assert_eq!(parse_gtid(&event), Some((gtid, false)));
+
+        // mariadb gtid, not standalone => spans several events
+        let mut event = vec![0; EVENT_HEADER_LEN];
+        event[4] = MARIADB_GTID_EVENT;
+        event[5..9].copy_from_slice(&2u32.to_le_bytes());
+        event.extend_from_slice(&100u64.to_le_bytes());
+        event.extend_from_slice(&1u32.to_le_bytes());
+        event.push(0);
+        let gtid = "1-2-100".parse::<Gtid>().unwrap();
+        assert_eq!(parse_gtid(&event), Some((gtid, true)));
+
+        // previous gtids => same encoding as COM_BINLOG_DUMP_GTID
+        let gtid_set = format!("{}:1-5:7", sid).parse::<GtidSet>().unwrap();
+        let mut event = vec![0; EVENT_HEADER_LEN];
+        event[4] = PREVIOUS_GTIDS_LOG_EVENT;
+        event.extend_from_slice(&encode_gtid_set(&gtid_set));
+        event.extend_from_slice(&[0; CHECKSUM_LEN]);
+        assert_eq!(parse_previous_gtids(&event, CHECKSUM_LEN), Some(gtid_set));
+    }
+}
@@ -0,0 +1,323 @@
+/// RelayLog
+///
+/// Local copy of a source binlog file, written by the replication client.
+///
+/// Events are appended at the same offsets they have on the source server, so the
+/// relay file can be parsed like a local binlog file and resume offsets (BnFile)
+/// remain valid for both modes.
+///
+/// When the stream starts from a gtid set, the server skips executed transactions: the
+/// skipped ranges are filled with IGNORABLE events to keep offsets aligned.
+///
+/// Relay files before the file of the resume position have been published, and are
+/// deleted by the replication manager.
+///
+use std::fs::{self, File, OpenOptions};
+use std::io::prelude::*;
+use std::io::{Error, ErrorKind, SeekFrom};
+use std::path::{Path, PathBuf};
+use tracing::{debug, info, warn};
+
+use super::raw_event::{ignorable_event, with_log_pos, EventHeader};
+use super::raw_event::{BINLOG_MAGIC, EVENT_HEADER_LEN, FORMAT_DESCRIPTION_EVENT};
+use super::split_file_name;
+
+#[derive(Debug)]
+pub struct RelayLog {
+    path: PathBuf,
+    file_name: String,
+    file: File,
+    position: u64,
+}
+
+impl RelayLog {
+    /// Open relay file for append, create it (with binlog header) if it does not exist.
+    /// An event partially written before a crash is truncated.
+    pub fn open(relay_dir: &Path, file_name: &str) -> Result<Self, Error> {
+        fs::create_dir_all(relay_dir)?;
+        let path = relay_dir.join(file_name);
+
+        let mut file = OpenOptions::new()
+            .create(true)
+            .read(true)
+            .append(true)
+            .open(&path)?;
+        let len = file.metadata()?.len();
+        let mut position = len;
+        if position < BINLOG_MAGIC.len() as u64 {
+            file.set_len(0)?;
+            file.write_all(&BINLOG_MAGIC)?;
+            position = BINLOG_MAGIC.len() as u64;
+        } else {
+            position = complete_events_len(&mut file, len)?;
+            if position < len {
+                warn!(%file_name, from = len, to = position, "Truncating partial relay event");
+                file.set_len(position)?;
+            }
+        }
+
+        Ok(Self {
+            path,
+            file_name: file_name.to_owned(),
+            file,
+            position,
+        })
+    }
+
+    pub fn file_name(&self) -> &str {
+        &self.file_name
+    }
+
+    /// Next offset to be written, which is also the position to request from the server
+    pub fn position(&self) -> u64 {
+        self.position
+    }
+
+    pub fn path_to_string(&self) -> String {
+        self.path.clone().into_os_string().into_string().unwrap()
+    }
+
+    /// Append event if it starts at or after the end of the relay file.
+    ///  - artificial events and events already in the relay file are skipped
+    ///  - format description of an empty relay file is always written
+    pub fn append_event(&mut self, event: &[u8], checksum_len: usize) -> Result<bool, Error> {
+        let header = match EventHeader::parse(event) {
+            Some(header) => header,
+            None => return Ok(false),
+        };
+
+        if header.type_code == FORMAT_DESCRIPTION_EVENT
+            && header.is_artificial()
+            && self.position == BINLOG_MAGIC.len() as u64
+        {
+            let log_pos = self.position as u32 + header.event_size;
+            self.write(&with_log_pos(event, log_pos, checksum_len))?;
+            return Ok(true);
+        }
+
+        let start = match header.start_offset() {
+            Some(start) if start >= self.position => start,
+            _ => return Ok(false),
+        };
+
+        if start > self.position {
+            debug!(from = self.position, to = start, "Filling relay gap");
+            let filler = ignorable_event(self.position, start, checksum_len).ok_or_else(|| {
+                Error::new(
+                    ErrorKind::InvalidData,
+                    format!("cannot fill relay gap {}..{}", self.position, start),
+                )
+            })?;
+            self.write(&filler)?;
+        }
+
+        self.write(event)?;
+        Ok(true)
+    }
+
+    fn write(&mut self, event: &[u8]) -> Result<(), Error> {
+        self.file.write_all(event)?;
+        self.file.flush()?;
+        self.position += event.len() as u64;
+
+        Ok(())
+    }
+}
+
+/// Length of the relay file up to the end of its last complete event
+fn complete_events_len(file: &mut File, len: u64) -> Result<u64, Error> {
+    let mut header = [0u8; EVENT_HEADER_LEN];
+    let mut position = BINLOG_MAGIC.len() as u64;
+    while position + EVENT_HEADER_LEN as u64 <= len {
+        file.seek(SeekFrom::Start(position))?;
+        file.read_exact(&mut header)?;
+        let event_size = match EventHeader::parse(&header) {
+            Some(header) => u64::from(header.event_size),
+            None => break,
+        };
+        if event_size < EVENT_HEADER_LEN as u64 || position + event_size > len {
+            break;
+        }
+        position += event_size;
+    }
+
+    Ok(position)
+}
+
+/// Delete the relay files of the same binlog sequence before `file_name`
+/// (binlog.000003 deletes binlog.000001 and binlog.000002).
+pub fn purge_relay_files(relay_dir: &Path, file_name: &str) -> Result<(), Error> {
+    let (base_name, index) = match split_file_name(file_name) {
+        Some(parts) => parts,
+        None => return Ok(()),
+    };
+    if !relay_dir.exists() {
+        return Ok(());
+    }
+
+    for entry in fs::read_dir(relay_dir)? {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().into_owned();
+        match split_file_name(&name) {
+            Some((base, idx)) if base == base_name && idx < index => {
+                info!(file_name = %name, "Deleting published relay file");
+                fs::remove_file(entry.path())?;
+            }
+            _ => {}
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::producer::binlog_manager::raw_event::IGNORABLE_LOG_EVENT;
+    use crate::producer::binlog_manager::raw_event::{CHECKSUM_LEN, EVENT_HEADER_LEN};
+
+    const TEST_PATH: &str = "test_files";
+    const RELAY_DIR: &str = "relay-log-test";
+    const GAP_RELAY_DIR: &str = "relay-log-gap-test";
+    const PARTIAL_RELAY_DIR: &str = "relay-log-partial-test";
+    const PURGE_RELAY_DIR: &str = "relay-log-purge-test";
+    const BL_FILE1: &str = "binlog.000001";
+
+    #[test]
+    fn test_relay_log_append() {
+        let program_dir = std::env::current_dir().unwrap();
+        let source = fs::read(program_dir.join(TEST_PATH).join(BL_FILE1)).unwrap();
+        let relay_dir = program_dir.join(TEST_PATH).join(RELAY_DIR);
+        let _ = fs::remove_dir_all(&relay_dir);
+
+        let mut relay = RelayLog::open(&relay_dir, BL_FILE1).unwrap();
+        assert_eq!(relay.position(), 4);
+
+        // replay all events, each one twice => duplicates are skipped
+        let mut pos = 4;
+        while pos < source.len() {
+            let header = EventHeader::parse(&source[pos..pos + EVENT_HEADER_LEN]).unwrap();
+            let event = &source[pos..pos + header.event_size as usize];
+            assert!(relay.append_event(event, CHECKSUM_LEN).unwrap());
+            assert!(!relay.append_event(event, CHECKSUM_LEN).unwrap());
+            pos += event.len();
+        }
+        assert_eq!(relay.position(), source.len() as u64);
+
+        // reopen => position restored from file
+        let relay = RelayLog::open(&relay_dir, BL_FILE1).unwrap();
+        assert_eq!(relay.position(), source.len() as u64);
+        assert_eq!(fs::read(relay.path_to_string()).unwrap(), source);
+
+        fs::remove_dir_all(&relay_dir).expect("delete relay dir failed");
+    }
+
+    #[test]
+    fn test_relay_log_gap() {
+        let program_dir = std::env::current_dir().unwrap();
+        let source = fs::read(program_dir.join(TEST_PATH).join(BL_FILE1)).unwrap();
+        let relay_dir = program_dir.join(TEST_PATH).join(GAP_RELAY_DIR);
+        let _ = fs::remove_dir_all(&relay_dir);
+
+        let mut events = vec![];
+        let mut pos = 4;
+        while pos < source.len() {
+            let header = EventHeader::parse(&source[pos..pos + EVENT_HEADER_LEN]).unwrap();
+            events.push(&source[pos..pos + header.event_size as usize]);
+            pos += header.event_size as usize;
+        }
+
+        // format description sent without position, second event skipped by the server
+        let mut relay = RelayLog::open(&relay_dir, BL_FILE1).unwrap();
+        let fde = with_log_pos(events[0], 0, CHECKSUM_LEN);
+        assert!(relay.append_event(&fde, CHECKSUM_LEN).unwrap());
+        for event in events.iter().skip(2) {
+            assert!(relay.append_event(event, CHECKSUM_LEN).unwrap());
+        }
+        assert_eq!(relay.position(), source.len() as u64);
+
+        // same offsets as the source file, skipped event replaced
+        let relay_bytes = fs::read(relay.path_to_string()).unwrap();
+        assert_eq!(
+            &relay_bytes[..4 + events[0].len()],
+            &source[..4 + events[0].len()]
+        );
+        let filler = EventHeader::parse(&relay_bytes[4 + events[0].len()..]).unwrap();
+        assert_eq!(filler.type_code, IGNORABLE_LOG_EVENT);
+        assert_eq!(filler.event_size as usize, events[1].len());
+
+        fs::remove_dir_all(&relay_dir).expect("delete relay dir failed");
+    }
+
+    #[test]
+    fn test_relay_log_partial_event() {
+        let program_dir = std::env::current_dir().unwrap();
+        let source = fs::read(program_dir.join(TEST_PATH).join(BL_FILE1)).unwrap();
+        let relay_dir = program_dir.join(TEST_PATH).join(PARTIAL_RELAY_DIR);
+        let _ = fs::remove_dir_all(&relay_dir);
+        fs::create_dir_all(&relay_dir).unwrap();
+
+        // crash in the middle of the last event
+        let header = EventHeader::parse(&source[4..4 + EVENT_HEADER_LEN]).unwrap();
+        let first_end = 4 + header.event_size as usize;
+        let second = EventHeader::parse(&source[first_end..first_end + EVENT_HEADER_LEN]).unwrap();
+        let partial_end = first_end + second.event_size as usize / 2;
+        fs::write(relay_dir.join(BL_FILE1), &source[..partial_end]).unwrap();
+
+        let mut relay = RelayLog::open(&relay_dir, BL_FILE1).unwrap();
+        assert_eq!(relay.position(), first_end as u64);
+        assert_eq!(
+            fs::read(relay.path_to_string()).unwrap(),
+            &source[..first_end]
+        );
+
+        // stream continues from the truncated event
+        let second_event = &source[first_end..first_end + second.event_size as usize];
+        assert!(relay.append_event(second_event, CHECKSUM_LEN).unwrap());
+        assert_eq!(
+            fs::read(relay.path_to_string()).unwrap(),
+            &source[..first_end + second_event.len()]
+        );
+
+        // partial header
+        fs::write(relay_dir.join(BL_FILE1), &source[..first_end + 5]).unwrap();
+        let relay = RelayLog::open(&relay_dir, BL_FILE1).unwrap();
+        assert_eq!(relay.position(), first_end as u64);
+
+        fs::remove_dir_all(&relay_dir).expect("delete relay dir failed");
+    }
+
+    #[test]
+    fn test_purge_relay_files() {
+        let program_dir = std::env::current_dir().unwrap();
+        let relay_dir = program_dir.join(TEST_PATH).join(PURGE_RELAY_DIR);
+        let _ = fs::remove_dir_all(&relay_dir);
+        fs::create_dir_all(&relay_dir).unwrap();
+
+        let files = [
+            "binlog.000001",
+            "binlog.000002",
+            "binlog.000003",
+            "binlog.000010",
+            "mysql-bin.000001",
+        ];
+        for file in files.iter() {
+            fs::write(relay_dir.join(file), BINLOG_MAGIC).unwrap();
+        }
+
+        purge_relay_files(&relay_dir, "binlog.000003").unwrap();
+        let mut remaining: Vec<String> = fs::read_dir(&relay_dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        remaining.sort();
+        assert_eq!(
+            remaining,
+            vec!["binlog.000003", "binlog.000010", "mysql-bin.000001"]
+        );
+
+        // missing relay dir
+        fs::remove_dir_all(&relay_dir).expect("delete relay dir failed");
+        assert!(purge_relay_files(&relay_dir, "binlog.000003").is_ok());
+    }
+}
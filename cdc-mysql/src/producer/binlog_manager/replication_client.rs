@@ -0,0 +1,412 @@
+/// ReplicationClient
+///
+/// Speaks just enough of the MySQL client/server protocol to register as a replica
+/// and receive the binlog stream:
+///     - handshake v10 with mysql_native_password authentication
+///     - COM_QUERY (session setup)
+///     - COM_REGISTER_SLAVE
+///     - COM_BINLOG_DUMP
+///     - COM_BINLOG_DUMP_GTID (MySQL only, MariaDB resumes from @slave_connect_state)
+///
+/// Each packet of the binlog stream holds one event, prefixed by an OK byte.
+///
+use std::cmp;
+use std::io::prelude::*;
+use std::io::{Error, ErrorKind};
+use std::net::TcpStream;
+use tracing::{debug, instrument};
+
+const MAX_PACKET_LEN: usize = 0xff_ffff;
+const NATIVE_PASSWORD_PLUGIN: &str = "mysql_native_password";
+
+const CLIENT_LONG_PASSWORD: u32 = 0x0000_0001;
+const CLIENT_LONG_FLAG: u32 = 0x0000_0004;
+const CLIENT_PROTOCOL_41: u32 = 0x0000_0200;
+const CLIENT_TRANSACTIONS: u32 = 0x0000_2000;
+const CLIENT_SECURE_CONNECTION: u32 = 0x0000_8000;
+const CLIENT_PLUGIN_AUTH: u32 = 0x0008_0000;
+const UTF8_GENERAL_CI: u8 = 33;
+
+const COM_QUERY: u8 = 0x03;
+const COM_BINLOG_DUMP: u8 = 0x12;
+const COM_REGISTER_SLAVE: u8 = 0x15;
+const COM_BINLOG_DUMP_GTID: u8 = 0x1e;
+const BINLOG_THROUGH_GTID: u16 = 0x04;
+
+const OK_PACKET: u8 = 0x00;
+const EOF_PACKET: u8 = 0xfe;
+const ERR_PACKET: u8 = 0xff;
+
+pub struct ReplicationClient {
+    stream: TcpStream,
+    seq: u8,
+}
+
+impl ReplicationClient {
+    /// Connect and authenticate against the source server
+    #[instrument(skip(password))]
+    pub fn connect(
+        ip_or_host: &str,
+        port: u16,
+        user: &str,
+        password: Option<&str>,
+    ) -> Result<Self, Error> {
+        let stream = TcpStream::connect((ip_or_host, port))?;
+        let mut client = Self { stream, seq: 0 };
+
+        let handshake = client.read_packet()?;
+        let (scramble, plugin) = parse_handshake(&handshake)?;
+        if plugin != NATIVE_PASSWORD_PLUGIN {
+            debug!(%plugin, "server default auth plugin, requesting native password");
+        }
+
+        let auth = scramble_native_password(password, &scramble);
+        client.write_packet(&handshake_response(user, &auth))?;
+
+        let response = client.read_packet()?;
+        match response.first() {
+            Some(&OK_PACKET) => Ok(client),
+            Some(&EOF_PACKET) => {
+                // auth switch request: plugin name (NUL terminated) + new scramble
+                let (plugin, scramble) = split_nul(&response[1..]);
+                if plugin != NATIVE_PASSWORD_PLUGIN.as_bytes() {
+                    return Err(Error::new(
+                        ErrorKind::PermissionDenied,
+                        format!(
+                            "unsupported auth plugin '{}'",
+                            String::from_utf8_lossy(plugin)
+                        ),
+                    ));
+                }
+                let scramble = scramble.strip_suffix(&[0]).unwrap_or(scramble);
+                client.write_packet(&scramble_native_password(password, scramble))?;
+                client.read_ok()?;
+                Ok(client)
+            }
+            _ => Err(packet_error(&response)),
+        }
+    }
+
+    /// Run a query and discard any result set
+    pub fn query_drop(&mut self, query: &str) -> Result<(), Error> {
+        self.query_first_value(query).map(|_| ())
+    }
+
+    /// Run a query and return the first column of the first row, if any
+    #[instrument(skip(self))]
+    pub fn query_first_value(&mut self, query: &str) -> Result<Option<String>, Error> {
+        let mut command = vec![COM_QUERY];
+        command.extend_from_slice(query.as_bytes());
+        self.write_command(&command)?;
+
+        let response = self.read_packet()?;
+        match response.first() {
+            Some(&OK_PACKET) => return Ok(None),
+            Some(&ERR_PACKET) | None => return Err(packet_error(&response)),
+            _ => {}
+        }
+
+        // result set: column definitions, EOF, rows, EOF
+        let mut value = None;
+        let mut eof_count = 0;
+        while eof_count < 2 {
+            let packet = self.read_packet()?;
+            if is_eof(&packet) {
+                eof_count += 1;
+            } else if packet.first() == Some(&ERR_PACKET) {
+                return Err(packet_error(&packet));
+            } else if eof_count == 1 && value.is_none() {
+                value = read_lenenc_str(&packet);
+            }
+        }
+
+        Ok(value)
+    }
+
+    /// Register as a replica with the given server id
+    #[instrument(skip(self))]
+    pub fn register_replica(&mut self, server_id: u32) -> Result<(), Error> {
+        let mut command = vec![COM_REGISTER_SLAVE];
+        command.extend_from_slice(&server_id.to_le_bytes());
+        command.push(0); // hostname
+        command.push(0); // user
+        command.push(0); // password
+        command.extend_from_slice(&0u16.to_le_bytes()); // port
+        command.extend_from_slice(&0u32.to_le_bytes()); // replication rank
+        command.extend_from_slice(&0u32.to_le_bytes()); // master id
+        self.write_command(&command)?;
+
+        self.read_ok()
+    }
+
+    /// Request the binlog stream, starting at file/position.
+    ///  - empty file name starts from the first binlog file known by the server
+    #[instrument(skip(self))]
+    pub fn binlog_dump(&mut self, server_id: u32, file_name: &str, pos: u32) -> Result<(), Error> {
+        let mut command = vec![COM_BINLOG_DUMP];
+        command.extend_from_slice(&pos.to_le_bytes());
+        command.extend_from_slice(&0u16.to_le_bytes()); // flags: blocking
+        command.extend_from_slice(&server_id.to_le_bytes());
+        command.extend_from_slice(file_name.as_bytes());
+
+        self.write_command(&command)
+    }
+
+    /// Request the binlog stream, starting at the first transaction not in the gtid set
+    ///  - gtid_set: MySQL binary encoding of the executed set
+    #[instrument(skip(self, gtid_set))]
+    pub fn binlog_dump_gtid(&mut self, server_id: u32, gtid_set: &[u8]) -> Result<(), Error> {
+        let mut command = vec![COM_BINLOG_DUMP_GTID];
+        command.extend_from_slice(&BINLOG_THROUGH_GTID.to_le_bytes());
+        command.extend_from_slice(&server_id.to_le_bytes());
+        command.extend_from_slice(&0u32.to_le_bytes()); // file name length
+        command.extend_from_slice(&4u64.to_le_bytes()); // position
+        command.extend_from_slice(&(gtid_set.len() as u32).to_le_bytes());
+        command.extend_from_slice(gtid_set);
+
+        self.write_command(&command)
+    }
+
+    /// Read the next event from the binlog stream (None when the server ends the stream)
+    pub fn next_event(&mut self) -> Result<Option<Vec<u8>>, Error> {
+        let mut packet = self.read_packet()?;
+        match packet.first() {
+            Some(&OK_PACKET) => {
+                packet.remove(0);
+                Ok(Some(packet))
+            }
+            Some(&EOF_PACKET) if is_eof(&packet) => Ok(None),
+            _ => Err(packet_error(&packet)),
+        }
+    }
+
+    fn read_ok(&mut self) -> Result<(), Error> {
+        let packet = self.read_packet()?;
+        if packet.first() == Some(&OK_PACKET) {
+            Ok(())
+        } else {
+            Err(packet_error(&packet))
+        }
+    }
+
+    fn write_command(&mut self, payload: &[u8]) -> Result<(), Error> {
+        self.seq = 0;
+        self.write_packet(payload)
+    }
+
+    fn write_packet(&mut self, payload: &[u8]) -> Result<(), Error> {
+        // payloads of max length or more are split, the last frame is always shorter
+        let mut start = 0;
+        loop {
+            let end = cmp::min(start + MAX_PACKET_LEN, payload.len());
+            self.write_frame(&payload[start..end])?;
+            if end - start < MAX_PACKET_LEN {
+                break;
+            }
+            start = end;
+        }
+
+        self.stream.flush()
+    }
+
+    fn write_frame(&mut self, chunk: &[u8]) -> Result<(), Error> {
+        let len = (chunk.len() as u32).to_le_bytes();
+        self.stream.write_all(&[len[0], len[1], len[2], self.seq])?;
+        self.stream.write_all(chunk)?;
+        self.seq = self.seq.wrapping_add(1);
+        Ok(())
+    }
+
+    fn read_packet(&mut self) -> Result<Vec<u8>, Error> {
+        let mut payload = vec![];
+
+        loop {
+            let mut header = [0; 4];
+            self.stream.read_exact(&mut header)?;
+            let len = u32::from_le_bytes([header[0], header[1], header[2], 0]) as usize;
+            self.seq = header[3].wrapping_add(1);
+
+            let start = payload.len();
+            payload.resize(start + len, 0);
+            self.stream.read_exact(&mut payload[start..])?;
+
+            if len < MAX_PACKET_LEN {
+                return Ok(payload);
+            }
+        }
+    }
+}
+
+/// Parse handshake v10, return auth scramble and default auth plugin name
+fn parse_handshake(packet: &[u8]) -> Result<(Vec<u8>, String), Error> {
+    let invalid = || Error::new(ErrorKind::InvalidData, "invalid handshake packet");
+
+    match packet.first() {
+        Some(10) => {}
+        Some(&ERR_PACKET) => return Err(packet_error(packet)),
+        _ => return Err(invalid()),
+    }
+
+    // protocol version, server version
+    let (_, rest) = split_nul(&packet[1..]);
+
+    // connection id (4), scramble part 1 (8), filler (1), capabilities lower (2),
+    // charset (1), status (2), capabilities upper (2), scramble len (1), reserved (10)
+    if rest.len() < 31 {
+        return Err(invalid());
+    }
+    let mut scramble = rest[4..12].to_vec();
+    let scramble_len = rest[20] as usize;
+    let rest = &rest[31..];
+
+    let part2_len = cmp::max(13, scramble_len.saturating_sub(8));
+    if rest.len() < part2_len {
+        return Err(invalid());
+    }
+    // last byte of part 2 is a NUL terminator
+    scramble.extend_from_slice(&rest[..part2_len - 1]);
+
+    let (plugin, _) = split_nul(&rest[part2_len..]);
+    Ok((scramble, String::from_utf8_lossy(plugin).to_string()))
+}
+
+fn handshake_response(user: &str, auth: &[u8]) -> Vec<u8> {
+    let capabilities = CLIENT_LONG_PASSWORD
+        | CLIENT_LONG_FLAG
+        | CLIENT_PROTOCOL_41
+        | CLIENT_TRANSACTIONS
+        | CLIENT_SECURE_CONNECTION
+        | CLIENT_PLUGIN_AUTH;
+
+    let mut payload = vec![];
+    payload.extend_from_slice(&capabilities.to_le_bytes());
+    payload.extend_from_slice(&(MAX_PACKET_LEN as u32).to_le_bytes());
+    payload.push(UTF8_GENERAL_CI);
+    payload.extend_from_slice(&[0; 23]);
+    payload.extend_from_slice(user.as_bytes());
+    payload.push(0);
+    payload.push(auth.len() as u8);
+    payload.extend_from_slice(auth);
+    payload.extend_from_slice(NATIVE_PASSWORD_PLUGIN.as_bytes());
+    payload.push(0);
+
+    payload
+}
+
+/// mysql_native_password: SHA1(password) XOR SHA1(scramble + SHA1(SHA1(password)))
+fn scramble_native_password(password: Option<&str>, scramble: &[u8]) -> Vec<u8> {
+    let password = match password {
+        Some(password) if !password.is_empty() => password,
+        _ => return vec![],
+    };
+
+    let stage1 = sha1::Sha1::from(password.as_bytes()).digest().bytes();
+    let stage2 = sha1::Sha1::from(&stage1[..]).digest().bytes();
+
+    let mut hasher = sha1::Sha1::new();
+    hasher.update(scramble);
+    hasher.update(&stage2);
+    let stage3 = hasher.digest().bytes();
+
+    stage1
+        .iter()
+        .zip(stage3.iter())
+        .map(|(a, b)| a ^ b)
+        .collect()
+}
+
+fn split_nul(bytes: &[u8]) -> (&[u8], &[u8]) {
+    match bytes.iter().position(|b| *b == 0) {
+        Some(pos) => (&bytes[..pos], &bytes[pos + 1..]),
+        None => (bytes, &[]),
+    }
+}
+
+fn is_eof(packet: &[u8]) -> bool {
+    packet.first() == Some(&EOF_PACKET) && packet.len() < 9
+}
+
+/// Read a length encoded string (text protocol row value), NULL is returned as None
+fn read_lenenc_str(packet: &[u8]) -> Option<String> {
+    let (len, start) = match packet.first()? {
+        0xfb => return None,
+        0xfc => (
+            u16::from_le_bytes([*packet.get(1)?, *packet.get(2)?]) as usize,
+            3,
+        ),
+        0xfd => (
+            u32::from_le_bytes([*packet.get(1)?, *packet.get(2)?, *packet.get(3)?, 0]) as usize,
+            4,
+        ),
+        0xfe => return None,
+        len => (*len as usize, 1),
+    };
+
+    let value = packet.get(start..start + len)?;
+    Some(String::from_utf8_lossy(value).to_string())
+}
+
+fn packet_error(packet: &[u8]) -> Error {
+    if packet.first() != Some(&ERR_PACKET) || packet.len() < 3 {
+        return Error::new(ErrorKind::InvalidData, "unexpected packet from server");
+    }
+
+    // error code (2), sql state marker + state (6), message
+    let code = u16::from_le_bytes([packet[1], packet[2]]);
+    let message = if packet.len() > 9 && packet[3] == b'#' {
+        String::from_utf8_lossy(&packet[9..])
+    } else {
+        String::from_utf8_lossy(&packet[3..])
+    };
+
+    Error::new(
+        ErrorKind::Other,
+        format!("server error {}: {}", code, message),
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_scramble_native_password() {
+        // empty password => empty auth response
+        assert!(scramble_native_password(None, &[1; 20]).is_empty());
+        assert!(scramble_native_password(Some(""), &[1; 20]).is_empty());
+
+        // SHA1(password) XOR SHA1(scramble + SHA1(SHA1(password)))
+        let auth = scramble_native_password(Some("fluvio4cdc!"), &[0x2a; 20]);
+        assert_eq!(auth.len(), 20);
+
+        let stage1 = sha1::Sha1::from("fluvio4cdc!").digest().bytes();
+        let stage2 = sha1::Sha1::from(&stage1[..]).digest().bytes();
+        let mut hasher = sha1::Sha1::new();
+        hasher.update(&[0x2a; 20]);
+        hasher.update(&stage2);
+        let stage3 = hasher.digest().bytes();
+
+        let unmasked: Vec<u8> = auth.iter().zip(stage3.iter()).map(|(a, b)| a ^ b).collect();
+        assert_eq!(unmasked, stage1.to_vec());
+    }
+
+    #[test]
+    fn test_read_lenenc_str() {
+        assert_eq!(
+            read_lenenc_str(&[5, b'C', b'R', b'C', b'3', b'2']),
+            Some("CRC32".to_owned())
+        );
+        assert_eq!(read_lenenc_str(&[0xfb]), None);
+        assert_eq!(read_lenenc_str(&[5, b'C']), None);
+    }
+
+    #[test]
+    fn test_packet_error() {
+        let mut packet = vec![ERR_PACKET];
+        packet.extend_from_slice(&1236u16.to_le_bytes());
+        packet.extend_from_slice(b"#HY000binlog not found");
+
+        let err = packet_error(&packet);
+        assert_eq!(err.to_string(), "server error 1236: binlog not found");
+    }
+}
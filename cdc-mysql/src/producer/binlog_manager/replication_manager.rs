@@ -0,0 +1,425 @@
+/// ReplicationManager
+///
+/// Registers with the mysql server as a replica and listens to the binlog stream.
+///
+/// Events are spooled into relay files (same name and offsets as on the server) and
+/// passed to the binlog parser when a statement or transaction completes.
+///
+/// Once transactions with gtids have been parsed, the stream is requested from the
+/// executed gtid set, so it survives binlog files purged or renamed after a failover.
+///
+/// Lost connections are retried with an exponential backoff, reset once the stream makes
+/// progress. After MAX_RECONNECT_ATTEMPTS without progress the manager stops, which closes
+/// the channel and stops the producer.
+///
+use crossbeam_channel::Sender;
+use std::io::Error;
+use std::path::PathBuf;
+use std::thread;
+use std::time::Duration;
+use tracing::{debug, error, info, instrument, trace, warn};
+
+use super::raw_event::{encode_gtid_set, rotate_file_name, EventHeader, CHECKSUM_LEN};
+use super::raw_event::{HEARTBEAT_LOG_EVENT, QUERY_EVENT, ROTATE_EVENT, XID_EVENT};
+use super::ParserState;
+use super::RelayLog;
+use super::ReplicationClient;
+use super::Resume;
+use super::{parse_records_from_file, purge_relay_files};
+use crate::error::CdcError;
+use crate::messages::GtidSet;
+use crate::producer::{Profile, Replication};
+
+const RECONNECT_DELAY_MILIS: u64 = 2000;
+const MAX_RECONNECT_DELAY_MILIS: u64 = 60_000;
+const MAX_RECONNECT_ATTEMPTS: u32 = 10;
+const BINLOG_START_POS: u64 = 4;
+const MARIADB_CAPABILITY_GTID: u8 = 4;
+
+#[derive(Debug)]
+pub struct ReplicationManager {
+    sender: Sender<String>,
+    replication: Replication,
+    relay_dir: PathBuf,
+    resume_path: Option<PathBuf>,
+
+    relay: Option<RelayLog>,
+    parsed_offset: Option<u64>,
+    checksum_len: usize,
+
+    state: ParserState,
+}
+
+impl ReplicationManager {
+    pub fn new(
+        profile: &Profile,
+        replication: Replication,
+        sender: Sender<String>,
+    ) -> Result<Self, Error> {
+        Ok(Self {
+            sender,
+            relay_dir: replication.relay_dir(),
+            replication,
+            resume_path: None,
+            relay: None,
+            parsed_offset: None,
+            checksum_len: 0,
+            state: ParserState::new(profile)?,
+        })
+    }
+
+    #[instrument(skip(self, resume))]
+    pub fn run(mut self, resume: Resume) {
+        thread::spawn(move || {
+            let mut attempts = 0;
+            loop {
+                let position = self.relay_position();
+                if let Err(err) = self.inner_run(&resume) {
+                    error!("Error: {}", err);
+                }
+
+                // server closed the stream or connection failed, reconnect after a bit
+                if self.relay_position() != position {
+                    attempts = 0;
+                }
+                attempts += 1;
+                if attempts > MAX_RECONNECT_ATTEMPTS {
+                    error!(attempts, "Replication failed, giving up");
+                    break;
+                }
+                let delay = reconnect_delay(attempts);
+                warn!(attempts, ?delay, "Reconnecting");
+                thread::sleep(delay);
+            }
+        });
+    }
+
+    fn relay_position(&self) -> Option<(String, u64)> {
+        self.relay
+            .as_ref()
+            .map(|relay| (relay.file_name().to_owned(), relay.position()))
+    }
+
+    #[instrument(skip(self, resume))]
+    fn inner_run(&mut self, resume: &Resume) -> Result<(), CdcError> {
+        if self.relay.is_none() {
+            self.set_current_relay(resume)
+                .map_err(|source| CdcError::BinlogFileError { source })?;
+            self.send_relay_records()?;
+        }
+
+        self.stream_events()
+    }
+
+    /// Resume from the relay file of the last processed event, or from the first
+    /// binlog on the server if there is nothing to resume from.
+    fn set_current_relay(&mut self, resume: &Resume) -> Result<(), Error> {
+        self.state.gtids.set_resume(resume.binfile.as_ref());
+        self.resume_path = Some(resume.path.clone());
+        if let Some(file_name) = resume.file() {
+            purge_relay_files(&self.relay_dir, file_name)?;
+            self.relay = Some(RelayLog::open(&self.relay_dir, file_name)?);
+            self.parsed_offset = resume.offset();
+        }
+
+        Ok(())
+    }
+
+    fn connect(&mut self) -> Result<ReplicationClient, Error> {
+        let mut client = ReplicationClient::connect(
+            &self.replication.ip_or_host,
+            self.replication.port(),
+            &self.replication.user,
+            self.replication.password.as_deref(),
+        )?;
+
+        // server refuses to stream to replicas unaware of binlog checksums
+        let checksum = client
+            .query_first_value("SELECT @@global.binlog_checksum")?
+            .unwrap_or_else(|| "NONE".to_owned());
+        client.query_drop(&format!("SET @master_binlog_checksum = '{}'", checksum))?;
+        self.checksum_len = if checksum.eq_ignore_ascii_case("NONE") {
+            0
+        } else {
+            CHECKSUM_LEN
+        };
+
+        client.register_replica(self.replication.server_id())?;
+        Ok(client)
+    }
+
+    #[instrument(skip(self))]
+    fn stream_events(&mut self) -> Result<(), CdcError> {
+        let mut client = self
+            .connect()
+            .map_err(|source| CdcError::ReplicationError { source })?;
+
+        match self.state.gtids.gtid_set() {
+            Some(gtid_set) => self.request_gtid_dump(&mut client, &gtid_set),
+            None => self.request_file_dump(&mut client),
+        }
+        .map_err(|source| CdcError::ReplicationError { source })?;
+
+        while let Some(event) = client
+            .next_event()
+            .map_err(|source| CdcError::ReplicationError { source })?
+        {
+            self.process_raw_event(&event)?;
+        }
+
+        info!("Binlog stream closed by server");
+        self.send_relay_records()
+    }
+
+    fn request_file_dump(&self, client: &mut ReplicationClient) -> Result<(), Error> {
+        let (file_name, position) = match self.relay.as_ref() {
+            Some(relay) => (relay.file_name().to_owned(), relay.position()),
+            None => ("".to_owned(), BINLOG_START_POS),
+        };
+
+        info!(%file_name, position, "Requesting binlog dump");
+        client.binlog_dump(self.replication.server_id(), &file_name, position as u32)
+    }
+
+    fn request_gtid_dump(
+        &self,
+        client: &mut ReplicationClient,
+        gtid_set: &GtidSet,
+    ) -> Result<(), Error> {
+        info!(%gtid_set, "Requesting binlog dump from gtid set");
+        let server_id = self.replication.server_id();
+
+        if gtid_set.is_mariadb() {
+            client.query_drop(&format!(
+                "SET @mariadb_slave_capability = {}",
+                MARIADB_CAPABILITY_GTID
+            ))?;
+            client.query_drop(&format!("SET @slave_connect_state = '{}'", gtid_set))?;
+            client.query_drop("SET @slave_gtid_strict_mode = 0")?;
+            client.query_drop("SET @slave_gtid_ignore_duplicates = 0")?;
+            client.binlog_dump(server_id, "", BINLOG_START_POS as u32)
+        } else {
+            client.binlog_dump_gtid(server_id, &encode_gtid_set(gtid_set))
+        }
+    }
+
+    fn process_raw_event(&mut self, event: &[u8]) -> Result<(), CdcError> {
+        let header = match EventHeader::parse(event) {
+            Some(header) => header,
+            None => return Ok(()),
+        };
+        trace!(?header, "Event from replication stream:");
+
+        match header.type_code {
+            HEARTBEAT_LOG_EVENT => {}
+            ROTATE_EVENT => {
+                // artificial rotate: sent when the server opens a file
+                // real rotate: last event of a file (written to relay)
+                self.append_event(event)?;
+                if let Some(next_file) = rotate_file_name(event, self.checksum_len) {
+                    self.rotate_relay(&next_file)?;
+                }
+            }
+            QUERY_EVENT | XID_EVENT => {
+                if self.append_event(event)? {
+                    self.send_relay_records()?;
+                }
+            }
+            _ => {
+                self.append_event(event)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn append_event(&mut self, event: &[u8]) -> Result<bool, CdcError> {
+        match self.relay.as_mut() {
+            Some(relay) => relay
+                .append_event(event, self.checksum_len)
+                .map_err(|source| CdcError::BinlogFileError { source }),
+            None => Ok(false),
+        }
+    }
+
+    fn rotate_relay(&mut self, file_name: &str) -> Result<(), CdcError> {
+        if self.relay.as_ref().map(|relay| relay.file_name()) == Some(file_name) {
+            return Ok(());
+        }
+
+        // flush records left in the previous file
+        self.send_relay_records()?;
+
+        let next_relay = RelayLog::open(&self.relay_dir, file_name)
+            .map_err(|source| CdcError::BinlogFileError { source })?;
+        debug!("Next relay file: {:?}", &next_relay);
+        self.relay = Some(next_relay);
+        self.parsed_offset = None;
+
+        self.purge_published_relays()
+            .map_err(|source| CdcError::BinlogFileError { source })
+    }
+
+    /// Delete the relay files before the file of the stored resume position
+    fn purge_published_relays(&self) -> Result<(), Error> {
+        let stored_file = self.resume_path.as_deref().and_then(Resume::stored_file);
+        match stored_file {
+            Some(file_name) => purge_relay_files(&self.relay_dir, &file_name),
+            None => Ok(()),
+        }
+    }
+
+    #[instrument(skip(self))]
+    fn send_relay_records(&mut self) -> Result<(), CdcError> {
+        if let Some(relay) = self.relay.as_ref() {
+            let new_offset = parse_records_from_file(
+                &self.sender,
+                &relay.path_to_string(),
+                relay.file_name(),
+                self.parsed_offset,
+                &mut self.state,
+            )?;
+
+            if new_offset.is_some() {
+                self.parsed_offset = new_offset;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Delay before reconnect attempt (1, 2, ..): doubles from RECONNECT_DELAY_MILIS, capped
+fn reconnect_delay(attempt: u32) -> Duration {
+    let factor = 1u64
+        .checked_shl(attempt.saturating_sub(1))
+        .unwrap_or(u64::MAX);
+    let millis = RECONNECT_DELAY_MILIS.saturating_mul(factor);
+    Duration::from_millis(millis.min(MAX_RECONNECT_DELAY_MILIS))
+}
+
+#[cfg(test)]
+mod test {
+    use crossbeam_channel::unbounded;
+    use std::fs;
+    use std::path::PathBuf;
+
+    use crate::producer::binlog_manager::fake_server;
+    use crate::producer::binlog_manager::{parse_records_from_file, LocalStore, ParserState};
+    use crate::producer::{Data, Filters, Profile, Replication, Source};
+
+    use super::Resume;
+    use super::{reconnect_delay, ReplicationManager};
+    use std::time::Duration;
+
+    const TEST_PATH: &str = "test_files";
+    const BL_FILES: [&str; 3] = ["binlog.000001", "binlog.000002", "binlog.000003"];
+    const RELAY_DIR: &str = "relay-manager-test";
+    const RPL_STORE: &str = "rpl-local.store";
+    const FILE_STORE: &str = "rpl-file-local.store";
+    const RESUME_OFFSET: &str = "rpl-resume.offset";
+
+    fn get_base_dir() -> PathBuf {
+        let program_dir = std::env::current_dir().unwrap();
+        program_dir.join(TEST_PATH)
+    }
+
+    fn filters() -> Filters {
+        Filters {
+            include_dbs: vec!["flvtest".to_owned()],
+            ..Default::default()
+        }
+    }
+
+    fn build_replication(port: u16) -> Replication {
+        Replication {
+            ip_or_host: "127.0.0.1".to_owned(),
+            port: Some(port),
+            user: "fluvio".to_owned(),
+            password: Some("fluvio4cdc!".to_owned()),
+            server_id: None,
+            relay_dir: Some(get_base_dir().join(RELAY_DIR)),
+        }
+    }
+
+    fn build_profile(replication: &Replication) -> Profile {
+        let base_path = get_base_dir();
+        Profile {
+            mysql_resource_name: "mysql_resource".to_owned(),
+            data: Data {
+                base_path: base_path.clone(),
+                binlog_index_file: base_path.join("binlog.index"),
+                resume_offset_file: base_path.join(RESUME_OFFSET),
+                local_store_file: base_path.join(RPL_STORE),
+            },
+            source: Some(Source::Replication(replication.clone())),
+            filters: Some(filters()),
+            masking: None,
+            fluvio: None,
+        }
+    }
+
+    /// Messages produced by parsing the binlog files directly
+    fn file_mode_messages() -> Vec<String> {
+        let (sender, receiver) = unbounded::<String>();
+        let store_path = get_base_dir().join(FILE_STORE);
+        let mut state = ParserState {
+            urn: "mysql_resource".to_owned(),
+            filters: Some(filters()),
+            masking: None,
+            local_store: LocalStore::new(&store_path).unwrap(),
+            gtids: Default::default(),
+            pending: None,
+        };
+
+        for file in BL_FILES.iter() {
+            let path = get_base_dir().join(file);
+            parse_records_from_file(&sender, path.to_str().unwrap(), file, None, &mut state)
+                .unwrap();
+        }
+        let _ = fs::remove_file(&store_path);
+
+        receiver.try_iter().collect()
+    }
+
+    #[test]
+    fn test_replication_stream_matches_files() {
+        let port = fake_server::start(get_base_dir(), &BL_FILES).unwrap();
+        let replication = build_replication(port);
+        let profile = build_profile(&replication);
+        let _ = fs::remove_dir_all(get_base_dir().join(RELAY_DIR));
+
+        let (sender, receiver) = unbounded::<String>();
+        let mut manager = ReplicationManager::new(&profile, replication, sender).unwrap();
+        let resume = Resume::empty(get_base_dir().join(RESUME_OFFSET)).unwrap();
+        let result = manager.inner_run(&resume);
+        if let Err(err) = &result {
+            println!("{:?}", err);
+        }
+        assert!(result.is_ok());
+
+        // relay files are identical to the source files
+        for file in BL_FILES.iter() {
+            let source = fs::read(get_base_dir().join(file)).unwrap();
+            let relay = fs::read(get_base_dir().join(RELAY_DIR).join(file)).unwrap();
+            assert_eq!(relay, source);
+        }
+
+        // same messages as file mode
+        let messages: Vec<String> = receiver.try_iter().collect();
+        assert!(!messages.is_empty());
+        assert_eq!(messages, file_mode_messages());
+
+        fs::remove_dir_all(get_base_dir().join(RELAY_DIR)).expect("delete relay dir failed");
+        let _ = fs::remove_file(profile.local_store_file());
+    }
+
+    #[test]
+    fn test_reconnect_delay() {
+        assert_eq!(reconnect_delay(1), Duration::from_millis(2000));
+        assert_eq!(reconnect_delay(2), Duration::from_millis(4000));
+        assert_eq!(reconnect_delay(5), Duration::from_millis(32000));
+        assert_eq!(reconnect_delay(6), Duration::from_millis(60000));
+        assert_eq!(reconnect_delay(100), Duration::from_millis(60000));
+    }
+}
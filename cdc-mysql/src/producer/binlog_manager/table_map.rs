@@ -0,0 +1,353 @@
+/// TableMap
+///
+/// Decoder for TABLE_MAP events, written before the rows events of a table to describe
+/// its columns:
+///     post header: table_id (6) | flags (2)
+///     body: db_len (1) | db | 0x00 | table_len (1) | table | 0x00 | column_count (lenenc)
+///           | column_types (column_count) | metadata_len (lenenc) | metadata
+///           | null_bitmap ((column_count + 7) / 8) | optional metadata
+///
+/// Optional metadata (MySQL 8.0.1+) is a list of type (1) | length (lenenc) | value fields.
+/// Signedness is always written, column names and primary key only with
+/// `binlog_row_metadata=FULL`.
+///
+use super::raw_event::{le_u16, le_u32, le_u64, EVENT_HEADER_LEN};
+use crate::messages::ColumnType;
+
+const TABLE_MAP_POST_HEADER_LEN: usize = 8;
+
+// optional metadata fields
+const SIGNEDNESS: u8 = 1;
+const COLUMN_NAME: u8 = 4;
+const SIMPLE_PRIMARY_KEY: u8 = 8;
+const PRIMARY_KEY_WITH_PREFIX: u8 = 9;
+
+// column types
+const MYSQL_TYPE_DECIMAL: u8 = 0;
+const MYSQL_TYPE_TINY: u8 = 1;
+const MYSQL_TYPE_SHORT: u8 = 2;
+const MYSQL_TYPE_LONG: u8 = 3;
+const MYSQL_TYPE_FLOAT: u8 = 4;
+const MYSQL_TYPE_DOUBLE: u8 = 5;
+const MYSQL_TYPE_NULL: u8 = 6;
+const MYSQL_TYPE_TIMESTAMP: u8 = 7;
+const MYSQL_TYPE_LONGLONG: u8 = 8;
+const MYSQL_TYPE_INT24: u8 = 9;
+const MYSQL_TYPE_DATE: u8 = 10;
+const MYSQL_TYPE_TIME: u8 = 11;
+const MYSQL_TYPE_DATETIME: u8 = 12;
+const MYSQL_TYPE_YEAR: u8 = 13;
+const MYSQL_TYPE_NEWDATE: u8 = 14;
+const MYSQL_TYPE_VARCHAR: u8 = 15;
+const MYSQL_TYPE_BIT: u8 = 16;
+const MYSQL_TYPE_TIMESTAMP2: u8 = 17;
+const MYSQL_TYPE_DATETIME2: u8 = 18;
+const MYSQL_TYPE_TIME2: u8 = 19;
+const MYSQL_TYPE_JSON: u8 = 245;
+const MYSQL_TYPE_NEWDECIMAL: u8 = 246;
+const MYSQL_TYPE_ENUM: u8 = 247;
+const MYSQL_TYPE_SET: u8 = 248;
+const MYSQL_TYPE_TINY_BLOB: u8 = 249;
+const MYSQL_TYPE_MEDIUM_BLOB: u8 = 250;
+const MYSQL_TYPE_LONG_BLOB: u8 = 251;
+const MYSQL_TYPE_BLOB: u8 = 252;
+const MYSQL_TYPE_VAR_STRING: u8 = 253;
+const MYSQL_TYPE_STRING: u8 = 254;
+const MYSQL_TYPE_GEOMETRY: u8 = 255;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TableMap {
+    pub db_name: String,
+    pub table_name: String,
+    pub column_types: Vec<ColumnType>,
+
+    /// only written with binlog_row_metadata=FULL
+    pub column_names: Option<Vec<String>>,
+}
+
+pub fn parse_table_map(event: &[u8], checksum_len: usize) -> Option<TableMap> {
+    let end = event.len().checked_sub(checksum_len)?;
+    let mut body = Body {
+        data: event.get(..end)?,
+        pos: EVENT_HEADER_LEN + TABLE_MAP_POST_HEADER_LEN,
+    };
+
+    let db_len = body.u8()? as usize;
+    let db_name = body.string(db_len)?;
+    body.skip(1)?;
+    let table_len = body.u8()? as usize;
+    let table_name = body.string(table_len)?;
+    body.skip(1)?;
+
+    let column_count = body.lenenc()? as usize;
+    let types = body.bytes(column_count)?.to_vec();
+
+    let metadata_len = body.lenenc()? as usize;
+    let mut metadata = Body {
+        data: body.bytes(metadata_len)?,
+        pos: 0,
+    };
+    let null_bitmap = body.bytes(bitmap_len(column_count))?;
+
+    let mut column_types = Vec::with_capacity(column_count);
+    for (idx, type_code) in types.iter().enumerate() {
+        let meta = metadata.bytes(meta_len(*type_code))?;
+        let nullable = null_bitmap[idx / 8] & (1 << (idx % 8)) != 0;
+        column_types.push(ColumnType::new(&type_name(*type_code, meta), nullable));
+    }
+
+    let mut column_names = None;
+    while body.remaining() > 0 {
+        let field_type = body.u8()?;
+        let field_len = body.lenenc()? as usize;
+        let mut field = Body {
+            data: body.bytes(field_len)?,
+            pos: 0,
+        };
+
+        match field_type {
+            SIGNEDNESS => {
+                let numeric = types.iter().enumerate().filter(|(_, t)| is_numeric(**t));
+                for (bit, (idx, _)) in numeric.enumerate() {
+                    let byte = field.data.get(bit / 8)?;
+                    column_types[idx].unsigned = byte & (0x80 >> (bit % 8)) != 0;
+                }
+            }
+            COLUMN_NAME => {
+                let mut names = vec![];
+                while field.remaining() > 0 {
+                    let len = field.lenenc()? as usize;
+                    names.push(field.string(len)?);
+                }
+                column_names = Some(names);
+            }
+            SIMPLE_PRIMARY_KEY | PRIMARY_KEY_WITH_PREFIX => {
+                while field.remaining() > 0 {
+                    let idx = field.lenenc()? as usize;
+                    if field_type == PRIMARY_KEY_WITH_PREFIX {
+                        field.lenenc()?;
+                    }
+                    column_types.get_mut(idx)?.primary_key = true;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    Some(TableMap {
+        db_name,
+        table_name,
+        column_types,
+        column_names,
+    })
+}
+
+/// Bytes of a bitmap with one bit per column
+fn bitmap_len(column_count: usize) -> usize {
+    (column_count + 7) >> 3
+}
+
+/// Metadata bytes of each column type
+fn meta_len(type_code: u8) -> usize {
+    match type_code {
+        MYSQL_TYPE_FLOAT | MYSQL_TYPE_DOUBLE => 1,
+        MYSQL_TYPE_TIMESTAMP2 | MYSQL_TYPE_DATETIME2 | MYSQL_TYPE_TIME2 => 1,
+        MYSQL_TYPE_TINY_BLOB..=MYSQL_TYPE_BLOB | MYSQL_TYPE_GEOMETRY | MYSQL_TYPE_JSON => 1,
+        MYSQL_TYPE_VARCHAR | MYSQL_TYPE_VAR_STRING | MYSQL_TYPE_STRING => 2,
+        MYSQL_TYPE_BIT | MYSQL_TYPE_NEWDECIMAL | MYSQL_TYPE_ENUM | MYSQL_TYPE_SET => 2,
+        _ => 0,
+    }
+}
+
+fn is_numeric(type_code: u8) -> bool {
+    matches!(
+        type_code,
+        MYSQL_TYPE_DECIMAL
+            | MYSQL_TYPE_TINY
+            | MYSQL_TYPE_SHORT
+            | MYSQL_TYPE_LONG
+            | MYSQL_TYPE_FLOAT
+            | MYSQL_TYPE_DOUBLE
+            | MYSQL_TYPE_LONGLONG
+            | MYSQL_TYPE_INT24
+            | MYSQL_TYPE_NEWDECIMAL
+    )
+}
+
+/// Type name, text columns are reported as blobs (binlog does not tell them apart)
+fn type_name(type_code: u8, meta: &[u8]) -> String {
+    let name = match type_code {
+        MYSQL_TYPE_DECIMAL => "decimal",
+        MYSQL_TYPE_TINY => "tinyint",
+        MYSQL_TYPE_SHORT => "smallint",
+        MYSQL_TYPE_LONG => "int",
+        MYSQL_TYPE_FLOAT => "float",
+        MYSQL_TYPE_DOUBLE => "double",
+        MYSQL_TYPE_NULL => "null",
+        MYSQL_TYPE_TIMESTAMP => "timestamp",
+        MYSQL_TYPE_LONGLONG => "bigint",
+        MYSQL_TYPE_INT24 => "mediumint",
+        MYSQL_TYPE_DATE | MYSQL_TYPE_NEWDATE => "date",
+        MYSQL_TYPE_TIME => "time",
+        MYSQL_TYPE_DATETIME => "datetime",
+        MYSQL_TYPE_YEAR => "year",
+        MYSQL_TYPE_VARCHAR | MYSQL_TYPE_VAR_STRING => "varchar",
+        MYSQL_TYPE_BIT => "bit",
+        MYSQL_TYPE_TIMESTAMP2 => return with_fsp("timestamp", meta[0]),
+        MYSQL_TYPE_DATETIME2 => return with_fsp("datetime", meta[0]),
+        MYSQL_TYPE_TIME2 => return with_fsp("time", meta[0]),
+        MYSQL_TYPE_JSON => "json",
+        MYSQL_TYPE_NEWDECIMAL => return format!("decimal({},{})", meta[0], meta[1]),
+        MYSQL_TYPE_ENUM => "enum",
+        MYSQL_TYPE_SET => "set",
+        MYSQL_TYPE_TINY_BLOB..=MYSQL_TYPE_BLOB => match meta[0] {
+            1 => "tinyblob",
+            3 => "mediumblob",
+            4 => "longblob",
+            _ => "blob",
+        },
+        // enum and set columns are written as strings, real type in metadata
+        MYSQL_TYPE_STRING => match meta[0] {
+            MYSQL_TYPE_ENUM => "enum",
+            MYSQL_TYPE_SET => "set",
+            _ => "char",
+        },
+        MYSQL_TYPE_GEOMETRY => "geometry",
+        _ => "unknown",
+    };
+
+    name.to_owned()
+}
+
+fn with_fsp(name: &str, fsp: u8) -> String {
+    if fsp == 0 {
+        name.to_owned()
+    } else {
+        format!("{}({})", name, fsp)
+    }
+}
+
+/// Bounds checked reader of an event body
+struct Body<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Body<'a> {
+    fn remaining(&self) -> usize {
+        self.data.len().saturating_sub(self.pos)
+    }
+
+    fn bytes(&mut self, len: usize) -> Option<&'a [u8]> {
+        let bytes = self.data.get(self.pos..self.pos + len)?;
+        self.pos += len;
+        Some(bytes)
+    }
+
+    fn skip(&mut self, len: usize) -> Option<()> {
+        self.bytes(len).map(|_| ())
+    }
+
+    fn u8(&mut self) -> Option<u8> {
+        self.bytes(1).map(|bytes| bytes[0])
+    }
+
+    fn string(&mut self, len: usize) -> Option<String> {
+        self.bytes(len)
+            .map(|bytes| String::from_utf8_lossy(bytes).to_string())
+    }
+
+    /// Length encoded integer
+    fn lenenc(&mut self) -> Option<u64> {
+        match self.u8()? {
+            0xfc => self.bytes(2).map(|bytes| le_u16(bytes, 0) as u64),
+            0xfd => self
+                .bytes(3)
+                .map(|bytes| le_u32(&[bytes[0], bytes[1], bytes[2], 0], 0) as u64),
+            0xfe => self.bytes(8).map(|bytes| le_u64(bytes, 0)),
+            value => Some(value as u64),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+    use std::path::PathBuf;
+
+    use super::*;
+    use crate::producer::binlog_manager::raw_event::{EventHeader, CHECKSUM_LEN};
+
+    const TEST_PATH: &str = "test_files";
+    const BL_FILE3: &str = "binlog.000003";
+    const PET_TABLE_MAP: usize = 780;
+
+    fn get_base_dir() -> PathBuf {
+        let program_dir = std::env::current_dir().unwrap();
+        program_dir.join(TEST_PATH)
+    }
+
+    fn event_at(bytes: &[u8], offset: usize) -> &[u8] {
+        let size = EventHeader::parse(&bytes[offset..]).unwrap().event_size as usize;
+        &bytes[offset..offset + size]
+    }
+
+    #[test]
+    fn test_table_map_minimal() {
+        // pet (name VARCHAR(20), owner VARCHAR(20), species VARCHAR(20), sex CHAR(1), birth DATE)
+        let bytes = fs::read(get_base_dir().join(BL_FILE3)).unwrap();
+        let table_map = parse_table_map(event_at(&bytes, PET_TABLE_MAP), CHECKSUM_LEN).unwrap();
+
+        assert_eq!(table_map.db_name, "flvTest");
+        assert_eq!(table_map.table_name, "pet");
+        assert_eq!(table_map.column_names, None);
+
+        let types: Vec<&str> = table_map
+            .column_types
+            .iter()
+            .map(|column| column.data_type.as_str())
+            .collect();
+        assert_eq!(types, vec!["varchar", "varchar", "varchar", "char", "date"]);
+        assert!(table_map.column_types.iter().all(|column| column.nullable));
+    }
+
+    #[test]
+    fn test_table_map_full() {
+        // orders (id INT UNSIGNED PRIMARY KEY, total DECIMAL(10,2) NOT NULL, at DATETIME(3))
+        let mut event = vec![0; EVENT_HEADER_LEN];
+        event[4] = 19;
+        event.extend_from_slice(&[1, 0, 0, 0, 0, 0, 1, 0]);
+        event.extend_from_slice(b"\x04shop\0\x06orders\0");
+        event.extend_from_slice(&[3, MYSQL_TYPE_LONG, MYSQL_TYPE_NEWDECIMAL, 18]);
+        event.extend_from_slice(&[3, 10, 2, 3]);
+        event.extend_from_slice(&[0b100]);
+        event.extend_from_slice(&[SIGNEDNESS, 1, 0b1000_0000]);
+        event.extend_from_slice(&[COLUMN_NAME, 12, 2, b'i', b'd']);
+        event.extend_from_slice(b"\x05total\x02at");
+        event.extend_from_slice(&[SIMPLE_PRIMARY_KEY, 1, 0]);
+        event.extend_from_slice(&[0; CHECKSUM_LEN]);
+
+        let table_map = parse_table_map(&event, CHECKSUM_LEN).unwrap();
+        assert_eq!(table_map.db_name, "shop");
+        assert_eq!(table_map.table_name, "orders");
+        assert_eq!(
+            table_map.column_names,
+            Some(vec!["id".to_owned(), "total".to_owned(), "at".to_owned()])
+        );
+
+        let mut id = ColumnType::new("int", false);
+        id.unsigned = true;
+        id.primary_key = true;
+        assert_eq!(
+            table_map.column_types,
+            vec![
+                id,
+                ColumnType::new("decimal(10,2)", false),
+                ColumnType::new("datetime(3)", true),
+            ]
+        );
+
+        // truncated event
+        assert!(parse_table_map(&event[..40], CHECKSUM_LEN).is_none());
+    }
+}
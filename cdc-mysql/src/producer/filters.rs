@@ -0,0 +1,204 @@
+//!
+//! # Producer filters
+//!
+//! Databases (`db`) and tables (`db.table`) to capture, names may use `*` and `?` wildcards:
+//!  - include_dbs/include_tables: when any is set, only matching databases/tables are captured
+//!  - exclude_dbs/exclude_tables: matching databases/tables are skipped, even if included
+//!
+//! Table patterns without a database (`audit_log`) match the table in all databases.
+//!
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Default, PartialEq, Serialize, Deserialize, Clone)]
+pub struct Filters {
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub include_dbs: Vec<String>,
+
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub exclude_dbs: Vec<String>,
+
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub include_tables: Vec<String>,
+
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub exclude_tables: Vec<String>,
+}
+
+impl Filters {
+    pub fn normalize(&mut self) {
+        for name in self
+            .include_dbs
+            .iter_mut()
+            .chain(self.exclude_dbs.iter_mut())
+            .chain(self.include_tables.iter_mut())
+            .chain(self.exclude_tables.iter_mut())
+        {
+            name.make_ascii_lowercase();
+        }
+    }
+
+    /// Statements that apply to the database (no table)
+    ///  - included by database, or by a table pattern of the database
+    ///  - excluded by database only
+    pub fn allows_db(&self, db_name: &str) -> bool {
+        let included = !self.has_includes()
+            || matches_any(&self.include_dbs, db_name)
+            || self
+                .include_tables
+                .iter()
+                .any(|pattern| glob_match(split_table_pattern(pattern).0, db_name));
+        let excluded = matches_any(&self.exclude_dbs, db_name);
+
+        included && !excluded
+    }
+
+    pub fn allows_table(&self, db_name: &str, table_name: &str) -> bool {
+        let included = !self.has_includes()
+            || matches_any(&self.include_dbs, db_name)
+            || matches_any_table(&self.include_tables, db_name, table_name);
+        let excluded = matches_any(&self.exclude_dbs, db_name)
+            || matches_any_table(&self.exclude_tables, db_name, table_name);
+
+        included && !excluded
+    }
+
+    fn has_includes(&self) -> bool {
+        !self.include_dbs.is_empty() || !self.include_tables.is_empty()
+    }
+}
+
+fn matches_any(patterns: &[String], name: &str) -> bool {
+    patterns.iter().any(|pattern| glob_match(pattern, name))
+}
+
+fn matches_any_table(patterns: &[String], db_name: &str, table_name: &str) -> bool {
+    patterns
+        .iter()
+        .any(|pattern| matches_table(pattern, db_name, table_name))
+}
+
+/// Match db and table names against a "db.table" pattern
+pub(crate) fn matches_table(pattern: &str, db_name: &str, table_name: &str) -> bool {
+    let (db_pattern, table_pattern) = split_table_pattern(pattern);
+    glob_match(db_pattern, db_name) && glob_match(table_pattern, table_name)
+}
+
+/// "db.table" => ("db", "table"), "table" => ("*", "table")
+fn split_table_pattern(pattern: &str) -> (&str, &str) {
+    match pattern.find('.') {
+        Some(pos) => (&pattern[..pos], &pattern[pos + 1..]),
+        None => ("*", pattern),
+    }
+}
+
+/// Match name against pattern with `*` (any sequence) and `?` (any character)
+fn glob_match(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+
+    let (mut p, mut n) = (0, 0);
+    let mut backtrack: Option<(usize, usize)> = None;
+
+    while n < name.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == name[n]) {
+            p += 1;
+            n += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            backtrack = Some((p, n));
+            p += 1;
+        } else if let Some((star_p, star_n)) = backtrack {
+            // let the last '*' absorb one more character
+            p = star_p + 1;
+            n = star_n + 1;
+            backtrack = Some((star_p, star_n + 1));
+        } else {
+            return false;
+        }
+    }
+
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn to_vec(names: &[&str]) -> Vec<String> {
+        names.iter().map(|name| name.to_string()).collect()
+    }
+
+    #[test]
+    fn test_glob_match() {
+        assert!(glob_match("shop", "shop"));
+        assert!(!glob_match("shop", "shops"));
+        assert!(glob_match("order_*", "order_items"));
+        assert!(glob_match("order_*", "order_"));
+        assert!(!glob_match("order_*", "orders"));
+        assert!(glob_match("*_log", "audit_log"));
+        assert!(glob_match("*a*b", "xaxxab"));
+        assert!(glob_match("t?", "t1"));
+        assert!(!glob_match("t?", "t12"));
+        assert!(glob_match("*", ""));
+    }
+
+    #[test]
+    fn test_normalize_filter() {
+        let mut filters = Filters {
+            include_dbs: to_vec(&["flvDb", "FLUVIO_DB"]),
+            exclude_tables: to_vec(&["Shop.Order_*"]),
+            ..Default::default()
+        };
+        filters.normalize();
+        assert_eq!(filters.include_dbs, to_vec(&["flvdb", "fluvio_db"]));
+        assert_eq!(filters.exclude_tables, to_vec(&["shop.order_*"]));
+    }
+
+    #[test]
+    fn test_db_filters() {
+        let filters = Filters::default();
+        assert!(filters.allows_db("shop"));
+        assert!(filters.allows_table("shop", "orders"));
+
+        let filters = Filters {
+            include_dbs: to_vec(&["shop*"]),
+            exclude_dbs: to_vec(&["shop_archive"]),
+            ..Default::default()
+        };
+        assert!(filters.allows_db("shop"));
+        assert!(filters.allows_db("shop_eu"));
+        assert!(!filters.allows_db("shop_archive"));
+        assert!(!filters.allows_db("crm"));
+        assert!(filters.allows_table("shop_eu", "orders"));
+        assert!(!filters.allows_table("shop_archive", "orders"));
+    }
+
+    #[test]
+    fn test_table_filters() {
+        let filters = Filters {
+            include_tables: to_vec(&["shop.order_*", "crm.customers"]),
+            exclude_tables: to_vec(&["*.audit_log", "shop.order_tmp"]),
+            ..Default::default()
+        };
+
+        assert!(filters.allows_table("shop", "order_items"));
+        assert!(!filters.allows_table("shop", "order_tmp"));
+        assert!(!filters.allows_table("shop", "products"));
+        assert!(filters.allows_table("crm", "customers"));
+        assert!(!filters.allows_table("crm", "audit_log"));
+
+        // database statements follow the database part of table patterns
+        assert!(filters.allows_db("shop"));
+        assert!(filters.allows_db("crm"));
+        assert!(!filters.allows_db("hr"));
+
+        // include by database, exclude by table
+        let filters = Filters {
+            include_dbs: to_vec(&["shop"]),
+            exclude_tables: to_vec(&["audit_log"]),
+            ..Default::default()
+        };
+        assert!(filters.allows_table("shop", "orders"));
+        assert!(!filters.allows_table("shop", "audit_log"));
+        assert!(filters.allows_db("shop"));
+    }
+}
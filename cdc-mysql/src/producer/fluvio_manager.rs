@@ -1,12 +1,20 @@
 use crate::error::CdcError;
-use crate::messages::{BinLogMessage, BnFile, FluvioMessage};
+use crate::messages::{cloud_events, debezium_records, BinLogMessage, BnFile};
+use crate::messages::{CloudEvent, DebeziumRecord, FluvioMessage};
+use crate::producer::Envelope;
 use fluvio::{FluvioError, Offset, PartitionConsumer, TopicProducer};
+use std::time::{SystemTime, UNIX_EPOCH};
 use tracing::instrument;
 
+/// Records fetched at once when looking back for the last complete message
+const SCAN_RECORDS: u32 = 16;
+
 pub struct FluvioManager {
     producer: TopicProducer,
     consumer: PartitionConsumer,
     sequence: u64,
+    envelope: Envelope,
+    interrupted: bool,
 }
 
 impl FluvioManager {
@@ -14,6 +22,7 @@ impl FluvioManager {
         topic: String,
         _replicas: i16,
         sequence: Option<u64>,
+        envelope: Envelope,
     ) -> Result<Self, CdcError> {
         let sequence = sequence.unwrap_or(0);
         let producer = fluvio::producer(&topic).await?;
@@ -23,38 +32,194 @@ impl FluvioManager {
             producer,
             consumer,
             sequence,
+            envelope,
+            interrupted: false,
         })
     }
 
+    /// Sequence of the next message
+    pub fn sequence(&self) -> u64 {
+        self.sequence
+    }
+
+    /// Last message in the topic was interrupted between its records (debezium and
+    /// cloudevents envelopes),
+    /// it is sent again, with the same sequence, after the last complete message
+    pub fn interrupted(&self) -> bool {
+        self.interrupted
+    }
+
+    /// Position of the last message in the topic, the sequence continues after it. When the
+    /// last message was interrupted, position of the complete message before it.
     #[instrument(skip(self))]
     pub async fn get_last_file_offset(&mut self) -> Result<Option<BnFile>, CdcError> {
+        match self.envelope {
+            Envelope::Native => {
+                let record = get_last_record(&self.consumer).await?;
+                if let Some(json_msg) = record {
+                    let flv_message = FluvioMessage::decode(&json_msg)?;
+                    self.sequence = flv_message.sequence + 1;
+
+                    Ok(Some(flv_message.bn_file))
+                } else {
+                    Ok(None)
+                }
+            }
+            Envelope::Debezium => self.get_last_debezium_offset().await,
+            Envelope::CloudEvents => self.get_last_cloud_event_offset().await,
+        }
+    }
+
+    /// Position of the last debezium record with a source block (tombstones follow their
+    /// delete), the position is only on the last event of each message
+    async fn get_last_debezium_offset(&mut self) -> Result<Option<BnFile>, CdcError> {
+        let mut records = fetch_records(&self.consumer, Offset::from_end(1)).await?;
+        if let Some(json_msg) = records.last() {
+            let record: DebeziumRecord = serde_json::from_str(json_msg)?;
+            if record.is_tombstone() {
+                records = fetch_records(&self.consumer, Offset::from_end(2)).await?;
+            }
+        }
+
+        for json_msg in records.iter().rev() {
+            let record: DebeziumRecord = serde_json::from_str(json_msg)?;
+            if let Some(source) = record.source() {
+                return match source.bn_file.as_ref() {
+                    Some(bn_file) => {
+                        self.sequence = source.sequence + 1;
+                        Ok(Some(bn_file.clone()))
+                    }
+                    None => {
+                        self.sequence = source.sequence;
+                        self.interrupted = true;
+                        self.last_complete_offset(debezium_position).await
+                    }
+                };
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Position of the last cloud event, the position is only on the last event of each message
+    async fn get_last_cloud_event_offset(&mut self) -> Result<Option<BnFile>, CdcError> {
         let record = get_last_record(&self.consumer).await?;
         if let Some(json_msg) = record {
-            let flv_message: FluvioMessage = serde_json::from_str(&json_msg)?;
-            self.sequence = flv_message.sequence + 1;
-
-            Ok(Some(flv_message.bn_file))
+            let event: CloudEvent = serde_json::from_str(&json_msg)?;
+            match event.bn_file()? {
+                Some(bn_file) => {
+                    self.sequence = event.sequence()? + 1;
+                    Ok(Some(bn_file))
+                }
+                None => {
+                    self.sequence = event.sequence()?;
+                    self.interrupted = true;
+                    self.last_complete_offset(cloud_event_position).await
+                }
+            }
         } else {
             Ok(None)
         }
     }
 
+    /// Position of the last message before the interrupted one (with sequence self.sequence),
+    /// None if the topic has none. The topic is read backwards from its end, position gives
+    /// the sequence and position of a record, if it carries one.
+    async fn last_complete_offset(
+        &self,
+        position: fn(&str) -> Result<Option<(u64, BnFile)>, CdcError>,
+    ) -> Result<Option<BnFile>, CdcError> {
+        let mut count = SCAN_RECORDS;
+        loop {
+            let mut records = fetch_records(&self.consumer, Offset::from_end(count)).await?;
+            let from_start = records.len() < count as usize;
+            if from_start {
+                records = fetch_records(&self.consumer, Offset::beginning()).await?;
+            }
+
+            for json_msg in records.iter().rev() {
+                if let Some((sequence, bn_file)) = position(json_msg)? {
+                    if sequence < self.sequence {
+                        return Ok(Some(bn_file));
+                    }
+                }
+            }
+            if from_start {
+                return Ok(None);
+            }
+            count *= 2;
+        }
+    }
+
+    /// Publish the records of a message, returns false if the message has none (rows events
+    /// without rows in the debezium and cloudevents envelopes): the sequence is not used, and
+    /// the topic has no position of the message.
     #[instrument(skip(self, bn_message))]
-    pub async fn process_msg(&mut self, bn_message: BinLogMessage) -> Result<(), CdcError> {
-        let flv_message = FluvioMessage::new(bn_message, self.sequence);
-        let msg = serde_json::to_string(&flv_message).unwrap();
-        self.producer.send_record(msg, 0).await?;
+    pub async fn process_msg(&mut self, bn_message: BinLogMessage) -> Result<bool, CdcError> {
+        let msgs = match self.envelope {
+            Envelope::Native => {
+                let flv_message = FluvioMessage::new(bn_message, self.sequence);
+                vec![serde_json::to_string(&flv_message).unwrap()]
+            }
+            Envelope::Debezium => debezium_records(&bn_message, self.sequence, now_ms())?
+                .iter()
+                .map(|record| serde_json::to_string(record).unwrap())
+                .collect(),
+            Envelope::CloudEvents => cloud_events(&bn_message, self.sequence)?
+                .iter()
+                .map(|event| serde_json::to_string(event).unwrap())
+                .collect(),
+        };
+        if msgs.is_empty() {
+            return Ok(false);
+        }
+        for msg in msgs {
+            self.producer.send_record(msg, 0).await?;
+        }
 
         // increment sequence
         self.sequence += 1;
 
-        Ok(())
+        Ok(true)
     }
 }
 
+/// Sequence and position of a debezium record, on the last event of its message
+fn debezium_position(json_msg: &str) -> Result<Option<(u64, BnFile)>, CdcError> {
+    let record: DebeziumRecord = serde_json::from_str(json_msg)?;
+    Ok(record
+        .source()
+        .and_then(|source| Some((source.sequence, source.bn_file.clone()?))))
+}
+
+/// Sequence and position of a cloud event, on the last event of its message
+fn cloud_event_position(json_msg: &str) -> Result<Option<(u64, BnFile)>, CdcError> {
+    let event: CloudEvent = serde_json::from_str(json_msg)?;
+    match event.bn_file()? {
+        Some(bn_file) => Ok(Some((event.sequence()?, bn_file))),
+        None => Ok(None),
+    }
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_millis() as u64)
+}
+
 #[instrument(skip(consumer))]
 pub async fn get_last_record(consumer: &PartitionConsumer) -> Result<Option<String>, FluvioError> {
-    let response = consumer.fetch(Offset::end()).await?;
+    let records = fetch_records(consumer, Offset::from_end(1)).await?;
+    Ok(records.into_iter().last())
+}
+
+/// Records from offset to the end of the topic
+async fn fetch_records(
+    consumer: &PartitionConsumer,
+    offset: Offset,
+) -> Result<Vec<String>, FluvioError> {
+    let response = consumer.fetch(offset).await?;
+    let mut records = vec![];
 
     if !response.error_code.is_ok() {
         if response.error_code.to_sentence() == "OffsetOutOfRange" {
@@ -65,13 +230,14 @@ pub async fn get_last_record(consumer: &PartitionConsumer) -> Result<Option<Stri
                 response.error_code.to_sentence()
             );
         }
-    } else if let Some(batch) = response.records.batches.first() {
-        if let Some(record) = batch.records().first() {
-            let bytes = record.value().as_ref();
-            let msg = String::from_utf8(bytes.to_vec()).unwrap();
-            return Ok(Some(msg));
+    } else {
+        for batch in response.records.batches.iter() {
+            for record in batch.records().iter() {
+                let bytes = record.value().as_ref();
+                records.push(String::from_utf8(bytes.to_vec()).unwrap());
+            }
         }
     }
 
-    Ok(None)
+    Ok(records)
 }
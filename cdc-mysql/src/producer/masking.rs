@@ -0,0 +1,342 @@
+//!
+//! # Column masking
+//!
+//! Per table column rules applied to row events before they are published:
+//!  - drop: remove the column (name and values)
+//!  - null: replace values with Null
+//!  - hash: replace values with the hex SHA-256 of salt + value
+//!  - truncate: keep the first `length` characters (strings) or bytes (blobs)
+//!
+//! Column types follow the masked values: nulled columns become nullable, hashed columns
+//! varchar(64), and masked columns are no longer part of the primary key.
+//!
+//! Tables are matched with the same `db.table` patterns as filters.
+//!
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::messages::{ColumnType, Value};
+use crate::producer::filters::matches_table;
+
+#[derive(Debug, Default, PartialEq, Serialize, Deserialize, Clone)]
+pub struct Masking {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub salt: Option<String>,
+
+    #[serde(default)]
+    pub rules: Vec<MaskRule>,
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize, Clone)]
+pub struct MaskRule {
+    pub table: String,
+    pub columns: Vec<String>,
+
+    #[serde(flatten)]
+    pub action: MaskAction,
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize, Clone)]
+#[serde(tag = "action", rename_all = "lowercase")]
+pub enum MaskAction {
+    Drop,
+    Null,
+    Hash,
+    Truncate { length: usize },
+}
+
+/// Masks of one table, aligned with the table columns
+#[derive(Debug)]
+pub struct TableMask<'a> {
+    actions: Vec<Option<&'a MaskAction>>,
+    salt: &'a str,
+}
+
+impl Masking {
+    pub fn normalize(&mut self) {
+        for rule in self.rules.iter_mut() {
+            rule.table.make_ascii_lowercase();
+            for column in rule.columns.iter_mut() {
+                column.make_ascii_lowercase();
+            }
+        }
+    }
+
+    /// Masks for the table columns, None if no rule applies to the table.
+    /// When several rules match a column, the first one wins.
+    pub fn table_mask(
+        &self,
+        db_name: &str,
+        table_name: &str,
+        columns: &[String],
+    ) -> Option<TableMask<'_>> {
+        let db_name = db_name.to_ascii_lowercase();
+        let table_name = table_name.to_ascii_lowercase();
+        let rules: Vec<&MaskRule> = self
+            .rules
+            .iter()
+            .filter(|rule| matches_table(&rule.table, &db_name, &table_name))
+            .collect();
+
+        let actions: Vec<Option<&MaskAction>> = columns
+            .iter()
+            .map(|column| {
+                let column = column.to_ascii_lowercase();
+                rules
+                    .iter()
+                    .find(|rule| rule.columns.contains(&column))
+                    .map(|rule| &rule.action)
+            })
+            .collect();
+
+        if actions.iter().all(|action| action.is_none()) {
+            return None;
+        }
+
+        Some(TableMask {
+            actions,
+            salt: self.salt.as_deref().unwrap_or(""),
+        })
+    }
+}
+
+impl<'a> TableMask<'a> {
+    /// Column names, without dropped columns
+    pub fn columns<T>(&self, columns: Vec<T>) -> Vec<T> {
+        columns
+            .into_iter()
+            .zip(self.actions.iter())
+            .filter(|(_, action)| **action != Some(&MaskAction::Drop))
+            .map(|(column, _)| column)
+            .collect()
+    }
+
+    /// Column types of the masked values, without dropped columns
+    pub fn column_types(&self, column_types: Vec<ColumnType>) -> Vec<ColumnType> {
+        let mut masked = vec![];
+
+        for (idx, column_type) in column_types.into_iter().enumerate() {
+            let action = match self.actions.get(idx).copied().flatten() {
+                None => {
+                    masked.push(column_type);
+                    continue;
+                }
+                Some(action) => action,
+            };
+
+            let column_type = match action {
+                MaskAction::Drop => continue,
+                MaskAction::Null => ColumnType {
+                    nullable: true,
+                    ..column_type
+                },
+                MaskAction::Hash => ColumnType::new(HASH_TYPE, column_type.nullable),
+                MaskAction::Truncate { .. } => column_type,
+            };
+            masked.push(ColumnType {
+                primary_key: false,
+                ..column_type
+            });
+        }
+
+        masked
+    }
+
+    /// Column at index has a mask
+    pub fn is_masked(&self, idx: usize) -> bool {
+        matches!(self.actions.get(idx), Some(Some(_)))
+    }
+
+    /// Row values with masks applied, without dropped columns
+    pub fn row(&self, cols: Vec<Value>) -> Vec<Value> {
+        let mut masked = vec![];
+
+        for (idx, value) in cols.into_iter().enumerate() {
+            match self.actions.get(idx).copied().flatten() {
+                None => masked.push(value),
+                Some(MaskAction::Drop) => {}
+                Some(MaskAction::Null) => masked.push(Value::Null),
+                Some(MaskAction::Hash) => masked.push(hash_value(self.salt, value)),
+                Some(MaskAction::Truncate { length }) => {
+                    masked.push(truncate_value(*length, value))
+                }
+            }
+        }
+
+        masked
+    }
+}
+
+/// Type of hashed values, hex SHA-256
+const HASH_TYPE: &str = "varchar(64)";
+
+fn hash_value(salt: &str, value: Value) -> Value {
+    let bytes = match value {
+        Value::Null => return Value::Null,
+        Value::String(val) => val.into_bytes(),
+        Value::Blob(bytes) => bytes,
+        other => other.to_string().trim_matches('"').as_bytes().to_vec(),
+    };
+
+    let mut hasher = Sha256::new();
+    hasher.update(salt.as_bytes());
+    hasher.update(&bytes);
+    let hex: String = hasher
+        .finalize()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect();
+
+    Value::String(hex)
+}
+
+fn truncate_value(length: usize, value: Value) -> Value {
+    match value {
+        Value::String(val) => Value::String(val.chars().take(length).collect()),
+        Value::Blob(mut bytes) => {
+            bytes.truncate(length);
+            Value::Blob(bytes)
+        }
+        other => other,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn to_vec(names: &[&str]) -> Vec<String> {
+        names.iter().map(|name| name.to_string()).collect()
+    }
+
+    fn build_masking() -> Masking {
+        Masking {
+            salt: Some("pepper".to_owned()),
+            rules: vec![
+                MaskRule {
+                    table: "shop.customers".to_owned(),
+                    columns: to_vec(&["password"]),
+                    action: MaskAction::Drop,
+                },
+                MaskRule {
+                    table: "shop.*".to_owned(),
+                    columns: to_vec(&["email"]),
+                    action: MaskAction::Hash,
+                },
+                MaskRule {
+                    table: "shop.customers".to_owned(),
+                    columns: to_vec(&["phone"]),
+                    action: MaskAction::Null,
+                },
+                MaskRule {
+                    table: "shop.customers".to_owned(),
+                    columns: to_vec(&["card"]),
+                    action: MaskAction::Truncate { length: 4 },
+                },
+            ],
+        }
+    }
+
+    #[test]
+    fn test_masking_from_toml() {
+        let toml_str = r#"
+            salt = "pepper"
+
+            [[rules]]
+            table = "shop.customers"
+            columns = ["card"]
+            action = "truncate"
+            length = 4
+
+            [[rules]]
+            table = "shop.*"
+            columns = ["email"]
+            action = "hash"
+        "#;
+        let masking: Masking = toml::from_str(toml_str).unwrap();
+
+        assert_eq!(masking.salt, Some("pepper".to_owned()));
+        assert_eq!(masking.rules[0].action, MaskAction::Truncate { length: 4 });
+        assert_eq!(masking.rules[1].action, MaskAction::Hash);
+    }
+
+    #[test]
+    fn test_table_mask() {
+        let masking = build_masking();
+        let columns = to_vec(&["id", "email", "password", "phone", "card"]);
+
+        assert!(masking.table_mask("crm", "customers", &columns).is_none());
+        assert!(masking
+            .table_mask("shop", "customers", &to_vec(&["id"]))
+            .is_none());
+
+        let mask = masking.table_mask("shop", "customers", &columns).unwrap();
+        assert_eq!(
+            mask.columns(columns),
+            to_vec(&["id", "email", "phone", "card"])
+        );
+
+        let row = mask.row(vec![
+            Value::SignedInteger(1),
+            Value::String("jane@example.com".to_owned()),
+            Value::String("secret".to_owned()),
+            Value::String("555-1234".to_owned()),
+            Value::String("4111111111111111".to_owned()),
+        ]);
+        assert_eq!(row.len(), 4);
+        assert_eq!(row[0], Value::SignedInteger(1));
+        assert_eq!(
+            row[1],
+            Value::String(
+                "2daebb07b6fe2686ef59cd504c8b776ad69262a4e97fbc978e5d7851bdc6fe15".to_owned()
+            )
+        );
+        assert_eq!(row[2], Value::Null);
+        assert_eq!(row[3], Value::String("4111".to_owned()));
+    }
+
+    #[test]
+    fn test_table_mask_column_types() {
+        let masking = build_masking();
+        let columns = to_vec(&["id", "email", "password", "phone", "card"]);
+        let mask = masking.table_mask("shop", "customers", &columns).unwrap();
+
+        let mut id = ColumnType::new("int", false);
+        id.unsigned = true;
+        id.primary_key = true;
+        let mut email = ColumnType::new("varchar(255)", false);
+        email.primary_key = true;
+        let column_types = vec![
+            id.clone(),
+            email,
+            ColumnType::new("varchar(64)", false),
+            ColumnType::new("varchar(20)", false),
+            ColumnType::new("char(16)", true),
+        ];
+
+        assert_eq!(
+            mask.column_types(column_types),
+            vec![
+                id,
+                ColumnType::new("varchar(64)", false),
+                ColumnType::new("varchar(20)", true),
+                ColumnType::new("char(16)", true),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_hash_value() {
+        // sha256("pepper" + "abc"), nulls are not hashed
+        let expected = hash_value("", Value::String("pepperabc".to_owned()));
+        assert_eq!(
+            hash_value("pepper", Value::String("abc".to_owned())),
+            expected
+        );
+        assert_eq!(hash_value("pepper", Value::Null), Value::Null);
+        assert_ne!(
+            hash_value("salt", Value::String("abc".to_owned())),
+            hash_value("pepper", Value::String("abc".to_owned()))
+        );
+    }
+}
@@ -1,15 +1,23 @@
 pub mod binlog_manager;
 pub mod cli;
+pub mod filters;
 pub mod fluvio_manager;
+pub mod masking;
 pub mod profile;
 
 pub use cli::get_cli_opt;
+pub use filters::Filters;
+pub use masking::Masking;
 pub use profile::Config;
 pub use profile::Data;
-pub use profile::Filters;
+pub use profile::Envelope;
 pub use profile::Fluvio;
 pub use profile::Profile;
+pub use profile::Replication;
+pub use profile::ResumeMismatch;
+pub use profile::Source;
 
 pub use binlog_manager::BinLogManager;
-pub use binlog_manager::Resume;
+pub use binlog_manager::ReplicationManager;
+pub use binlog_manager::{Resume, ResumeCheck};
 pub use fluvio_manager::FluvioManager;
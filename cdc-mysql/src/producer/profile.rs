@@ -6,10 +6,15 @@ use std::fs::read_to_string;
 use std::io::{Error, ErrorKind};
 use std::path::{Path, PathBuf};
 
+use crate::producer::{Filters, Masking};
 use crate::util::expand_tilde;
 
 const DEFAULT_TOPIC: &str = "rust-mysql-cdc";
 const DEFAULT_REPLICAS: i16 = 1;
+const DEFAULT_PORT: u16 = 3306;
+const DEFAULT_SERVER_ID: u32 = 1001;
+const DEFAULT_RELAY_DIR: &str = "relay";
+
 pub struct Config {
     profile: Profile,
 }
@@ -33,6 +38,10 @@ impl Config {
             filter.normalize();
         }
 
+        if let Some(masking) = profile.masking.as_mut() {
+            masking.normalize();
+        }
+
         if let Some(base_path) = expand_tilde(&profile.data.base_path) {
             profile.data.base_path = base_path;
             profile.data.binlog_index_file =
@@ -43,6 +52,14 @@ impl Config {
                 profile.data.base_path.join(profile.data.local_store_file);
         }
 
+        if let Some(Source::Replication(replication)) = profile.source.as_mut() {
+            let relay_dir = replication
+                .relay_dir
+                .take()
+                .unwrap_or_else(|| PathBuf::from(DEFAULT_RELAY_DIR));
+            replication.relay_dir = Some(profile.data.base_path.join(relay_dir));
+        }
+
         Ok(Self { profile })
     }
 
@@ -56,7 +73,9 @@ impl Config {
 pub struct Profile {
     pub mysql_resource_name: String,
     pub data: Data,
+    pub source: Option<Source>,
     pub filters: Option<Filters>,
+    pub masking: Option<Masking>,
     pub fluvio: Option<Fluvio>,
 }
 #[derive(Debug, Default, PartialEq, Serialize, Deserialize, Clone)]
@@ -67,27 +86,39 @@ pub struct Data {
     pub local_store_file: PathBuf,
 }
 
+/// Binlog source:
+///  - file: tail binlog files from the local mysql data directory (default)
+///  - replication: register as a replica and stream binlog events from the server
 #[derive(Debug, PartialEq, Serialize, Deserialize, Clone)]
-#[serde(untagged)]
-pub enum Filters {
-    Include { include_dbs: Vec<String> },
-    Exclude { exclude_dbs: Vec<String> },
+#[serde(tag = "mode", rename_all = "lowercase")]
+pub enum Source {
+    File,
+    Replication(Replication),
 }
 
-impl Filters {
-    fn normalize(&mut self) {
-        match self {
-            Filters::Include { include_dbs } => {
-                for name in include_dbs {
-                    name.make_ascii_lowercase();
-                }
-            }
-            Filters::Exclude { exclude_dbs } => {
-                for name in exclude_dbs {
-                    name.make_ascii_lowercase();
-                }
-            }
-        }
+#[derive(Debug, Default, PartialEq, Serialize, Deserialize, Clone)]
+pub struct Replication {
+    pub ip_or_host: String,
+    pub port: Option<u16>,
+    pub user: String,
+    pub password: Option<String>,
+    pub server_id: Option<u32>,
+    pub relay_dir: Option<PathBuf>,
+}
+
+impl Replication {
+    pub fn port(&self) -> u16 {
+        self.port.unwrap_or(DEFAULT_PORT)
+    }
+
+    pub fn server_id(&self) -> u32 {
+        self.server_id.unwrap_or(DEFAULT_SERVER_ID)
+    }
+
+    pub fn relay_dir(&self) -> PathBuf {
+        self.relay_dir
+            .clone()
+            .unwrap_or_else(|| PathBuf::from(DEFAULT_RELAY_DIR))
     }
 }
 
@@ -95,6 +126,42 @@ impl Filters {
 pub struct Fluvio {
     topic: String,
     replicas: Option<i16>,
+    resume_mismatch: Option<ResumeMismatch>,
+    envelope: Option<Envelope>,
+}
+
+/// Resume file and last message in the topic disagree at startup:
+///  - fail: refuse to start (default)
+///  - warn: log a warning and resume from the topic
+#[derive(Debug, PartialEq, Serialize, Deserialize, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum ResumeMismatch {
+    Fail,
+    Warn,
+}
+
+impl Default for ResumeMismatch {
+    fn default() -> Self {
+        Self::Fail
+    }
+}
+
+/// Shape of the records published to the topic:
+///  - native: one FluvioMessage per binlog message (default), read by the consumer
+///  - debezium: Debezium change events, one per row, with tombstones after deletes
+///  - cloudevents: CloudEvents 1.0 JSON events, one per row
+#[derive(Debug, PartialEq, Serialize, Deserialize, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum Envelope {
+    Native,
+    Debezium,
+    CloudEvents,
+}
+
+impl Default for Envelope {
+    fn default() -> Self {
+        Self::Native
+    }
 }
 
 impl Profile {
@@ -119,10 +186,18 @@ impl Profile {
         &self.mysql_resource_name
     }
 
+    pub fn source(&self) -> Source {
+        self.source.clone().unwrap_or(Source::File)
+    }
+
     pub fn filters(&self) -> Option<Filters> {
         self.filters.clone()
     }
 
+    pub fn masking(&self) -> Option<Masking> {
+        self.masking.clone()
+    }
+
     pub fn topic(&self) -> String {
         if let Some(fluvio) = &self.fluvio {
             fluvio.topic.clone()
@@ -139,11 +214,26 @@ impl Profile {
         }
         DEFAULT_REPLICAS
     }
+
+    pub fn resume_mismatch(&self) -> ResumeMismatch {
+        self.fluvio
+            .as_ref()
+            .and_then(|fluvio| fluvio.resume_mismatch)
+            .unwrap_or_default()
+    }
+
+    pub fn envelope(&self) -> Envelope {
+        self.fluvio
+            .as_ref()
+            .and_then(|fluvio| fluvio.envelope)
+            .unwrap_or_default()
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::producer::masking::{MaskAction, MaskRule};
 
     const TEST_PATH: &str = "test_files";
     const PROFILE_FULL: &str = "producer_profile_full.toml";
@@ -177,12 +267,33 @@ mod tests {
                 resume_offset_file: base_path.join(resume_offset_file.clone()),
                 local_store_file: base_path.join(local_store_file.clone()),
             },
-            filters: Some(Filters::Include {
+            source: Some(Source::Replication(Replication {
+                ip_or_host: "localhost".to_owned(),
+                port: Some(3080),
+                user: "fluvio".to_owned(),
+                password: Some("fluvio4cdc!".to_owned()),
+                server_id: Some(2001),
+                relay_dir: Some(base_path.join("relay-log")),
+            })),
+            filters: Some(Filters {
                 include_dbs: vec!["flvtest".to_owned()],
+                include_tables: vec!["shop.order_*".to_owned()],
+                exclude_tables: vec!["*.audit_log".to_owned()],
+                ..Default::default()
+            }),
+            masking: Some(Masking {
+                salt: Some("s3cr3t".to_owned()),
+                rules: vec![MaskRule {
+                    table: "shop.customers".to_owned(),
+                    columns: vec!["email".to_owned(), "phone".to_owned()],
+                    action: MaskAction::Hash,
+                }],
             }),
             fluvio: Some(Fluvio {
                 topic: "rust-mysql-cdc".to_owned(),
                 replicas: Some(2),
+                resume_mismatch: Some(ResumeMismatch::Warn),
+                envelope: Some(Envelope::Debezium),
             }),
         };
 
@@ -203,6 +314,17 @@ mod tests {
         );
         assert_eq!(profile.topic(), "rust-mysql-cdc".to_owned());
         assert_eq!(profile.replicas(), 2);
+        assert_eq!(profile.resume_mismatch(), ResumeMismatch::Warn);
+        assert_eq!(profile.envelope(), Envelope::Debezium);
+
+        match profile.source() {
+            Source::File => panic!("wrong variant"),
+            Source::Replication(replication) => {
+                assert_eq!(replication.port(), 3080);
+                assert_eq!(replication.server_id(), 2001);
+                assert_eq!(replication.relay_dir(), base_path.join("relay-log"));
+            }
+        }
     }
 
     #[test]
@@ -228,7 +350,9 @@ mod tests {
                 resume_offset_file: base_path.join(resume_offset_file.clone()),
                 local_store_file: base_path.join(local_store_file.clone()),
             },
+            source: None,
             filters: None,
+            masking: None,
             fluvio: None,
         };
 
@@ -249,25 +373,8 @@ mod tests {
         );
         assert_eq!(profile.topic(), "rust-mysql-cdc".to_owned());
         assert_eq!(profile.replicas(), 1);
-    }
-
-    #[test]
-    fn test_normalize_filter() {
-        let mut filter = Filters::Include {
-            include_dbs: vec![
-                "flvDb".to_string(),
-                "fluviodatabase".to_string(),
-                "FLUVIO_DB".to_string(),
-            ],
-        };
-        filter.normalize();
-        match filter {
-            Filters::Exclude { .. } => panic!("wrong variant"),
-            Filters::Include { include_dbs } => {
-                assert_eq!(&include_dbs[0], "flvdb");
-                assert_eq!(&include_dbs[1], "fluviodatabase");
-                assert_eq!(&include_dbs[2], "fluvio_db");
-            }
-        }
+        assert_eq!(profile.resume_mismatch(), ResumeMismatch::Fail);
+        assert_eq!(profile.envelope(), Envelope::Native);
+        assert_eq!(profile.source(), Source::File);
     }
 }